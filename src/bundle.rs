@@ -0,0 +1,291 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{Cursor, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::Local;
+use serde_json::Value;
+use zip::write::FileOptions;
+use zip::{ZipArchive, ZipWriter};
+
+use crate::config::Settings;
+use crate::data::data_brokers::stream::redact_url;
+use crate::data::HistoricalData;
+use crate::error::QuarsError;
+use crate::portfolio::PortfolioStats;
+use crate::utils::{write_atomic, write_csv_atomic, write_stats, BenchmarkReturns};
+
+// Archives written by --export-bundle, one per run named after its start time
+pub const BUNDLE_DIR: &str = "bundles";
+const CONFIG_FILE: &str = "config.json";
+const PRICES_FILE: &str = "prices.csv";
+const STATS_DIR: &str = "stats";
+const RESULTS_DIR: &str = "results";
+const RESULTS_FILE: &str = "results.json";
+// Outputs of the working directory collected into the bundle
+const OUTPUT_EXTENSIONS: [&str; 6] = ["png", "csv", "json", "xlsx", "dat", "gp"];
+const REDACTED: &str = "REDACTED";
+
+/// Role of a run towards a bundle
+pub enum Mode {
+    // Stages its inputs and outputs in the directory, archived once the run is done
+    Export(PathBuf),
+    // Replays the bundle extracted to the directory
+    Replay(PathBuf),
+}
+
+impl Mode {
+    /// Stages a new bundle named after the current time
+    pub fn export() -> Self {
+        let name = Local::now().format("%Y%m%d_%H%M%S").to_string();
+        Self::Export(Path::new(BUNDLE_DIR).join(name))
+    }
+
+    /// Staging directory of an exported bundle
+    pub fn export_dir(&self) -> Option<&Path> {
+        match self {
+            Self::Export(dir) => Some(dir),
+            Self::Replay(_) => None,
+        }
+    }
+}
+
+/// Writes the resolved settings to the bundle as JSON, with the seed the run drew and the
+/// API key and the query of the monitor's URL, which may carry a key of its own, redacted
+pub fn write_config(settings: &Settings, seed: u64, dir: &Path) -> Result<(), QuarsError> {
+    let mut config = serde_json::to_value(settings)?;
+    config["seed"] = Value::from(seed);
+    config["data_api"]["api_key"] = Value::from(REDACTED);
+    if let Some(url) = &settings.monitor.url {
+        config["monitor"]["url"] = Value::from(redact_url(url));
+    }
+    fs::create_dir_all(dir)?;
+    write_atomic(
+        dir.join(CONFIG_FILE),
+        serde_json::to_string_pretty(&config)?.as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Writes the prices as fetched to the bundle, at full precision and with the assets in the
+/// order of the data, which is the order of the statistics estimated from them
+pub fn write_prices(data: &HistoricalData, dir: &Path) -> Result<(), QuarsError> {
+    let mut assets: Vec<&str> = Vec::new();
+    let mut prices = BTreeMap::new();
+    for record in data {
+        if !assets.contains(&record.asset.as_str()) {
+            assets.push(&record.asset);
+        }
+        prices.insert((record.date.as_str(), record.asset.as_str()), record.price);
+    }
+    let mut dates: Vec<&str> = prices.keys().map(|(date, _)| *date).collect();
+    dates.dedup();
+    fs::create_dir_all(dir)?;
+    write_csv_atomic(dir.join(PRICES_FILE), |wtr| {
+        let mut header = vec!["date"];
+        header.extend(&assets);
+        wtr.write_record(&header)?;
+        for date in &dates {
+            let mut row = vec![date.to_string()];
+            row.extend(assets.iter().map(|asset| {
+                prices
+                    .get(&(*date, *asset))
+                    .map_or_else(String::new, f64::to_string)
+            }));
+            wtr.write_record(&row)?;
+        }
+        Ok(())
+    })?;
+    Ok(())
+}
+
+/// Writes the estimated statistics, with the benchmark returns, to the bundle in the
+/// --stats-only cache format
+pub fn write_bundle_stats(
+    stats: &PortfolioStats,
+    benchmark: Option<&BenchmarkReturns>,
+    dir: &Path,
+) -> Result<(), QuarsError> {
+    write_stats(stats, benchmark, &dir.join(STATS_DIR).to_string_lossy())
+}
+
+/// Copies the outputs the run wrote to the working directory since `since` into the staging
+/// directory and archives it as a .zip next to it, removing the directory. Returns the
+/// path of the archive.
+pub fn archive(dir: &Path, since: SystemTime) -> Result<PathBuf, QuarsError> {
+    let results_dir = dir.join(RESULTS_DIR);
+    fs::create_dir_all(&results_dir)?;
+    for entry in fs::read_dir(".")? {
+        let path = entry?.path();
+        let is_output = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| OUTPUT_EXTENSIONS.contains(&ext));
+        if is_output && path.is_file() && fs::metadata(&path)?.modified()? >= since {
+            if let Some(name) = path.file_name() {
+                fs::copy(&path, results_dir.join(name))?;
+            }
+        }
+    }
+
+    let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+    add_dir(&mut zip, dir, "")?;
+    let path = dir.with_extension("zip");
+    write_atomic(&path, &zip.finish()?.into_inner())?;
+    fs::remove_dir_all(dir)?;
+    Ok(path)
+}
+
+// Adds the files under `dir` to the archive, named by their path below the bundle root
+fn add_dir(
+    zip: &mut ZipWriter<Cursor<Vec<u8>>>,
+    dir: &Path,
+    prefix: &str,
+) -> Result<(), QuarsError> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        let name = format!(
+            "{}{}",
+            prefix,
+            path.file_name().unwrap_or_default().to_string_lossy()
+        );
+        if path.is_dir() {
+            add_dir(zip, &path, &format!("{}/", name))?;
+        } else {
+            zip.start_file(name, FileOptions::default())?;
+            zip.write_all(&fs::read(&path)?)?;
+        }
+    }
+    Ok(())
+}
+
+/// Extracts a bundle into the directory named after the archive, next to it
+pub fn extract(path: &str) -> Result<PathBuf, QuarsError> {
+    let dir = Path::new(path).with_extension("");
+    if dir == Path::new(path) {
+        return Err(QuarsError::Config(format!(
+            "Bundle {} should be a .zip archive",
+            path
+        )));
+    }
+    ZipArchive::new(fs::File::open(path)?)?.extract(&dir)?;
+    Ok(dir)
+}
+
+/// Settings of an extracted bundle, with the prices read from the bundled CSV so the replay
+/// runs offline. Mock data is generated again from the recorded seed instead, which keeps
+/// the later random draws (resampling, Monte Carlo) those of the exported run.
+pub fn load_settings(dir: &Path) -> Result<Settings, QuarsError> {
+    let mut settings = Settings::from_file(&dir.join(CONFIG_FILE))?;
+    let general = &mut settings.general;
+    if general.data_source != "mock" {
+        general.data_source = "csv".to_string();
+        general.data_file = dir.join(PRICES_FILE).to_string_lossy().into_owned();
+        general.data_file_entry = None;
+        general.csv_delimiter = ',';
+        general.decimal_separator = '.';
+        general.csv_has_header = true;
+        general.csv_column_names = None;
+        general.date_format = None;
+    }
+    Ok(settings)
+}
+
+/// Whether the optimization results the replay wrote to the working directory are those of
+/// the bundle
+pub fn check_results(dir: &Path) -> Result<bool, QuarsError> {
+    let read = |path: &Path| -> Result<Value, QuarsError> {
+        Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+    };
+    let expected = read(&dir.join(RESULTS_DIR).join(RESULTS_FILE))?;
+    let actual = read(Path::new(RESULTS_FILE))?;
+    Ok(expected["results"] == actual["results"])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::read_csv;
+    use crate::optimization::optimize_portfolio;
+    use crate::portfolio::calculate_portfolio_stats;
+
+    // Prices of the configured CSV, read as a run does
+    fn read_prices(settings: &Settings) -> HistoricalData {
+        let general = &settings.general;
+        read_csv(
+            &general.data_file,
+            general.csv_delimiter,
+            general.decimal_separator,
+            general.csv_has_header,
+            general.csv_column_names.as_deref(),
+            general.data_file_entry.as_deref(),
+            general.date_format.as_deref(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn a_replayed_bundle_reproduces_the_optimization() {
+        let root = std::env::temp_dir().join(format!("quars-bundle-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        // Prices at full precision, which the bundle has to keep for the replay to match
+        let mut prices = "date,AAA,BBB,CCC\n".to_string();
+        for day in 1..=30 {
+            let t = day as f64;
+            prices += &format!(
+                "2024-01-{:02},{},{},{}\n",
+                day,
+                100.0 + t + 3.0 * (t * 1.3).sin() / 7.0,
+                50.0 + 0.2 * t + 2.0 * (t * 0.7).cos() / 3.0,
+                80.0 - 0.1 * t + (t * 2.1).sin() / 9.0
+            );
+        }
+        let data_file = root.join("prices.csv");
+        fs::write(&data_file, prices).unwrap();
+        let config_file = root.join("config.toml");
+        fs::write(
+            &config_file,
+            format!(
+                "[general]\ndata_source = \"csv\"\ndata_file = \"{}\"\n\
+                 [data_api]\nsource = \"twelve\"\napi_key = \"secret\"\ntickers = [\"AAA\", \"BBB\", \"CCC\"]\n\
+                 start_date = \"2024-01-01\"\nend_date = \"2024-01-30\"\ntimeframe = \"daily\"\n\
+                 [portofolio_optimization]\nmethod = \"mvo\"\nsub_method = \"risk-adjusted\"\n\
+                 risk_free_rate = 0.0\nparams = [0.5]\n\
+                 [monitor]\nurl = \"wss://prices.example.com/v1?apikey=secret\"\n",
+                data_file.display()
+            ),
+        )
+        .unwrap();
+        let settings = Settings::from_file(&config_file).unwrap();
+        let optimize = |settings: &Settings| {
+            let stats = calculate_portfolio_stats(&read_prices(settings), "daily", false).unwrap();
+            optimize_portfolio(&stats, 10, &settings.portofolio_optimization).unwrap()
+        };
+        let exported = optimize(&settings);
+
+        let staging = root.join("run");
+        write_config(&settings, 7, &staging).unwrap();
+        write_prices(&read_prices(&settings), &staging).unwrap();
+        let mut zip = ZipWriter::new(Cursor::new(Vec::new()));
+        add_dir(&mut zip, &staging, "").unwrap();
+        fs::remove_dir_all(&staging).unwrap();
+        let archive = staging.with_extension("zip");
+        fs::write(&archive, zip.finish().unwrap().into_inner()).unwrap();
+
+        let dir = extract(&archive.to_string_lossy()).unwrap();
+        let config = fs::read_to_string(dir.join(CONFIG_FILE)).unwrap();
+        assert!(!config.contains("secret"), "{}", config);
+        let replayed = optimize(&load_settings(&dir).unwrap());
+        assert_eq!(
+            replayed.optimal_risky_portfolio,
+            exported.optimal_risky_portfolio
+        );
+        assert_eq!(replayed.optimal_risky_return, exported.optimal_risky_return);
+        assert_eq!(replayed.optimal_risky_std, exported.optimal_risky_std);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,102 @@
+use std::iter::Peekable;
+
+use crate::error::QuarsError;
+
+/// Command-line flags of a run, parsed once at startup
+#[derive(Debug, Default, PartialEq)]
+pub struct Args {
+    // Cache the statistics without optimizing
+    pub stats_only: bool,
+    // Directory of statistics cached by --stats-only, the optimizer runs on them alone
+    pub from_stats: Option<String>,
+    // Archive the resolved config, the prices, the statistics and the outputs under bundles/
+    pub export_bundle: bool,
+    // Archive of an exported bundle, replayed offline
+    pub run_bundle: Option<String>,
+    // Run every optimization method on the statistics instead of the reports
+    pub compare_methods: bool,
+    // Print the optimizer's intermediate quantities
+    pub explain: bool,
+    // Follow the tangency portfolio on live prices instead of the reports
+    pub monitor: bool,
+    // Print the stage timings at the end of the run
+    pub timings: bool,
+}
+
+impl Args {
+    /// Parses the arguments after the program name, rejecting unknown flags and flags
+    /// missing their value
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, QuarsError> {
+        let mut parsed = Self::default();
+        let mut args = args.into_iter().peekable();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--stats-only" => parsed.stats_only = true,
+                "--from-stats" => parsed.from_stats = Some(value_of(&arg, &mut args)?),
+                "--export-bundle" => parsed.export_bundle = true,
+                "--run-bundle" => parsed.run_bundle = Some(value_of(&arg, &mut args)?),
+                "--compare-methods" => parsed.compare_methods = true,
+                "--explain" => parsed.explain = true,
+                "--monitor" => parsed.monitor = true,
+                "--timings" => parsed.timings = true,
+                other => return Err(QuarsError::Config(format!("Unknown argument '{}'", other))),
+            }
+        }
+        if parsed.stats_only && parsed.from_stats.is_some() {
+            return Err(QuarsError::Config(
+                "--stats-only and --from-stats cannot be combined".to_string(),
+            ));
+        }
+        Ok(parsed)
+    }
+}
+
+// Value of a flag, the next argument unless it is another flag
+fn value_of(
+    flag: &str,
+    args: &mut Peekable<impl Iterator<Item = String>>,
+) -> Result<String, QuarsError> {
+    args.next_if(|value| !value.starts_with("--"))
+        .ok_or_else(|| QuarsError::Config(format!("{} needs a value", flag)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, QuarsError> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_flags_and_their_values() {
+        let args = parse(&["--from-stats", "data/raw/2024-01-02", "--explain"]).unwrap();
+        assert_eq!(
+            args,
+            Args {
+                from_stats: Some("data/raw/2024-01-02".to_string()),
+                explain: true,
+                ..Args::default()
+            }
+        );
+        let args = parse(&["--run-bundle", "bundles/run.zip", "--timings"]).unwrap();
+        assert_eq!(args.run_bundle.as_deref(), Some("bundles/run.zip"));
+        assert!(args.timings);
+    }
+
+    #[test]
+    fn rejects_missing_values_and_unknown_flags() {
+        for args in [
+            &["--from-stats"][..],
+            &["--from-stats", "--explain"],
+            &["--stats-only", "--from-stats", "dir"],
+            &["--stats_only"],
+        ] {
+            assert!(
+                matches!(parse(args), Err(QuarsError::Config(_))),
+                "{:?}",
+                args
+            );
+        }
+    }
+}
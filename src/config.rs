@@ -1,45 +1,569 @@
-use config::{Config, File};
-use serde::Deserialize;
+use chrono::{Datelike, Days, Local, Months, NaiveDate};
+use config::{builder::DefaultState, Config, ConfigBuilder, ConfigError, File};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct Settings {
+    // Seeds every random draw of a run, a random seed is drawn and reported when omitted
+    #[serde(default)]
+    pub seed: Option<u64>,
     pub general: General,
     pub data_api: DataAPI,
     pub portofolio_optimization: PortofolioOptimization,
+    #[serde(default)]
+    pub mock: MockSettings,
+    #[serde(default)]
+    pub monitor: MonitorSettings,
+    pub volatility_target: Option<VolatilityTarget>,
+    #[serde(default)]
+    pub visualization: VizSettings,
+    pub tracking: Option<TrackingSettings>,
+    pub backtest: Option<BacktestSettings>,
+    pub momentum_tilt: Option<MomentumTilt>,
+    pub score_tilt: Option<ScoreTilt>,
+    pub ticker_normalization: Option<TickerNormalization>,
+    #[serde(default)]
+    pub synthetic_assets: Vec<SyntheticAsset>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct General {
     pub data_source: String,
     pub data_file: String,
+    // Member of a .zip data_file to read, needed when the archive holds several CSV files
+    pub data_file_entry: Option<String>,
+    // CSV store the fetched prices are appended to, only the dates after its last one fetched
+    pub append_store: Option<String>,
+    // Error instead of warning when the data frequency disagrees with the configured timeframe
+    #[serde(default)]
+    pub strict_frequency: bool,
+    // Ticker fetched with the universe but kept out of the optimization, used for comparisons
+    pub benchmark_ticker: Option<String>,
+    #[serde(default = "default_csv_delimiter")]
+    pub csv_delimiter: char,
+    #[serde(default = "default_decimal_separator")]
+    pub decimal_separator: char,
+    // Whether the first CSV row holds the column names
+    #[serde(default = "default_csv_has_header")]
+    pub csv_has_header: bool,
+    // Asset names of the CSV price columns, after the date column
+    pub csv_column_names: Option<Vec<String>>,
+    // chrono layout of the CSV dates, e.g. "%d/%m/%Y", detected from the dates when omitted
+    pub date_format: Option<String>,
+    // "warn", "exclude", "carry-forward" or "error" for assets whose prices stop more than
+    // delisting_tolerance_days calendar days before the others'
+    #[serde(default = "default_delisting_policy")]
+    pub delisting_policy: String,
+    #[serde(default = "default_delisting_tolerance_days")]
+    pub delisting_tolerance_days: i64,
+    // "warn", "drop-asset" or "drop-segment" (drop the dates of the repeats) for the runs of
+    // more than `max_stale_prices` identical consecutive prices of an asset
+    #[serde(default = "default_stale_policy")]
+    pub stale_policy: String,
+    #[serde(default = "default_max_stale_prices")]
+    pub max_stale_prices: usize,
+    // "weekdays", "nyse" or "continuous": trading days the daily prices are checked for gaps
+    // against, the closed days (weekends, holidays) not counting as missing data
+    pub trading_calendar: Option<String>,
+    // Further closed dates of the trading calendar, e.g. unscheduled exchange closures
+    #[serde(default)]
+    pub holidays: Vec<String>,
+    // "warn", "adjust" (back-adjust the earlier prices) or "drop" (zero return on the split
+    // date) for the stock splits detected in prices that are not split adjusted
+    #[serde(default = "default_split_policy")]
+    pub split_policy: String,
+    // Assets whose average price is below this floor are dropped before the statistics
+    pub min_average_price: Option<f64>,
+    // Decimals of the prices and returns written to CSV, full precision when omitted
+    pub price_decimals: Option<usize>,
+    pub return_decimals: Option<usize>,
+    // Decimal separator of the numbers written to CSV, "," also switches the delimiter to ";"
+    #[serde(default = "default_decimal_separator")]
+    pub output_decimal_separator: char,
+    // Least severe events logged: "off", "error", "warn", "info", "debug" or "trace"
+    #[serde(default = "default_log_level")]
+    pub log_level: String,
+    // File the events are written to as JSON lines, the console then only shows warnings
+    pub log_file: Option<String>,
+    // Worker threads of the parallel computations (covariance, Monte Carlo VaR, resampled
+    // frontier), all cores when omitted. Results do not depend on it.
+    pub threads: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+fn default_log_level() -> String {
+    "warn".to_string()
+}
+
+fn default_blend_steps() -> usize {
+    100
+}
+
+fn default_horizon() -> usize {
+    1
+}
+
+fn default_covariance_estimator() -> String {
+    "sample".to_string()
+}
+
+fn default_compounding() -> String {
+    "discrete".to_string()
+}
+
+fn default_frontier_points() -> usize {
+    50
+}
+
+fn default_periods_per_year() -> f64 {
+    252.0
+}
+
+fn default_frontier_spacing() -> String {
+    "uniform".to_string()
+}
+
+fn default_csv_delimiter() -> char {
+    ','
+}
+
+fn default_decimal_separator() -> char {
+    '.'
+}
+
+fn default_delisting_policy() -> String {
+    "warn".to_string()
+}
+
+fn default_delisting_tolerance_days() -> i64 {
+    7
+}
+
+fn default_stale_policy() -> String {
+    "warn".to_string()
+}
+
+fn default_max_stale_prices() -> usize {
+    10
+}
+
+fn default_split_policy() -> String {
+    "warn".to_string()
+}
+
+fn default_csv_has_header() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Serialize)]
 pub struct PortofolioOptimization {
     pub method: String,
     pub sub_method: String,
     pub risk_free_rate: f64,
+    // Annual rate earned on a positive risk-free weight, replacing `risk_free_rate` when set
+    pub lending_rate: Option<f64>,
+    // Annual rate paid on a negative risk-free weight, the borrowing that levers the CAL past
+    // the tangency portfolio, the lending rate when omitted
+    pub borrowing_rate: Option<f64>,
+    // Compounding of the annual rates, "discrete" or "continuous"
+    #[serde(default = "default_compounding")]
+    pub compounding: String,
     pub params: Vec<f64>,
+    // Ticker held as the risk-free leg, its mean return replaces `risk_free_rate`
+    pub cash_ticker: Option<String>,
+    // Metric the assets are ranked by to keep the top `preselect_n` before optimizing,
+    // "sharpe", "momentum" or "mean-return", no pre-selection when omitted
+    pub preselect_by: Option<String>,
+    pub preselect_n: Option<usize>,
+    // Annualized expected returns per ticker, replacing the historical means
+    pub expected_returns: Option<HashMap<String, f64>>,
+    // CSV with `asset,expected_return` rows, used when `expected_returns` is not set
+    pub expected_returns_file: Option<String>,
+    // Annualized return below which the min-semivariance sub_method counts downside,
+    // `risk_free_rate` when omitted
+    pub downside_target: Option<f64>,
+    // Target the mean returns are shrunk toward, "grand-mean" or "min-variance", no shrinkage
+    // when omitted
+    pub mean_shrinkage: Option<String>,
+    // Weight of the target in [0, 1], the Bayes-Stein estimate when omitted
+    pub mean_shrinkage_intensity: Option<f64>,
+    // Budget the tangency portfolio is converted into share counts for, at the latest prices
+    pub share_budget: Option<f64>,
+    // Allow fractional shares in that conversion instead of whole ones
+    #[serde(default)]
+    pub fractional_shares: bool,
+    // Annualized concentration penalties δ of the return, risk and concentration frontier, the
+    // return given up per unit of xᵀx, no frontier when omitted
+    pub concentration_penalties: Option<Vec<f64>>,
+    // Grid resolution of the near-optimal blend search
+    #[serde(default = "default_blend_steps")]
+    pub blend_steps: usize,
+    // "sample" covariance of the returns, or "realized" covariance of the log-price increments
+    #[serde(default = "default_covariance_estimator")]
+    pub covariance_estimator: String,
+    // Periods each listed asset's returns are shifted back by before the estimation, for
+    // lead-lag studies, all series truncated to the common overlap
+    pub return_lags: Option<HashMap<String, usize>>,
+    // Annualized λ added to the covariance diagonal before the optimizer inverts it, pulling
+    // the weights toward equal weights as it grows
+    #[serde(default)]
+    pub covariance_ridge: f64,
+    // Estimate the means and covariance in one pass over the prices without building the
+    // returns matrix, the prices are still held; the return series reports are skipped
+    #[serde(default)]
+    pub streaming_stats: bool,
+    // Holding period, in data periods, of the overlapping compounded returns the means and
+    // covariance are estimated on
+    #[serde(default = "default_horizon")]
+    pub horizon: usize,
+    // Autocorrelation lags of the Newey-West correction of the realized covariance
+    #[serde(default)]
+    pub realized_lags: usize,
+    // Annualized covariance supplied externally, replacing the estimated one. A CSV with the
+    // asset names in the header row and first column, or a JSON {"assets", "covariance"}
+    pub covariance_file: Option<String>,
+    // Least weight of the risk-free asset, capping the risky exposure of the frontier at
+    // 1 - min_cash_weight
+    pub min_cash_weight: Option<f64>,
+    // Tangency weights smaller in magnitude are zeroed and the others rescaled to the same
+    // total, e.g. 0.005 drops the positions under 0.5%
+    pub min_trade_weight: Option<f64>,
+    // Number of points of the frontiers: the capital allocation line, the minimum-variance
+    // frontier of the risky assets and the active frontier
+    #[serde(default = "default_frontier_points")]
+    pub frontier_points: usize,
+    // Caps on the plotted frontier, annualized
+    pub max_frontier_return: Option<f64>,
+    pub max_frontier_vol: Option<f64>,
+    // Annualized volatilities the frontier portfolios are reported at, on the capital
+    // allocation line and on the minimum-variance frontier of the risky assets
+    pub target_vols: Option<Vec<f64>>,
+    // Target returns of the plotted minimum-variance frontier, "uniform" or "adaptive"
+    #[serde(default = "default_frontier_spacing")]
+    pub frontier_spacing: String,
+    // Number of bootstrap resamples of a Michaud resampled frontier, plain frontier if omitted
+    pub resamples: Option<usize>,
+    // Annualized bump of each expected return in the return sensitivity report, no report
+    // when omitted
+    pub sensitivity_bump: Option<f64>,
+    // Shift of the annual risk-free rate in the rate sensitivity report, no report when omitted
+    pub rate_sensitivity_bump: Option<f64>,
+    // Optimize the returns in excess of `benchmark_ticker` instead of the raw returns
+    #[serde(default)]
+    pub excess_over_benchmark: bool,
+    // Print the optimizer's intermediate quantities, also enabled by the --explain flag
+    #[serde(default)]
+    pub explain: bool,
+    // Return periods per year of the [data_api] timeframe, the annual rates are converted with
+    #[serde(skip, default = "default_periods_per_year")]
+    pub periods_per_year: f64,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct DataAPI {
     pub source: String,
+    // Not needed for the csv and mock data sources
+    #[serde(default)]
     pub api_key: String,
     pub tickers: Vec<String>,
+    // Broker per ticker for tickers not available from `source`
+    #[serde(default)]
+    pub ticker_sources: HashMap<String, String>,
+    // YYYY-MM-DD, or relative to the load date: "today", "ytd", "-N" with d, w, m or y
     pub start_date: String,
     pub end_date: String,
+    // Runs as if on this date, same formats as end_date: the prices after it are dropped
+    // before any statistic, so nothing is known that was not known then
+    pub as_of_date: Option<String>,
     pub timeframe: String,
+    // Use dividend and split adjusted close prices where the broker supports it
+    #[serde(default)]
+    pub use_adjusted: bool,
+    // IANA timezone intraday timestamps are reported in, UTC when omitted
+    pub timezone: Option<String>,
+    // Load the most recent CSV saved in data/raw when the API fetch fails
+    #[serde(default)]
+    pub fallback_to_cache_on_error: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct VizSettings {
+    // Logarithmic risk axis in the efficient frontier plot
+    pub log_risk_axis: bool,
+    // Logarithmic frequency axis in the return distribution plot
+    pub log_frequency_axis: bool,
+    // Show annualized returns and volatilities in the efficient frontier plot
+    pub annualized: bool,
+    // Draw the capital allocation line up to the maximum leverage instead of the tangency
+    pub extend_cal: bool,
+    // Also write the data of each chart to a CSV next to the image, e.g. portfolio.csv
+    pub export_plot_data: bool,
+    // Sort the bars of the portfolio weights chart by weight, largest first
+    pub sort_weights: bool,
+    // Draw the bars of the portfolio weights chart horizontally, e.g. for many assets or
+    // long tickers
+    pub horizontal_weights: bool,
+    // Also write the frontier and the weights as gnuplot data files with a script plotting
+    // them, efficient_frontier.dat, weights.dat and efficient_frontier.gp
+    pub gnuplot: bool,
+    // Date of a point-in-time run shown in the chart titles, taken from [data_api]
+    #[serde(skip)]
+    pub as_of_date: Option<String>,
+}
+
+// Rebalancing of the backtest
+#[derive(Debug, Deserialize, Serialize)]
+pub struct BacktestSettings {
+    // "monthly", "quarterly" or "every-n-days"
+    #[serde(default = "default_rebalance_schedule")]
+    pub rebalance_schedule: String,
+    // Calendar days between rebalances of the "every-n-days" schedule
+    pub rebalance_days: Option<u32>,
+    // τ values of the risk-adjusted method the walk-forward selection compares, none when
+    // omitted
+    pub tau_grid: Option<Vec<f64>>,
+    // Trailing periods each rebalance of the walk-forward estimates on
+    #[serde(default = "default_estimation_window")]
+    pub estimation_window: usize,
+    // Out-of-sample criterion the τ is selected by, "sharpe" or "drawdown"
+    #[serde(default = "default_tau_objective")]
+    pub tau_objective: String,
+}
+
+fn default_rebalance_schedule() -> String {
+    "monthly".to_string()
+}
+
+fn default_estimation_window() -> usize {
+    252
+}
+
+fn default_tau_objective() -> String {
+    "sharpe".to_string()
+}
+
+// Minimum tracking error portfolio replicating target weights, e.g. an index
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TrackingSettings {
+    pub target_weights: HashMap<String, f64>,
+    pub min_weight: Option<f64>,
+    pub max_weight: Option<f64>,
+    pub max_assets: Option<usize>, // largest number of assets held
+    #[serde(default)]
+    pub active_frontier: bool, // also plot the active frontier over the target weights
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct VolatilityTarget {
+    pub target_vol: f64, // annualized
+    pub window: usize,   // trailing observations used to estimate the realized volatility
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct MomentumTilt {
+    pub weight: f64,     // blend weight of the trailing momentum in the expected returns
+    pub lookback: usize, // trailing periods the momentum is compounded over
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ScoreTilt {
+    // η of the tilt w·exp(η·score), 0 leaves the weights as optimized
+    pub strength: f64,
+    // External score per ticker, 0 for the tickers left out
+    pub scores: HashMap<String, f64>,
+}
+
+// Normalization of the tickers of the data, so that the variants of a ticker are one asset.
+// Tickers are uppercased first, the suffixes and aliases match uppercased.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct TickerNormalization {
+    // Exchange suffixes stripped from the tickers, e.g. ".US"
+    #[serde(default)]
+    pub strip_suffixes: Vec<String>,
+    // Ticker renames applied after the suffixes are stripped, e.g. GOOG = "GOOGL"
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+}
+
+// Asset optimized as a linear combination of fetched ones, e.g. the spread of a long/short pair
+#[derive(Debug, Deserialize, Serialize)]
+pub struct SyntheticAsset {
+    pub name: String,
+    // Constituent tickers and their weights, e.g. { AAPL = 1.0, MSFT = -1.0 }
+    pub weights: HashMap<String, f64>,
+    // Remove the constituents from the universe, which the combination otherwise leaves with
+    // a singular covariance, only optimizable with a covariance_ridge
+    #[serde(default = "default_drop_constituents")]
+    pub drop_constituents: bool,
+}
+
+fn default_drop_constituents() -> bool {
+    true
+}
+
+// Parameters of the synthetic geometric Brownian motion used by the "mock" data source
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MockSettings {
+    pub seed: Option<u64>, // pins the prices independently of the run seed
+    pub initial_price: f64,
+    pub drift: f64,      // annualized
+    pub volatility: f64, // annualized
+    pub correlation: f64,
+}
+
+impl Default for MockSettings {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            initial_price: 100.0,
+            drift: 0.07,
+            volatility: 0.2,
+            correlation: 0.3,
+        }
+    }
+}
+
+// Live monitoring of the tangency portfolio on streamed prices, run with --monitor
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct MonitorSettings {
+    // Price websocket, Twelve Data's with the data_api key when omitted
+    pub url: Option<String>,
+    // Seconds between the price snapshots the returns are taken on
+    pub interval_secs: u64,
+    // Number of returns kept for the VaR and the re-optimized weights
+    pub window: usize,
+}
+
+impl Default for MonitorSettings {
+    fn default() -> Self {
+        Self {
+            url: None,
+            interval_secs: 60,
+            window: 120,
+        }
+    }
 }
 
 impl Settings {
-    pub fn new() -> Result<Self, config::ConfigError> {
+    pub fn new() -> Result<Self, ConfigError> {
         dotenv::dotenv().ok();
-        let s = Config::builder()
-            .add_source(File::with_name("config"))
-            // Retrieve the api key from .env
-            .add_source(config::Environment::with_prefix("APP").separator("__"))
-            .build()?;
-        s.try_deserialize()
+        Self::build(
+            Config::builder()
+                .add_source(File::with_name("config"))
+                // Retrieve the api key from .env
+                .add_source(config::Environment::with_prefix("APP").separator("__")),
+        )
+    }
+
+    /// Settings of a single file of any format the config crate reads, without the
+    /// environment, e.g. the resolved config of a run bundle
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        Self::build(Config::builder().add_source(File::from(path)))
+    }
+
+    fn build(builder: ConfigBuilder<DefaultState>) -> Result<Self, ConfigError> {
+        let mut settings: Self = builder.build()?.try_deserialize()?;
+        // Relative ranges are pinned once, so every stage of the run sees the same dates
+        let today = Local::now().date_naive();
+        settings.data_api.start_date = resolve_date(&settings.data_api.start_date, today)?;
+        settings.data_api.end_date = resolve_date(&settings.data_api.end_date, today)?;
+        if let Some(as_of) = &settings.data_api.as_of_date {
+            let as_of = resolve_date(as_of, today)?;
+            if as_of < settings.data_api.start_date {
+                return Err(ConfigError::Message(format!(
+                    "as_of_date {} is before start_date {}",
+                    as_of, settings.data_api.start_date
+                )));
+            }
+            settings.data_api.as_of_date = Some(as_of);
+        }
+        if let Some(lending_rate) = settings.portofolio_optimization.lending_rate {
+            settings.portofolio_optimization.risk_free_rate = lending_rate;
+        }
+        Ok(settings)
+    }
+}
+
+/// Resolves a relative date of the config against `today`: "today", "ytd" for the first day
+/// of the year, or "-N" followed by d, w, m or y for N days, weeks, months or years before.
+/// Months and years keep the day of the month, clamped to the month's end. Any other string,
+/// e.g. a YYYY-MM-DD date, is returned unchanged.
+pub fn resolve_date(spec: &str, today: NaiveDate) -> Result<String, ConfigError> {
+    let date = match spec.trim() {
+        "today" => today,
+        "ytd" => NaiveDate::from_ymd_opt(today.year(), 1, 1).unwrap_or(today),
+        relative if relative.starts_with('-') => {
+            let invalid = || {
+                ConfigError::Message(format!(
+                    "Invalid relative date '{}', use e.g. -30d, -2w, -6m or -1y",
+                    spec
+                ))
+            };
+            let body = &relative[1..];
+            let (count, unit) = body.split_at(
+                body.find(|c: char| !c.is_ascii_digit())
+                    .unwrap_or(body.len()),
+            );
+            let count: u32 = count.parse().map_err(|_| invalid())?;
+            match unit {
+                "d" => today.checked_sub_days(Days::new(count.into())),
+                "w" => today.checked_sub_days(Days::new(7 * u64::from(count))),
+                "m" => today.checked_sub_months(Months::new(count)),
+                "y" => count
+                    .checked_mul(12)
+                    .and_then(|months| today.checked_sub_months(Months::new(months))),
+                _ => return Err(invalid()),
+            }
+            .ok_or_else(invalid)?
+        }
+        _ => return Ok(spec.to_string()),
+    };
+    Ok(date.format("%Y-%m-%d").to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_dates_resolve_against_the_reference_date() {
+        let reference = NaiveDate::from_ymd_opt(2024, 3, 15).unwrap();
+        let resolve = |spec: &str| resolve_date(spec, reference).unwrap();
+        assert_eq!(resolve("-1y"), "2023-03-15");
+        assert_eq!(resolve("today"), "2024-03-15");
+        assert_eq!(resolve("ytd"), "2024-01-01");
+        assert_eq!(resolve("-6m"), "2023-09-15");
+        assert_eq!(resolve("-2w"), "2024-03-01");
+        assert_eq!(resolve("2020-01-02"), "2020-01-02");
+        // A year back from a leap day clamps to the month's end
+        let leap_day = NaiveDate::from_ymd_opt(2024, 2, 29).unwrap();
+        assert_eq!(resolve_date("-1y", leap_day).unwrap(), "2023-02-28");
+        assert!(resolve_date("-1q", reference).is_err());
+
+        // Loading the config pins "today" to the current date
+        let path = std::env::temp_dir().join(format!("quars-config-{}.toml", std::process::id()));
+        std::fs::write(
+            &path,
+            "[general]\ndata_source = \"csv\"\ndata_file = \"prices.csv\"\n\
+             [data_api]\nsource = \"mock\"\ntickers = [\"AAA\"]\n\
+             start_date = \"-1y\"\nend_date = \"today\"\ntimeframe = \"daily\"\n\
+             [portofolio_optimization]\nmethod = \"mvo\"\nsub_method = \"risk-adjusted\"\n\
+             risk_free_rate = 0.0\nparams = [0.5]\n",
+        )
+        .unwrap();
+        let before = Local::now().date_naive();
+        let settings = Settings::from_file(&path).unwrap();
+        let after = Local::now().date_naive();
+        std::fs::remove_file(&path).unwrap();
+        let end = NaiveDate::parse_from_str(&settings.data_api.end_date, "%Y-%m-%d").unwrap();
+        assert!(before <= end && end <= after);
+        assert_eq!(
+            settings.data_api.start_date,
+            resolve_date("-1y", end).unwrap()
+        );
     }
 }
@@ -0,0 +1,188 @@
+use crate::error::QuarsError;
+use crate::utils::parse_date;
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use std::collections::BTreeSet;
+
+// First year of each NYSE holiday in its current form
+const MLK_DAY_SINCE: i32 = 1998;
+const JUNETEENTH_SINCE: i32 = 2022;
+
+/// Days a market trades, which tells the dates a daily price series skips by design
+/// (weekends, holidays) from missing data
+#[derive(Debug, Clone)]
+pub struct TradingCalendar {
+    // Closed on Saturdays and Sundays
+    weekends: bool,
+    // Closed on the NYSE holidays
+    nyse: bool,
+    // Further closed dates of the configuration
+    holidays: BTreeSet<NaiveDate>,
+}
+
+impl TradingCalendar {
+    /// "weekdays" (Monday to Friday), "nyse" (weekdays but the NYSE holidays) or "continuous"
+    /// (every day, e.g. crypto), closed as well on the `holidays` dates
+    pub fn from_config(calendar: &str, holidays: &[String]) -> Result<Self, QuarsError> {
+        let (weekends, nyse) = match calendar {
+            "weekdays" => (true, false),
+            "nyse" => (true, true),
+            "continuous" => (false, false),
+            other => {
+                return Err(QuarsError::Config(format!(
+                    "Unknown trading calendar: {} (expected weekdays, nyse or continuous)",
+                    other
+                )))
+            }
+        };
+        Ok(Self {
+            weekends,
+            nyse,
+            holidays: holidays
+                .iter()
+                .map(|date| parse_date(date))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn is_trading_day(&self, date: NaiveDate) -> bool {
+        !(self.weekends && matches!(date.weekday(), Weekday::Sat | Weekday::Sun)
+            || self.holidays.contains(&date)
+            || self.nyse && nyse_holidays(date.year()).contains(&date))
+    }
+
+    /// Trading days strictly between two dates
+    pub fn trading_days_between(&self, start: NaiveDate, end: NaiveDate) -> Vec<NaiveDate> {
+        start
+            .iter_days()
+            .skip(1)
+            .take_while(|date| *date < end)
+            .filter(|date| self.is_trading_day(*date))
+            .collect()
+    }
+}
+
+/// Full-day NYSE holidays of a year, on the dates they are observed: a holiday on a Saturday
+/// closes the Friday before, one on a Sunday the Monday after, except New Year's Day, which
+/// is not made up on a Saturday. Unscheduled closures (e.g. 2001-09-11, 2012-10-29 or days of
+/// national mourning) are not included, list them as holidays.
+pub fn nyse_holidays(year: i32) -> Vec<NaiveDate> {
+    let date = |month, day| NaiveDate::from_ymd_opt(year, month, day).unwrap_or_default();
+    let mut holidays = Vec::new();
+    let new_year = date(1, 1);
+    if new_year.weekday() != Weekday::Sat {
+        holidays.push(observed(new_year));
+    }
+    if year >= MLK_DAY_SINCE {
+        holidays.push(nth_weekday(year, 1, Weekday::Mon, 3));
+    }
+    holidays.push(nth_weekday(year, 2, Weekday::Mon, 3));
+    holidays.push(easter(year) - Duration::days(2));
+    holidays.push(last_weekday(year, 5, Weekday::Mon));
+    if year >= JUNETEENTH_SINCE {
+        holidays.push(observed(date(6, 19)));
+    }
+    holidays.push(observed(date(7, 4)));
+    holidays.push(nth_weekday(year, 9, Weekday::Mon, 1));
+    holidays.push(nth_weekday(year, 11, Weekday::Thu, 4));
+    holidays.push(observed(date(12, 25)));
+    holidays
+}
+
+// Weekday a holiday falling on a weekend is observed on
+fn observed(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Duration::days(1),
+        Weekday::Sun => date + Duration::days(1),
+        _ => date,
+    }
+}
+
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).unwrap_or_default()
+}
+
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    let fifth = NaiveDate::from_weekday_of_month_opt(year, month, weekday, 5);
+    fifth.unwrap_or_else(|| nth_weekday(year, month, weekday, 4))
+}
+
+// Gregorian Easter Sunday (anonymous Gregorian algorithm)
+fn easter(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let (b, c) = (year / 100, year % 100);
+    let (d, e) = (b / 4, b % 4);
+    let g = (8 * b + 13) / 25;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let (i, k) = (c / 4, c % 4);
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let n = h + l - 7 * m + 114;
+    NaiveDate::from_ymd_opt(year, (n / 31) as u32, (n % 31 + 1) as u32).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dates(dates: &[&str]) -> Vec<NaiveDate> {
+        dates.iter().map(|date| parse_date(date).unwrap()).collect()
+    }
+
+    #[test]
+    fn nyse_holidays_match_the_published_schedules() {
+        assert_eq!(
+            nyse_holidays(2024),
+            dates(&[
+                "2024-01-01",
+                "2024-01-15",
+                "2024-02-19",
+                "2024-03-29",
+                "2024-05-27",
+                "2024-06-19",
+                "2024-07-04",
+                "2024-09-02",
+                "2024-11-28",
+                "2024-12-25",
+            ])
+        );
+        // New Year's Day on a Saturday is not made up, the Sunday holidays close the Monday
+        assert_eq!(
+            nyse_holidays(2022),
+            dates(&[
+                "2022-01-17",
+                "2022-02-21",
+                "2022-04-15",
+                "2022-05-30",
+                "2022-06-20",
+                "2022-07-04",
+                "2022-09-05",
+                "2022-11-24",
+                "2022-12-26",
+            ])
+        );
+    }
+
+    #[test]
+    fn trading_days_skip_the_closed_days_of_the_calendar() {
+        let (start, end) = (
+            parse_date("2024-03-27").unwrap(),
+            parse_date("2024-04-03").unwrap(),
+        );
+        let holidays = ["2024-04-02".to_string()];
+        let days = |calendar: &str| {
+            TradingCalendar::from_config(calendar, &holidays)
+                .unwrap()
+                .trading_days_between(start, end)
+        };
+        assert_eq!(days("nyse"), dates(&["2024-03-28", "2024-04-01"]));
+        assert_eq!(
+            days("weekdays"),
+            dates(&["2024-03-28", "2024-03-29", "2024-04-01"])
+        );
+        assert_eq!(days("continuous").len(), 5);
+        assert!(matches!(
+            TradingCalendar::from_config("lse", &[]),
+            Err(QuarsError::Config(_))
+        ));
+    }
+}
@@ -1,92 +1,258 @@
+use super::{json_to_f64, response_json};
 use crate::config::Settings;
 use crate::data::{HistoricalData, Record};
-use crate::utils::parse_date;
-use chrono::Local;
+use crate::error::QuarsError;
+use crate::utils::{self, parse_date};
+use chrono::{Local, NaiveDate};
 use reqwest::Client;
 use serde_json::Value;
-use std::error::Error;
 use std::fs;
 
-/// Alpha Vantage fetcher
-pub async fn fetch_data(settings: &Settings) -> Result<HistoricalData, Box<dyn Error>> {
+// Calendar days back the compact daily output reaches, its latest 100 points span more
+const COMPACT_DAYS: i64 = 100;
+
+/// Alpha Vantage fetcher of the prices from `start_date`, which may be later than the
+/// configured one when appending to a store
+pub async fn fetch_data(
+    settings: &Settings,
+    tickers: &[String],
+    start_date: NaiveDate,
+) -> Result<HistoricalData, QuarsError> {
     // Read parameters from config under [data_api]
     let api_key = &settings.data_api.api_key;
-    let tickers = &settings.data_api.tickers;
     let timeframe = settings.data_api.timeframe.to_lowercase();
-    let start_date_str = &settings.data_api.start_date;
-    let end_date_str = &settings.data_api.end_date;
-
-    let start_date = parse_date(start_date_str)?;
-    let end_date = parse_date(end_date_str)?;
-
-    let function = match timeframe.as_str() {
-        "daily" => "TIME_SERIES_DAILY",
-        "weekly" => "TIME_SERIES_WEEKLY",
-        "monthly" => "TIME_SERIES_MONTHLY",
-        _ => return Err(format!("Unsupported timeframe: {}", timeframe).into()),
+    let end_date = parse_date(&settings.data_api.end_date)?;
+    // The full history is only requested when the compact one would not reach the start
+    let outputsize = if timeframe == "daily"
+        && (Local::now().date_naive() - start_date).num_days() < COMPACT_DAYS
+    {
+        "compact"
+    } else {
+        "full"
     };
 
+    let (function, time_series_key, close_field) =
+        endpoint(&timeframe, settings.data_api.use_adjusted)?;
+
     let client = Client::new();
 
     // Store all records from all tickers in a vec
     let mut all_records = Vec::new();
 
     for ticker in tickers {
+        utils::check_cancelled()?;
         let url = format!(
-            "https://www.alphavantage.co/query?function={function}&symbol={symbol}&outputsize=full&apikey={apikey}",
+            "https://www.alphavantage.co/query?function={function}&symbol={symbol}&outputsize={outputsize}&apikey={apikey}",
             function = function,
             symbol = ticker,
+            outputsize = outputsize,
             apikey = api_key
         );
 
         let resp = client.get(&url).send().await?;
-        let json_val: Value = resp.json().await?;
+        let json_val = response_json(resp, "Alpha Vantage", ticker).await?;
 
         // Save raw API result in data/raw/{ticker}/{timeframe}/{datetimenow}
         save_api_result(&json_val, ticker, &timeframe)?;
-        let time_series_key = match timeframe.as_str() {
-            "daily" => "Time Series (Daily)",
-            "weekly" => "Weekly Time Series",
-            "monthly" => "Monthly Time Series",
-            _ => unreachable!(),
-        };
+        all_records.extend(parse_time_series(
+            &json_val,
+            ticker,
+            time_series_key,
+            close_field,
+            start_date,
+            end_date,
+        )?);
+    }
 
-        // Extract time series from response
-        let series_obj = json_val[time_series_key]
-            .as_object()
-            .ok_or("Could not parse time series JSON from Alpha Vantage")?;
-
-        for (date_str, values) in series_obj {
-            if let Ok(current_date) = parse_date(date_str) {
-                // Filter by date range
-                if current_date < start_date || current_date > end_date {
-                    continue;
-                }
-
-                let close_val = values["4. close"]
-                    .as_str()
-                    .ok_or("Missing close value in JSON")?;
-                let close_price = close_val.parse::<f64>()?;
-
-                all_records.push(Record {
-                    date: date_str.clone().to_string(),
-                    asset: ticker.to_string(),
-                    price: close_price,
-                });
+    Ok(all_records)
+}
+
+/// Alpha Vantage function, time series key and close field for a timeframe.
+/// The adjusted endpoints account for dividends and splits.
+fn endpoint(
+    timeframe: &str,
+    use_adjusted: bool,
+) -> Result<(&'static str, &'static str, &'static str), QuarsError> {
+    let endpoint = match (timeframe, use_adjusted) {
+        ("daily", false) => ("TIME_SERIES_DAILY", "Time Series (Daily)", "4. close"),
+        ("weekly", false) => ("TIME_SERIES_WEEKLY", "Weekly Time Series", "4. close"),
+        ("monthly", false) => ("TIME_SERIES_MONTHLY", "Monthly Time Series", "4. close"),
+        ("daily", true) => (
+            "TIME_SERIES_DAILY_ADJUSTED",
+            "Time Series (Daily)",
+            "5. adjusted close",
+        ),
+        ("weekly", true) => (
+            "TIME_SERIES_WEEKLY_ADJUSTED",
+            "Weekly Adjusted Time Series",
+            "5. adjusted close",
+        ),
+        ("monthly", true) => (
+            "TIME_SERIES_MONTHLY_ADJUSTED",
+            "Monthly Adjusted Time Series",
+            "5. adjusted close",
+        ),
+        _ => {
+            return Err(QuarsError::Config(format!(
+                "Unsupported timeframe: {}",
+                timeframe
+            )))
+        }
+    };
+    Ok(endpoint)
+}
+
+/// Extracts the close prices within [start_date, end_date] from an Alpha Vantage response
+fn parse_time_series(
+    json_val: &Value,
+    ticker: &str,
+    time_series_key: &str,
+    close_field: &str,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+) -> Result<HistoricalData, QuarsError> {
+    let series_obj = json_val[time_series_key].as_object().ok_or_else(|| {
+        QuarsError::Network("Could not parse time series JSON from Alpha Vantage".to_string())
+    })?;
+
+    let mut records = Vec::new();
+    for (date_str, values) in series_obj {
+        if let Ok(current_date) = parse_date(date_str) {
+            // Filter by date range
+            if current_date < start_date || current_date > end_date {
+                continue;
             }
+
+            let close_price = json_to_f64(&values[close_field]).ok_or_else(|| {
+                QuarsError::Parse(format!(
+                    "Missing or non-numeric close value for {} on {}",
+                    ticker, date_str
+                ))
+            })?;
+
+            records.push(Record {
+                date: date_str.clone().to_string(),
+                asset: ticker.to_string(),
+                price: close_price,
+            });
         }
     }
-
-    Ok(all_records)
+    Ok(records)
 }
 
 /// Saves the raw result in
 /// data/raw/{ticker}/{timeframe}/{datetimenow}/raw.json
-fn save_api_result(json_val: &Value, ticker: &str, timeframe: &str) -> Result<(), Box<dyn Error>> {
+fn save_api_result(json_val: &Value, ticker: &str, timeframe: &str) -> Result<(), QuarsError> {
     let today = Local::now().format("%Y-%m-%d").to_string();
     let dir_path = format!("data/raw/{}/{}/{}", ticker, timeframe, today);
     fs::create_dir_all(&dir_path)?;
     let file_path = format!("{}/raw_alphavantage.json", dir_path);
-    fs::write(&file_path, json_val.to_string())?;
+    utils::write_atomic(&file_path, json_val.to_string().as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn string_and_numeric_closes_parse_to_the_same_prices() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        let prices = |payload: Value| -> Vec<(String, f64)> {
+            let mut prices: Vec<(String, f64)> = parse_time_series(
+                &payload,
+                "IBM",
+                "Time Series (Daily)",
+                "4. close",
+                start,
+                end,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.date, record.price))
+            .collect();
+            prices.sort_by(|a, b| a.0.cmp(&b.0));
+            prices
+        };
+        let stringified = prices(json!({"Time Series (Daily)": {
+            "2024-01-03": {"4. close": "161.5000"},
+            "2024-01-02": {"4. close": " 158.6"},
+        }}));
+        let numeric = prices(json!({"Time Series (Daily)": {
+            "2024-01-03": {"4. close": 161.5},
+            "2024-01-02": {"4. close": 158.6},
+        }}));
+        assert_eq!(
+            stringified,
+            [
+                ("2024-01-02".to_string(), 158.6),
+                ("2024-01-03".to_string(), 161.5)
+            ]
+        );
+        assert_eq!(numeric, stringified);
+
+        let missing = json!({"Time Series (Daily)": {"2024-01-02": {"4. close": null}}});
+        assert!(matches!(
+            parse_time_series(
+                &missing,
+                "IBM",
+                "Time Series (Daily)",
+                "4. close",
+                start,
+                end
+            ),
+            Err(QuarsError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn adjusted_payloads_parse_to_the_adjusted_closes_of_every_timeframe() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 3, 31).unwrap();
+        // Trimmed captures of the adjusted endpoints, a dividend paid in February
+        let bar = |close: &str, adjusted: &str, dividend: &str| {
+            json!({
+                "1. open": "180.0000",
+                "2. high": "186.0000",
+                "3. low": "178.0000",
+                "4. close": close,
+                "5. adjusted close": adjusted,
+                "6. volume": "4123456",
+                "7. dividend amount": dividend,
+            })
+        };
+        for (timeframe, dates) in [
+            ("daily", ["2024-02-08", "2024-02-09"]),
+            ("weekly", ["2024-02-02", "2024-02-09"]),
+            ("monthly", ["2024-01-31", "2024-02-29"]),
+        ] {
+            let (function, key, close_field) = endpoint(timeframe, true).unwrap();
+            assert!(function.ends_with("_ADJUSTED"), "{}", function);
+            let payload = json!({
+                "Meta Data": {"1. Information": "Adjusted Prices and Volumes", "2. Symbol": "IBM"},
+                key: {
+                    dates[0]: bar("183.4200", "181.7700", "0.0000"),
+                    dates[1]: bar("185.9000", "185.9000", "1.6600"),
+                },
+            });
+            let mut prices: Vec<(String, f64)> =
+                parse_time_series(&payload, "IBM", key, close_field, start, end)
+                    .unwrap()
+                    .into_iter()
+                    .map(|record| (record.date, record.price))
+                    .collect();
+            prices.sort_by(|a, b| a.0.cmp(&b.0));
+            assert_eq!(
+                prices,
+                [
+                    (dates[0].to_string(), 181.77),
+                    (dates[1].to_string(), 185.9)
+                ],
+                "{}",
+                timeframe
+            );
+        }
+    }
+}
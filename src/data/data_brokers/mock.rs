@@ -0,0 +1,178 @@
+use crate::config::Settings;
+use crate::data::{HistoricalData, Record};
+use crate::error::QuarsError;
+use crate::utils::{parse_date, periods_per_year};
+use chrono::{Datelike, Duration, Months, NaiveDate, Weekday};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rand_distr::StandardNormal;
+
+/// Generate synthetic correlated prices following a geometric Brownian motion.
+/// All tickers share the same drift and volatility, and are pairwise correlated through
+/// a single common factor: z_i = √ρ·f + √(1-ρ)·ε_i
+/// Draws from the run RNG unless the mock settings pin their own seed.
+pub fn fetch_data(
+    settings: &Settings,
+    tickers: &[String],
+    rng: &mut StdRng,
+) -> Result<HistoricalData, QuarsError> {
+    let mock = &settings.mock;
+    let timeframe = settings.data_api.timeframe.to_lowercase();
+    let start_date = parse_date(&settings.data_api.start_date)?;
+    let end_date = parse_date(&settings.data_api.end_date)?;
+
+    if !(0.0..1.0).contains(&mock.correlation) {
+        return Err(QuarsError::Config(format!(
+            "Mock correlation must be in [0, 1), got {}",
+            mock.correlation
+        )));
+    }
+    if mock.volatility < 0.0 || mock.initial_price <= 0.0 {
+        return Err(QuarsError::Config(
+            "Mock volatility must be non-negative and initial price positive.".to_string(),
+        ));
+    }
+
+    let dates = generate_dates(start_date, end_date, &timeframe)?;
+    let dt = 1.0 / periods_per_year(&timeframe)?;
+    let drift_term = (mock.drift - 0.5 * mock.volatility.powi(2)) * dt;
+    let diffusion = mock.volatility * dt.sqrt();
+
+    let mut pinned_rng;
+    let rng = match mock.seed {
+        Some(seed) => {
+            pinned_rng = StdRng::seed_from_u64(seed);
+            &mut pinned_rng
+        }
+        None => rng,
+    };
+
+    let mut prices = vec![mock.initial_price; tickers.len()];
+    let mut all_records = Vec::with_capacity(dates.len() * tickers.len());
+    for (t, date) in dates.iter().enumerate() {
+        if t > 0 {
+            let common: f64 = rng.sample(StandardNormal);
+            for price in prices.iter_mut() {
+                let idio: f64 = rng.sample(StandardNormal);
+                let z = mock.correlation.sqrt() * common + (1.0 - mock.correlation).sqrt() * idio;
+                *price *= (drift_term + diffusion * z).exp();
+            }
+        }
+        for (ticker, price) in tickers.iter().zip(prices.iter()) {
+            all_records.push(Record {
+                date: date.format("%Y-%m-%d").to_string(),
+                asset: ticker.to_string(),
+                price: *price,
+            });
+        }
+    }
+
+    Ok(all_records)
+}
+
+/// Observation dates between start and end: weekdays for daily data, otherwise
+/// fixed weekly or monthly steps from the start date
+fn generate_dates(
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    timeframe: &str,
+) -> Result<Vec<NaiveDate>, QuarsError> {
+    let mut dates = Vec::new();
+    let mut current = start_date;
+    while current <= end_date {
+        match timeframe {
+            "daily" => {
+                if !matches!(current.weekday(), Weekday::Sat | Weekday::Sun) {
+                    dates.push(current);
+                }
+                current += Duration::days(1);
+            }
+            "weekly" => {
+                dates.push(current);
+                current += Duration::weeks(1);
+            }
+            "monthly" => {
+                dates.push(current);
+                current = current.checked_add_months(Months::new(1)).ok_or_else(|| {
+                    QuarsError::Config("Date overflow while generating mock dates".to_string())
+                })?;
+            }
+            _ => {
+                return Err(QuarsError::Config(format!(
+                    "Unsupported timeframe: {}",
+                    timeframe
+                )))
+            }
+        }
+    }
+    Ok(dates)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(seed: u64) -> Settings {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                &format!(
+                    "[general]\ndata_source = \"api\"\ndata_file = \"unused.csv\"\n\
+                     [data_api]\nsource = \"mock\"\ntickers = [\"AAA\", \"BBB\"]\n\
+                     start_date = \"2024-01-04\"\nend_date = \"2024-01-09\"\ntimeframe = \"daily\"\n\
+                     [portofolio_optimization]\nmethod = \"mvo\"\nsub_method = \"risk-adjusted\"\n\
+                     risk_free_rate = 0.0\nparams = [0.5]\n\
+                     [mock]\nseed = {}\n",
+                    seed
+                ),
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .and_then(config::Config::try_deserialize)
+            .unwrap()
+    }
+
+    #[test]
+    fn a_seeded_mock_source_matches_its_snapshot() {
+        let tickers = ["AAA".to_string(), "BBB".to_string()];
+        // The pinned seed makes the prices independent of the run RNG
+        let prices = |seed: u64, run_seed: u64| -> Vec<(String, String, f64)> {
+            fetch_data(
+                &settings(seed),
+                &tickers,
+                &mut StdRng::seed_from_u64(run_seed),
+            )
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.date, record.asset, record.price))
+            .collect()
+        };
+        let data = prices(7, 1);
+        let snapshot = [
+            ("2024-01-04", "AAA", 100.0),
+            ("2024-01-04", "BBB", 100.0),
+            ("2024-01-05", "AAA", 98.70027971376092),
+            ("2024-01-05", "BBB", 98.56043930363651),
+            ("2024-01-08", "AAA", 98.24678848739832),
+            ("2024-01-08", "BBB", 99.43583585779226),
+            ("2024-01-09", "AAA", 97.11367529077759),
+            ("2024-01-09", "BBB", 98.04046027409129),
+        ];
+        assert_eq!(data.len(), snapshot.len());
+        for ((date, asset, price), (expected_date, expected_asset, expected_price)) in
+            data.iter().zip(snapshot)
+        {
+            assert_eq!(
+                (date.as_str(), asset.as_str()),
+                (expected_date, expected_asset)
+            );
+            assert!(
+                (price - expected_price).abs() < 1e-9,
+                "{} vs {}",
+                price,
+                expected_price
+            );
+        }
+        assert_eq!(prices(7, 2), data);
+        assert_ne!(prices(8, 1), data);
+    }
+}
@@ -1,13 +1,190 @@
 pub mod alphavantage;
+pub mod mock;
+pub mod stream;
 pub mod twelve;
 use crate::config::Settings;
+use crate::error::QuarsError;
+use crate::utils;
+use chrono::NaiveDate;
+use rand::rngs::StdRng;
+use reqwest::Response;
+use serde_json::Value;
 
-use super::HistoricalData;
+use super::{merge_sources, HistoricalData};
 
-pub async fn fetch_data(settings: &Settings) -> Result<HistoricalData, Box<dyn std::error::Error>> {
-    match settings.data_api.source.to_lowercase().as_str() {
-        "alphavantage" => alphavantage::fetch_data(settings).await,
-        "twelve" => twelve::fetch_data(settings).await,
-        _ => Err("Unsupported data broker specified. Please open an issue, specifying your data broker and useful links.".into()),
+/// Fetches every ticker from its broker, `ticker_sources` overriding the default `source`,
+/// and merges the results into one data set. Only the prices from `start_date` on are
+/// fetched, which is later than the configured start when appending to a store.
+pub async fn fetch_data(
+    settings: &Settings,
+    rng: &mut StdRng,
+    start_date: NaiveDate,
+) -> Result<HistoricalData, QuarsError> {
+    // Tickers grouped by broker, brokers in order of their first ticker
+    let mut groups: Vec<(String, Vec<String>)> = Vec::new();
+    for ticker in &settings.data_api.tickers {
+        let source = settings
+            .data_api
+            .ticker_sources
+            .iter()
+            .find(|(mapped, _)| mapped.eq_ignore_ascii_case(ticker))
+            .map_or(&settings.data_api.source, |(_, source)| source)
+            .to_lowercase();
+        match groups.iter_mut().find(|(group, _)| *group == source) {
+            Some((_, tickers)) => tickers.push(ticker.clone()),
+            None => groups.push((source, vec![ticker.clone()])),
+        }
+    }
+
+    let mut sources = Vec::with_capacity(groups.len());
+    for (source, tickers) in &groups {
+        let data = match source.as_str() {
+            "alphavantage" => alphavantage::fetch_data(settings, tickers, start_date).await?,
+            "twelve" => twelve::fetch_data(settings, tickers, start_date).await?,
+            // Generated from the configured start, so that appended prices continue the path
+            "mock" => mock::fetch_data(settings, tickers, rng)?
+                .into_iter()
+                .filter(|record| {
+                    utils::parse_date(record.date.get(..10).unwrap_or(&record.date))
+                        .is_ok_and(|date| date >= start_date)
+                })
+                .collect(),
+            _ => return Err(QuarsError::Config(format!("Unsupported data broker '{}' specified. Please open an issue, specifying your data broker and useful links.", source))),
+        };
+        sources.push(data);
+    }
+    Ok(merge_sources(sources))
+}
+
+// Characters of an error response body quoted in the error
+const ERROR_BODY_SNIPPET: usize = 200;
+
+/// JSON body of a broker response. A non-success status is reported with its code and the
+/// start of the body, which is often an HTML error page that would not parse as JSON.
+pub async fn response_json(
+    resp: Response,
+    broker: &str,
+    ticker: &str,
+) -> Result<Value, QuarsError> {
+    let status = resp.status();
+    if !status.is_success() {
+        let body = resp.text().await.unwrap_or_default();
+        let snippet: String = body
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .join(" ")
+            .chars()
+            .take(ERROR_BODY_SNIPPET)
+            .collect();
+        return Err(QuarsError::Network(format!(
+            "HTTP {} from {} for {}: {}",
+            status, broker, ticker, snippet
+        )));
+    }
+    Ok(resp.json().await?)
+}
+
+/// Reads a numeric JSON field that brokers return either as a number or as a string
+pub fn json_to_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.trim().parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::calculate_portfolio_stats;
+    use rand::SeedableRng;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    fn mock_settings() -> Settings {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                "[general]\ndata_source = \"api\"\ndata_file = \"unused.csv\"\n\
+                 [data_api]\nsource = \"mock\"\ntickers = [\"AAA\", \"BTC\"]\n\
+                 start_date = \"2024-01-01\"\nend_date = \"2024-02-29\"\ntimeframe = \"daily\"\n\
+                 [portofolio_optimization]\nmethod = \"mvo\"\nsub_method = \"risk-adjusted\"\n\
+                 risk_free_rate = 0.0\nparams = [0.5]",
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .and_then(config::Config::try_deserialize)
+            .unwrap()
+    }
+
+    #[test]
+    fn two_mock_sources_merge_into_one_universe_on_their_common_dates() {
+        let settings = mock_settings();
+        let equities = mock::fetch_data(
+            &settings,
+            &["AAA".to_string()],
+            &mut StdRng::seed_from_u64(1),
+        )
+        .unwrap();
+        // The second source is closed on a day the first trades
+        let closed = "2024-01-15";
+        let crypto: HistoricalData = mock::fetch_data(
+            &settings,
+            &["BTC".to_string()],
+            &mut StdRng::seed_from_u64(2),
+        )
+        .unwrap()
+        .into_iter()
+        .filter(|record| record.date != closed)
+        .collect();
+        let n_dates = equities.len();
+        let btc_prices: Vec<f64> = crypto.iter().map(|record| record.price).collect();
+
+        let merged = merge_sources(vec![equities, crypto]);
+        assert_eq!(merged.len(), 2 * (n_dates - 1));
+        assert!(merged.iter().all(|record| record.date != closed));
+        let stats = calculate_portfolio_stats(&merged, "daily", false).unwrap();
+        assert_eq!(stats.assets, ["AAA", "BTC"]);
+        assert_eq!(stats.dates.len(), n_dates - 2);
+        let btc_returns: Vec<f64> = btc_prices.windows(2).map(|p| p[1] / p[0] - 1.0).collect();
+        for (actual, expected) in stats.returns_matrix.row(1).iter().zip(&btc_returns) {
+            assert!((actual - expected).abs() < 1e-12);
+        }
+    }
+
+    #[tokio::test]
+    async fn an_error_status_is_reported_with_its_code_and_body() {
+        // Broker stand-in answering one request with an HTML error page
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            let body = "<html>\n  <body>Forbidden: invalid API key</body>\n</html>";
+            write!(
+                stream,
+                "HTTP/1.1 403 Forbidden\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+        let client = reqwest::Client::builder().no_proxy().build().unwrap();
+        let resp = client
+            .get(format!("http://{}/time_series", addr))
+            .send()
+            .await
+            .unwrap();
+        let err = response_json(resp, "Twelve Data", "AAPL")
+            .await
+            .unwrap_err();
+        server.join().unwrap();
+        assert!(
+            matches!(&err, QuarsError::Network(msg)
+                if msg == "HTTP 403 Forbidden from Twelve Data for AAPL: \
+                           <html> <body>Forbidden: invalid API key</body> </html>"),
+            "{}",
+            err
+        );
     }
 }
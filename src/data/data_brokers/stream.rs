@@ -0,0 +1,154 @@
+use crate::error::QuarsError;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration};
+use tokio_tungstenite::connect_async;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::debug;
+
+const TWELVE_STREAM_URL: &str = "wss://ws.twelvedata.com/v1/quotes/price";
+const REDACTED: &str = "REDACTED";
+// Twelve Data closes connections without a heartbeat for more than 10 seconds
+const HEARTBEAT_SECS: u64 = 10;
+
+/// Price update of a streamed ticker
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tick {
+    pub asset: String,
+    pub price: f64,
+    pub timestamp: i64, // Unix seconds
+}
+
+/// Twelve Data price stream of the API key
+pub fn twelve_url(api_key: &str) -> String {
+    format!("{}?apikey={}", TWELVE_STREAM_URL, api_key)
+}
+
+/// URL with the values of its query parameters redacted, e.g. the API key of `twelve_url`,
+/// for messages and saved configs
+pub fn redact_url(url: &str) -> String {
+    match url.split_once('?') {
+        Some((base, query)) => {
+            let params: Vec<String> = query
+                .split('&')
+                .map(|param| match param.split_once('=') {
+                    Some((name, _)) => format!("{}={}", name, REDACTED),
+                    None => param.to_string(),
+                })
+                .collect();
+            format!("{}?{}", base, params.join("&"))
+        }
+        None => url.to_string(),
+    }
+}
+
+// Message of an error about `url`, with the values of its query parameters redacted wherever
+// the message quotes them
+fn redact_message(message: String, url: &str) -> String {
+    let values = url
+        .split_once('?')
+        .map_or("", |(_, query)| query)
+        .split('&')
+        .filter_map(|param| param.split_once('=').map(|(_, value)| value))
+        .filter(|value| !value.is_empty());
+    values.fold(message, |message, value| message.replace(value, REDACTED))
+}
+
+/// Connects to a price websocket speaking the Twelve Data protocol, subscribes to the tickers
+/// and sends every price event through `sender`, until the server closes the connection or
+/// the receiver is dropped. Other events (subscription status, heartbeat replies) are skipped.
+pub async fn stream_prices(
+    url: &str,
+    tickers: &[String],
+    sender: mpsc::Sender<Tick>,
+) -> Result<(), QuarsError> {
+    let (mut socket, _) = connect_async(url).await.map_err(|e| {
+        QuarsError::Network(format!(
+            "Failed to connect to the price stream {}: {}",
+            redact_url(url),
+            redact_message(e.to_string(), url)
+        ))
+    })?;
+    let subscribe = json!({
+        "action": "subscribe",
+        "params": { "symbols": tickers.join(",") }
+    });
+    socket.send(Message::Text(subscribe.to_string())).await?;
+
+    let mut heartbeat = interval(Duration::from_secs(HEARTBEAT_SECS));
+    loop {
+        tokio::select! {
+            _ = heartbeat.tick() => {
+                let message = json!({ "action": "heartbeat" });
+                socket.send(Message::Text(message.to_string())).await?;
+            }
+            message = socket.next() => match message {
+                Some(Ok(Message::Text(text))) => match parse_tick(&text) {
+                    Some(tick) => {
+                        if sender.send(tick).await.is_err() {
+                            return Ok(());
+                        }
+                    }
+                    None => debug!(message = %text, "Skipped stream event"),
+                },
+                Some(Ok(Message::Close(_))) | None => return Ok(()),
+                // Pings are answered by the socket itself
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+/// Tick of a price event, {"event": "price", "symbol": ..., "price": ..., "timestamp": ...},
+/// None for any other message
+pub fn parse_tick(message: &str) -> Option<Tick> {
+    let value: Value = serde_json::from_str(message).ok()?;
+    if value["event"] != "price" {
+        return None;
+    }
+    Some(Tick {
+        asset: value["symbol"].as_str()?.to_string(),
+        price: value["price"].as_f64()?,
+        timestamp: value["timestamp"].as_i64()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn the_query_values_of_a_url_are_redacted() {
+        assert_eq!(
+            redact_url(&twelve_url("secret")),
+            "wss://ws.twelvedata.com/v1/quotes/price?apikey=REDACTED"
+        );
+        assert_eq!(
+            redact_url("wss://example.com/prices?token=abc&format=json&debug"),
+            "wss://example.com/prices?token=REDACTED&format=REDACTED&debug"
+        );
+        assert_eq!(redact_url("ws://localhost:8080"), "ws://localhost:8080");
+    }
+
+    #[tokio::test]
+    async fn a_failed_connection_does_not_report_the_api_key() {
+        // A port nothing listens on once the listener is dropped
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let url = format!("ws://127.0.0.1:{}/quotes?apikey=secret", port);
+        let (sender, _receiver) = mpsc::channel(1);
+        let err = stream_prices(&url, &["AAPL".to_string()], sender)
+            .await
+            .unwrap_err();
+        let message = err.to_string();
+        assert!(matches!(err, QuarsError::Network(_)), "{}", message);
+        assert!(message.contains("apikey=REDACTED"), "{}", message);
+        assert!(!message.contains("secret"), "{}", message);
+    }
+}
@@ -1,30 +1,41 @@
+use super::{json_to_f64, response_json};
 use crate::config::Settings;
 use crate::data::{HistoricalData, Record};
+use crate::error::QuarsError;
 use crate::utils;
-use chrono::{Local, NaiveDate};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use reqwest::Client;
 use serde_json::Value;
-use std::error::Error;
 use std::fs;
 
-/// Fetch data from Twelve Data API
-pub async fn fetch_data(settings: &Settings) -> Result<HistoricalData, Box<dyn Error>> {
+/// Fetch data from Twelve Data API, from `start_date` on, which may be later than the
+/// configured one when appending to a store
+pub async fn fetch_data(
+    settings: &Settings,
+    tickers: &[String],
+    start_date: NaiveDate,
+) -> Result<HistoricalData, QuarsError> {
     // Read parameters from the [data_api] section.
     let api_key = &settings.data_api.api_key;
-    let tickers = &settings.data_api.tickers;
     let timeframe = settings.data_api.timeframe.to_lowercase(); // Expected "daily", "1min", etc.
-    let start_date_str = &settings.data_api.start_date;
-    let end_date_str = &settings.data_api.end_date;
-
-    let start_date = utils::parse_date(start_date_str)?;
-    let end_date = utils::parse_date(end_date_str)?;
+    let end_date = utils::parse_date(&settings.data_api.end_date)?;
+    let display_tz = match &settings.data_api.timezone {
+        Some(name) => parse_timezone(name)?,
+        None => Tz::UTC,
+    };
 
     // TODO: Expand to intraday
     let tf_twelve = match timeframe.as_str() {
         "daily" => "1day",
         "weekly" => "1week",
         "monthly" => "1month",
-        _ => return Err(format!("Unsupported timeframe: {}", timeframe).into()),
+        _ => {
+            return Err(QuarsError::Config(format!(
+                "Unsupported timeframe: {}",
+                timeframe
+            )))
+        }
     };
 
     let base_url = "https://api.twelvedata.com/time_series";
@@ -33,62 +44,261 @@ pub async fn fetch_data(settings: &Settings) -> Result<HistoricalData, Box<dyn E
     let mut all_records = Vec::new();
 
     for ticker in tickers {
+        utils::check_cancelled()?;
         let url = format!(
-            "{}?symbol={}&interval={}&outputsize=5000&apikey={}",
-            base_url, ticker, tf_twelve, api_key
+            "{}?symbol={}&interval={}&start_date={}&outputsize=5000&apikey={}",
+            base_url, ticker, tf_twelve, start_date, api_key
         );
 
         let resp = client.get(&url).send().await?;
-        let json_val: Value = resp.json().await?;
+        let json_val = response_json(resp, "Twelve Data", ticker).await?;
 
         save_api_result(&json_val, ticker, &timeframe)?;
 
         if let Some(status) = json_val.get("status") {
             if status == "error" {
-                return Err(
-                    format!("Error from Twelve Data API for {}: {:?}", ticker, json_val).into(),
-                );
+                return Err(QuarsError::Network(format!(
+                    "Error from Twelve Data API for {}: {:?}",
+                    ticker, json_val
+                )));
             }
         }
 
         let values = json_val
             .get("values")
             .and_then(|v| v.as_array())
-            .ok_or("Could not parse 'values' array from Twelve response")?;
-
-        for entry in values {
-            if let Some(date_str) = entry.get("datetime").and_then(|v| v.as_str()) {
-                // Twelve returns datetime such as "2020-02-26 15:59:00"
-                // Extract the date part
-                let date_part = &date_str[..10];
-                if let Ok(current_date) = NaiveDate::parse_from_str(date_part, "%Y-%m-%d") {
-                    if current_date < start_date || current_date > end_date {
-                        continue;
-                    }
-
-                    if let Some(close_str) = entry.get("close").and_then(|v| v.as_str()) {
-                        let close_price = close_str.parse::<f64>()?;
-                        all_records.push(Record {
-                            date: date_str.to_string(),
-                            asset: ticker.to_string(),
-                            price: close_price,
-                        });
-                    }
-                }
+            .ok_or_else(|| {
+                QuarsError::Network(
+                    "Could not parse 'values' array from Twelve response".to_string(),
+                )
+            })?;
+        // Intraday datetimes without an offset are in the exchange's local time
+        let exchange_tz = match json_val
+            .get("meta")
+            .and_then(|meta| meta.get("exchange_timezone"))
+            .and_then(|v| v.as_str())
+        {
+            Some(name) => parse_timezone(name)?,
+            None => Tz::UTC,
+        };
+
+        all_records.extend(parse_values(
+            values,
+            ticker,
+            start_date,
+            end_date,
+            exchange_tz,
+            display_tz,
+        )?);
+    }
+
+    Ok(all_records)
+}
+
+/// Timestamp of a Twelve bar, daily and longer bars carry a bare date
+enum BarTime {
+    Date(NaiveDate),
+    Instant(DateTime<Utc>),
+}
+
+fn parse_timezone(name: &str) -> Result<Tz, QuarsError> {
+    name.parse::<Tz>().map_err(|_| {
+        QuarsError::Config(format!(
+            "Unknown timezone '{}', expected an IANA name such as 'America/New_York'",
+            name
+        ))
+    })
+}
+
+/// Parses a Twelve datetime. An offset in the string (ISO-8601) is honored, otherwise the
+/// datetime is taken as wall-clock time in the exchange timezone, which resolves DST.
+fn parse_datetime(date_str: &str, exchange_tz: Tz) -> Result<BarTime, QuarsError> {
+    if let Ok(date) = NaiveDate::parse_from_str(date_str, "%Y-%m-%d") {
+        return Ok(BarTime::Date(date));
+    }
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(date_str)
+        .or_else(|_| DateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S%z"))
+    {
+        return Ok(BarTime::Instant(datetime.with_timezone(&Utc)));
+    }
+    let naive = NaiveDateTime::parse_from_str(date_str, "%Y-%m-%d %H:%M:%S").map_err(|e| {
+        QuarsError::Parse(format!(
+            "Could not parse Twelve datetime '{}': {}",
+            date_str, e
+        ))
+    })?;
+    // The repeated hour at the end of DST is ambiguous, the first occurrence is taken
+    let local = exchange_tz
+        .from_local_datetime(&naive)
+        .earliest()
+        .ok_or_else(|| {
+            QuarsError::Parse(format!(
+                "Datetime '{}' does not exist in timezone {} (DST gap)",
+                date_str, exchange_tz
+            ))
+        })?;
+    Ok(BarTime::Instant(local.with_timezone(&Utc)))
+}
+
+/// Parses the "values" array of a Twelve time series into records within [start, end].
+/// Intraday timestamps are normalized to the display timezone and filtered on their
+/// date there; the records are returned in chronological order.
+pub fn parse_values(
+    values: &[Value],
+    ticker: &str,
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+    exchange_tz: Tz,
+    display_tz: Tz,
+) -> Result<HistoricalData, QuarsError> {
+    let mut bars = Vec::with_capacity(values.len());
+    for entry in values {
+        let (Some(date_str), Some(close)) = (
+            entry.get("datetime").and_then(|v| v.as_str()),
+            entry.get("close"),
+        ) else {
+            continue;
+        };
+        let (date, timestamp, label) = match parse_datetime(date_str, exchange_tz)? {
+            BarTime::Date(date) => (
+                date,
+                date.and_hms_opt(0, 0, 0).unwrap().and_utc(),
+                date_str.to_string(),
+            ),
+            BarTime::Instant(instant) => {
+                let local = instant.with_timezone(&display_tz);
+                (
+                    local.date_naive(),
+                    instant,
+                    local.format("%Y-%m-%d %H:%M:%S").to_string(),
+                )
             }
+        };
+        if date < start_date || date > end_date {
+            continue;
         }
+        let close_price = json_to_f64(close).ok_or_else(|| {
+            QuarsError::Parse(format!(
+                "Non-numeric close value for {} on {}",
+                ticker, date_str
+            ))
+        })?;
+        bars.push((timestamp, label, close_price));
     }
+    // Twelve returns the most recent bar first
+    bars.sort_by_key(|(timestamp, _, _)| *timestamp);
 
-    Ok(all_records)
+    Ok(bars
+        .into_iter()
+        .map(|(_, date, price)| Record {
+            date,
+            asset: ticker.to_string(),
+            price,
+        })
+        .collect())
 }
 
 /// Save the raw JSON API result in
 /// data/raw/{ticker}/{timeframe}/{datetimenow}/raw.json
-fn save_api_result(json_val: &Value, ticker: &str, timeframe: &str) -> Result<(), Box<dyn Error>> {
+fn save_api_result(json_val: &Value, ticker: &str, timeframe: &str) -> Result<(), QuarsError> {
     let today = Local::now().format("%Y-%m-%d").to_string();
     let dir_path = format!("data/raw/{}/{}/{}", ticker, timeframe, today);
     fs::create_dir_all(&dir_path)?;
     let file_path = format!("{}/raw.json", dir_path);
-    fs::write(&file_path, json_val.to_string())?;
+    utils::write_atomic(&file_path, json_val.to_string().as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn string_and_numeric_closes_parse_to_the_same_prices() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 1, 31).unwrap();
+        let prices = |values: Value| -> Vec<(String, f64)> {
+            parse_values(
+                values.as_array().unwrap(),
+                "AAPL",
+                start,
+                end,
+                Tz::UTC,
+                Tz::UTC,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|record| (record.date, record.price))
+            .collect()
+        };
+        let stringified = prices(json!([
+            {"datetime": "2024-01-03", "close": "184.25000"},
+            {"datetime": "2024-01-02", "close": "185.64"},
+        ]));
+        let numeric = prices(json!([
+            {"datetime": "2024-01-03", "close": 184.25},
+            {"datetime": "2024-01-02", "close": 185.64},
+        ]));
+        assert_eq!(
+            stringified,
+            [
+                ("2024-01-02".to_string(), 185.64),
+                ("2024-01-03".to_string(), 184.25)
+            ]
+        );
+        assert_eq!(numeric, stringified);
+    }
+
+    #[test]
+    fn datetimes_with_offsets_are_normalized_to_utc_and_filtered() {
+        let start = NaiveDate::from_ymd_opt(2024, 3, 2).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 3, 10).unwrap();
+        let new_york: Tz = "America/New_York".parse().unwrap();
+        // Most recent first, as Twelve returns them
+        let values = json!([
+            {"datetime": "2024-03-11 00:30:00+0000", "close": "5"},
+            {"datetime": "2024-03-10 03:30:00", "close": "4"},
+            {"datetime": "2024-03-10 01:30:00", "close": "3"},
+            {"datetime": "2024-03-01T23:30:00-05:00", "close": "2"},
+            {"datetime": "2024-03-01T10:00:00+09:00", "close": "1"},
+        ]);
+        let records = parse_values(
+            values.as_array().unwrap(),
+            "AAPL",
+            start,
+            end,
+            new_york,
+            Tz::UTC,
+        )
+        .unwrap();
+        let prices: Vec<(&str, f64)> = records
+            .iter()
+            .map(|record| (record.date.as_str(), record.price))
+            .collect();
+        // Local times without an offset are New York's, EST before the switch to EDT and
+        // EDT after; the first and last bars fall outside the range once in UTC
+        assert_eq!(
+            prices,
+            [
+                ("2024-03-02 04:30:00", 2.0),
+                ("2024-03-10 06:30:00", 3.0),
+                ("2024-03-10 07:30:00", 4.0),
+            ]
+        );
+
+        // Wall-clock times skipped by the switch to DST do not exist
+        let gap = json!([{"datetime": "2024-03-10 02:30:00", "close": "1"}]);
+        assert!(matches!(
+            parse_values(
+                gap.as_array().unwrap(),
+                "AAPL",
+                start,
+                end,
+                new_york,
+                Tz::UTC
+            ),
+            Err(QuarsError::Parse(_))
+        ));
+    }
+}
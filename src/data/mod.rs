@@ -1,8 +1,20 @@
+pub mod calendar;
 pub mod data_brokers;
 
-use crate::config::Settings;
+use crate::config::{Settings, TickerNormalization};
+use crate::data::calendar::TradingCalendar;
+use crate::error::QuarsError;
+use crate::utils;
+use chrono::{NaiveDate, NaiveDateTime};
+use flate2::read::GzDecoder;
+use ndarray::Array2;
+use rand::rngs::StdRng;
 use serde::Deserialize;
-use std::error::Error;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufReader, Cursor, Read};
+use std::path::Path;
+use tracing::warn;
 
 #[derive(Debug, Deserialize)]
 pub struct Record {
@@ -13,35 +25,1829 @@ pub struct Record {
 
 pub type HistoricalData = Vec<Record>;
 
-/// Reads CSV into HistoricalData
-fn read_csv(path: &str) -> Result<HistoricalData, Box<dyn Error>> {
-    let mut rdr = csv::Reader::from_path(path)?;
-    let headers = rdr.headers()?.clone();
+// Candidate date layouts, tried in order. Ambiguous day/month orders are told apart by the
+// whole column, see `detect_date_format`.
+const DATE_FORMATS: [&str; 9] = [
+    "%Y-%m-%d",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y/%m/%d",
+    "%m/%d/%Y",
+    "%d/%m/%Y",
+    "%d-%m-%Y",
+    "%m-%d-%Y",
+    "%d.%m.%Y",
+    "%Y%m%d",
+];
+
+// Number of leading date values used to detect the format
+const DATE_SAMPLE_SIZE: usize = 20;
+// Missing days listed per reported gap, the longer gaps only counted
+const MAX_LISTED_GAP_DAYS: usize = 5;
+
+fn parse_with_format(date_str: &str, format: &str) -> Option<NaiveDateTime> {
+    if format.contains("%H") {
+        NaiveDateTime::parse_from_str(date_str, format).ok()
+    } else {
+        NaiveDate::parse_from_str(date_str, format)
+            .ok()
+            .and_then(|d| d.and_hms_opt(0, 0, 0))
+    }
+}
+
+/// Detects the date format of a column by sampling its first values and picking the
+/// first known format that parses all of them. Layouts swapping the day and the month both
+/// parse days up to the 12th, the whole column then tells them apart, and a column that
+/// reads either way is an error asking for an explicit `date_format`.
+pub fn detect_date_format(dates: &[&str]) -> Result<&'static str, QuarsError> {
+    let sample: Vec<&str> = dates
+        .iter()
+        .take(DATE_SAMPLE_SIZE)
+        .map(|d| d.trim())
+        .collect();
+    if sample.is_empty() {
+        return Err(QuarsError::Parse(
+            "No date values to detect the date format from.".to_string(),
+        ));
+    }
+    let parses_all = |dates: &[&str], format: &str| {
+        dates
+            .iter()
+            .all(|d| parse_with_format(d.trim(), format).is_some())
+    };
+    let candidates: Vec<&'static str> = DATE_FORMATS
+        .iter()
+        .filter(|format| parses_all(&sample, format))
+        .copied()
+        .collect();
+    let Some(&first) = candidates.first() else {
+        return Err(QuarsError::Parse(format!(
+            "Could not detect the date format of {:?}, supported formats are {:?}",
+            &sample[..sample.len().min(3)],
+            DATE_FORMATS
+        )));
+    };
+    // Candidates reading the sample as other dates than the first one
+    let rivals = candidates[1..].iter().filter(|format| {
+        sample
+            .iter()
+            .any(|d| parse_with_format(d, format) != parse_with_format(d, first))
+    });
+    let remaining: Vec<&'static str> = std::iter::once(&first)
+        .chain(rivals)
+        .filter(|format| parses_all(dates, format))
+        .copied()
+        .collect();
+    match remaining.as_slice() {
+        // A malformed date further down, reported with its line when it is parsed
+        [] => Ok(first),
+        [format] => Ok(format),
+        _ => Err(QuarsError::Parse(format!(
+            "The dates {:?} read as {}, set date_format to the layout of the file",
+            &sample[..sample.len().min(3)],
+            remaining.join(" or ")
+        ))),
+    }
+}
+
+/// Parses a date with the given format and returns it in the canonical
+/// "%Y-%m-%d" (or "%Y-%m-%d %H:%M:%S" for datetimes) layout.
+fn normalize_date(date_str: &str, format: &str) -> Result<String, QuarsError> {
+    let parsed = parse_with_format(date_str.trim(), format).ok_or_else(|| {
+        QuarsError::Parse(format!(
+            "Date '{}' does not match format '{}'",
+            date_str, format
+        ))
+    })?;
+    if format.contains("%H") {
+        Ok(parsed.format("%Y-%m-%d %H:%M:%S").to_string())
+    } else {
+        Ok(parsed.format("%Y-%m-%d").to_string())
+    }
+}
+
+// Cells of a price column that mark a missing observation, compared case-insensitively
+const MISSING_PRICE_MARKERS: [&str; 3] = ["nan", "n/a", "na"];
+
+/// Parses a price, normalizing the decimal separator to '.' first
+fn parse_price(price_str: &str, decimal_separator: char) -> Result<f64, QuarsError> {
+    let normalized = price_str.trim().replace(decimal_separator, ".");
+    normalized.parse::<f64>().map_err(|e| {
+        QuarsError::Parse(format!(
+            "Could not parse price '{}' with decimal separator '{}': {}",
+            price_str, decimal_separator, e
+        ))
+    })
+}
+
+/// Opens the CSV content of `path`, decompressed for a `.gz` file and taken from the
+/// `archive_entry` member of a `.zip` archive, or from its only CSV member when none is named.
+fn open_csv(path: &str, archive_entry: Option<&str>) -> Result<Box<dyn Read>, QuarsError> {
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+    let file = File::open(path)?;
+    match extension.as_deref() {
+        Some("gz") => Ok(Box::new(GzDecoder::new(file))),
+        Some("zip") => {
+            let mut archive = zip::ZipArchive::new(BufReader::new(file))?;
+            let name = match archive_entry {
+                Some(name) => name.to_string(),
+                None => {
+                    let csv_entries: Vec<&str> = archive
+                        .file_names()
+                        .filter(|name| name.to_ascii_lowercase().ends_with(".csv"))
+                        .collect();
+                    match csv_entries.as_slice() {
+                        [name] => name.to_string(),
+                        _ => {
+                            return Err(QuarsError::Config(format!(
+                                "{} holds {} CSV files, set data_file_entry to the one to read",
+                                path,
+                                csv_entries.len()
+                            )))
+                        }
+                    }
+                }
+            };
+            // The entry borrows the archive, so its content is read out whole
+            let mut content = Vec::new();
+            archive
+                .by_name(&name)
+                .map_err(|e| QuarsError::Config(format!("{} in {}: {}", name, path, e)))?
+                .read_to_end(&mut content)?;
+            Ok(Box::new(Cursor::new(content)))
+        }
+        _ => Ok(Box::new(file)),
+    }
+}
+
+/// Reads CSV into HistoricalData. The first column holds the dates, in `date_format` or the
+/// detected layout, and the others the prices of one asset each, named by the header row, by
+/// `column_names`, or `asset_0`, `asset_1`, ... for headerless files. A `.csv.gz` file or a
+/// `.zip` archive is read the same way, see `open_csv`.
+pub fn read_csv(
+    path: &str,
+    delimiter: char,
+    decimal_separator: char,
+    has_header: bool,
+    column_names: Option<&[String]>,
+    archive_entry: Option<&str>,
+    date_format: Option<&str>,
+) -> Result<HistoricalData, QuarsError> {
+    if !delimiter.is_ascii() {
+        return Err(QuarsError::Config(format!(
+            "CSV delimiter must be an ASCII character, got '{}'",
+            delimiter
+        )));
+    }
+    if delimiter == decimal_separator {
+        return Err(QuarsError::Config(
+            "CSV delimiter and decimal separator must differ.".to_string(),
+        ));
+    }
+    let mut rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter as u8)
+        .has_headers(has_header)
+        .from_reader(open_csv(path, archive_entry)?);
+    let headers = if has_header {
+        Some(rdr.headers()?.clone())
+    } else {
+        None
+    };
+    let records = rdr.records().collect::<Result<Vec<_>, _>>()?;
+    let date_format = match date_format {
+        Some(format) => format,
+        None => {
+            let raw_dates: Vec<&str> = records.iter().map(|r| r.get(0).unwrap_or("")).collect();
+            detect_date_format(&raw_dates).map_err(|e| {
+                QuarsError::Parse(format!(
+                    "The first column of {} must hold the dates: {}",
+                    path, e
+                ))
+            })?
+        }
+    };
+    if let Some(first) = headers.as_ref().and_then(|h| h.get(0)) {
+        if parse_with_format(first.trim(), date_format).is_some() {
+            warn!(
+                "the header row of {} starts with a date, set csv_has_header = false if the file has no header",
+                path
+            );
+        }
+    }
+
+    let n_columns = headers
+        .as_ref()
+        .map(|h| h.len())
+        .or_else(|| records.first().map(|r| r.len()))
+        .unwrap_or(0);
+    let asset_names: Vec<String> = match column_names {
+        Some(names) if names.len() + 1 == n_columns => names.to_vec(),
+        Some(names) => {
+            return Err(QuarsError::Config(format!(
+                "csv_column_names has {} names but {} has {} price columns",
+                names.len(),
+                path,
+                n_columns.saturating_sub(1)
+            )))
+        }
+        None => match &headers {
+            Some(headers) => headers.iter().skip(1).map(String::from).collect(),
+            None => (0..n_columns.saturating_sub(1))
+                .map(|i| format!("asset_{}", i))
+                .collect(),
+        },
+    };
+
     let mut data = Vec::new();
+    let mut n_marked_missing = 0;
+    for record in &records {
+        let date = normalize_date(record.get(0).unwrap_or(""), date_format).map_err(|e| {
+            let line = record.position().map_or(0, |pos| pos.line());
+            QuarsError::Parse(format!("Line {} of {}: {}", line, path, e))
+        })?;
+        for (asset_name, price_str) in asset_names.iter().zip(record.iter().skip(1)) {
+            let price_str = price_str.trim();
+            // Blank cells are missing observations, and so are NaN and N/A
+            if price_str.is_empty() {
+                continue;
+            }
+            if MISSING_PRICE_MARKERS
+                .iter()
+                .any(|marker| price_str.eq_ignore_ascii_case(marker))
+            {
+                n_marked_missing += 1;
+                continue;
+            }
+            data.push(Record {
+                date: date.clone(),
+                asset: asset_name.clone(),
+                price: parse_price(price_str, decimal_separator)?,
+            });
+        }
+    }
+    if n_marked_missing > 0 {
+        warn!(
+            "{} price cells of {} read NaN or N/A, skipped as missing observations",
+            n_marked_missing, path
+        );
+    }
+    Ok(data)
+}
 
+/// Reads user supplied expected returns from an `asset,expected_return` CSV
+pub fn read_expected_returns(path: &str) -> Result<HashMap<String, f64>, QuarsError> {
+    let mut rdr = csv::Reader::from_path(path)?;
+    let mut expected = HashMap::new();
     for result in rdr.records() {
         let record = result?;
-        let date = record.get(0).unwrap().to_string();
-        for (i, asset_name) in headers.iter().enumerate().skip(1) {
-            if let Some(price_str) = record.get(i) {
-                if let Ok(price) = price_str.parse::<f64>() {
-                    data.push(Record {
-                        date: date.clone(),
-                        asset: asset_name.to_string(),
-                        price,
-                    });
-                }
+        let asset = record
+            .get(0)
+            .ok_or_else(|| QuarsError::Parse("Missing asset column".to_string()))?
+            .trim();
+        let value = record
+            .get(1)
+            .ok_or_else(|| QuarsError::Parse("Missing expected_return column".to_string()))?
+            .trim()
+            .parse::<f64>()?;
+        expected.insert(asset.to_string(), value);
+    }
+    Ok(expected)
+}
+
+/// Asset matrix as stored in a JSON file
+#[derive(Deserialize)]
+struct AssetMatrixFile {
+    assets: Vec<String>,
+    covariance: Vec<Vec<f64>>,
+}
+
+/// Reads a square asset matrix, e.g. a covariance, with its asset names. CSV files hold the
+/// names in the header row and the first column (the layout of `covariance.csv`), JSON files
+/// an `{"assets": [...], "covariance": [[...]]}` object.
+pub fn read_asset_matrix(path: &str) -> Result<(Vec<String>, Array2<f64>), QuarsError> {
+    let (assets, rows) = if Path::new(path)
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("json"))
+    {
+        let file: AssetMatrixFile = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        (file.assets, file.covariance)
+    } else {
+        let mut rdr = csv::Reader::from_path(path)?;
+        let assets: Vec<String> = rdr
+            .headers()?
+            .iter()
+            .skip(1)
+            .map(|a| a.trim().to_string())
+            .collect();
+        // A headerless matrix would read its first row as the asset names
+        if assets.iter().any(|asset| asset.parse::<f64>().is_ok()) {
+            return Err(QuarsError::Parse(format!(
+                "The header row of {} holds numbers, it must name the assets of the columns",
+                path
+            )));
+        }
+        let mut rows = Vec::new();
+        for (i, result) in rdr.records().enumerate() {
+            let record = result?;
+            let row_asset = record.get(0).unwrap_or("").trim();
+            if assets.get(i).map(String::as_str) != Some(row_asset) {
+                return Err(QuarsError::Parse(format!(
+                    "Row {} of {} is labeled '{}', expected the header order {:?}",
+                    i + 1,
+                    path,
+                    row_asset,
+                    assets
+                )));
+            }
+            rows.push(
+                record
+                    .iter()
+                    .skip(1)
+                    .map(|v| v.trim().parse::<f64>())
+                    .collect::<Result<Vec<_>, _>>()?,
+            );
+        }
+        (assets, rows)
+    };
+
+    let n = assets.len();
+    if rows.len() != n || rows.iter().any(|row| row.len() != n) {
+        return Err(QuarsError::Parse(format!(
+            "The matrix in {} is not square over its {} assets",
+            path, n
+        )));
+    }
+    let matrix = Array2::from_shape_vec((n, n), rows.into_iter().flatten().collect())
+        .map_err(|e| QuarsError::Parse(format!("Malformed matrix in {}: {}", path, e)))?;
+    Ok((assets, matrix))
+}
+
+/// Checks the fetched data is usable before any statistics are computed: it must be
+/// non-empty and hold at least two distinct dates per asset.
+pub fn validate_historical_data(
+    data: &HistoricalData,
+    settings: &Settings,
+) -> Result<(), QuarsError> {
+    let source = match settings.general.data_source.as_str() {
+        "api" => format!("the {} API", settings.data_api.source),
+        "csv" => format!("the CSV file {}", settings.general.data_file),
+        other => format!("the {} data source", other),
+    };
+    if data.is_empty() {
+        let cause = if settings.general.data_source != "csv" && settings.data_api.tickers.is_empty()
+        {
+            "no tickers are configured in [data_api]".to_string()
+        } else {
+            format!(
+                "check the date range {} to {}, the data source, and whether the API quota is exhausted",
+                settings.data_api.start_date, settings.data_api.end_date
+            )
+        };
+        return Err(QuarsError::InsufficientData(format!(
+            "No data was returned by {}: {}.",
+            source, cause
+        )));
+    }
+
+    let mut asset_dates: HashMap<&str, Vec<&str>> = HashMap::new();
+    for record in data {
+        asset_dates
+            .entry(record.asset.as_str())
+            .or_default()
+            .push(record.date.as_str());
+    }
+    let mut short_assets: Vec<&str> = asset_dates
+        .iter_mut()
+        .filter_map(|(asset, dates)| {
+            dates.sort_unstable();
+            dates.dedup();
+            (dates.len() < 2).then_some(*asset)
+        })
+        .collect();
+    if !short_assets.is_empty() {
+        short_assets.sort_unstable();
+        return Err(QuarsError::InsufficientData(format!(
+            "{:?} from {} have fewer than two observations, returns cannot be computed: widen the date range {} to {} or check the tickers.",
+            short_assets, source, settings.data_api.start_date, settings.data_api.end_date
+        )));
+    }
+    Ok(())
+}
+
+/// Ticker under `normalization`: uppercased, stripped of the first matching suffix and then
+/// renamed by its alias, if any
+pub fn normalize_ticker(ticker: &str, normalization: &TickerNormalization) -> String {
+    let upper = ticker.trim().to_uppercase();
+    let stripped = normalization
+        .strip_suffixes
+        .iter()
+        .map(|suffix| suffix.to_uppercase())
+        .find_map(|suffix| {
+            upper
+                .strip_suffix(&suffix)
+                .filter(|base| !base.is_empty())
+                .map(str::to_string)
+        })
+        .unwrap_or(upper);
+    normalization
+        .aliases
+        .iter()
+        .find(|(alias, _)| alias.to_uppercase() == stripped)
+        .map_or(stripped, |(_, target)| target.trim().to_uppercase())
+}
+
+/// Renames the assets of the data with `normalize_ticker`, so that every later grouping by
+/// asset sees one asset per ticker. Tickers merged into one are reported, and of the prices
+/// they then share a date for the first one is kept.
+pub fn normalize_tickers(
+    data: HistoricalData,
+    normalization: &TickerNormalization,
+) -> HistoricalData {
+    let mut variants: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    let mut seen: HashSet<(String, String)> = HashSet::new();
+    let n_records = data.len();
+    let normalized: HistoricalData = data
+        .into_iter()
+        .filter_map(|record| {
+            let asset = normalize_ticker(&record.asset, normalization);
+            variants
+                .entry(asset.clone())
+                .or_default()
+                .insert(record.asset);
+            seen.insert((asset.clone(), record.date.clone()))
+                .then_some(Record { asset, ..record })
+        })
+        .collect();
+    for (asset, tickers) in &variants {
+        if tickers.len() > 1 {
+            let tickers: Vec<&str> = tickers.iter().map(String::as_str).collect();
+            println!("Merged tickers {} into {}", tickers.join(", "), asset);
+        }
+    }
+    if normalized.len() < n_records {
+        println!(
+            "Dropped {} duplicate observations of merged tickers",
+            n_records - normalized.len()
+        );
+    }
+    normalized
+}
+
+/// Keeps the prices dated up to `as_of` (YYYY-MM-DD) included, the data as it was known on that
+/// date, reporting how many later observations were dropped
+pub fn truncate_as_of(data: HistoricalData, as_of: &str) -> Result<HistoricalData, QuarsError> {
+    utils::parse_date(as_of)
+        .map_err(|e| QuarsError::Config(format!("Invalid as_of_date '{}': {}", as_of, e)))?;
+    let n_records = data.len();
+    // Dates are ISO formatted, intraday ones with a time after the day
+    let truncated: HistoricalData = data
+        .into_iter()
+        .filter(|record| {
+            record
+                .date
+                .get(..as_of.len())
+                .is_none_or(|day| day <= as_of)
+        })
+        .collect();
+    if truncated.len() < n_records {
+        println!(
+            "As of {}: dropped {} later observations",
+            as_of,
+            n_records - truncated.len()
+        );
+    }
+    Ok(truncated)
+}
+
+/// Drops the assets whose average price is below `min_price`, e.g. penny stocks, except the
+/// `keep` tickers (case-insensitive). Each dropped asset is reported with its average price.
+pub fn filter_by_price(data: HistoricalData, min_price: f64, keep: &[&str]) -> HistoricalData {
+    let mut price_sums: HashMap<&str, (f64, usize)> = HashMap::new();
+    for record in &data {
+        let entry = price_sums.entry(record.asset.as_str()).or_insert((0.0, 0));
+        entry.0 += record.price;
+        entry.1 += 1;
+    }
+    let mut dropped: Vec<(String, f64)> = price_sums
+        .into_iter()
+        .map(|(asset, (sum, count))| (asset, sum / count as f64))
+        .filter(|(asset, average)| {
+            *average < min_price && !keep.iter().any(|k| k.eq_ignore_ascii_case(asset))
+        })
+        .map(|(asset, average)| (asset.to_string(), average))
+        .collect();
+    dropped.sort_by(|a, b| a.0.cmp(&b.0));
+    for (asset, average) in &dropped {
+        println!(
+            "Filtered out {}: average price {:.4} is below min_average_price {}",
+            asset, average, min_price
+        );
+    }
+    data.into_iter()
+        .filter(|record| !dropped.iter().any(|(asset, _)| *asset == record.asset))
+        .collect()
+}
+
+/// Treatment of the runs of identical consecutive prices of an asset, e.g. of a halted stock
+/// or a broker that repeats its last quote
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StalePolicy {
+    // Only report them, the run then shows as zero returns
+    Warn,
+    // Drop the assets with a stale run
+    DropAsset,
+    // Drop the dates of the repeated prices of each run, for every asset so that the series
+    // stay aligned, keeping the first price of the run
+    DropSegment,
+}
+
+impl StalePolicy {
+    pub fn from_config(policy: &str) -> Result<Self, QuarsError> {
+        match policy {
+            "warn" => Ok(Self::Warn),
+            "drop-asset" => Ok(Self::DropAsset),
+            "drop-segment" => Ok(Self::DropSegment),
+            other => Err(QuarsError::Config(format!(
+                "Unknown stale policy: {} (expected warn, drop-asset or drop-segment)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Run of identical consecutive prices of an asset
+#[derive(Debug, Clone, PartialEq)]
+pub struct StaleRun {
+    pub asset: String,
+    // Dates of the first and the last price of the run
+    pub start: String,
+    pub end: String,
+    // Prices in the run, the first included
+    pub length: usize,
+}
+
+/// Finds the runs of more than `max_length` identical consecutive prices of each asset,
+/// except the `keep` tickers (case-insensitive), e.g. a cash asset of constant price
+pub fn detect_stale_runs(data: &HistoricalData, max_length: usize, keep: &[&str]) -> Vec<StaleRun> {
+    let mut series: HashMap<&str, Vec<(&str, f64)>> = HashMap::new();
+    for record in data {
+        if !keep.iter().any(|k| k.eq_ignore_ascii_case(&record.asset)) {
+            series
+                .entry(record.asset.as_str())
+                .or_default()
+                .push((record.date.as_str(), record.price));
+        }
+    }
+    let mut runs = Vec::new();
+    for (asset, mut prices) in series {
+        prices.sort_by(|a, b| a.0.cmp(b.0));
+        let mut start = 0;
+        for end in 1..=prices.len() {
+            if end < prices.len() && prices[end].1 == prices[start].1 {
+                continue;
+            }
+            if end - start > max_length {
+                runs.push(StaleRun {
+                    asset: asset.to_string(),
+                    start: prices[start].0.to_string(),
+                    end: prices[end - 1].0.to_string(),
+                    length: end - start,
+                });
+            }
+            start = end;
+        }
+    }
+    runs.sort_by(|a, b| (&a.asset, &a.start).cmp(&(&b.asset, &b.start)));
+    runs
+}
+
+/// Detects the stale price runs, reports each with its dates and length, and applies `policy`
+/// to them
+pub fn handle_stale_prices(
+    data: HistoricalData,
+    policy: StalePolicy,
+    max_length: usize,
+    keep: &[&str],
+) -> Result<HistoricalData, QuarsError> {
+    let runs = detect_stale_runs(&data, max_length, keep);
+    for run in &runs {
+        println!(
+            "Stale prices detected: {} unchanged over {} prices from {} to {}",
+            run.asset, run.length, run.start, run.end
+        );
+    }
+    if runs.is_empty() {
+        return Ok(data);
+    }
+    match policy {
+        StalePolicy::Warn => Ok(data),
+        StalePolicy::DropAsset => {
+            let data: HistoricalData = data
+                .into_iter()
+                .filter(|record| !runs.iter().any(|run| run.asset == record.asset))
+                .collect();
+            if data.is_empty() {
+                return Err(QuarsError::InsufficientData(
+                    "Every asset has stale prices, nothing is left to optimize.".to_string(),
+                ));
+            }
+            Ok(data)
+        }
+        StalePolicy::DropSegment => Ok(data
+            .into_iter()
+            .filter(|record| {
+                !runs
+                    .iter()
+                    .any(|run| record.date > run.start && record.date <= run.end)
+            })
+            .collect()),
+    }
+}
+
+/// Treatment of an asset whose prices stop before the others', e.g. after a delisting
+/// Trading days missing from an asset's prices between two consecutive dates
+#[derive(Debug, Clone, PartialEq)]
+pub struct Gap {
+    pub asset: String,
+    // Dates of the prices around the gap
+    pub after: String,
+    pub before: String,
+    pub missing: Vec<NaiveDate>,
+}
+
+/// Finds the trading days of `calendar` each asset has no daily price on, between its first
+/// and its last date. The days the calendar closes (weekends, holidays) are expected breaks,
+/// not gaps.
+pub fn detect_gaps(
+    data: &HistoricalData,
+    calendar: &TradingCalendar,
+) -> Result<Vec<Gap>, QuarsError> {
+    let mut series: HashMap<&str, Vec<&str>> = HashMap::new();
+    for record in data {
+        series
+            .entry(record.asset.as_str())
+            .or_default()
+            .push(&record.date);
+    }
+    let mut gaps = Vec::new();
+    for (asset, mut dates) in series {
+        dates.sort_unstable();
+        dates.dedup();
+        for pair in dates.windows(2) {
+            // Intraday timestamps carry a time part after the date
+            let [after, before] = [pair[0], pair[1]].map(|date| date.get(..10).unwrap_or(date));
+            let missing = calendar
+                .trading_days_between(utils::parse_date(after)?, utils::parse_date(before)?);
+            if !missing.is_empty() {
+                gaps.push(Gap {
+                    asset: asset.to_string(),
+                    after: pair[0].to_string(),
+                    before: pair[1].to_string(),
+                    missing,
+                });
+            }
+        }
+    }
+    gaps.sort_by(|a, b| (&a.asset, &a.after).cmp(&(&b.asset, &b.after)));
+    Ok(gaps)
+}
+
+/// Reports the gaps of the daily prices against the trading calendar, one line per gap
+pub fn report_gaps(data: &HistoricalData, calendar: &TradingCalendar) -> Result<(), QuarsError> {
+    for gap in detect_gaps(data, calendar)? {
+        let mut missing: Vec<String> = gap
+            .missing
+            .iter()
+            .take(MAX_LISTED_GAP_DAYS)
+            .map(NaiveDate::to_string)
+            .collect();
+        if gap.missing.len() > MAX_LISTED_GAP_DAYS {
+            missing.push("...".to_string());
+        }
+        println!(
+            "Missing data: {} has no price on {} trading day(s) between {} and {}: {}",
+            gap.asset,
+            gap.missing.len(),
+            gap.after,
+            gap.before,
+            missing.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelistingPolicy {
+    // Only report it, the statistics then only cover the dates up to its last price, those
+    // common to all assets
+    Warn,
+    // Drop the asset
+    Exclude,
+    // Hold its last price on every later date of the other assets, i.e. zero returns
+    CarryForward,
+    // Fail the run
+    Error,
+}
+
+impl DelistingPolicy {
+    pub fn from_config(policy: &str) -> Result<Self, QuarsError> {
+        match policy {
+            "warn" => Ok(Self::Warn),
+            "exclude" => Ok(Self::Exclude),
+            "carry-forward" => Ok(Self::CarryForward),
+            "error" => Ok(Self::Error),
+            other => Err(QuarsError::Config(format!(
+                "Unknown delisting policy: {} (expected warn, exclude, carry-forward or error)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Detects the assets whose last observation is more than `tolerance_days` calendar days
+/// before the latest one of any asset, reports each with its last trade date, and applies
+/// `policy` to them.
+pub fn handle_delistings(
+    data: HistoricalData,
+    policy: DelistingPolicy,
+    tolerance_days: i64,
+) -> Result<HistoricalData, QuarsError> {
+    let mut last_dates: HashMap<&str, &str> = HashMap::new();
+    for record in &data {
+        let last = last_dates
+            .entry(record.asset.as_str())
+            .or_insert(&record.date);
+        if record.date.as_str() > *last {
+            *last = &record.date;
+        }
+    }
+    let mut parsed = Vec::with_capacity(last_dates.len());
+    for (asset, date) in &last_dates {
+        // Intraday timestamps carry a time part after the date
+        parsed.push((
+            *asset,
+            *date,
+            utils::parse_date(date.get(..10).unwrap_or(date))?,
+        ));
+    }
+    let Some(latest) = parsed.iter().map(|(_, _, day)| *day).max() else {
+        return Ok(data);
+    };
+    let mut delisted: Vec<(String, String)> = parsed
+        .into_iter()
+        .filter(|(_, _, day)| (latest - *day).num_days() > tolerance_days)
+        .map(|(asset, date, _)| (asset.to_string(), date.to_string()))
+        .collect();
+    if delisted.is_empty() {
+        return Ok(data);
+    }
+    delisted.sort_unstable();
+    for (asset, date) in &delisted {
+        warn!(
+            "delisting detected: {} last traded on {}, the data runs to {}",
+            asset, date, latest
+        );
+    }
+
+    match policy {
+        DelistingPolicy::Warn => Ok(data),
+        DelistingPolicy::Error => Err(QuarsError::InsufficientData(format!(
+            "Assets stop trading before the others: {}. Set delisting_policy to exclude or carry-forward.",
+            delisted
+                .iter()
+                .map(|(asset, date)| format!("{} ({})", asset, date))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+        DelistingPolicy::Exclude => {
+            if delisted.len() == last_dates.len() {
+                return Err(QuarsError::InsufficientData(
+                    "Every asset is delisted, nothing is left to optimize.".to_string(),
+                ));
+            }
+            Ok(data
+                .into_iter()
+                .filter(|record| !delisted.iter().any(|(asset, _)| *asset == record.asset))
+                .collect())
+        }
+        DelistingPolicy::CarryForward => {
+            let mut dates: Vec<String> = data
+                .iter()
+                .filter(|record| !delisted.iter().any(|(asset, _)| *asset == record.asset))
+                .map(|record| record.date.clone())
+                .collect();
+            dates.sort_unstable();
+            dates.dedup();
+            let mut filled = Vec::new();
+            for (asset, last_date) in &delisted {
+                let last_price = data
+                    .iter()
+                    .find(|record| record.asset == *asset && record.date == *last_date)
+                    .map_or(0.0, |record| record.price);
+                filled.extend(
+                    dates
+                        .iter()
+                        .filter(|date| *date > last_date)
+                        .map(|date| Record {
+                            date: date.clone(),
+                            asset: asset.clone(),
+                            price: last_price,
+                        }),
+                );
+            }
+            let mut data = data;
+            data.extend(filled);
+            Ok(data)
+        }
+    }
+}
+
+/// Treatment of the stock splits found in unadjusted prices
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SplitPolicy {
+    // Only report them, the split date then carries a spurious return
+    Warn,
+    // Divide the prices before each split by its factor, keeping the actual move of the day
+    Adjust,
+    // Scale the prices before each split onto the first post-split price, a zero return
+    Drop,
+}
+
+impl SplitPolicy {
+    pub fn from_config(policy: &str) -> Result<Self, QuarsError> {
+        match policy {
+            "warn" => Ok(Self::Warn),
+            "adjust" => Ok(Self::Adjust),
+            "drop" => Ok(Self::Drop),
+            other => Err(QuarsError::Config(format!(
+                "Unknown split policy: {} (expected warn, adjust or drop)",
+                other
+            ))),
+        }
+    }
+}
+
+// Split factors looked for, both as k:1 splits and as 1:k reverse splits
+const SPLIT_FACTORS: [f64; 9] = [2.0, 3.0, 4.0, 5.0, 8.0, 10.0, 15.0, 20.0, 50.0];
+// Largest relative move left on the split date once the factor is taken out, e.g. a 2:1
+// split is a price ratio within 0.5·(1 ± 0.05)
+const SPLIT_TOLERANCE: f64 = 0.05;
+
+/// Stock split inferred from a jump between consecutive prices of an asset
+#[derive(Debug, Clone, PartialEq)]
+pub struct Split {
+    pub asset: String,
+    // Date of the first post-split price
+    pub date: String,
+    // New shares per old share, below 1 for a reverse split
+    pub factor: f64,
+    // Observed price over the previous one
+    pub ratio: f64,
+}
+
+impl Split {
+    /// "2:1" for a split, "1:10" for a reverse split
+    pub fn label(&self) -> String {
+        if self.factor >= 1.0 {
+            format!("{}:1", self.factor)
+        } else {
+            format!("1:{}", 1.0 / self.factor)
+        }
+    }
+}
+
+/// Flags the consecutive prices of an asset whose ratio is within `SPLIT_TOLERANCE` of a
+/// common split factor, a heuristic for raw prices that are not split adjusted
+pub fn detect_splits(data: &HistoricalData) -> Vec<Split> {
+    let mut series: HashMap<&str, Vec<(&str, f64)>> = HashMap::new();
+    for record in data {
+        series
+            .entry(record.asset.as_str())
+            .or_default()
+            .push((record.date.as_str(), record.price));
+    }
+    let mut splits = Vec::new();
+    for (asset, mut prices) in series {
+        prices.sort_by(|a, b| a.0.cmp(b.0));
+        for pair in prices.windows(2) {
+            let ((_, before), (date, after)) = (pair[0], pair[1]);
+            if before <= 0.0 || after <= 0.0 {
+                continue;
             }
+            let ratio = after / before;
+            let factor = SPLIT_FACTORS
+                .iter()
+                .flat_map(|&k| [k, 1.0 / k])
+                .find(|factor| (ratio * factor - 1.0).abs() <= SPLIT_TOLERANCE);
+            if let Some(factor) = factor {
+                splits.push(Split {
+                    asset: asset.to_string(),
+                    date: date.to_string(),
+                    factor,
+                    ratio,
+                });
+            }
+        }
+    }
+    splits.sort_by(|a, b| (&a.asset, &a.date).cmp(&(&b.asset, &b.date)));
+    splits
+}
+
+/// Detects the splits in raw prices, reports each with its date and factor, and applies
+/// `policy` to them
+pub fn handle_splits(
+    data: HistoricalData,
+    policy: SplitPolicy,
+) -> Result<HistoricalData, QuarsError> {
+    let splits = detect_splits(&data);
+    for split in &splits {
+        println!(
+            "Split detected: {} {} on {} (price ratio {:.4})",
+            split.asset,
+            split.label(),
+            split.date,
+            split.ratio
+        );
+    }
+    if policy == SplitPolicy::Warn || splits.is_empty() {
+        return Ok(data);
+    }
+    let mut data = data;
+    for split in &splits {
+        let scale = match policy {
+            SplitPolicy::Adjust => 1.0 / split.factor,
+            _ => split.ratio,
+        };
+        for record in data
+            .iter_mut()
+            .filter(|record| record.asset == split.asset && record.date < split.date)
+        {
+            record.price *= scale;
         }
     }
     Ok(data)
 }
 
-/// Main Alpha Vantage fetcher
-pub async fn fetch_data(settings: &Settings) -> Result<HistoricalData, Box<dyn Error>> {
-    match settings.general.data_source.as_str() {
-        "csv" => read_csv(&settings.general.data_file),
-        "api" => data_brokers::fetch_data(settings).await,
-        _ => Err("Unknown data source specified.".into()),
+/// Most recent price of each asset, e.g. to size trades
+pub fn latest_prices(data: &HistoricalData) -> HashMap<String, f64> {
+    let mut latest: HashMap<&str, &Record> = HashMap::new();
+    for record in data {
+        let entry = latest.entry(record.asset.as_str()).or_insert(record);
+        if record.date > entry.date {
+            *entry = record;
+        }
+    }
+    latest
+        .into_iter()
+        .map(|(asset, record)| (asset.to_string(), record.price))
+        .collect()
+}
+
+/// Merges the data of several sources into one data set. Sources may follow different
+/// trading calendars (e.g. crypto trades on weekends), so with more than one source only the
+/// dates every asset has a price for are kept.
+pub fn merge_sources(sources: Vec<HistoricalData>) -> HistoricalData {
+    if sources.len() <= 1 {
+        return sources.into_iter().flatten().collect();
+    }
+    let records: HistoricalData = sources.into_iter().flatten().collect();
+
+    let mut asset_dates: HashMap<&str, HashSet<&str>> = HashMap::new();
+    for record in &records {
+        asset_dates
+            .entry(record.asset.as_str())
+            .or_default()
+            .insert(record.date.as_str());
+    }
+    let mut date_sets = asset_dates.into_values();
+    let common: HashSet<String> = match date_sets.next() {
+        Some(first) => date_sets
+            .fold(first, |acc, dates| {
+                acc.intersection(&dates).cloned().collect()
+            })
+            .into_iter()
+            .map(String::from)
+            .collect(),
+        None => HashSet::new(),
+    };
+
+    let n_records = records.len();
+    let merged: HistoricalData = records
+        .into_iter()
+        .filter(|record| common.contains(&record.date))
+        .collect();
+    if merged.len() < n_records {
+        warn!(
+            "dropped {} observations on dates not shared by all sources",
+            n_records - merged.len()
+        );
+    }
+    merged
+}
+
+// Directory the fetched data is saved to, one subdirectory per day
+const RAW_DATA_DIR: &str = "data/raw";
+
+/// Fetched data, with the date of the cached data used instead when the fetch failed
+pub struct FetchedData {
+    pub data: HistoricalData,
+    pub fallback_date: Option<String>,
+}
+
+/// Loads the most recent `hist_data_*.csv` saved under `raw_dir` (in `{date}` subdirectories)
+/// in the `format` it was written with that holds every ticker, restricted to the tickers and
+/// to the [start, end] date range. Returns the data with the date of its directory.
+pub fn load_cached_data(
+    raw_dir: &str,
+    format: utils::CsvFormat,
+    tickers: &[String],
+    start_date: NaiveDate,
+    end_date: NaiveDate,
+) -> Result<(HistoricalData, String), QuarsError> {
+    let mut days: Vec<(String, std::path::PathBuf)> = std::fs::read_dir(raw_dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            (entry.path().is_dir() && utils::parse_date(&name).is_ok())
+                .then(|| (name, entry.path()))
+        })
+        .collect();
+    days.sort_unstable_by(|a, b| b.0.cmp(&a.0));
+
+    for (day, dir) in days {
+        let mut files: Vec<std::path::PathBuf> = std::fs::read_dir(&dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| {
+                path.file_name()
+                    .and_then(|name| name.to_str())
+                    .is_some_and(|name| name.starts_with("hist_data_") && name.ends_with(".csv"))
+            })
+            .collect();
+        files.sort_unstable();
+        for file in files {
+            let Ok(data) = read_csv(
+                &file.to_string_lossy(),
+                format.delimiter(),
+                format.decimal_separator,
+                true,
+                None,
+                None,
+                None,
+            ) else {
+                continue;
+            };
+            let data: HistoricalData = data
+                .into_iter()
+                .filter(|record| {
+                    tickers
+                        .iter()
+                        .any(|t| t.eq_ignore_ascii_case(&record.asset))
+                        && utils::parse_date(record.date.get(..10).unwrap_or(&record.date))
+                            .is_ok_and(|date| start_date <= date && date <= end_date)
+                })
+                .collect();
+            let covered = tickers.iter().all(|ticker| {
+                data.iter()
+                    .any(|record| record.asset.eq_ignore_ascii_case(ticker))
+            });
+            if covered {
+                return Ok((data, day));
+            }
+        }
+    }
+    Err(QuarsError::InsufficientData(format!(
+        "No cached data in {} covers the tickers {:?}",
+        raw_dir, tickers
+    )))
+}
+
+/// Prices of the brokers. With an `append_store`, only the dates after the last one of the
+/// store are fetched and appended to it, unless it misses a ticker, and the prices within
+/// [start_date, end_date] are read back from it.
+async fn fetch_brokers(
+    settings: &Settings,
+    rng: &mut StdRng,
+) -> Result<HistoricalData, QuarsError> {
+    let start_date = utils::parse_date(&settings.data_api.start_date)?;
+    let Some(store) = &settings.general.append_store else {
+        return data_brokers::fetch_data(settings, rng, start_date).await;
+    };
+    let end_date = utils::parse_date(&settings.data_api.end_date)?;
+    let format = utils::CsvFormat::prices(&settings.general)?;
+    let stored = if Path::new(store).exists() {
+        read_csv(
+            store,
+            format.delimiter(),
+            format.decimal_separator,
+            true,
+            None,
+            None,
+            None,
+        )?
+    } else {
+        Vec::new()
+    };
+    let covered = settings.data_api.tickers.iter().all(|ticker| {
+        stored
+            .iter()
+            .any(|record| record.asset.eq_ignore_ascii_case(ticker))
+    });
+    let last_date = stored
+        .iter()
+        .filter_map(|record| utils::parse_date(record.date.get(..10).unwrap_or(&record.date)).ok())
+        .max();
+    let fetch_start = match last_date {
+        Some(last) if covered => start_date.max(last + chrono::Duration::days(1)),
+        _ => start_date,
+    };
+
+    let fetched = if fetch_start <= end_date {
+        data_brokers::fetch_data(settings, rng, fetch_start).await?
+    } else {
+        Vec::new()
+    };
+    let n_stored = stored.len();
+    let union = utils::append_to_csv(fetched, format, store)?;
+    println!(
+        "Appended {} prices from {} to {}",
+        union.len() - n_stored,
+        fetch_start,
+        store
+    );
+    Ok(union
+        .into_iter()
+        .filter(|record| {
+            settings
+                .data_api
+                .tickers
+                .iter()
+                .any(|t| t.eq_ignore_ascii_case(&record.asset))
+                && utils::parse_date(record.date.get(..10).unwrap_or(&record.date))
+                    .is_ok_and(|date| start_date <= date && date <= end_date)
+        })
+        .collect())
+}
+
+// Falls back on a failed fetch to the most recent data cached under `raw_dir` when
+// `fallback_to_cache_on_error` is set, returning the data with the date of the cache used
+fn with_cache_fallback(
+    fetched: Result<HistoricalData, QuarsError>,
+    settings: &Settings,
+    raw_dir: &str,
+) -> Result<(HistoricalData, Option<String>), QuarsError> {
+    match fetched {
+        Err(e)
+            if settings.data_api.fallback_to_cache_on_error
+                && !matches!(e, QuarsError::Cancelled(_)) =>
+        {
+            let (data, date) = load_cached_data(
+                raw_dir,
+                utils::CsvFormat::prices(&settings.general)?,
+                &settings.data_api.tickers,
+                utils::parse_date(&settings.data_api.start_date)?,
+                utils::parse_date(&settings.data_api.end_date)?,
+            )
+            .map_err(|cache_err| {
+                QuarsError::Network(format!("API fetch failed ({}) and {}", e, cache_err))
+            })?;
+            warn!(
+                "the API fetch failed ({}), using STALE cached data saved on {}",
+                e, date
+            );
+            Ok((data, Some(date)))
+        }
+        result => result.map(|data| (data, None)),
+    }
+}
+
+/// Main Alpha Vantage fetcher. With `fallback_to_cache_on_error`, a failed API fetch falls
+/// back to the most recent data saved in data/raw.
+pub async fn fetch_data(settings: &Settings, rng: &mut StdRng) -> Result<FetchedData, QuarsError> {
+    let general = &settings.general;
+    let (data, fallback_date) = match general.data_source.as_str() {
+        "csv" => read_csv(
+            &general.data_file,
+            general.csv_delimiter,
+            general.decimal_separator,
+            general.csv_has_header,
+            general.csv_column_names.as_deref(),
+            general.data_file_entry.as_deref(),
+            general.date_format.as_deref(),
+        )
+        .map(|data| (data, None)),
+        "api" => with_cache_fallback(fetch_brokers(settings, rng).await, settings, RAW_DATA_DIR),
+        "mock" => data_brokers::mock::fetch_data(settings, &settings.data_api.tickers, rng)
+            .map(|data| (data, None)),
+        _ => Err(QuarsError::Config(
+            "Unknown data source specified.".to_string(),
+        )),
+    }?;
+    // At the ingestion boundary, every later grouping by asset sees the normalized tickers
+    let data = match &settings.ticker_normalization {
+        Some(normalization) => normalize_tickers(data, normalization),
+        None => data,
+    };
+    Ok(FetchedData {
+        data,
+        fallback_date,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portfolio::calculate_portfolio_stats;
+
+    // Writes a CSV fixture to the temporary directory, returning its path
+    fn fixture(name: &str, content: &str) -> String {
+        let path = std::env::temp_dir().join(format!("quars-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    // Settings of an API run over AAA and BBB in January 2024
+    fn api_settings() -> Settings {
+        config::Config::builder()
+            .add_source(config::File::from_str(
+                "[general]\ndata_source = \"api\"\ndata_file = \"unused.csv\"\n\
+                 [data_api]\nsource = \"alphavantage\"\ntickers = [\"AAA\", \"BBB\"]\n\
+                 start_date = \"2024-01-01\"\nend_date = \"2024-01-31\"\ntimeframe = \"daily\"\n\
+                 [portofolio_optimization]\nmethod = \"mvo\"\nsub_method = \"risk-adjusted\"\n\
+                 risk_free_rate = 0.0\nparams = [0.5]",
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .and_then(config::Config::try_deserialize)
+            .unwrap()
+    }
+
+    #[test]
+    fn reads_semicolon_delimited_prices_with_decimal_commas() {
+        let path = fixture(
+            "semicolon.csv",
+            "date;AAA;BBB\n2021-03-15;101,5;20,25\n2021-03-16;NaN;20,5\n2021-03-17;102,25;N/A\n",
+        );
+        let data = read_csv(&path, ';', ',', true, None, None, None).unwrap();
+        let prices: Vec<(&str, &str, f64)> = data
+            .iter()
+            .map(|r| (r.date.as_str(), r.asset.as_str(), r.price))
+            .collect();
+        assert_eq!(
+            prices,
+            [
+                ("2021-03-15", "AAA", 101.5),
+                ("2021-03-15", "BBB", 20.25),
+                ("2021-03-16", "BBB", 20.5),
+                ("2021-03-17", "AAA", 102.25),
+            ]
+        );
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn gzipped_and_zipped_files_read_like_the_plain_csv() {
+        use std::io::Write;
+
+        let content = "date,AAA,BBB\n2021-03-15,101.5,20.25\n2021-03-16,102,20.5\n";
+        let rows = |data: HistoricalData| -> Vec<(String, String, f64)> {
+            data.into_iter()
+                .map(|r| (r.date, r.asset, r.price))
+                .collect()
+        };
+        let read = |path: &str, entry: Option<&str>| {
+            rows(read_csv(path, ',', '.', true, None, entry, None).unwrap())
+        };
+        let plain_path = fixture("plain.csv", content);
+        let plain = read(&plain_path, None);
+        assert_eq!(plain.len(), 4);
+
+        let gz_path = fixture("prices.csv.gz", "");
+        let mut encoder = flate2::write::GzEncoder::new(
+            File::create(&gz_path).unwrap(),
+            flate2::Compression::default(),
+        );
+        encoder.write_all(content.as_bytes()).unwrap();
+        encoder.finish().unwrap();
+        assert_eq!(read(&gz_path, None), plain);
+
+        let zip_path = fixture("prices.zip", "");
+        let mut archive = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        for name in ["README.txt", "prices.csv"] {
+            archive
+                .start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            archive.write_all(content.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap();
+        // The only CSV member is read when no entry is named
+        assert_eq!(read(&zip_path, None), plain);
+        assert_eq!(read(&zip_path, Some("prices.csv")), plain);
+
+        for path in [plain_path, gz_path, zip_path] {
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn detects_common_date_layouts() {
+        let layouts = [
+            (["2021-03-15", "2021-03-16"], "%Y-%m-%d"),
+            (
+                ["2021-03-15 09:30:00", "2021-03-16 09:30:00"],
+                "%Y-%m-%d %H:%M:%S",
+            ),
+            (["2021/03/15", "2021/03/16"], "%Y/%m/%d"),
+            (["03/15/2021", "03/16/2021"], "%m/%d/%Y"),
+            (["15/03/2021", "16/03/2021"], "%d/%m/%Y"),
+            (["15-03-2021", "16-03-2021"], "%d-%m-%Y"),
+            (["15.03.2021", "16.03.2021"], "%d.%m.%Y"),
+            (["20210315", "20210316"], "%Y%m%d"),
+        ];
+        for (dates, expected) in layouts {
+            let format = detect_date_format(&dates).unwrap();
+            assert_eq!(format, expected, "layout of {:?}", dates);
+            assert_eq!(
+                &normalize_date(dates[0], format).unwrap()[..10],
+                "2021-03-15"
+            );
+        }
+    }
+
+    #[test]
+    fn ambiguous_day_month_dates_need_a_date_format() {
+        let err = detect_date_format(&["01/02/2021", "01/03/2021"]).unwrap_err();
+        assert!(err.to_string().contains("date_format"), "{}", err);
+    }
+
+    #[test]
+    fn later_dates_resolve_an_ambiguous_sample() {
+        let mut dates: Vec<String> = (1..=DATE_SAMPLE_SIZE)
+            .map(|month| format!("01/{:02}/2021", (month - 1) % 12 + 1))
+            .collect();
+        dates.push("25/12/2022".to_string());
+        let dates: Vec<&str> = dates.iter().map(String::as_str).collect();
+        assert_eq!(detect_date_format(&dates).unwrap(), "%d/%m/%Y");
+    }
+
+    #[test]
+    fn truncating_as_of_a_date_keeps_the_prices_up_to_that_day() {
+        let data = || -> HistoricalData {
+            [
+                "2024-03-28",
+                "2024-03-29",
+                "2024-03-29 15:30:00",
+                "2024-04-01",
+                "2024-04-01 09:30:00",
+            ]
+            .iter()
+            .map(|date| Record {
+                date: date.to_string(),
+                asset: "AAA".to_string(),
+                price: 100.0,
+            })
+            .collect()
+        };
+        let truncated = truncate_as_of(data(), "2024-03-29").unwrap();
+        let dates: Vec<&str> = truncated.iter().map(|r| r.date.as_str()).collect();
+        assert_eq!(dates, ["2024-03-28", "2024-03-29", "2024-03-29 15:30:00"]);
+        assert!(matches!(
+            truncate_as_of(data(), "29/03/2024"),
+            Err(QuarsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn tickers_differing_in_case_or_suffix_merge_into_one_asset() {
+        let normalization = TickerNormalization {
+            strip_suffixes: vec![".us".to_string()],
+            aliases: [("goog".to_string(), "googl".to_string())].into(),
+        };
+        assert_eq!(normalize_ticker("aapl", &normalization), "AAPL");
+        assert_eq!(normalize_ticker("AAPL.US", &normalization), "AAPL");
+        assert_eq!(normalize_ticker("GOOG.US", &normalization), "GOOGL");
+        let record = |asset: &str, date: &str, price: f64| Record {
+            date: date.to_string(),
+            asset: asset.to_string(),
+            price,
+        };
+        let data = vec![
+            record("aapl", "2024-01-02", 185.0),
+            record("AAPL", "2024-01-02", 186.0),
+            record("AAPL", "2024-01-03", 184.0),
+        ];
+        let normalized = normalize_tickers(data, &normalization);
+        let records: Vec<(&str, &str, f64)> = normalized
+            .iter()
+            .map(|r| (r.asset.as_str(), r.date.as_str(), r.price))
+            .collect();
+        // The first of the prices sharing a date is kept
+        assert_eq!(
+            records,
+            [("AAPL", "2024-01-02", 185.0), ("AAPL", "2024-01-03", 184.0)]
+        );
+    }
+
+    #[test]
+    fn stale_runs_are_detected_and_dropped_per_policy() {
+        let data = || -> HistoricalData {
+            let prices = [
+                ("AAA", [10.0, 10.5, 10.5, 10.5, 10.5, 11.0]),
+                ("BBB", [20.0, 20.1, 20.2, 20.3, 20.4, 20.5]),
+                ("CASH", [1.0; 6]),
+            ];
+            prices
+                .iter()
+                .flat_map(|(asset, prices)| {
+                    prices.iter().enumerate().map(|(day, price)| Record {
+                        date: format!("2024-01-{:02}", day + 2),
+                        asset: asset.to_string(),
+                        price: *price,
+                    })
+                })
+                .collect()
+        };
+        let runs = detect_stale_runs(&data(), 3, &["cash"]);
+        assert_eq!(
+            runs,
+            [StaleRun {
+                asset: "AAA".to_string(),
+                start: "2024-01-03".to_string(),
+                end: "2024-01-06".to_string(),
+                length: 4,
+            }]
+        );
+        assert!(detect_stale_runs(&data(), 4, &["cash"]).is_empty());
+
+        let kept = |data: &HistoricalData, asset: &str| -> Vec<String> {
+            data.iter()
+                .filter(|r| r.asset == asset)
+                .map(|r| r.date.clone())
+                .collect()
+        };
+        let warned = handle_stale_prices(data(), StalePolicy::Warn, 3, &["cash"]).unwrap();
+        assert_eq!(warned.len(), 18);
+        let dropped = handle_stale_prices(data(), StalePolicy::DropAsset, 3, &["cash"]).unwrap();
+        assert!(kept(&dropped, "AAA").is_empty());
+        assert_eq!(kept(&dropped, "BBB").len(), 6);
+        // The repeated dates go for every asset, the first price of the run stays
+        let segment = handle_stale_prices(data(), StalePolicy::DropSegment, 3, &["cash"]).unwrap();
+        for asset in ["AAA", "BBB", "CASH"] {
+            assert_eq!(
+                kept(&segment, asset),
+                ["2024-01-02", "2024-01-03", "2024-01-07"]
+            );
+        }
+    }
+
+    #[test]
+    fn an_asset_ending_early_is_handled_per_delisting_policy() {
+        // BBB stops trading twelve days before AAA
+        let data = || -> HistoricalData {
+            (1..=20)
+                .flat_map(|day| {
+                    let date = format!("2024-01-{:02}", day);
+                    let aaa = Record {
+                        date: date.clone(),
+                        asset: "AAA".to_string(),
+                        price: 100.0 + day as f64,
+                    };
+                    let bbb = (day <= 8).then(|| Record {
+                        date,
+                        asset: "BBB".to_string(),
+                        price: 50.0 - 0.5 * day as f64,
+                    });
+                    std::iter::once(aaa).chain(bbb)
+                })
+                .collect()
+        };
+        let dates = |data: &HistoricalData, asset: &str| -> Vec<String> {
+            data.iter()
+                .filter(|r| r.asset == asset)
+                .map(|r| r.date.clone())
+                .collect()
+        };
+        assert_eq!(
+            handle_delistings(data(), DelistingPolicy::Warn, 12)
+                .unwrap()
+                .len(),
+            28
+        );
+
+        let warned = handle_delistings(data(), DelistingPolicy::Warn, 7).unwrap();
+        assert_eq!(warned.len(), 28);
+        let stats = calculate_portfolio_stats(&warned, "daily", false).unwrap();
+        assert_eq!(stats.dates.last().map(String::as_str), Some("2024-01-08"));
+        assert_eq!(stats.dates.len(), 7);
+
+        let excluded = handle_delistings(data(), DelistingPolicy::Exclude, 7).unwrap();
+        assert!(dates(&excluded, "BBB").is_empty());
+        assert_eq!(dates(&excluded, "AAA").len(), 20);
+
+        let carried = handle_delistings(data(), DelistingPolicy::CarryForward, 7).unwrap();
+        assert_eq!(dates(&carried, "BBB").len(), 20);
+        let stats = calculate_portfolio_stats(&carried, "daily", false).unwrap();
+        assert_eq!(stats.dates.len(), 19);
+        assert!(stats
+            .returns_matrix
+            .row(1)
+            .iter()
+            .skip(7)
+            .all(|r| *r == 0.0));
+        assert!(stats.returns_matrix.row(1).iter().take(7).all(|r| *r < 0.0));
+
+        let err = handle_delistings(data(), DelistingPolicy::Error, 7).unwrap_err();
+        assert!(
+            matches!(&err, QuarsError::InsufficientData(msg) if msg.contains("BBB (2024-01-08)")),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn gaps_are_the_trading_days_missing_from_each_asset() {
+        let record = |asset: &str, date: &str| Record {
+            date: date.to_string(),
+            asset: asset.to_string(),
+            price: 100.0,
+        };
+        // Around Good Friday and a weekend, BBB also misses a Tuesday
+        let data = vec![
+            record("AAA", "2024-03-28"),
+            record("AAA", "2024-04-01"),
+            record("AAA", "2024-04-02"),
+            record("BBB", "2024-03-28"),
+            record("BBB", "2024-04-01"),
+            record("BBB", "2024-04-03"),
+        ];
+        let nyse = TradingCalendar::from_config("nyse", &[]).unwrap();
+        let gaps = detect_gaps(&data, &nyse).unwrap();
+        assert_eq!(
+            gaps,
+            [Gap {
+                asset: "BBB".to_string(),
+                after: "2024-04-01".to_string(),
+                before: "2024-04-03".to_string(),
+                missing: vec![utils::parse_date("2024-04-02").unwrap()],
+            }]
+        );
+        // Without the NYSE holidays Good Friday is missing from both
+        let weekdays = TradingCalendar::from_config("weekdays", &[]).unwrap();
+        let gaps = detect_gaps(&data, &weekdays).unwrap();
+        assert_eq!(gaps.len(), 3);
+        assert!(gaps[..2]
+            .iter()
+            .all(|gap| gap.missing == [utils::parse_date("2024-03-29").unwrap()]));
+    }
+
+    #[test]
+    fn a_two_for_one_split_is_back_adjusted() {
+        let data = || -> HistoricalData {
+            let prices = [
+                ("AAA", [100.0, 102.0, 101.0, 51.0, 52.0]),
+                ("BBB", [40.0, 41.0, 40.5, 41.5, 42.0]),
+            ];
+            prices
+                .iter()
+                .flat_map(|(asset, prices)| {
+                    prices.iter().enumerate().map(|(day, price)| Record {
+                        date: format!("2024-01-{:02}", day + 2),
+                        asset: asset.to_string(),
+                        price: *price,
+                    })
+                })
+                .collect()
+        };
+        let splits = detect_splits(&data());
+        assert_eq!(splits.len(), 1);
+        assert_eq!(
+            (splits[0].asset.as_str(), splits[0].date.as_str()),
+            ("AAA", "2024-01-05")
+        );
+        assert_eq!(splits[0].label(), "2:1");
+
+        let returns = |data: &HistoricalData, asset: &str| -> Vec<f64> {
+            let prices: Vec<f64> = data
+                .iter()
+                .filter(|r| r.asset == asset)
+                .map(|r| r.price)
+                .collect();
+            prices.windows(2).map(|p| p[1] / p[0] - 1.0).collect()
+        };
+        let warned = handle_splits(data(), SplitPolicy::Warn).unwrap();
+        assert!(returns(&warned, "AAA")[2] < -0.49);
+
+        // The spurious -49.5% return becomes the actual move of the day
+        let adjusted = handle_splits(data(), SplitPolicy::Adjust).unwrap();
+        let expected = [
+            102.0 / 100.0 - 1.0,
+            101.0 / 102.0 - 1.0,
+            51.0 / 50.5 - 1.0,
+            52.0 / 51.0 - 1.0,
+        ];
+        for (actual, expected) in returns(&adjusted, "AAA").iter().zip(expected) {
+            assert!(
+                (actual - expected).abs() < 1e-12,
+                "{} vs {}",
+                actual,
+                expected
+            );
+        }
+        assert_eq!(returns(&adjusted, "BBB"), returns(&data(), "BBB"));
+
+        let dropped = handle_splits(data(), SplitPolicy::Drop).unwrap();
+        assert!(returns(&dropped, "AAA")[2].abs() < 1e-12);
+    }
+
+    #[test]
+    fn headered_and_headerless_files_read_the_same_prices() {
+        let prices = |data: HistoricalData| -> Vec<(String, String, f64)> {
+            data.iter()
+                .map(|r| (r.date.clone(), r.asset.clone(), r.price))
+                .collect()
+        };
+        let rows = "2021-03-15,101.5,20.25\n2021-03-16,102,20.5\n";
+        let headered = fixture("headered.csv", &format!("date,AAA,BBB\n{}", rows));
+        let headerless = fixture("headerless.csv", rows);
+
+        let expected = prices(read_csv(&headered, ',', '.', true, None, None, None).unwrap());
+        assert_eq!(expected.len(), 4);
+        assert_eq!(
+            (expected[0].1.as_str(), expected[1].1.as_str()),
+            ("AAA", "BBB")
+        );
+        // The first row is data, the assets named after their column or by the config
+        let synthetic = prices(read_csv(&headerless, ',', '.', false, None, None, None).unwrap());
+        let renamed: Vec<_> = expected
+            .iter()
+            .map(|(date, asset, price)| {
+                let name = if asset == "AAA" { "asset_0" } else { "asset_1" };
+                (date.clone(), name.to_string(), *price)
+            })
+            .collect();
+        assert_eq!(synthetic, renamed);
+        let names = ["AAA", "BBB"].map(String::from);
+        let named =
+            prices(read_csv(&headerless, ',', '.', false, Some(&names), None, None).unwrap());
+        assert_eq!(named, expected);
+        assert!(matches!(
+            read_csv(&headerless, ',', '.', false, Some(&names[..1]), None, None),
+            Err(QuarsError::Config(_))
+        ));
+        // A header read as data is no date
+        assert!(matches!(
+            read_csv(&headered, ',', '.', false, None, None, None),
+            Err(QuarsError::Parse(_))
+        ));
+        std::fs::remove_file(headered).unwrap();
+        std::fs::remove_file(headerless).unwrap();
+    }
+
+    #[test]
+    fn a_covariance_file_needs_its_asset_labels() {
+        let headered = fixture(
+            "covariance.csv",
+            ",BBB,AAA\nBBB,0.04,0.004\nAAA,0.004,0.01\n",
+        );
+        let (assets, covariance) = read_asset_matrix(&headered).unwrap();
+        assert_eq!(assets, ["BBB", "AAA"]);
+        assert_eq!(covariance, ndarray::array![[0.04, 0.004], [0.004, 0.01]]);
+        // Without the labels there is no telling which asset a row belongs to
+        let headerless = fixture("covariance_headerless.csv", "0.04,0.004\n0.004,0.01\n");
+        assert!(matches!(
+            read_asset_matrix(&headerless),
+            Err(QuarsError::Parse(_))
+        ));
+        std::fs::remove_file(headered).unwrap();
+        std::fs::remove_file(headerless).unwrap();
+    }
+
+    #[test]
+    fn a_failed_fetch_falls_back_to_the_latest_cached_prices() {
+        let mut settings = api_settings();
+        settings.data_api.fallback_to_cache_on_error = true;
+        let raw_dir = std::env::temp_dir().join(format!("quars-raw-{}", std::process::id()));
+        for (day, prices) in [
+            (
+                "2024-01-10",
+                "date,AAA,BBB\n2024-01-08,10,20\n2024-01-09,11,21\n",
+            ),
+            (
+                "2024-01-20",
+                "date,AAA,BBB\n2024-01-18,12,22\n2024-01-19,13,23\n",
+            ),
+        ] {
+            std::fs::create_dir_all(raw_dir.join(day)).unwrap();
+            std::fs::write(raw_dir.join(day).join("hist_data_AAA_BBB.csv"), prices).unwrap();
+        }
+        let raw_dir_path = raw_dir.to_string_lossy();
+        let failed = || Err(QuarsError::Network("quota exhausted".to_string()));
+
+        let (data, date) = with_cache_fallback(failed(), &settings, &raw_dir_path).unwrap();
+        assert_eq!(date.as_deref(), Some("2024-01-20"));
+        let prices: Vec<(&str, &str, f64)> = data
+            .iter()
+            .map(|r| (r.date.as_str(), r.asset.as_str(), r.price))
+            .collect();
+        assert_eq!(
+            prices,
+            [
+                ("2024-01-18", "AAA", 12.0),
+                ("2024-01-18", "BBB", 22.0),
+                ("2024-01-19", "AAA", 13.0),
+                ("2024-01-19", "BBB", 23.0),
+            ]
+        );
+        // A successful fetch is kept, a cancelled one is not retried from the cache
+        let (_, date) = with_cache_fallback(Ok(Vec::new()), &settings, &raw_dir_path).unwrap();
+        assert_eq!(date, None);
+        assert!(matches!(
+            with_cache_fallback(
+                Err(crate::utils::Cancelled.into()),
+                &settings,
+                &raw_dir_path
+            ),
+            Err(QuarsError::Cancelled(_))
+        ));
+        settings.data_api.fallback_to_cache_on_error = false;
+        assert!(matches!(
+            with_cache_fallback(failed(), &settings, &raw_dir_path),
+            Err(QuarsError::Network(_))
+        ));
+        std::fs::remove_dir_all(raw_dir).unwrap();
+    }
+
+    #[test]
+    fn empty_or_single_date_data_fails_early_naming_the_cause() {
+        let mut settings = api_settings();
+        let message = |data: &HistoricalData, settings: &Settings| match validate_historical_data(
+            data, settings,
+        ) {
+            Err(QuarsError::InsufficientData(msg)) => msg,
+            other => panic!("expected insufficient data, got {:?}", other),
+        };
+        let empty = message(&Vec::new(), &settings);
+        assert!(
+            empty.contains("the alphavantage API")
+                && empty.contains("2024-01-01 to 2024-01-31")
+                && empty.contains("quota"),
+            "{}",
+            empty
+        );
+        settings.data_api.tickers.clear();
+        assert!(message(&Vec::new(), &settings).contains("no tickers are configured"));
+
+        let record = |date: &str, asset: &str| Record {
+            date: date.to_string(),
+            asset: asset.to_string(),
+            price: 100.0,
+        };
+        let mut data = vec![
+            record("2024-01-02", "AAA"),
+            record("2024-01-03", "AAA"),
+            record("2024-01-02", "BBB"),
+            record("2024-01-02", "BBB"),
+        ];
+        let single = message(&data, &settings);
+        assert!(single.starts_with("[\"BBB\"]"), "{}", single);
+        data.truncate(2);
+        assert!(validate_historical_data(&data, &settings).is_ok());
+    }
+
+    #[test]
+    fn a_low_priced_asset_is_filtered_out_under_the_price_floor() {
+        let record = |date: &str, asset: &str, price: f64| Record {
+            date: date.to_string(),
+            asset: asset.to_string(),
+            price,
+        };
+        let data = || {
+            vec![
+                record("2024-01-02", "AAA", 101.0),
+                record("2024-01-02", "PENNY", 0.8),
+                record("2024-01-02", "bil", 0.9),
+                record("2024-01-03", "AAA", 99.0),
+                record("2024-01-03", "PENNY", 1.4),
+                record("2024-01-03", "bil", 0.9),
+            ]
+        };
+        let assets = |data: &HistoricalData| {
+            let mut assets: Vec<String> = data.iter().map(|r| r.asset.clone()).collect();
+            assets.sort();
+            assets.dedup();
+            assets
+        };
+        // PENNY averages 1.1, the cash asset is kept below the floor
+        assert_eq!(
+            assets(&filter_by_price(data(), 2.0, &["BIL"])),
+            ["AAA", "bil"]
+        );
+        assert_eq!(
+            assets(&filter_by_price(data(), 1.0, &["BIL"])),
+            ["AAA", "PENNY", "bil"]
+        );
+        assert_eq!(assets(&filter_by_price(data(), 2.0, &[])), ["AAA"]);
+        assert_eq!(filter_by_price(data(), 2.0, &["BIL"]).len(), 4);
     }
 }
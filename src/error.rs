@@ -0,0 +1,153 @@
+use std::num::{ParseFloatError, ParseIntError};
+
+use plotters::drawing::DrawingAreaErrorKind;
+use thiserror::Error;
+
+use crate::utils::Cancelled;
+
+/// Error of every stage of a run, by category so callers can tell a bad configuration from
+/// a network failure or a numerical one. The messages are shown to the user as they are.
+#[derive(Debug, Error)]
+pub enum QuarsError {
+    // Invalid or inconsistent settings
+    #[error("{0}")]
+    Config(String),
+    // Failed request or unexpected response of a data broker
+    #[error("{0}")]
+    Network(String),
+    // Malformed input: CSV, JSON, dates or numbers
+    #[error("{0}")]
+    Parse(String),
+    // Too few observations or assets, or data missing for a computation
+    #[error("{0}")]
+    InsufficientData(String),
+    // Matrix that cannot be inverted or factorized
+    #[error("{0}")]
+    Singular(String),
+    // Problem without a solution under its constraints, or a solver that did not converge
+    #[error("{0}")]
+    Infeasible(String),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Plotting error: {0}")]
+    Plot(String),
+    #[error(transparent)]
+    Cancelled(#[from] Cancelled),
+}
+
+impl From<reqwest::Error> for QuarsError {
+    fn from(e: reqwest::Error) -> Self {
+        Self::Network(e.to_string())
+    }
+}
+
+impl From<tokio_tungstenite::tungstenite::Error> for QuarsError {
+    fn from(e: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::Network(e.to_string())
+    }
+}
+
+impl From<csv::Error> for QuarsError {
+    fn from(e: csv::Error) -> Self {
+        let message = e.to_string();
+        match e.into_kind() {
+            csv::ErrorKind::Io(io) => Self::Io(io),
+            _ => Self::Parse(message),
+        }
+    }
+}
+
+impl From<serde_json::Error> for QuarsError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<zip::result::ZipError> for QuarsError {
+    fn from(e: zip::result::ZipError) -> Self {
+        match e {
+            zip::result::ZipError::Io(io) => Self::Io(io),
+            _ => Self::Parse(e.to_string()),
+        }
+    }
+}
+
+#[cfg(feature = "xlsx")]
+impl From<rust_xlsxwriter::XlsxError> for QuarsError {
+    fn from(e: rust_xlsxwriter::XlsxError) -> Self {
+        match e {
+            rust_xlsxwriter::XlsxError::IoError(io) => Self::Io(io),
+            _ => Self::Io(std::io::Error::other(e.to_string())),
+        }
+    }
+}
+
+impl From<chrono::ParseError> for QuarsError {
+    fn from(e: chrono::ParseError) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<ParseFloatError> for QuarsError {
+    fn from(e: ParseFloatError) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<ParseIntError> for QuarsError {
+    fn from(e: ParseIntError) -> Self {
+        Self::Parse(e.to_string())
+    }
+}
+
+impl From<config::ConfigError> for QuarsError {
+    fn from(e: config::ConfigError) -> Self {
+        Self::Config(e.to_string())
+    }
+}
+
+impl From<ndarray_linalg::error::LinalgError> for QuarsError {
+    fn from(e: ndarray_linalg::error::LinalgError) -> Self {
+        Self::Singular(e.to_string())
+    }
+}
+
+impl<E: std::error::Error + Send + Sync> From<DrawingAreaErrorKind<E>> for QuarsError {
+    fn from(e: DrawingAreaErrorKind<E>) -> Self {
+        Self::Plot(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{array, Array1, Array2};
+
+    #[test]
+    fn known_failures_have_their_category() {
+        let singular = array![[1.0, 1.0], [1.0, 1.0]];
+        assert!(matches!(
+            crate::portfolio::check_positive_definite(&singular),
+            Err(QuarsError::Singular(_))
+        ));
+
+        let warm_start = Array1::from_elem(3, 1.0 / 3.0);
+        let solution = crate::math::optimization::minimize_budget_qp(
+            &Array2::eye(2),
+            &Array1::zeros(2),
+            &Array1::zeros(2),
+            &Array1::ones(2),
+            Some(&warm_start),
+        );
+        assert!(matches!(solution, Err(QuarsError::Config(_))));
+
+        let missing = std::env::temp_dir().join("quars-missing-file.csv");
+        let read =
+            crate::data::read_csv(missing.to_str().unwrap(), ',', '.', true, None, None, None);
+        assert!(matches!(read, Err(QuarsError::Io(_))));
+        assert!(matches!(
+            "1.5x".parse::<f64>().map_err(QuarsError::from),
+            Err(QuarsError::Parse(_))
+        ));
+    }
+}
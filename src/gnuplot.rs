@@ -0,0 +1,190 @@
+use crate::config::VizSettings;
+use crate::error::QuarsError;
+use crate::optimization::OptimizationResults;
+use crate::utils::write_atomic;
+use crate::visualization::{cal_points, CalRates, FrontierScale};
+
+pub const FRONTIER_FILE: &str = "efficient_frontier.dat";
+pub const WEIGHTS_FILE: &str = "weights.dat";
+pub const SCRIPT_FILE: &str = "efficient_frontier.gp";
+// Two blank lines end a data block, which gnuplot then addresses with `index`
+const BLOCK_SEPARATOR: [&str; 2] = ["", ""];
+
+/// Writes the frontier, the CAL and the tangency portfolio to `efficient_frontier.dat`, the
+/// tangency weights to `weights.dat`, and a gnuplot script drawing them to
+/// `efficient_frontier.gp`, run with `gnuplot efficient_frontier.gp`. The figures are in the
+/// scale of the efficient frontier chart, annualized when it is. The CAL starts at the
+/// per-period `risk_free` rate and is financed at `borrowing` past the tangency portfolio.
+pub fn export(
+    results: &OptimizationResults,
+    assets: &[String],
+    rates: CalRates,
+    periods_per_year: f64,
+    viz: &VizSettings,
+) -> Result<(), QuarsError> {
+    let scale = FrontierScale::new(viz.annualized, periods_per_year);
+    let cal = cal_points(results, rates, scale, viz.extend_cal);
+    write_atomic(FRONTIER_FILE, frontier_data(results, cal, scale).as_bytes())?;
+    write_atomic(
+        WEIGHTS_FILE,
+        weights_data(assets, &results.optimal_risky_portfolio).as_bytes(),
+    )?;
+    write_atomic(SCRIPT_FILE, script(viz.annualized).as_bytes())?;
+    println!(
+        "gnuplot data saved to {} and {}, plotted by {}",
+        FRONTIER_FILE, WEIGHTS_FILE, SCRIPT_FILE
+    );
+    Ok(())
+}
+
+/// Whitespace-delimited data of the frontier chart in three blocks, separated by two blank
+/// lines so gnuplot selects them with `index`: 0 the frontier points (risk, expected return,
+/// risk-free weight), 1 the points of the CAL, 2 the tangency portfolio
+pub fn frontier_data(
+    results: &OptimizationResults,
+    cal: [(f64, f64); 3],
+    scale: FrontierScale,
+) -> String {
+    let mut lines = vec![
+        "# Efficient frontier written by quars".to_string(),
+        "# index 0: frontier".to_string(),
+        "# risk expected_return risk_free_weight".to_string(),
+    ];
+    for pt in &results.frontier {
+        let (risk, ret) = scale.point(pt.portfolio_std, pt.expected_return);
+        lines.push(format!("{} {} {}", risk, ret, pt.risk_free_weight));
+    }
+    lines.extend(BLOCK_SEPARATOR.map(String::from));
+    lines.push("# index 1: capital allocation line".to_string());
+    lines.push("# risk expected_return".to_string());
+    lines.extend(cal.iter().map(|(risk, ret)| format!("{} {}", risk, ret)));
+    let (risk, ret) = scale.point(results.optimal_risky_std, results.optimal_risky_return);
+    lines.extend(BLOCK_SEPARATOR.map(String::from));
+    lines.push("# index 2: tangency portfolio".to_string());
+    lines.push("# risk expected_return".to_string());
+    lines.push(format!("{} {}", risk, ret));
+    lines.join("\n") + "\n"
+}
+
+/// Whitespace-delimited tangency weights, one quoted asset and its weight per line
+pub fn weights_data(assets: &[String], weights: &[f64]) -> String {
+    let mut data = String::from("# Tangency portfolio weights written by quars\n# asset weight\n");
+    for (asset, weight) in assets.iter().zip(weights) {
+        data.push_str(&format!("\"{}\" {}\n", asset, weight));
+    }
+    data
+}
+
+// Script drawing the frontier with its CAL and the weights, as the built-in charts do
+fn script(annualized: bool) -> String {
+    let suffix = if annualized { " (annualized)" } else { "" };
+    format!(
+        r#"# Written by quars, run with: gnuplot {script}
+set terminal pngcairo size 800,600
+set output 'efficient_frontier_gnuplot.png'
+set title 'Efficient Frontier'
+set xlabel 'Risk (standard deviation){suffix}'
+set ylabel 'Expected return{suffix}'
+set key top left
+set grid
+plot '{frontier}' index 0 using 1:2 with lines lw 2 lc rgb 'blue' title 'Efficient frontier', \
+     '' index 1 using 1:2 with lines dt 2 lc rgb 'red' title 'Capital allocation line', \
+     '' index 2 using 1:2 with points pt 7 ps 1.5 lc rgb 'red' title 'Tangency portfolio'
+
+set output 'weights_gnuplot.png'
+set title 'Tangency Portfolio Weights'
+set xlabel 'Asset'
+set ylabel 'Weight'
+set key off
+set style fill solid 0.8
+set boxwidth 0.8
+set xzeroaxis
+plot '{weights}' using 0:2:xtic(1) with boxes lc rgb 'blue'
+"#,
+        script = SCRIPT_FILE,
+        frontier = FRONTIER_FILE,
+        weights = WEIGHTS_FILE,
+        suffix = suffix,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::PortofolioOptimization;
+    use crate::optimization::optimize_portfolio;
+    use crate::portfolio::PortfolioStats;
+    use config::{Config, File, FileFormat};
+    use ndarray::array;
+
+    // Values of the data lines of a block, comments skipped
+    fn rows(block: &str) -> Vec<Vec<f64>> {
+        block
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| line.split(' ').map(|v| v.parse().unwrap()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn the_frontier_data_has_one_block_per_series_in_the_chart_scale() {
+        let returns = array![
+            [0.010, -0.004, 0.006, 0.002, -0.001, 0.008, -0.003, 0.005],
+            [0.002, 0.004, -0.006, 0.007, 0.003, -0.002, 0.006, 0.001],
+        ];
+        let assets = vec!["AAA".to_string(), "BBB Corp".to_string()];
+        let dates = (1..=8).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(assets.clone(), returns, dates).unwrap();
+        let po: PortofolioOptimization = Config::builder()
+            .add_source(File::from_str(
+                "method = \"mvo\"\nsub_method = \"risk-adjusted\"\nrisk_free_rate = 0.0\nparams = [0.5]",
+                FileFormat::Toml,
+            ))
+            .build()
+            .and_then(Config::try_deserialize)
+            .unwrap();
+        let results = optimize_portfolio(&stats, 5, &po).unwrap();
+        // Four periods a year double the risk and quadruple the returns
+        let scale = FrontierScale::new(true, 4.0);
+        let rates = CalRates {
+            lending: 0.0,
+            borrowing: 0.0,
+        };
+        let cal = cal_points(&results, rates, scale, false);
+        let data = frontier_data(&results, cal, scale);
+
+        let blocks: Vec<&str> = data.split("\n\n\n").collect();
+        assert_eq!(blocks.len(), 3);
+        let frontier = rows(blocks[0]);
+        assert_eq!(frontier.len(), results.frontier.len());
+        for (row, pt) in frontier.iter().zip(&results.frontier) {
+            assert_eq!(
+                row,
+                &[
+                    2.0 * pt.portfolio_std,
+                    4.0 * pt.expected_return,
+                    pt.risk_free_weight
+                ]
+            );
+        }
+        assert_eq!(rows(blocks[1]).len(), cal.len());
+        assert_eq!(
+            rows(blocks[2]),
+            [[
+                2.0 * results.optimal_risky_std,
+                4.0 * results.optimal_risky_return
+            ]]
+        );
+
+        // Asset names with spaces stay one quoted column
+        let weights = weights_data(&assets, &results.optimal_risky_portfolio);
+        let lines: Vec<&str> = weights.lines().filter(|l| !l.starts_with('#')).collect();
+        assert_eq!(
+            lines,
+            [
+                format!("\"AAA\" {}", results.optimal_risky_portfolio[0]),
+                format!("\"BBB Corp\" {}", results.optimal_risky_portfolio[1]),
+            ]
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::path::Path;
+
+use ndarray::{Array2, ArrayView1};
+
+use crate::data;
+use crate::error::QuarsError;
+use crate::portfolio::{calculate_portfolio_stats, PortfolioStats};
+use crate::utils::write_atomic;
+
+// Committed fixture, relative to the crate root: daily prices and the statistics expected
+// from them
+const GOLDEN_DIR: &str = "fixtures/golden";
+// Set to regenerate the expected statistics instead of comparing with them
+const UPDATE_VAR: &str = "UPDATE_GOLDEN";
+const PRICES_FILE: &str = "prices.csv";
+const EXPECTED_FILE: &str = "expected_stats.json";
+const TIMEFRAME: &str = "daily";
+
+// A value matches within RELATIVE_TOLERANCE·|expected| + ABSOLUTE_TOLERANCE, loose enough for
+// the summation orders of BLAS builds and tight enough to catch any real change
+const RELATIVE_TOLERANCE: f64 = 1e-9;
+const ABSOLUTE_TOLERANCE: f64 = 1e-15;
+
+/// Statistics estimated from the price fixture in `dir`, the way a run estimates them
+pub fn compute_stats(dir: &str) -> Result<PortfolioStats, QuarsError> {
+    let path = Path::new(dir).join(PRICES_FILE);
+    let data = data::read_csv(&path.to_string_lossy(), ',', '.', true, None, None, None)?;
+    calculate_portfolio_stats(&data, TIMEFRAME, false)
+}
+
+/// Regenerates the expected statistics of `dir` from its price fixture, after a change of the
+/// estimation that is meant to move the numbers
+pub fn update(dir: &str) -> Result<(), QuarsError> {
+    let stats = compute_stats(dir)?;
+    write_atomic(
+        Path::new(dir).join(EXPECTED_FILE),
+        serde_json::to_string_pretty(&stats)?.as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Compares the statistics estimated from the price fixture in `dir` with the expected ones,
+/// returning one line per mismatch, none when they agree
+pub fn check(dir: &str) -> Result<Vec<String>, QuarsError> {
+    let content = std::fs::read_to_string(Path::new(dir).join(EXPECTED_FILE))?;
+    let expected: PortfolioStats = serde_json::from_str(&content)?;
+    let actual = compute_stats(dir)?;
+
+    // Labels and shapes first, the values are only comparable when those agree
+    if actual.assets != expected.assets {
+        return Ok(vec![format!(
+            "assets: {:?}, expected {:?}",
+            actual.assets, expected.assets
+        )]);
+    }
+    if actual.dates != expected.dates {
+        return Ok(vec![format!(
+            "dates: {} from {:?} to {:?}, expected {} from {:?} to {:?}",
+            actual.dates.len(),
+            actual.dates.first(),
+            actual.dates.last(),
+            expected.dates.len(),
+            expected.dates.first(),
+            expected.dates.last()
+        )]);
+    }
+
+    let mut mismatches = Vec::new();
+    compare_vector(
+        "mean_returns",
+        &actual.assets,
+        actual.mean_returns.view(),
+        expected.mean_returns.view(),
+        &mut mismatches,
+    );
+    compare_matrix(
+        "covariance",
+        &actual.assets,
+        &actual.assets,
+        &actual.covariance,
+        &expected.covariance,
+        &mut mismatches,
+    );
+    compare_matrix(
+        "returns_matrix",
+        &actual.assets,
+        &actual.dates,
+        &actual.returns_matrix,
+        &expected.returns_matrix,
+        &mut mismatches,
+    );
+    Ok(mismatches)
+}
+
+fn matches(actual: f64, expected: f64) -> bool {
+    (actual - expected).abs() <= RELATIVE_TOLERANCE * expected.abs() + ABSOLUTE_TOLERANCE
+}
+
+fn compare_vector(
+    name: &str,
+    labels: &[String],
+    actual: ArrayView1<f64>,
+    expected: ArrayView1<f64>,
+    mismatches: &mut Vec<String>,
+) {
+    if actual.len() != expected.len() {
+        mismatches.push(format!(
+            "{}: {} values, expected {}",
+            name,
+            actual.len(),
+            expected.len()
+        ));
+        return;
+    }
+    for (i, (&a, &e)) in actual.iter().zip(expected.iter()).enumerate() {
+        if !matches(a, e) {
+            mismatches.push(format!(
+                "{}[{}]: {:e}, expected {:e}",
+                name, labels[i], a, e
+            ));
+        }
+    }
+}
+
+fn compare_matrix(
+    name: &str,
+    row_labels: &[String],
+    column_labels: &[String],
+    actual: &Array2<f64>,
+    expected: &Array2<f64>,
+    mismatches: &mut Vec<String>,
+) {
+    if actual.dim() != expected.dim() {
+        mismatches.push(format!(
+            "{}: shape {:?}, expected {:?}",
+            name,
+            actual.dim(),
+            expected.dim()
+        ));
+        return;
+    }
+    for ((i, j), &a) in actual.indexed_iter() {
+        let e = expected[[i, j]];
+        if !matches(a, e) {
+            mismatches.push(format!(
+                "{}[{}, {}]: {:e}, expected {:e}",
+                name, row_labels[i], column_labels[j], a, e
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run with UPDATE_GOLDEN=1 to regenerate the expected statistics after a change that is
+    // meant to move the numbers, and commit them with the change
+    #[test]
+    fn statistics_match_the_golden_file() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join(GOLDEN_DIR);
+        let dir = dir.to_string_lossy();
+        if std::env::var_os(UPDATE_VAR).is_some() {
+            update(&dir).unwrap();
+            return;
+        }
+        let mismatches = check(&dir).unwrap();
+        assert!(
+            mismatches.is_empty(),
+            "{} statistics differ from the golden file in {}:\n{}",
+            mismatches.len(),
+            dir,
+            mismatches.join("\n")
+        );
+    }
+}
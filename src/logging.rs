@@ -0,0 +1,50 @@
+use std::fs::File;
+use std::io::IsTerminal;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::fmt;
+use tracing_subscriber::prelude::*;
+
+use crate::config::General;
+use crate::error::QuarsError;
+
+// The console keeps to warnings and errors when the events go to a log file, next to the reports
+const CONSOLE_LEVEL_WITH_FILE: LevelFilter = LevelFilter::WARN;
+
+/// Installs the logger of the run. Events at `log_level` and above are printed to stderr, or
+/// written as JSON lines to `log_file` when set, which leaves only the warnings and errors on
+/// the console.
+pub fn init(general: &General) -> Result<(), QuarsError> {
+    let level = LevelFilter::from_str(&general.log_level).map_err(|_| {
+        QuarsError::Config(format!(
+            "Unknown log_level '{}', use off, error, warn, info, debug or trace",
+            general.log_level
+        ))
+    })?;
+    let file = match &general.log_file {
+        Some(path) => Some(
+            fmt::layer()
+                .json()
+                .with_writer(Mutex::new(File::create(path)?))
+                .with_filter(level),
+        ),
+        None => None,
+    };
+    let console_level = match file {
+        Some(_) => level.min(CONSOLE_LEVEL_WITH_FILE),
+        None => level,
+    };
+    let console = fmt::layer()
+        .with_writer(std::io::stderr)
+        .with_ansi(std::io::stderr().is_terminal())
+        .without_time()
+        .with_target(false)
+        .with_filter(console_level);
+    tracing_subscriber::registry()
+        .with(console)
+        .with(file)
+        .try_init()
+        .map_err(|e| QuarsError::Config(format!("Failed to install the logger: {}", e)))
+}
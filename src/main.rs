@@ -1,69 +1,1723 @@
-use core::error;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::{Instant, SystemTime};
 
 use chrono::Local;
+use error::QuarsError;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use tracing::{info, info_span, warn, Instrument};
 use utils::write_to_csv;
 
+mod bundle;
+mod cli;
 mod config;
 mod data;
+mod error;
+mod gnuplot;
+#[cfg(test)]
+mod golden;
+mod logging;
+mod monitor;
 mod optimization;
 mod portfolio;
+mod report;
 mod utils;
 mod visualization;
 mod math;
 
+// Number of simulated paths behind the Monte Carlo VaR
+const MONTE_CARLO_PATHS: usize = 10_000;
+
+// Exit code of a run cancelled with Ctrl-C, the shell convention for SIGINT
+const EXIT_CANCELLED: i32 = 130;
+
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn error::Error>> {
-    let settings = config::Settings::new().expect("Failed to load configuration");
-    let historical_data = data::fetch_data(&settings).await.expect("Data fetch error");
-    let today = Local::now().format("%Y-%m-%d").to_string();
-    let output_path = format!(
-        "data/raw/{}/hist_data_{}.csv",
-        today, settings.data_api.source
-    );
-    write_to_csv(&historical_data, &output_path).expect("Failed to write CSV");
+async fn main() -> Result<(), QuarsError> {
+    // The first Ctrl-C lets the running stage finish its writes and stop at the next
+    // checkpoint, a second one exits right away
+    tokio::spawn(async {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Interrupted, stopping after the current step (Ctrl-C again to force)");
+            utils::request_cancellation();
+        }
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(EXIT_CANCELLED);
+        }
+    });
+    let args = cli::Args::parse(std::env::args().skip(1))?;
+    match run(&args).await {
+        Err(e @ QuarsError::Cancelled(_)) => {
+            eprintln!("{}", e);
+            std::process::exit(EXIT_CANCELLED);
+        }
+        Ok(()) if args.timings => {
+            report::print_table("Timings", &report::timings_table(&utils::timings()));
+            Ok(())
+        }
+        result => result,
+    }
+}
 
-    // Compute statistics
-    let portfolio_stats = portfolio::calculate_portfolio_stats(&historical_data)
-        .expect("Error computing portfolio stats");
+async fn run(args: &cli::Args) -> Result<(), QuarsError> {
+    let run_start = Instant::now();
+    // --stats-only caches the statistics without optimizing, --from-stats <dir> runs the
+    // optimizer alone on statistics cached that way
+    let stats_only = args.stats_only;
+    let from_stats = &args.from_stats;
+    // --export-bundle archives the resolved config, the prices, the statistics and the outputs
+    // of the run under bundles/, --run-bundle <zip> replays such an archive offline
+    let bundle = match (args.export_bundle, &args.run_bundle) {
+        (true, Some(_)) => {
+            return Err(QuarsError::Config(
+                "--export-bundle and --run-bundle cannot be combined".to_string(),
+            ))
+        }
+        (true, None) => Some(bundle::Mode::export()),
+        (false, Some(path)) => Some(bundle::Mode::Replay(bundle::extract(path)?)),
+        (false, None) => None,
+    };
+    if bundle.is_some() && (stats_only || from_stats.is_some() || args.compare_methods) {
+        return Err(QuarsError::Config(
+            "bundles cover a full run from the prices, without --stats-only, --from-stats or --compare-methods"
+                .to_string(),
+        ));
+    }
+
+    let mut settings = match &bundle {
+        Some(bundle::Mode::Replay(dir)) => {
+            println!("Replaying the bundle extracted to {}", dir.display());
+            bundle::load_settings(dir)?
+        }
+        _ => config::Settings::new()?,
+    };
+    logging::init(&settings.general)?;
+    // The configured tickers name assets of the data, whose tickers are normalized
+    if let Some(normalization) = &settings.ticker_normalization {
+        for ticker in [
+            &mut settings.general.benchmark_ticker,
+            &mut settings.portofolio_optimization.cash_ticker,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *ticker = data::normalize_ticker(ticker, normalization);
+        }
+    }
+    if args.explain {
+        settings.portofolio_optimization.explain = true;
+    }
+    settings.portofolio_optimization.periods_per_year =
+        utils::periods_per_year(&settings.data_api.timeframe)?;
+    if let Some(as_of) = &settings.data_api.as_of_date {
+        println!("As of {}", as_of);
+        settings.visualization.as_of_date = Some(as_of.clone());
+    }
+    if let Some(threads) = settings.general.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(|e| {
+                QuarsError::Config(format!("Failed to start {} threads: {}", threads, e))
+            })?;
+    }
+    // A single RNG drives every random draw, so a run is reproduced from its seed
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    println!("Seed = {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let export_dir = bundle.as_ref().and_then(bundle::Mode::export_dir);
+    if let Some(dir) = export_dir {
+        bundle::write_config(&settings, seed, dir)?;
+    }
+
+    let (mut portfolio_stats, benchmark_returns, fallback_data_date, latest_prices) =
+        match &from_stats {
+            Some(dir) => {
+                println!("Loading cached statistics from {}", dir);
+                let (stats, cached_benchmark) = utils::read_stats(dir)?;
+                // The benchmark was split off the cached universe, its returns cached with it
+                let benchmark = match (&settings.general.benchmark_ticker, cached_benchmark) {
+                    (Some(ticker), Some(cached)) if &cached.0 == ticker => Some(cached),
+                    (Some(ticker), _) => {
+                        return Err(QuarsError::Config(format!(
+                            "The cached statistics in {} hold no returns of the benchmark {}, cache them with it configured",
+                            dir, ticker
+                        )))
+                    }
+                    (None, _) => None,
+                };
+                // Cached statistics cannot be truncated, they must already end by the date
+                if let (Some(as_of), Some(last)) =
+                    (&settings.data_api.as_of_date, stats.dates.last())
+                {
+                    if last
+                        .get(..as_of.len())
+                        .is_some_and(|day| day > as_of.as_str())
+                    {
+                        return Err(QuarsError::Config(format!(
+                            "The cached statistics in {} run to {}, after as_of_date {}",
+                            dir, last, as_of
+                        )));
+                    }
+                }
+                (stats, benchmark, None, HashMap::new())
+            }
+            None => estimate_stats(&settings, &mut rng, export_dir).await?,
+        };
+    utils::check_cancelled()?;
+    if let Some(dir) = export_dir {
+        bundle::write_bundle_stats(&portfolio_stats, benchmark_returns.as_ref(), dir)?;
+    }
+    if stats_only {
+        let dir = format!("data/raw/{}", Local::now().format("%Y-%m-%d"));
+        utils::write_stats(&portfolio_stats, benchmark_returns.as_ref(), &dir)?;
+        println!("Statistics saved to {}", dir);
+        return Ok(());
+    }
+
+    let periods_per_year = settings.portofolio_optimization.periods_per_year;
+    let compounding =
+        optimization::Compounding::from_config(&settings.portofolio_optimization.compounding)?;
+
+    // Synthetic assets, ranked and optimized like the fetched ones
+    if !settings.synthetic_assets.is_empty() {
+        portfolio_stats.add_synthetic_assets(&settings.synthetic_assets)?;
+        for synthetic in &settings.synthetic_assets {
+            let mut constituents: Vec<(&String, &f64)> = synthetic.weights.iter().collect();
+            constituents.sort_by(|a, b| a.0.cmp(b.0));
+            let mut combination = String::new();
+            for (i, (ticker, weight)) in constituents.into_iter().enumerate() {
+                combination += &match (i, *weight < 0.0) {
+                    (0, _) => format!("{}×{}", weight, ticker),
+                    (_, true) => format!(" - {}×{}", -weight, ticker),
+                    (_, false) => format!(" + {}×{}", weight, ticker),
+                };
+            }
+            println!("Synthetic asset {} = {}", synthetic.name, combination);
+        }
+    }
+
+    // Optional pre-selection of the top assets by a metric, the cash asset always kept
+    let po = &settings.portofolio_optimization;
+    match (&po.preselect_by, po.preselect_n) {
+        (Some(metric), Some(n)) => {
+            let n_assets = portfolio_stats.assets.len();
+            let (stats, selected) = portfolio_stats.preselect(
+                portfolio::Preselection::from_config(metric)?,
+                n,
+                optimization::annual_to_period_rate(
+                    po.risk_free_rate,
+                    periods_per_year,
+                    compounding,
+                ),
+                &po.cash_ticker
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>(),
+            )?;
+            portfolio_stats = stats;
+            println!(
+                "Pre-selected {} of {} assets by {}: {}",
+                selected.len(),
+                n_assets,
+                metric,
+                selected
+                    .iter()
+                    .map(|(asset, score)| format!("{} ({:.4})", asset, score))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        (None, None) => {}
+        _ => {
+            return Err(QuarsError::Config(
+                "preselect_by and preselect_n must be set together".to_string(),
+            ))
+        }
+    }
+
+    // Optional user views on expected returns, replacing the historical means
+    let expected_returns = match (&po.expected_returns, &po.expected_returns_file) {
+        (Some(expected), _) => Some(expected.clone()),
+        (None, Some(path)) => Some(data::read_expected_returns(path)?),
+        (None, None) => None,
+    };
+    if let Some(expected) = expected_returns {
+        portfolio_stats.override_mean_returns(&expected, periods_per_year)?;
+    }
+    // Optional externally supplied covariance, replacing the estimated one
+    if let Some(path) = &po.covariance_file {
+        if po.excess_over_benchmark || po.resamples.is_some() {
+            return Err(QuarsError::Config(
+                "covariance_file cannot be combined with excess_over_benchmark or resamples, which re-estimate the covariance"
+                    .to_string(),
+            ));
+        }
+        let (assets, covariance) = data::read_asset_matrix(path)?;
+        portfolio_stats.override_covariance(&assets, &covariance, periods_per_year)?;
+        println!("Covariance loaded from {}", path);
+    }
+    // Optional shrinkage of the historical means, after the covariance it is weighed with
+    if let Some(target) = &po.mean_shrinkage {
+        if po.expected_returns.is_some() || po.expected_returns_file.is_some() {
+            return Err(QuarsError::Config(
+                "mean_shrinkage applies to the historical means, which expected returns replace"
+                    .to_string(),
+            ));
+        }
+        let (intensity, target_mean) = portfolio_stats.shrink_mean_returns(
+            portfolio::MeanShrinkage::from_config(target)?,
+            po.mean_shrinkage_intensity,
+        )?;
+        println!(
+            "Mean returns shrunk by {} toward the {} target of {} annualized",
+            report::percent(intensity),
+            target,
+            report::percent(target_mean * periods_per_year)
+        );
+    }
+    // Optional tilt of the means toward the trailing momentum
+    if let Some(tilt) = &settings.momentum_tilt {
+        if po.expected_returns.is_some() || po.expected_returns_file.is_some() {
+            return Err(QuarsError::Config(
+                "momentum_tilt applies to the historical means, which expected returns replace"
+                    .to_string(),
+            ));
+        }
+        portfolio_stats.apply_momentum_tilt(tilt.weight, tilt.lookback)?;
+        println!(
+            "Mean returns tilted {} toward the momentum of the last {} periods",
+            report::percent(tilt.weight),
+            tilt.lookback
+        );
+    }
+
+    // Optionally optimize the alpha over the benchmark. The risk-free rate then acts as a
+    // hurdle on the alpha, the tangency maximizes (alpha - r_f) / tracking error, so a zero
+    // rate maximizes the information ratio. Risk reports below stay on the raw returns.
+    let mut optimizer_stats = if po.excess_over_benchmark {
+        let (ticker, _, returns) = benchmark_returns.as_ref().ok_or_else(|| {
+            QuarsError::Config("excess_over_benchmark requires a benchmark_ticker".to_string())
+        })?;
+        println!(
+            "Optimizing returns in excess of {} (risk-free rate {:.2}% as alpha hurdle)",
+            ticker,
+            po.risk_free_rate * 100.0
+        );
+        Some(portfolio_stats.excess_over(returns)?)
+    } else {
+        None
+    };
+    // Optional ridge on the covariance the optimizer inverts, the risk reports keep the
+    // estimated one
+    if po.covariance_ridge != 0.0 {
+        if po.resamples.is_some() {
+            return Err(QuarsError::Config(
+                "covariance_ridge cannot be combined with resamples, which re-estimate the covariance"
+                    .to_string(),
+            ));
+        }
+        optimizer_stats
+            .get_or_insert_with(|| portfolio_stats.clone())
+            .add_covariance_ridge(po.covariance_ridge / periods_per_year)?;
+    }
+
+    if po.frontier_points < 2 {
+        return Err(QuarsError::Config(format!(
+            "frontier_points must be at least 2, got {}",
+            po.frontier_points
+        )));
+    }
 
     //Run optimization
-    let results =
-        optimization::optimize_portfolio(&portfolio_stats, 50, &settings.portofolio_optimization)
-            .expect("Error in Markowitz optimization");
+    let optimization_stats = optimizer_stats.as_ref().unwrap_or(&portfolio_stats);
+    // --compare-methods runs every optimization method on these statistics instead
+    if args.compare_methods {
+        return compare_methods(
+            &portfolio_stats,
+            optimization_stats,
+            &settings,
+            periods_per_year,
+        );
+    }
+    let results = utils::timed("optimization", || match po.resamples {
+        Some(n_resamples) => optimization::resampled_frontier(
+            optimization_stats,
+            po.frontier_points,
+            &settings.portofolio_optimization,
+            n_resamples,
+            &mut rng,
+        ),
+        None => optimization::optimize_portfolio(
+            optimization_stats,
+            po.frontier_points,
+            &settings.portofolio_optimization,
+        ),
+    });
+    // A cancelled resampling surfaces as an error, which is not an optimization failure
+    utils::check_cancelled()?;
+    let mut results = results?;
+    // Per-period rate the CAL starts at, the cash mean when a cash asset is configured
+    let risk_free = optimization::risk_free_return(optimization_stats, po)?;
+    info!(
+        sub_method = %po.sub_method,
+        points = results.frontier.len(),
+        "frontier optimized"
+    );
+    // Before the CAL constraints, which apply to the portfolio as traded
+    if let Some(threshold) = po.min_trade_weight {
+        let dropped = results.apply_min_trade_weight(threshold, optimization_stats, risk_free)?;
+        if !dropped.is_empty() {
+            println!(
+                "min_trade_weight dropped {} asset(s) under {}: {}",
+                dropped.len(),
+                report::percent(threshold),
+                dropped
+                    .iter()
+                    .map(|(i, weight)| format!(
+                        "{} ({})",
+                        portfolio_stats.assets[*i],
+                        report::percent(*weight)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
+    // Before the caps, which apply to the returns net of the borrowing cost
+    if let Some(borrowing_rate) = po.borrowing_rate {
+        if po.cash_ticker.is_some() {
+            return Err(QuarsError::Config(
+                "borrowing_rate applies to the risk_free_rate, not to a cash_ticker".to_string(),
+            ));
+        }
+        results.apply_borrowing_rate(
+            optimization::annual_to_period_rate(po.risk_free_rate, periods_per_year, compounding),
+            optimization::annual_to_period_rate(borrowing_rate, periods_per_year, compounding),
+        )?;
+    }
+    results.truncate_frontier(
+        po.max_frontier_return,
+        po.max_frontier_vol,
+        periods_per_year,
+    )?;
+    // Portfolio held when min_cash_weight raises the cash allocation, a point of the CAL
+    let mut cash_floor = None;
+    if let Some(min_cash_weight) = po.min_cash_weight {
+        cash_floor = results.apply_min_cash_weight(min_cash_weight, risk_free)?;
+        if cash_floor.is_some() {
+            println!(
+                "Binding constraint: min_cash_weight caps the risky exposure at {}",
+                report::percent(1.0 - min_cash_weight)
+            );
+        }
+    }
 
     // Show tangency portfolio
-    println!(
-        "Tangency Portfolio Weights = {:?}",
-        results.optimal_risky_portfolio
-    );
-    println!(
-        "Tangency Expected Return = {:.4}",
-        results.optimal_risky_return
+    let weights = &results.optimal_risky_portfolio;
+    report::print_table(
+        "Tangency Portfolio",
+        &report::asset_table(&portfolio_stats, weights, periods_per_year),
     );
-    println!("Tangency Std Dev = {:.4}", results.optimal_risky_std);
-    println!("Max Sharpe = {:.4}", results.max_sharpe);
+    let mut summary = vec![
+        (
+            "Expected Return (ann.)".to_string(),
+            report::percent(results.optimal_risky_return * periods_per_year),
+        ),
+        (
+            "Volatility (ann.)".to_string(),
+            report::percent(results.optimal_risky_std * periods_per_year.sqrt()),
+        ),
+        (
+            "Max Sharpe (ann.)".to_string(),
+            report::number(results.max_sharpe * periods_per_year.sqrt()),
+        ),
+    ];
+    if let Some(blend) = &results.near_optimal {
+        summary.push((
+            "Near-Optimal Blend Alpha".to_string(),
+            report::number(blend.alpha),
+        ));
+        summary.push((
+            "Near-Optimal Utility (floor)".to_string(),
+            format!("{:.6e} ({:.6e})", blend.utility, blend.required_utility),
+        ));
+    }
+    if let Some(certainty_equivalent) = results.certainty_equivalent {
+        summary.push((
+            "Certainty Equivalent (ann.)".to_string(),
+            report::percent(certainty_equivalent * periods_per_year),
+        ));
+    }
+    if let Some(gamma) = optimization::implied_risk_aversion(optimization_stats, weights, risk_free)
+    {
+        summary.push(("Implied Risk Aversion".to_string(), report::number(gamma)));
+    }
+    if let Some(div_ratio) = portfolio::diversification_ratio(weights, &portfolio_stats.covariance)
+    {
+        summary.push((
+            "Diversification Ratio".to_string(),
+            report::number(div_ratio),
+        ));
+    }
+    if let Some(cash_ticker) = &settings.portofolio_optimization.cash_ticker {
+        summary.push((
+            format!("Cash ({}) Weight", cash_ticker),
+            report::percent(results.risk_free_weight),
+        ));
+    } else if po.min_cash_weight.is_some() {
+        summary.push((
+            "Cash Weight".to_string(),
+            report::percent(results.risk_free_weight),
+        ));
+    }
+    if let Some(held) = &cash_floor {
+        summary.extend([
+            (
+                "Return at the Cash Floor (ann.)".to_string(),
+                report::percent(held.expected_return * periods_per_year),
+            ),
+            (
+                "Volatility at the Cash Floor (ann.)".to_string(),
+                report::percent(held.portfolio_std * periods_per_year.sqrt()),
+            ),
+        ]);
+        if let Some(sharpe) =
+            optimization::sharpe_ratio(held.expected_return, risk_free, held.portfolio_std)
+        {
+            summary.push((
+                "Sharpe at the Cash Floor (ann.)".to_string(),
+                report::number(sharpe * periods_per_year.sqrt()),
+            ));
+        }
+    }
 
-    // Plot frontier
-    visualization::plot_efficient_frontier(
-        &results,
-        settings.portofolio_optimization.risk_free_rate
-    )?;
-    // Plot portofolio weights
-    visualization::plot_portfolio(&settings.data_api.tickers, &results.optimal_risky_portfolio)?;
+    // Exposure of the tangency portfolio and of the most levered frontier point
+    let exposure = portfolio::exposure_metrics(weights);
+    summary.extend([
+        (
+            "Gross Exposure".to_string(),
+            report::percent(exposure.gross),
+        ),
+        ("Net Exposure".to_string(), report::percent(exposure.net)),
+        ("Long Exposure".to_string(), report::percent(exposure.long)),
+        (
+            "Short Exposure".to_string(),
+            report::percent(exposure.short),
+        ),
+        ("Leverage".to_string(), format!("{:.2}x", exposure.leverage)),
+    ]);
+    if let Some(last_point) = results.frontier.last() {
+        summary.push((
+            "Max Frontier Leverage".to_string(),
+            format!("{:.2}x", last_point.exposure_metrics().leverage),
+        ));
+    }
+    report::print_table("Tangency Summary", &report::metrics_table(summary));
+    // --monitor follows the tangency portfolio on live prices instead of the reports
+    if args.monitor {
+        return monitor::run(&settings, &portfolio_stats.assets, weights).await;
+    }
+
+    // Overlay tilting the tangency weights toward externally scored assets
+    if let Some(tilt) = &settings.score_tilt {
+        let lookup: HashMap<String, f64> = tilt
+            .scores
+            .iter()
+            .map(|(asset, score)| (asset.to_uppercase(), *score))
+            .collect();
+        let scores: Vec<f64> = portfolio_stats
+            .assets
+            .iter()
+            .map(|asset| lookup.get(&asset.to_uppercase()).copied().unwrap_or(0.0))
+            .collect();
+        if portfolio_stats
+            .assets
+            .iter()
+            .filter(|asset| lookup.contains_key(&asset.to_uppercase()))
+            .count()
+            < lookup.len()
+        {
+            warn!("scores given for assets that are not in the data are ignored");
+        }
+        let tilted = portfolio::score_tilt(weights, &scores, tilt.strength)?;
+        let rows = portfolio_stats
+            .assets
+            .iter()
+            .enumerate()
+            .map(|(i, asset)| {
+                vec![
+                    asset.clone(),
+                    report::number(scores[i]),
+                    report::percent(weights[i]),
+                    report::percent(tilted[i]),
+                ]
+            })
+            .collect();
+        report::print_table(
+            &format!("Score Tilt (strength {})", tilt.strength),
+            &report::table(&["Asset", "Score", "Optimized", "Tilted"], rows),
+        );
+        let tilted = ndarray::Array1::from(tilted);
+        let tilted_return = optimization_stats.mean_returns.dot(&tilted) * periods_per_year;
+        let tilted_std = portfolio::portfolio_variance(&tilted, &optimization_stats.covariance)?
+            .sqrt()
+            * periods_per_year.sqrt();
+        let optimized_return = results.optimal_risky_return * periods_per_year;
+        let optimized_std = results.optimal_risky_std * periods_per_year.sqrt();
+        report::print_table(
+            "Score Tilt Effect",
+            &report::table(
+                &["", "Optimized", "Tilted", "Change"],
+                vec![
+                    vec![
+                        "Expected Return (ann.)".to_string(),
+                        report::percent(optimized_return),
+                        report::percent(tilted_return),
+                        report::percent(tilted_return - optimized_return),
+                    ],
+                    vec![
+                        "Volatility (ann.)".to_string(),
+                        report::percent(optimized_std),
+                        report::percent(tilted_std),
+                        report::percent(tilted_std - optimized_std),
+                    ],
+                ],
+            ),
+        );
+    }
+
+    // Weight response of the risk-adjusted portfolio to each expected return estimate
+    if let Some(bump) = po.sensitivity_bump {
+        match optimization::MvoOptMethod::from_config(po)?.tau() {
+            Some(tau) => {
+                let sensitivities = optimization::return_sensitivity(
+                    optimization_stats,
+                    optimization::annual_to_period_rate(
+                        po.risk_free_rate,
+                        periods_per_year,
+                        compounding,
+                    ),
+                    tau,
+                    bump / periods_per_year,
+                )?;
+                let rows = sensitivities
+                    .iter()
+                    .map(|s| {
+                        vec![
+                            s.asset.clone(),
+                            report::percent(s.weight),
+                            report::percent(s.weight_up),
+                            report::percent(s.weight_down),
+                            // Weight change per unit of annualized return
+                            report::number(s.gradient / periods_per_year),
+                        ]
+                    })
+                    .collect();
+                report::print_table(
+                    &format!(
+                        "Return Sensitivity (risk-adjusted, tau {}, expected returns ±{} ann.)",
+                        tau,
+                        report::percent(bump)
+                    ),
+                    &report::table(
+                        &[
+                            "Asset",
+                            "Weight",
+                            "Weight (+)",
+                            "Weight (-)",
+                            "dw/dμ (ann.)",
+                        ],
+                        rows,
+                    ),
+                );
+            }
+            None => {
+                warn!("the return sensitivity needs a risk aversion, use a tau-based sub_method")
+            }
+        }
+    }
+
+    // Allocation response of the risk-adjusted portfolio to the risk-free rate assumption
+    if let Some(bump) = po.rate_sensitivity_bump {
+        match optimization::MvoOptMethod::from_config(po)?.tau() {
+            Some(tau) => {
+                let sensitivity = optimization::rate_sensitivity(
+                    optimization_stats,
+                    po.risk_free_rate,
+                    periods_per_year,
+                    compounding,
+                    tau,
+                    bump,
+                )?;
+                let scenario_row = |name: &str, s: &optimization::RateScenario| {
+                    vec![
+                        name.to_string(),
+                        report::percent(s.risk_free_rate),
+                        report::percent(s.risky_return * periods_per_year),
+                        report::number(s.sharpe_ratio * periods_per_year.sqrt()),
+                        report::percent(s.risky_share),
+                        report::percent(s.expected_return * periods_per_year),
+                    ]
+                };
+                let rows = vec![
+                    scenario_row("Rate (-)", &sensitivity.down),
+                    scenario_row("Base", &sensitivity.base),
+                    scenario_row("Rate (+)", &sensitivity.up),
+                    vec![
+                        "d/dr".to_string(),
+                        "-".to_string(),
+                        "-".to_string(),
+                        "-".to_string(),
+                        report::number(sensitivity.risky_share_gradient),
+                        // Annualized return change per unit of annual rate
+                        report::number(sensitivity.return_gradient * periods_per_year),
+                    ],
+                ];
+                report::print_table(
+                    &format!(
+                        "Rate Sensitivity (risk-adjusted, tau {}, risk-free rate ±{})",
+                        tau,
+                        report::percent(bump)
+                    ),
+                    &report::table(
+                        &[
+                            "Scenario",
+                            "Risk-Free Rate",
+                            "Risky Return (ann.)",
+                            "Sharpe (ann.)",
+                            "Risky Share",
+                            "Expected Return (ann.)",
+                        ],
+                        rows,
+                    ),
+                );
+                let rows = optimization_stats
+                    .assets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, asset)| {
+                        vec![
+                            asset.clone(),
+                            report::percent(sensitivity.down.weights[i]),
+                            report::percent(sensitivity.base.weights[i]),
+                            report::percent(sensitivity.up.weights[i]),
+                            report::number(sensitivity.weight_gradients[i]),
+                        ]
+                    })
+                    .collect();
+                report::print_table(
+                    "Rate Sensitivity of the Risky Weights",
+                    &report::table(
+                        &["Asset", "Weight (-)", "Weight", "Weight (+)", "dw/dr"],
+                        rows,
+                    ),
+                );
+            }
+            None => {
+                warn!("the rate sensitivity needs a risk aversion, use a tau-based sub_method")
+            }
+        }
+    }
+
+    // Return, risk and concentration trade-off around the mean-variance portfolio
+    if let Some(penalties) = &po.concentration_penalties {
+        match optimization::MvoOptMethod::from_config(po)?.tau() {
+            Some(tau) => {
+                let per_period: Vec<f64> = penalties.iter().map(|p| p / periods_per_year).collect();
+                // The τxᵀΣx penalty is the (γ/2)xᵀΣx of the sweep
+                let points = optimization::concentration_frontier(
+                    optimization_stats,
+                    2.0 * tau,
+                    &per_period,
+                )?;
+                let rows = points
+                    .iter()
+                    .map(|pt| {
+                        vec![
+                            report::number(pt.penalty * periods_per_year),
+                            report::percent(pt.expected_return * periods_per_year),
+                            report::percent(pt.portfolio_std * periods_per_year.sqrt()),
+                            report::number(pt.concentration),
+                            if pt.pareto_efficient { "yes" } else { "no" }.to_string(),
+                        ]
+                    })
+                    .collect();
+                report::print_table(
+                    &format!("Concentration Frontier (gamma {})", 2.0 * tau),
+                    &report::table(
+                        &[
+                            "Penalty (ann.)",
+                            "Return (ann.)",
+                            "Volatility (ann.)",
+                            "Concentration",
+                            "Pareto-Efficient",
+                        ],
+                        rows,
+                    ),
+                );
+            }
+            None => warn!(
+                "the concentration frontier needs a risk aversion, use a tau-based sub_method"
+            ),
+        }
+    }
+
+    // Whole (or fractional) share counts of the tangency portfolio for the configured budget
+    if let Some(budget) = po.share_budget {
+        let prices = portfolio_stats
+            .assets
+            .iter()
+            .map(|asset| {
+                latest_prices.get(asset).copied().ok_or_else(|| {
+                    QuarsError::InsufficientData(format!(
+                        "No price for {}, share rounding needs fetched data rather than --from-stats",
+                        asset
+                    ))
+                })
+            })
+            .collect::<Result<Vec<f64>, QuarsError>>()?;
+        let allocation =
+            portfolio::round_to_shares(weights, &prices, budget, po.fractional_shares)?;
+        let rows = portfolio_stats
+            .assets
+            .iter()
+            .enumerate()
+            .map(|(i, asset)| {
+                vec![
+                    asset.clone(),
+                    report::number(prices[i]),
+                    if po.fractional_shares {
+                        report::number(allocation.shares[i])
+                    } else {
+                        format!("{:.0}", allocation.shares[i])
+                    },
+                    report::percent(weights[i]),
+                    report::percent(allocation.realized_weights[i]),
+                ]
+            })
+            .collect();
+        report::print_table(
+            &format!("Share Allocation (budget {})", budget),
+            &report::table(&["Asset", "Price", "Shares", "Target", "Realized"], rows),
+        );
+        let tracking_error = allocation.tracking_error(weights, &portfolio_stats.covariance)?;
+        println!(
+            "Residual cash {} ({}), tracking error to the targets {} annualized",
+            report::number(allocation.residual_cash),
+            report::percent(allocation.residual_cash / budget),
+            report::percent(tracking_error * periods_per_year.sqrt())
+        );
+    }
+
+    // Replication of the configured target weights with the least tracking error
+    if let Some(tracking) = &settings.tracking {
+        let constraints = optimization::TrackingConstraints {
+            min_weight: tracking.min_weight,
+            max_weight: tracking.max_weight,
+            max_assets: tracking.max_assets,
+        };
+        let target_weights = portfolio_stats.weights_from_map(&tracking.target_weights);
+        let tracking_portfolio = utils::timed("optimization", || {
+            // The solution lies close to the targets, which the solver starts from
+            optimization::optimize_min_tracking_error(
+                &portfolio_stats,
+                &target_weights,
+                &constraints,
+                Some(&target_weights),
+            )
+        })?;
+        if po.explain {
+            println!(
+                "[explain] Min tracking error portfolio, {} QP solver iterations",
+                tracking_portfolio.iterations
+            );
+        }
+        let rows = portfolio_stats
+            .assets
+            .iter()
+            .enumerate()
+            .map(|(i, asset)| {
+                vec![
+                    asset.clone(),
+                    report::percent(target_weights[i]),
+                    report::percent(tracking_portfolio.weights[i]),
+                ]
+            })
+            .collect();
+        report::print_table(
+            &format!(
+                "Min Tracking Error Portfolio (tracking error {} annualized)",
+                report::percent(tracking_portfolio.tracking_error * periods_per_year.sqrt())
+            ),
+            &report::table(&["Asset", "Target", "Weight"], rows),
+        );
+        // Returns that make the targets optimal, with the risk aversion the targets imply on
+        // the historical means
+        let risk_free =
+            optimization::annual_to_period_rate(po.risk_free_rate, periods_per_year, compounding);
+        match optimization::implied_risk_aversion(&portfolio_stats, &target_weights, risk_free) {
+            Some(gamma) if gamma > 0.0 => {
+                let implied = optimization::reverse_optimize(
+                    &portfolio_stats.covariance,
+                    &target_weights,
+                    gamma,
+                )?;
+                let rows = portfolio_stats
+                    .assets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, asset)| {
+                        vec![
+                            asset.clone(),
+                            report::percent(target_weights[i]),
+                            report::percent(implied[i] * periods_per_year),
+                            report::percent(
+                                (portfolio_stats.mean_returns[i] - risk_free) * periods_per_year,
+                            ),
+                        ]
+                    })
+                    .collect();
+                report::print_table(
+                    &format!(
+                        "Implied Returns of the Targets (risk aversion {})",
+                        report::number(gamma)
+                    ),
+                    &report::table(
+                        &[
+                            "Asset",
+                            "Target",
+                            "Implied Excess (ann.)",
+                            "Historical Excess (ann.)",
+                        ],
+                        rows,
+                    ),
+                );
+            }
+            _ => warn!("no implied returns, the target weights imply no positive risk aversion"),
+        }
+        if tracking.active_frontier {
+            let active_frontier = optimization::optimize_active_frontier(
+                &portfolio_stats,
+                &target_weights,
+                po.frontier_points,
+            )?;
+            utils::timed("plotting", || {
+                visualization::plot_active_frontier(
+                    &active_frontier,
+                    periods_per_year,
+                    &settings.visualization,
+                )
+            })?;
+        }
+    }
+    if from_stats.is_some() || po.streaming_stats {
+        // Only the optimizer runs on cached statistics, or on streamed ones without returns
+        return write_results(
+            &portfolio_stats,
+            &results,
+            periods_per_year,
+            utils::RunMetadata {
+                seed,
+                fallback_data_date: fallback_data_date.as_deref(),
+                as_of_date: settings.data_api.as_of_date.as_deref(),
+            },
+            run_start,
+            bundle.as_ref(),
+        );
+    }
+    utils::check_cancelled()?;
+
+    // Minimum-variance frontier of the risky assets, drawn under the CAL within the same caps
+    let spacing = optimization::FrontierSpacing::from_config(&po.frontier_spacing)?;
+    let mut risky_frontier = match optimization::min_variance_frontier(
+        optimization_stats,
+        optimization::annual_to_period_rate(po.risk_free_rate, periods_per_year, compounding),
+        po.frontier_points,
+        spacing,
+    ) {
+        Ok(frontier) => frontier,
+        Err(e) => {
+            warn!("minimum-variance frontier not plotted: {}", e);
+            Vec::new()
+        }
+    };
+    let max_return = po
+        .max_frontier_return
+        .map_or(f64::INFINITY, |r| r / periods_per_year);
+    let max_std = po
+        .max_frontier_vol
+        .map_or(f64::INFINITY, |v| v / periods_per_year.sqrt());
+    risky_frontier.retain(|pt| pt.expected_return <= max_return && pt.portfolio_std <= max_std);
+    if po.explain {
+        if let Ok((a, b, c)) = optimization::frontier_constants(optimization_stats) {
+            println!("[explain] Minimum-variance frontier");
+            println!("[explain]   A = 1ᵀΣ⁻¹1 = {:.6e}", a);
+            println!("[explain]   B = μᵀΣ⁻¹1 = {:.6e}", b);
+            println!("[explain]   C = μᵀΣ⁻¹μ = {:.6e}", c);
+            println!(
+                "[explain]   vertex: return B/A = {:.6e}, variance 1/A = {:.6e}",
+                b / a,
+                1.0 / a
+            );
+        }
+    }
+
+    // Frontier portfolios at the requested volatilities, on the CAL and on the risky frontier
+    if let Some(target_vols) = &po.target_vols {
+        let borrowing = po.borrowing_rate.map_or(risk_free, |rate| {
+            optimization::annual_to_period_rate(rate, periods_per_year, compounding)
+        });
+        let volatilities: Vec<f64> = target_vols
+            .iter()
+            .map(|vol| vol / periods_per_year.sqrt())
+            .collect();
+        // The risky frontier starts at the volatility of the minimum-variance portfolio
+        let min_std = optimization::frontier_constants(optimization_stats)
+            .map_or(0.0, |(a, _, _)| (1.0 / a).sqrt());
+        let risky_volatilities: Vec<f64> = volatilities
+            .iter()
+            .copied()
+            .filter(|&std| std >= min_std)
+            .collect();
+        if risky_volatilities.len() < volatilities.len() {
+            warn!(
+                "target volatilities below the {} of the minimum-variance portfolio are left out of the minimum-variance frontier",
+                report::percent(min_std * periods_per_year.sqrt())
+            );
+        }
+        let lines = [
+            (
+                "Capital Allocation Line at Target Volatilities",
+                optimization::cal_points_at_volatilities(
+                    &results,
+                    risk_free,
+                    borrowing,
+                    &volatilities,
+                ),
+            ),
+            (
+                "Minimum-Variance Frontier at Target Volatilities",
+                optimization::min_variance_points_at_volatilities(
+                    optimization_stats,
+                    risk_free,
+                    &risky_volatilities,
+                ),
+            ),
+        ];
+        for (title, points) in lines {
+            let points = match points {
+                Ok(points) => points,
+                Err(e) => {
+                    warn!("{} not reported: {}", title.to_lowercase(), e);
+                    continue;
+                }
+            };
+            if points.is_empty() {
+                continue;
+            }
+            let mut headers = vec!["Target Volatility (ann.)".to_string()];
+            headers.extend(
+                points
+                    .iter()
+                    .map(|pt| report::percent(pt.portfolio_std * periods_per_year.sqrt())),
+            );
+            let headers: Vec<&str> = headers.iter().map(String::as_str).collect();
+            let mut rows: Vec<Vec<String>> = optimization_stats
+                .assets
+                .iter()
+                .enumerate()
+                .map(|(i, asset)| {
+                    let mut row = vec![asset.clone()];
+                    row.extend(points.iter().map(|pt| report::percent(pt.risky_weights[i])));
+                    row
+                })
+                .collect();
+            for (name, values) in [
+                (
+                    "Risk-Free",
+                    points
+                        .iter()
+                        .map(|pt| pt.risk_free_weight)
+                        .collect::<Vec<_>>(),
+                ),
+                (
+                    "Return (ann.)",
+                    points
+                        .iter()
+                        .map(|pt| pt.expected_return * periods_per_year)
+                        .collect(),
+                ),
+            ] {
+                let mut row = vec![name.to_string()];
+                row.extend(values.into_iter().map(report::percent));
+                rows.push(row);
+            }
+            report::print_table(title, &report::table(&headers, rows));
+        }
+    }
+
+    // Reference portfolios, reported and marked on the frontier plot
+    let special = match optimization::optimize_all_special(
+        optimization_stats,
+        optimization::annual_to_period_rate(po.risk_free_rate, periods_per_year, compounding),
+    ) {
+        Ok(special) => {
+            let rows = special
+                .named()
+                .into_iter()
+                .map(|(name, pt)| {
+                    vec![
+                        name.to_string(),
+                        report::percent(pt.expected_return * periods_per_year),
+                        report::percent(pt.portfolio_std * periods_per_year.sqrt()),
+                        pt.sharpe_ratio.map_or("-".to_string(), |sharpe| {
+                            report::number(sharpe * periods_per_year.sqrt())
+                        }),
+                        pt.diversification_ratio
+                            .map_or("-".to_string(), report::number),
+                    ]
+                })
+                .collect();
+            report::print_table(
+                "Special Portfolios",
+                &report::table(
+                    &[
+                        "Portfolio",
+                        "Return (ann.)",
+                        "Volatility (ann.)",
+                        "Sharpe (ann.)",
+                        "Diversification Ratio",
+                    ],
+                    rows,
+                ),
+            );
+            Some(special)
+        }
+        Err(e) => {
+            warn!("special portfolios not computed: {}", e);
+            None
+        }
+    };
+
+    let rates = visualization::CalRates {
+        lending: risk_free,
+        borrowing: po.borrowing_rate.map_or(risk_free, |rate| {
+            optimization::annual_to_period_rate(rate, periods_per_year, compounding)
+        }),
+    };
+    utils::timed("plotting", || {
+        // Plot frontier
+        visualization::plot_efficient_frontier(
+            "efficient_frontier.png",
+            &results,
+            &risky_frontier,
+            special.as_ref(),
+            rates,
+            Some(&portfolio_stats.covariance),
+            periods_per_year,
+            &settings.visualization,
+        )?;
+        if settings.visualization.gnuplot {
+            gnuplot::export(
+                &results,
+                &portfolio_stats.assets,
+                rates,
+                periods_per_year,
+                &settings.visualization,
+            )?;
+        }
+        // Plot portofolio weights
+        visualization::plot_portfolio(
+            &portfolio_stats.assets,
+            &results.optimal_risky_portfolio,
+            &settings.visualization,
+        )
+    })?;
 
     // Compute VaR & CVaR for tangency portfolio
     let tang_returns = portfolio::compute_portfolio_returns(
         &portfolio_stats.returns_matrix,
         &results.optimal_risky_portfolio,
     );
+    utils::write_portfolio_returns_csv(
+        &portfolio_stats.dates,
+        &tang_returns,
+        utils::CsvFormat::returns(&settings.general)?,
+        "portfolio_returns.csv",
+    )?;
     let var_95 = portfolio::portfolio_var(&tang_returns, 0.95);
     let cvar_95 = portfolio::portfolio_cvar(&tang_returns, 0.95);
 
-    println!("VaR(95%) = {:.2}%", var_95 * 100.0);
-    println!("CVaR(95%) = {:.2}%", cvar_95 * 100.0);
+    utils::check_cancelled()?;
+    let mc_var_95 = utils::timed("monte carlo var", || {
+        portfolio::monte_carlo_var(
+            &portfolio_stats,
+            &results.optimal_risky_portfolio,
+            MONTE_CARLO_PATHS,
+            0.95,
+            &mut rng,
+        )
+    })?;
+    // Risk metrics of the tangency portfolio, printed as a table once all are computed
+    let mut risk_metrics = vec![
+        ("VaR(95%)".to_string(), report::Metric::Percent(var_95)),
+        ("CVaR(95%)".to_string(), report::Metric::Percent(cvar_95)),
+        (
+            "Gaussian VaR(95%)".to_string(),
+            report::Metric::Percent(portfolio::gaussian_var(&tang_returns, 0.95)),
+        ),
+        (
+            "Cornish-Fisher VaR(95%)".to_string(),
+            report::Metric::Percent(portfolio::cornish_fisher_var(&tang_returns, 0.95)),
+        ),
+        (
+            "Monte Carlo VaR(95%)".to_string(),
+            report::Metric::Percent(mc_var_95),
+        ),
+        (
+            "Skewness".to_string(),
+            report::Metric::Number(portfolio::skewness(&tang_returns)),
+        ),
+        (
+            "Excess Kurtosis".to_string(),
+            report::Metric::Number(portfolio::excess_kurtosis(&tang_returns)),
+        ),
+    ];
+    // Return distributions compared in a single plot, as (name, returns, VaR, CVaR)
+    let mut distributions = vec![(
+        "Tangency".to_string(),
+        tang_returns.clone(),
+        var_95,
+        cvar_95,
+    )];
+
+    // Decompose VaR into per-asset marginal and component contributions
+    let comp_var = portfolio::component_var(
+        &portfolio_stats.returns_matrix,
+        &results.optimal_risky_portfolio,
+        0.95,
+    )?;
+    let rows = portfolio_stats
+        .assets
+        .iter()
+        .enumerate()
+        .map(|(i, asset)| {
+            vec![
+                asset.clone(),
+                report::number(comp_var.marginal[i]),
+                report::percent(comp_var.component[i]),
+            ]
+        })
+        .collect();
+    report::print_table(
+        &format!(
+            "VaR(95%) Contributions (VaR {} on {})",
+            report::percent(comp_var.var),
+            portfolio_stats.dates[comp_var.scenario]
+        ),
+        &report::table(&["Asset", "Marginal VaR", "Component VaR"], rows),
+    );
+
+    // Attribute the average loss over the CVaR tail scenarios to the assets, against the
+    // CVaR of each position held alone
+    let tail = portfolio::cvar_decomposition(
+        &portfolio_stats.returns_matrix,
+        &results.optimal_risky_portfolio,
+        0.95,
+    )?;
+    let rows = portfolio_stats
+        .assets
+        .iter()
+        .enumerate()
+        .map(|(i, asset)| {
+            vec![
+                asset.clone(),
+                report::percent(tail.contribution[i]),
+                report::percent(tail.contribution[i] / tail.cvar),
+                report::percent(tail.standalone[i]),
+            ]
+        })
+        .collect();
+    report::print_table(
+        &format!(
+            "CVaR(95%) Tail Attribution ({} worst scenarios, CVaR {})",
+            tail.n_scenarios,
+            report::percent(tail.cvar)
+        ),
+        &report::table(&["Asset", "Contribution", "Share", "Standalone CVaR"], rows),
+    );
+    let standalone_sum = tail.cvar - tail.diversification_benefit;
+    if tail.diversified {
+        println!(
+            "Diversification reduces the CVaR(95%) by {}, from {} for the positions held alone",
+            report::percent(tail.diversification_benefit),
+            report::percent(standalone_sum)
+        );
+    } else if tail.subadditive {
+        println!(
+            "No diversification benefit: the CVaR(95%) equals the {} of the positions held alone",
+            report::percent(standalone_sum)
+        );
+    } else {
+        warn!(
+            "CVaR(95%) of {} is worse than the {} of the positions held alone, not subadditive",
+            report::percent(tail.cvar),
+            report::percent(standalone_sum)
+        );
+    }
+
+    // Volatility-targeting overlay on the tangency portfolio
+    if let Some(vol_target) = &settings.volatility_target {
+        let multipliers = portfolio::volatility_target(
+            &tang_returns,
+            vol_target.target_vol,
+            vol_target.window,
+            periods_per_year,
+        );
+        let targeted_returns = portfolio::apply_leverage(&tang_returns, &multipliers);
+        risk_metrics.extend([
+            (
+                "Realized Vol (ann.)".to_string(),
+                report::Metric::Percent(portfolio::realized_volatility(
+                    &tang_returns,
+                    periods_per_year,
+                )),
+            ),
+            (
+                format!(
+                    "Realized Vol Targeting {}",
+                    report::percent(vol_target.target_vol)
+                ),
+                report::Metric::Percent(portfolio::realized_volatility(
+                    &targeted_returns,
+                    periods_per_year,
+                )),
+            ),
+        ]);
+        let targeted_var = portfolio::portfolio_var(&targeted_returns, 0.95);
+        let targeted_cvar = portfolio::portfolio_cvar(&targeted_returns, 0.95);
+        distributions.push((
+            "Volatility Targeted".to_string(),
+            targeted_returns,
+            targeted_var,
+            targeted_cvar,
+        ));
+    }
+
+    if let Some(backtest) = &settings.backtest {
+        let schedule = portfolio::RebalanceSchedule::from_config(backtest)?;
+        let rebalances = portfolio::rebalance_indices(&portfolio_stats.dates, schedule)?;
+        println!(
+            "Rebalance Dates ({:?}) = {} over {} periods",
+            schedule,
+            rebalances.len(),
+            portfolio_stats.dates.len()
+        );
+        if let Some(taus) = &backtest.tau_grid {
+            let objective = optimization::TauObjective::from_config(&backtest.tau_objective)?;
+            let (best, runs) = optimization::select_tau(
+                optimization_stats,
+                &rebalances,
+                backtest.estimation_window,
+                taus,
+                objective,
+                optimization::annual_to_period_rate(
+                    po.risk_free_rate,
+                    periods_per_year,
+                    compounding,
+                ),
+            )?;
+            let rows = runs
+                .iter()
+                .enumerate()
+                .map(|(i, run)| {
+                    vec![
+                        format!("{}{}", run.tau, if i == best { " *" } else { "" }),
+                        report::percent(run.mean_return * periods_per_year),
+                        report::percent(run.volatility * periods_per_year.sqrt()),
+                        run.sharpe_ratio.map_or("-".to_string(), |sharpe| {
+                            report::number(sharpe * periods_per_year.sqrt())
+                        }),
+                        report::percent(run.max_drawdown),
+                    ]
+                })
+                .collect();
+            report::print_table(
+                &format!(
+                    "Walk-Forward Selection of Tau ({} periods estimation window, out of sample over {} periods)",
+                    backtest.estimation_window,
+                    runs[best].returns.len()
+                ),
+                &report::table(
+                    &[
+                        "Tau",
+                        "Return (ann.)",
+                        "Volatility (ann.)",
+                        "Sharpe (ann.)",
+                        "Max Drawdown",
+                    ],
+                    rows,
+                ),
+            );
+            println!(
+                "Walk-forward tau = {} by out-of-sample {}",
+                runs[best].tau, backtest.tau_objective
+            );
+        }
+    }
+
+    risk_metrics.push((
+        "Max Drawdown".to_string(),
+        report::Metric::Percent(portfolio::max_drawdown(&tang_returns)),
+    ));
+    if let Some((ticker, dates, returns)) = &benchmark_returns {
+        let (_, port_aligned, bench_aligned) =
+            portfolio::align_series(&portfolio_stats.dates, &tang_returns, dates, returns);
+        risk_metrics.push((
+            format!("Benchmark ({}) Max Drawdown", ticker),
+            report::Metric::Percent(portfolio::max_drawdown(&bench_aligned)),
+        ));
+        utils::timed("plotting", || {
+            visualization::plot_drawdown_comparison(
+                &portfolio::drawdown_series(&port_aligned),
+                &portfolio::drawdown_series(&bench_aligned),
+                ticker,
+                &settings.visualization,
+            )
+        })?;
+        distributions.push((
+            ticker.clone(),
+            bench_aligned.clone(),
+            portfolio::portfolio_var(&bench_aligned, 0.95),
+            portfolio::portfolio_cvar(&bench_aligned, 0.95),
+        ));
+    }
+
+    report::print_table(
+        "Tangency Risk",
+        &report::metrics_table(
+            risk_metrics
+                .iter()
+                .map(|(name, metric)| (name.clone(), metric.format()))
+                .collect(),
+        ),
+    );
+    #[cfg(feature = "xlsx")]
+    utils::write_results_xlsx(
+        &portfolio_stats,
+        &results,
+        &risk_metrics,
+        periods_per_year,
+        "results.xlsx",
+    )?;
 
-    // Plot portfolio distribution and computed VaR and CVaR
-    visualization::plot_return_distribution(&tang_returns, var_95, cvar_95)
-        .expect("Failed to plot return distribution");
+    utils::timed("plotting", || {
+        // Plot portfolio distribution and computed VaR and CVaR
+        visualization::plot_return_distribution(
+            "portfolio_distribution.png",
+            &tang_returns,
+            var_95,
+            cvar_95,
+            &settings.visualization,
+        )?;
+        if distributions.len() > 1 {
+            visualization::plot_return_distributions(
+                "portfolio_distributions.png",
+                &distributions,
+                &settings.visualization,
+            )?;
+        }
+        Ok::<(), QuarsError>(())
+    })?;
+    write_results(
+        &portfolio_stats,
+        &results,
+        periods_per_year,
+        utils::RunMetadata {
+            seed,
+            fallback_data_date: fallback_data_date.as_deref(),
+            as_of_date: settings.data_api.as_of_date.as_deref(),
+        },
+        run_start,
+        bundle.as_ref(),
+    )
+}
+
+/// Prints one row of tangency metrics per optimization method and overlays their frontiers.
+/// Drawdown and VaR are those of the raw returns, like the reports of a single method.
+fn compare_methods(
+    portfolio_stats: &portfolio::PortfolioStats,
+    optimization_stats: &portfolio::PortfolioStats,
+    settings: &config::Settings,
+    periods_per_year: f64,
+) -> Result<(), QuarsError> {
+    let po = &settings.portofolio_optimization;
+    if po.resamples.is_some() {
+        return Err(QuarsError::Config(
+            "--compare-methods runs each method once, remove resamples".to_string(),
+        ));
+    }
+    let comparison = utils::timed("optimization", || {
+        optimization::compare_methods(optimization_stats, po.frontier_points, po)
+    })?;
+    let rows = comparison
+        .iter()
+        .map(|(method, results)| {
+            let weights = &results.optimal_risky_portfolio;
+            let returns =
+                portfolio::compute_portfolio_returns(&portfolio_stats.returns_matrix, weights);
+            vec![
+                method.to_string(),
+                report::percent(results.optimal_risky_return * periods_per_year),
+                report::percent(results.optimal_risky_std * periods_per_year.sqrt()),
+                report::number(results.max_sharpe * periods_per_year.sqrt()),
+                report::percent(portfolio::max_drawdown(&returns)),
+                report::percent(portfolio::portfolio_var(&returns, 0.95)),
+                report::number(weights.iter().map(|w| w * w).sum::<f64>()),
+            ]
+        })
+        .collect();
+    report::print_table(
+        "Method Comparison",
+        &report::table(
+            &[
+                "Method",
+                "Return (ann.)",
+                "Volatility (ann.)",
+                "Sharpe (ann.)",
+                "Max Drawdown",
+                "VaR 95%",
+                "Concentration",
+            ],
+            rows,
+        ),
+    );
+    visualization::plot_method_comparison(&comparison, periods_per_year, &settings.visualization)
+}
+
+/// Writes the results with the run metadata once the run is done, stage timings included.
+/// An exported bundle is then archived with every output of the run, a replayed one has its
+/// results compared with those it holds.
+fn write_results(
+    stats: &portfolio::PortfolioStats,
+    results: &optimization::OptimizationResults,
+    periods_per_year: f64,
+    run: utils::RunMetadata,
+    run_start: Instant,
+    bundle: Option<&bundle::Mode>,
+) -> Result<(), QuarsError> {
+    utils::record_timing("total", run_start.elapsed());
+    utils::write_results_json(stats, results, periods_per_year, run, "results.json")?;
+    match bundle {
+        Some(bundle::Mode::Export(dir)) => {
+            let path = bundle::archive(dir, SystemTime::now() - run_start.elapsed())?;
+            println!("Run bundled in {}", path.display());
+        }
+        Some(bundle::Mode::Replay(dir)) => {
+            if bundle::check_results(dir)? {
+                println!("Optimization results reproduce the bundle");
+            } else {
+                warn!("optimization results differ from those of the bundle");
+            }
+        }
+        None => {}
+    }
     Ok(())
 }
+
+/// Fetches the data and estimates the statistics of the optimization universe, with the
+/// benchmark returns split off when one is configured, the date of the cached data used
+/// when the fetch fell back to it, and the latest price of each asset
+async fn estimate_stats(
+    settings: &config::Settings,
+    rng: &mut StdRng,
+    bundle_dir: Option<&Path>,
+) -> Result<
+    (
+        portfolio::PortfolioStats,
+        Option<utils::BenchmarkReturns>,
+        Option<String>,
+        HashMap<String, f64>,
+    ),
+    QuarsError,
+> {
+    let fetch_start = Instant::now();
+    let fetched = data::fetch_data(settings, rng)
+        .instrument(info_span!("stage", name = "fetch"))
+        .await;
+    utils::record_timing("fetch", fetch_start.elapsed());
+    utils::check_cancelled()?;
+    let stats_start = Instant::now();
+    let data::FetchedData {
+        data: historical_data,
+        fallback_date,
+    } = fetched?;
+    info!(
+        records = historical_data.len(),
+        source = %settings.general.data_source,
+        "prices fetched"
+    );
+    // Before anything looks at the prices, later ones were not known as of the date
+    let historical_data = match &settings.data_api.as_of_date {
+        Some(as_of) => data::truncate_as_of(historical_data, as_of)?,
+        None => historical_data,
+    };
+    data::validate_historical_data(&historical_data, settings)?;
+    if let Some(dir) = bundle_dir {
+        bundle::write_prices(&historical_data, dir)?;
+    }
+    // The benchmark and the cash asset are kept whatever their price
+    let historical_data = match settings.general.min_average_price {
+        Some(min_price) => {
+            let keep: Vec<&str> = [
+                &settings.general.benchmark_ticker,
+                &settings.portofolio_optimization.cash_ticker,
+            ]
+            .into_iter()
+            .flatten()
+            .map(String::as_str)
+            .collect();
+            let filtered = data::filter_by_price(historical_data, min_price, &keep);
+            if filtered.is_empty() {
+                return Err(QuarsError::InsufficientData(
+                    "min_average_price filtered out every asset.".to_string(),
+                ));
+            }
+            filtered
+        }
+        None => historical_data,
+    };
+    // Cached data is not saved again as today's
+    if fallback_date.is_none() {
+        let today = Local::now().format("%Y-%m-%d").to_string();
+        let output_path = format!(
+            "data/raw/{}/hist_data_{}.csv",
+            today, settings.data_api.source
+        );
+        let format = utils::CsvFormat::prices(&settings.general)?;
+        format.check_precision(&historical_data);
+        write_to_csv(&historical_data, format, &output_path)?;
+    }
+
+    match &settings.general.trading_calendar {
+        Some(calendar) if settings.data_api.timeframe.eq_ignore_ascii_case("daily") => {
+            let calendar =
+                data::calendar::TradingCalendar::from_config(calendar, &settings.general.holidays)?;
+            data::report_gaps(&historical_data, &calendar)?;
+        }
+        None if !settings.general.holidays.is_empty() => {
+            return Err(QuarsError::Config(
+                "holidays need a trading_calendar to be added to".to_string(),
+            ))
+        }
+        _ => {}
+    }
+    // After the cache write, which keeps the data as fetched, and before a carry-forward of
+    // the delistings adds constant prices on purpose
+    let historical_data = data::handle_stale_prices(
+        historical_data,
+        data::StalePolicy::from_config(&settings.general.stale_policy)?,
+        settings.general.max_stale_prices,
+        &settings
+            .portofolio_optimization
+            .cash_ticker
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<_>>(),
+    )?;
+    let historical_data = data::handle_delistings(
+        historical_data,
+        data::DelistingPolicy::from_config(&settings.general.delisting_policy)?,
+        settings.general.delisting_tolerance_days,
+    )?;
+    // Broker-adjusted prices have no split jumps left to find
+    let historical_data = if settings.data_api.use_adjusted {
+        historical_data
+    } else {
+        data::handle_splits(
+            historical_data,
+            data::SplitPolicy::from_config(&settings.general.split_policy)?,
+        )?
+    };
+
+    // Compute statistics
+    let po = &settings.portofolio_optimization;
+    let mut portfolio_stats = if po.streaming_stats {
+        if po.covariance_estimator != "sample"
+            || po.horizon != 1
+            || po.return_lags.is_some()
+            || po.excess_over_benchmark
+            || po.resamples.is_some()
+            || po.sub_method == "min-semivariance"
+            || (po.mean_shrinkage.is_some() && po.mean_shrinkage_intensity.is_none())
+            || settings.momentum_tilt.is_some()
+        {
+            return Err(QuarsError::Config(
+                "streaming_stats keeps no return series, which the realized covariance, horizon, return_lags, excess_over_benchmark, resamples, min-semivariance, the Bayes-Stein shrinkage intensity and momentum_tilt need"
+                    .to_string(),
+            ));
+        }
+        portfolio::calculate_streaming_stats(
+            &historical_data,
+            &settings.data_api.timeframe,
+            settings.general.strict_frequency,
+        )?
+    } else {
+        portfolio::calculate_portfolio_stats(
+            &historical_data,
+            &settings.data_api.timeframe,
+            settings.general.strict_frequency,
+        )?
+    };
+    if let Some(lags) = &settings.portofolio_optimization.return_lags {
+        portfolio_stats = portfolio_stats.lag_returns(lags)?;
+    }
+    match settings
+        .portofolio_optimization
+        .covariance_estimator
+        .as_str()
+    {
+        "sample" => {}
+        "realized" => portfolio_stats
+            .use_realized_covariance(settings.portofolio_optimization.realized_lags)?,
+        other => {
+            return Err(QuarsError::Config(format!(
+                "Unknown covariance estimator: {}",
+                other
+            )))
+        }
+    }
+    if po.horizon != 1 {
+        if po.covariance_estimator != "sample" || po.excess_over_benchmark || po.resamples.is_some()
+        {
+            return Err(QuarsError::Config(
+                "horizon cannot be combined with the realized covariance, excess_over_benchmark or resamples, which estimate on single-period returns"
+                    .to_string(),
+            ));
+        }
+        portfolio_stats.use_horizon_returns(po.horizon)?;
+    }
+
+    // Keep the benchmark out of the optimization universe
+    let benchmark_returns = match &settings.general.benchmark_ticker {
+        Some(ticker) => {
+            let (stats, returns) = portfolio_stats.split_off_asset(ticker)?;
+            portfolio_stats = stats;
+            Some((ticker.clone(), portfolio_stats.dates.clone(), returns))
+        }
+        None => None,
+    };
+    utils::record_timing("statistics", stats_start.elapsed());
+    info!(
+        assets = portfolio_stats.assets.len(),
+        periods = portfolio_stats.dates.len(),
+        "statistics estimated"
+    );
+    Ok((
+        portfolio_stats,
+        benchmark_returns,
+        fallback_date,
+        data::latest_prices(&historical_data),
+    ))
+}
@@ -1 +1,2 @@
-mod optimization;
\ No newline at end of file
+pub mod optimization;
+pub mod statistics;
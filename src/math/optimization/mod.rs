@@ -1,4 +1,136 @@
+use ndarray::{Array1, Array2};
+use crate::error::QuarsError;
+
 // TODO
 pub fn minimize_obj_quadratic_func(params: Vec<f64>) -> f64 {
     0f64
-}
\ No newline at end of file
+}
+
+// Iteration budget and convergence tolerance of the projected gradient solver
+const MAX_ITERATIONS: usize = 50_000;
+const TOLERANCE: f64 = 1e-14;
+
+/// Solution of an iterative solver with the number of iterations it took
+#[derive(Debug)]
+pub struct QpSolution {
+    pub x: Array1<f64>,
+    pub iterations: usize,
+}
+
+/// Minimizes the convex quadratic ½xᵀQx + cᵀx subject to the budget 1ᵀx = 1 and the bounds
+/// lower ≤ x ≤ upper, with accelerated projected gradient descent (FISTA). Q must be
+/// positive semi-definite, its trace bounds the largest eigenvalue and sets the step size.
+/// The iteration starts from `warm_start` (projected onto the constraints) when given, e.g.
+/// the previous solution of a similar problem, and from equal weights otherwise.
+pub fn minimize_budget_qp(
+    q: &Array2<f64>,
+    c: &Array1<f64>,
+    lower: &Array1<f64>,
+    upper: &Array1<f64>,
+    warm_start: Option<&Array1<f64>>,
+) -> Result<QpSolution, QuarsError> {
+    if lower.sum() > 1.0 || upper.sum() < 1.0 {
+        return Err(QuarsError::Infeasible(format!(
+            "Bounds are infeasible with a fully invested budget: lower bounds sum to {}, upper bounds to {}",
+            lower.sum(),
+            upper.sum()
+        )));
+    }
+    let n = c.len();
+    let start = match warm_start {
+        Some(x0) if x0.len() == n => x0.clone(),
+        Some(x0) => {
+            return Err(QuarsError::Config(format!(
+                "Warm start has {} weights, expected {}",
+                x0.len(),
+                n
+            )))
+        }
+        None => Array1::from_elem(n, 1.0 / n as f64),
+    };
+    let mut x = project_budget_box(&start, lower, upper);
+    let trace = q.diag().sum();
+    if trace <= 0.0 {
+        return Ok(QpSolution { x, iterations: 0 });
+    }
+    let step = 1.0 / trace;
+
+    let mut y = x.clone();
+    let mut t: f64 = 1.0;
+    let mut iterations = 0;
+    while iterations < MAX_ITERATIONS {
+        iterations += 1;
+        let gradient = q.dot(&y) + c;
+        let next = project_budget_box(&(&y - &(gradient * step)), lower, upper);
+        let t_next = (1.0 + (1.0 + 4.0 * t * t).sqrt()) / 2.0;
+        y = &next + &((&next - &x) * ((t - 1.0) / t_next));
+        let change = (&next - &x).mapv(f64::abs).sum();
+        x = next;
+        t = t_next;
+        if change < TOLERANCE {
+            break;
+        }
+    }
+    Ok(QpSolution { x, iterations })
+}
+
+/// Euclidean projection onto {x : 1ᵀx = 1, lower ≤ x ≤ upper}, x_i = clip(y_i - ν) with the
+/// shift ν found by bisection
+fn project_budget_box(y: &Array1<f64>, lower: &Array1<f64>, upper: &Array1<f64>) -> Array1<f64> {
+    let clipped = |shift: f64| -> Array1<f64> {
+        let mut x = y - shift;
+        for ((xi, lo), hi) in x.iter_mut().zip(lower.iter()).zip(upper.iter()) {
+            *xi = xi.clamp(*lo, *hi);
+        }
+        x
+    };
+    // The budget is non-increasing in ν, widen a bracket around the root (bounds may be
+    // infinite, so it cannot be read off them)
+    let spread = y.iter().fold(0.0, |acc: f64, yi| acc.max(yi.abs())) + 1.0;
+    let mut low = -spread;
+    let mut high = spread;
+    for _ in 0..100 {
+        if clipped(low).sum() >= 1.0 {
+            break;
+        }
+        low *= 2.0;
+    }
+    for _ in 0..100 {
+        if clipped(high).sum() <= 1.0 {
+            break;
+        }
+        high *= 2.0;
+    }
+    for _ in 0..200 {
+        let mid = 0.5 * (low + high);
+        if clipped(mid).sum() > 1.0 {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    clipped(0.5 * (low + high))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn a_warm_start_near_the_solution_takes_fewer_iterations() {
+        let q = array![[0.08, 0.01, 0.0], [0.01, 0.02, 0.0], [0.0, 0.0, 0.02]];
+        let c = array![-0.01, 0.002, -0.004];
+        let lower = Array1::zeros(3);
+        let upper = Array1::from_elem(3, 0.6);
+        let cold = minimize_budget_qp(&q, &c, &lower, &upper, None).unwrap();
+        let warm = minimize_budget_qp(&q, &c, &lower, &upper, Some(&cold.x)).unwrap();
+        assert!(
+            warm.iterations < cold.iterations,
+            "{} warm, {} cold iterations",
+            warm.iterations,
+            cold.iterations
+        );
+        assert!((&warm.x - &cold.x).mapv(f64::abs).sum() < 1e-9);
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::VecDeque;
+
+use chrono::Local;
+use ndarray::Array2;
+use tokio::sync::mpsc;
+use tokio::time::{interval, Duration, MissedTickBehavior};
+use tracing::warn;
+
+use crate::config::Settings;
+use crate::data::data_brokers::stream::{self, Tick};
+use crate::error::QuarsError;
+use crate::optimization::optimize_portfolio;
+use crate::portfolio::{compute_portfolio_returns, portfolio_var, PortfolioStats};
+use crate::utils::check_cancelled;
+
+// Ticks buffered between two snapshots before the stream waits on the monitor
+const CHANNEL_CAPACITY: usize = 1024;
+const VAR_CONFIDENCE: f64 = 0.95;
+// Frontier points of a re-optimization, only its tangency portfolio is used
+const FRONTIER_POINTS: usize = 20;
+
+/// Rolling window of the returns between snapshots of the latest streamed prices
+pub struct RollingReturns {
+    assets: Vec<String>,
+    window: usize,
+    // Latest price of each asset, None until its first tick
+    prices: Vec<Option<f64>>,
+    // Prices at the previous snapshot
+    previous: Option<Vec<f64>>,
+    returns: VecDeque<Vec<f64>>,
+}
+
+impl RollingReturns {
+    pub fn new(assets: &[String], window: usize) -> Self {
+        Self {
+            assets: assets.to_vec(),
+            window,
+            prices: vec![None; assets.len()],
+            previous: None,
+            returns: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Records the price of a tick, false when its ticker is not one of the assets
+    pub fn update(&mut self, tick: &Tick) -> bool {
+        match self
+            .assets
+            .iter()
+            .position(|asset| asset.eq_ignore_ascii_case(&tick.asset))
+        {
+            Some(idx) => {
+                self.prices[idx] = Some(tick.price);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Snapshots the latest prices, once every asset has one, and pushes the returns since
+    /// the previous snapshot, dropping the oldest beyond the window. Returns whether returns
+    /// were pushed, the first snapshot only sets the reference prices.
+    pub fn snapshot(&mut self) -> bool {
+        let Some(prices) = self.prices.iter().copied().collect::<Option<Vec<f64>>>() else {
+            return false;
+        };
+        let pushed = match &self.previous {
+            Some(previous) => {
+                let returns = prices
+                    .iter()
+                    .zip(previous)
+                    .map(|(price, previous)| price / previous - 1.0)
+                    .collect();
+                if self.returns.len() == self.window {
+                    self.returns.pop_front();
+                }
+                self.returns.push_back(returns);
+                true
+            }
+            None => false,
+        };
+        self.previous = Some(prices);
+        pushed
+    }
+
+    /// Assets without a price yet
+    pub fn missing(&self) -> Vec<&str> {
+        self.assets
+            .iter()
+            .zip(&self.prices)
+            .filter(|(_, price)| price.is_none())
+            .map(|(asset, _)| asset.as_str())
+            .collect()
+    }
+
+    fn len(&self) -> usize {
+        self.returns.len()
+    }
+
+    /// Statistics of the buffered returns, dated by their position in the window
+    pub fn stats(&self) -> Result<PortfolioStats, QuarsError> {
+        let returns_matrix =
+            Array2::from_shape_fn((self.assets.len(), self.returns.len()), |(i, t)| {
+                self.returns[t][i]
+            });
+        let dates = (1..=self.returns.len()).map(|t| t.to_string()).collect();
+        PortfolioStats::from_returns(self.assets.clone(), returns_matrix, dates)
+    }
+}
+
+/// Streams the prices of the assets and, every interval, updates the rolling returns and
+/// prints the historical VaR of the current weights over them. Once the window holds more
+/// returns than assets the weights are re-optimized on it, keeping the previous ones when the
+/// optimization fails. Runs until the stream ends or the run is cancelled.
+pub async fn run(
+    settings: &Settings,
+    assets: &[String],
+    weights: &[f64],
+) -> Result<(), QuarsError> {
+    let monitor = &settings.monitor;
+    if monitor.interval_secs == 0 || monitor.window < 2 {
+        return Err(QuarsError::Config(
+            "monitor needs an interval_secs above 0 and a window of at least 2".to_string(),
+        ));
+    }
+    let url = monitor
+        .url
+        .clone()
+        .unwrap_or_else(|| stream::twelve_url(&settings.data_api.api_key));
+    let (sender, mut receiver) = mpsc::channel(CHANNEL_CAPACITY);
+    let tickers = assets.to_vec();
+    let mut stream =
+        tokio::spawn(async move { stream::stream_prices(&url, &tickers, sender).await });
+    println!(
+        "Monitoring {} every {}s over the last {} returns, Ctrl-C to stop",
+        assets.join(", "),
+        monitor.interval_secs,
+        monitor.window
+    );
+
+    let mut buffer = RollingReturns::new(assets, monitor.window);
+    let mut weights = weights.to_vec();
+    let mut timer = interval(Duration::from_secs(monitor.interval_secs));
+    timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let result = loop {
+        tokio::select! {
+            tick = receiver.recv() => match tick {
+                Some(tick) => {
+                    buffer.update(&tick);
+                }
+                // The stream ended, with its error if it failed
+                None => {
+                    break (&mut stream)
+                        .await
+                        .unwrap_or_else(|e| Err(QuarsError::Network(e.to_string())))
+                }
+            },
+            _ = timer.tick() => {
+                if let Err(e) = check_cancelled() {
+                    break Err(e.into());
+                }
+                if buffer.snapshot() {
+                    if let Err(e) = print_update(settings, &buffer, &mut weights) {
+                        break Err(e);
+                    }
+                } else {
+                    let missing = buffer.missing();
+                    if !missing.is_empty() {
+                        println!("{} waiting for prices of {}", now(), missing.join(", "));
+                    }
+                }
+            }
+        }
+    };
+    stream.abort();
+    result
+}
+
+// Prints the VaR of the weights over the window and re-optimizes them on it
+fn print_update(
+    settings: &Settings,
+    buffer: &RollingReturns,
+    weights: &mut Vec<f64>,
+) -> Result<(), QuarsError> {
+    if buffer.len() < 2 {
+        println!("{} {} return, VaR needs 2", now(), buffer.len());
+        return Ok(());
+    }
+    let stats = buffer.stats()?;
+    let returns = compute_portfolio_returns(&stats.returns_matrix, weights);
+    let var = portfolio_var(&returns, VAR_CONFIDENCE);
+    let mut line = format!(
+        "{} {} returns, VaR({:.0}%) {:.4}%",
+        now(),
+        buffer.len(),
+        VAR_CONFIDENCE * 100.0,
+        var * 100.0
+    );
+    if buffer.len() > stats.assets.len() {
+        match optimize_portfolio(&stats, FRONTIER_POINTS, &settings.portofolio_optimization) {
+            Ok(results) => {
+                *weights = results.optimal_risky_portfolio;
+                let allocation: Vec<String> = stats
+                    .assets
+                    .iter()
+                    .zip(weights.iter())
+                    .map(|(asset, weight)| format!("{} {:.2}%", asset, weight * 100.0))
+                    .collect();
+                line.push_str(&format!(", weights {}", allocation.join(", ")));
+            }
+            Err(e) => warn!(error = %e, "Re-optimization failed, keeping the weights"),
+        }
+    }
+    println!("{}", line);
+    Ok(())
+}
+
+fn now() -> String {
+    Local::now().format("%H:%M:%S").to_string()
+}
@@ -1,41 +1,179 @@
-use crate::{config::PortofolioOptimization, portfolio::PortfolioStats};
-use ndarray::{Array1, Array2};
-use ndarray_linalg::InverseInto;
-use std::error::Error;
+use crate::{
+    config::PortofolioOptimization,
+    error::QuarsError,
+    math::{optimization::minimize_budget_qp, statistics::normal_cdf},
+    portfolio::{
+        check_positive_definite, compute_portfolio_returns, diversification_ratio,
+        exposure_metrics, max_drawdown, nearest_positive_definite, portfolio_variance,
+        semi_covariance, ExposureMetrics, PortfolioStats,
+    },
+    utils,
+};
+use ndarray::{s, Array1, Array2, Axis};
+use ndarray_linalg::{Eigh, InverseInto, UPLO};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rayon::prelude::*;
+use serde::Serialize;
+use tracing::warn;
+
+// Risk aversion of the methods compared against one without
+const DEFAULT_TAU: f64 = 0.3;
+// Utility fraction the near-optimal blend keeps when compared against another method
+const DEFAULT_THETA: f64 = 0.95;
 
 // Optim. method Enum for Mean Variance Optimization
 pub enum MvoOptMethod {
     // Maximize risk-adjusted return
-    RiskAdjusted { tau: f64 },
+    RiskAdjusted {
+        tau: f64,
+    },
     // Near-optimality method, minimize concentration of weights after computing standart MVO
-    NearOptimal { tau: f64, theta: f64 },
+    NearOptimal {
+        tau: f64,
+        theta: f64,
+        blend_steps: usize,
+    },
+    // Minimum correlation heuristic, no risk aversion parameter
+    MinCorrelation,
+    // Inverse volatility weighting, no risk aversion parameter
+    InverseVolatility,
+    // Maximize the CARA certainty equivalent, gamma is the absolute risk aversion
+    Cara {
+        gamma: f64,
+    },
+    // Minimize the downside variance below a target return, no risk aversion parameter
+    MinSemivariance,
+    // Global minimum-variance portfolio, no risk aversion parameter
+    MinVariance,
+    // Equal risk contributions, no risk aversion parameter
+    RiskParity,
 }
 
 impl MvoOptMethod {
-    pub fn from_config(portofolio_optimization_config: &PortofolioOptimization) -> Self {
-        match portofolio_optimization_config.sub_method.as_str() {
-            "risk-adjusted" => Self::RiskAdjusted {
-                tau: portofolio_optimization_config.params[0],
-            },
-            "near-optimal" => Self::NearOptimal {
-                tau: portofolio_optimization_config.params[0],
-                theta: portofolio_optimization_config.params[1],
+    pub fn from_config(
+        portofolio_optimization_config: &PortofolioOptimization,
+    ) -> Result<Self, QuarsError> {
+        let sub_method = portofolio_optimization_config.sub_method.as_str();
+        // The leading params of the methods that take any, in order
+        let param = |i: usize, name: &str| {
+            portofolio_optimization_config
+                .params
+                .get(i)
+                .copied()
+                .ok_or_else(|| {
+                    QuarsError::Config(format!(
+                        "sub_method {} takes {} as params[{}], got {} params",
+                        sub_method,
+                        name,
+                        i,
+                        portofolio_optimization_config.params.len()
+                    ))
+                })
+        };
+        match sub_method {
+            "risk-adjusted" => Ok(Self::RiskAdjusted {
+                tau: param(0, "tau")?,
+            }),
+            "near-optimal" => Ok(Self::NearOptimal {
+                tau: param(0, "tau")?,
+                theta: param(1, "theta")?,
+                blend_steps: portofolio_optimization_config.blend_steps,
+            }),
+            "min-correlation" => Ok(Self::MinCorrelation),
+            "inverse-vol" => Ok(Self::InverseVolatility),
+            "cara" => Ok(Self::Cara {
+                gamma: param(0, "gamma")?,
+            }),
+            "min-semivariance" => Ok(Self::MinSemivariance),
+            "min-variance" => Ok(Self::MinVariance),
+            "risk-parity" => Ok(Self::RiskParity),
+            other => Err(QuarsError::Config(format!(
+                "Unknown sub_method '{}', use risk-adjusted, near-optimal, min-correlation, \
+                 inverse-vol, cara, min-semivariance, min-variance or risk-parity",
+                other
+            ))),
+        }
+    }
+
+    /// Name of the method in the `sub_method` setting
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::RiskAdjusted { .. } => "risk-adjusted",
+            Self::NearOptimal { .. } => "near-optimal",
+            Self::MinCorrelation => "min-correlation",
+            Self::InverseVolatility => "inverse-vol",
+            Self::Cara { .. } => "cara",
+            Self::MinSemivariance => "min-semivariance",
+            Self::MinVariance => "min-variance",
+            Self::RiskParity => "risk-parity",
+        }
+    }
+
+    /// Every available method, parameterized with the risk aversion of the configured one
+    /// (or the default τ when it has none) so that they are compared on the same footing.
+    /// The near-optimal θ is the configured one for that method, the default otherwise.
+    pub fn registry(
+        portofolio_optimization_config: &PortofolioOptimization,
+    ) -> Result<Vec<Self>, QuarsError> {
+        let configured = Self::from_config(portofolio_optimization_config)?;
+        let tau = configured.tau().unwrap_or(DEFAULT_TAU);
+        let theta = match configured {
+            Self::NearOptimal { theta, .. } => theta,
+            _ => DEFAULT_THETA,
+        };
+        Ok(vec![
+            Self::RiskAdjusted { tau },
+            Self::NearOptimal {
+                tau,
+                theta,
+                blend_steps: portofolio_optimization_config.blend_steps,
             },
-            _ => Self::RiskAdjusted { tau: 0.3 },
+            Self::MinCorrelation,
+            Self::InverseVolatility,
+            Self::Cara { gamma: 2.0 * tau },
+            Self::MinSemivariance,
+            Self::MinVariance,
+            Self::RiskParity,
+        ])
+    }
+
+    pub fn tau(&self) -> Option<f64> {
+        match self {
+            Self::RiskAdjusted { tau } | Self::NearOptimal { tau, .. } => Some(*tau),
+            // (γ/2)xᵀΣx is the τ xᵀΣx penalty of the other methods
+            Self::Cara { gamma } => Some(gamma / 2.0),
+            Self::MinCorrelation
+            | Self::InverseVolatility
+            | Self::MinSemivariance
+            | Self::MinVariance
+            | Self::RiskParity => None,
         }
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize)]
 pub struct FrontierPoint {
-    risk_free_weight: f64,
-    risky_weights: Vec<f64>,
+    pub risk_free_weight: f64,
+    pub risky_weights: Vec<f64>,
     pub expected_return: f64,
     pub portfolio_std: f64,
     sharpe_ratio: f64,
 }
 
-#[derive(Debug)]
+impl FrontierPoint {
+    /// Exposure of the risky leg, e.g. a leverage of 2.0 at the end of the CAL
+    pub fn exposure_metrics(&self) -> ExposureMetrics {
+        exposure_metrics(&self.risky_weights)
+    }
+
+    /// Diversification ratio of the risky leg, None for the all risk-free point
+    pub fn diversification_ratio(&self, covariance: &Array2<f64>) -> Option<f64> {
+        diversification_ratio(&self.risky_weights, covariance)
+    }
+}
+
+#[derive(Debug, Serialize)]
 pub struct OptimizationResults {
     pub frontier: Vec<FrontierPoint>,
     // The optimal risky asset weights
@@ -44,195 +182,3112 @@ pub struct OptimizationResults {
     pub optimal_risky_return: f64,
     pub optimal_risky_std: f64,
     pub max_sharpe: f64,
+    // Allocation to the cash asset, zero unless a `cash_ticker` is configured
+    pub risk_free_weight: f64,
+    // Chosen blend, only set by the near-optimal method
+    pub near_optimal: Option<NearOptimalBlend>,
+    // Certainty-equivalent return μᵀx - (γ/2)xᵀΣx, only set by the CARA method
+    pub certainty_equivalent: Option<f64>,
+}
+
+/// Outcome of the near-optimal blend search
+#[derive(Debug, Clone, Serialize)]
+pub struct NearOptimalBlend {
+    // Weight of the classic MVO portfolio in the blend, the rest is equal-weight
+    pub alpha: f64,
+    pub utility: f64,
+    // θε, the utility floor the blend must reach
+    pub required_utility: f64,
+    // xᵀx of the blend
+    pub concentration: f64,
+}
+
+impl OptimizationResults {
+    /// Drops the frontier points above an annualized expected return or volatility cap.
+    /// The tangency portfolio is kept even when it exceeds the caps.
+    pub fn truncate_frontier(
+        &mut self,
+        max_return: Option<f64>,
+        max_vol: Option<f64>,
+        periods_per_year: f64,
+    ) -> Result<(), QuarsError> {
+        let max_return = max_return.map_or(f64::INFINITY, |r| r / periods_per_year);
+        let max_std = max_vol.map_or(f64::INFINITY, |v| v / periods_per_year.sqrt());
+        self.frontier
+            .retain(|pt| pt.expected_return <= max_return && pt.portfolio_std <= max_std);
+        if self.frontier.is_empty() {
+            return Err(QuarsError::Config(
+                "No frontier point is within max_frontier_return/max_frontier_vol.".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Holds at least `min_cash_weight` in the risk-free asset: drops the frontier points with
+    /// more than 1 - min_cash_weight in risky assets, i.e. the high-leverage end of the CAL,
+    /// and raises the cash allocation to the floor. With a `cash_ticker` the floor applies to
+    /// the cash asset, which the risky weights hold none of. When the floor binds, returns the
+    /// portfolio then held, the CAL point at the floor with `risk_free` per period.
+    pub fn apply_min_cash_weight(
+        &mut self,
+        min_cash_weight: f64,
+        risk_free: f64,
+    ) -> Result<Option<FrontierPoint>, QuarsError> {
+        if !(0.0..1.0).contains(&min_cash_weight) {
+            return Err(QuarsError::Config(format!(
+                "min_cash_weight must lie in [0, 1), got {}",
+                min_cash_weight
+            )));
+        }
+        // The CAL steps may land on the floor up to rounding
+        self.frontier
+            .retain(|pt| pt.risk_free_weight >= min_cash_weight - 1e-12);
+        if self.frontier.is_empty() {
+            return Err(QuarsError::Infeasible(format!(
+                "No frontier point holds at least {} in cash.",
+                min_cash_weight
+            )));
+        }
+        if self.risk_free_weight >= min_cash_weight {
+            return Ok(None);
+        }
+        self.risk_free_weight = min_cash_weight;
+        Ok(Some(self.cal_point(min_cash_weight, risk_free)))
+    }
+
+    // Point of the CAL through the tangency portfolio holding `risk_free_weight` in the
+    // risk-free asset, earning `risk_free` per period
+    fn cal_point(&self, risk_free_weight: f64, risk_free: f64) -> FrontierPoint {
+        let exposure = 1.0 - risk_free_weight;
+        let expected_return = risk_free_weight * risk_free + exposure * self.optimal_risky_return;
+        let portfolio_std = exposure.abs() * self.optimal_risky_std;
+        FrontierPoint {
+            risk_free_weight,
+            risky_weights: self
+                .optimal_risky_portfolio
+                .iter()
+                .map(|w| exposure * w)
+                .collect(),
+            expected_return,
+            portfolio_std,
+            sharpe_ratio: sharpe_ratio(expected_return, risk_free, portfolio_std).unwrap_or(0.0),
+        }
+    }
+
+    /// Zeros the tangency weights smaller in magnitude than `threshold`, too small to be worth
+    /// trading, and rescales the others to the total of the optimized weights, so a fully
+    /// invested portfolio stays fully invested. The tangency return, volatility and Sharpe
+    /// ratio are recomputed on `stats`, `risk_free` per period, and the frontier, the CAL
+    /// through the tangency portfolio, is rebuilt on the cleaned weights at the same risk-free
+    /// weights. Applies before the borrowing rate and the frontier caps, which it would undo.
+    /// Returns the zeroed assets, by index, with their optimized weights.
+    pub fn apply_min_trade_weight(
+        &mut self,
+        threshold: f64,
+        stats: &PortfolioStats,
+        risk_free: f64,
+    ) -> Result<Vec<(usize, f64)>, QuarsError> {
+        if !(0.0..1.0).contains(&threshold) {
+            return Err(QuarsError::Config(format!(
+                "min_trade_weight must lie in [0, 1), got {}",
+                threshold
+            )));
+        }
+        let weights = &mut self.optimal_risky_portfolio;
+        let dropped: Vec<(usize, f64)> = weights
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|(_, w)| *w != 0.0 && w.abs() < threshold)
+            .collect();
+        if dropped.is_empty() {
+            return Ok(dropped);
+        }
+        let budget: f64 = weights.iter().sum();
+        let kept = budget - dropped.iter().map(|(_, w)| w).sum::<f64>();
+        // Up to rounding, e.g. offsetting longs and shorts
+        if kept.abs() < 1e-12 {
+            return Err(QuarsError::Infeasible(format!(
+                "The tangency weights above min_trade_weight {} sum to zero, they cannot be rescaled",
+                threshold
+            )));
+        }
+        for &(i, _) in &dropped {
+            weights[i] = 0.0;
+        }
+        for w in weights.iter_mut() {
+            *w *= budget / kept;
+        }
+        let weights = Array1::from(weights.clone());
+        self.optimal_risky_return = stats.mean_returns.dot(&weights);
+        self.optimal_risky_std = portfolio_variance(&weights, &stats.covariance)?.sqrt();
+        self.max_sharpe =
+            sharpe_ratio(self.optimal_risky_return, risk_free, self.optimal_risky_std)
+                .unwrap_or(0.0);
+        self.frontier = self
+            .frontier
+            .iter()
+            .map(|pt| self.cal_point(pt.risk_free_weight, risk_free))
+            .collect();
+        Ok(dropped)
+    }
+
+    /// Charges the borrowing rate instead of the lending rate on the leveraged frontier points,
+    /// those with a negative risk-free weight, which lowers their return and Sharpe ratio and
+    /// kinks the CAL at the tangency portfolio. Both rates are per period, a borrowing rate
+    /// below the lending rate would be an arbitrage and is rejected.
+    pub fn apply_borrowing_rate(&mut self, lending: f64, borrowing: f64) -> Result<(), QuarsError> {
+        if borrowing < lending {
+            return Err(QuarsError::Config(
+                "borrowing_rate must be at least the lending rate".to_string(),
+            ));
+        }
+        for pt in self
+            .frontier
+            .iter_mut()
+            .filter(|pt| pt.risk_free_weight < 0.0)
+        {
+            pt.expected_return += pt.risk_free_weight * (borrowing - lending);
+            pt.sharpe_ratio =
+                sharpe_ratio(pt.expected_return, lending, pt.portfolio_std).unwrap_or(0.0);
+        }
+        Ok(())
+    }
+
+    /// Frontier portfolio achieving the target expected return, linearly interpolated between
+    /// the two adjacent frontier points. Targets outside the frontier are clamped to its ends.
+    pub fn weights_for_return(&self, target: f64) -> Result<FrontierPoint, QuarsError> {
+        self.interpolate_frontier(target, |pt| pt.expected_return, "return")
+    }
+
+    /// Frontier portfolio achieving the target standard deviation, see `weights_for_return`
+    pub fn weights_for_risk(&self, target: f64) -> Result<FrontierPoint, QuarsError> {
+        self.interpolate_frontier(target, |pt| pt.portfolio_std, "risk")
+    }
+
+    fn interpolate_frontier(
+        &self,
+        target: f64,
+        key: fn(&FrontierPoint) -> f64,
+        label: &str,
+    ) -> Result<FrontierPoint, QuarsError> {
+        let (first, last) = match (self.frontier.first(), self.frontier.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => {
+                return Err(QuarsError::InsufficientData(
+                    "Cannot query an empty frontier.".to_string(),
+                ))
+            }
+        };
+
+        for pair in self.frontier.windows(2) {
+            let (lo, hi) = (key(&pair[0]), key(&pair[1]));
+            if (lo <= target && target <= hi) || (hi <= target && target <= lo) {
+                let t = if hi == lo {
+                    0.0
+                } else {
+                    (target - lo) / (hi - lo)
+                };
+                return Ok(blend_points(&pair[0], &pair[1], t));
+            }
+        }
+
+        // Outside the frontier: clamp to whichever end is closest to the target
+        let clamped = if (key(first) - target).abs() <= (key(last) - target).abs() {
+            first
+        } else {
+            last
+        };
+        warn!(
+            "target {} {:.6} is outside the frontier, clamping to {:.6}",
+            label,
+            target,
+            key(clamped)
+        );
+        Ok(clamped.clone())
+    }
+}
+
+// Linear blend (1 - t)·a + t·b of two frontier points
+fn blend_points(a: &FrontierPoint, b: &FrontierPoint, t: f64) -> FrontierPoint {
+    let lerp = |x: f64, y: f64| (1.0 - t) * x + t * y;
+    FrontierPoint {
+        risk_free_weight: lerp(a.risk_free_weight, b.risk_free_weight),
+        risky_weights: a
+            .risky_weights
+            .iter()
+            .zip(b.risky_weights.iter())
+            .map(|(x, y)| lerp(*x, *y))
+            .collect(),
+        expected_return: lerp(a.expected_return, b.expected_return),
+        portfolio_std: lerp(a.portfolio_std, b.portfolio_std),
+        sharpe_ratio: lerp(a.sharpe_ratio, b.sharpe_ratio),
+    }
 }
 
 pub fn optimize_portfolio(
     stats: &PortfolioStats,
     n_points: usize,
     po: &PortofolioOptimization,
-) -> Result<OptimizationResults, Box<dyn Error>> {
-    let opt_method = MvoOptMethod::from_config(po);
+) -> Result<OptimizationResults, QuarsError> {
+    let opt_method = MvoOptMethod::from_config(po)?;
+    if let Some(cash_ticker) = &po.cash_ticker {
+        return optimize_with_cash(stats, cash_ticker, opt_method, n_points, po);
+    }
+    // Past the cash asset, whose zero variance leaves the full covariance singular
+    check_positive_definite(&stats.covariance)?;
+    optimize_with_method(
+        stats,
+        opt_method,
+        risk_free_return(stats, po)?,
+        n_points,
+        po,
+    )
+}
+
+/// Runs one optimization method against the per-period `risk_free` rate, with the
+/// compounding and method settings of `po` and without a cash asset
+pub fn optimize_with_method(
+    stats: &PortfolioStats,
+    opt_method: MvoOptMethod,
+    risk_free: f64,
+    n_points: usize,
+    po: &PortofolioOptimization,
+) -> Result<OptimizationResults, QuarsError> {
     match opt_method {
         MvoOptMethod::RiskAdjusted { tau } => {
-            optimize_risk_adjusted(stats, po.risk_free_rate, tau, n_points)
+            optimize_risk_adjusted(stats, risk_free, tau, n_points, po.explain)
+        }
+        MvoOptMethod::NearOptimal {
+            theta,
+            tau,
+            blend_steps,
+        } => optimize_near_optimal(
+            stats,
+            risk_free,
+            tau,
+            theta,
+            blend_steps,
+            n_points,
+            po.explain,
+        ),
+        MvoOptMethod::MinCorrelation => {
+            optimize_min_correlation(stats, risk_free, n_points, po.explain)
+        }
+        MvoOptMethod::InverseVolatility => {
+            optimize_inverse_volatility(stats, risk_free, n_points, po.explain)
+        }
+        MvoOptMethod::Cara { gamma } => {
+            optimize_cara(stats, gamma, risk_free, n_points, po.explain)
+        }
+        MvoOptMethod::MinSemivariance => {
+            let downside_target = match po.downside_target {
+                Some(target) => annual_to_period_rate(
+                    target,
+                    po.periods_per_year,
+                    Compounding::from_config(&po.compounding)?,
+                ),
+                None => risk_free,
+            };
+            optimize_min_semivariance(stats, downside_target, risk_free, n_points, po.explain)
+        }
+        MvoOptMethod::MinVariance => optimize_min_variance(stats, risk_free, n_points, po.explain),
+        MvoOptMethod::RiskParity => optimize_risk_parity(stats, risk_free, n_points, po.explain),
+    }
+}
+
+/// Runs every method of `MvoOptMethod::registry` on the same statistics, in registry order
+pub fn compare_methods(
+    stats: &PortfolioStats,
+    n_points: usize,
+    po: &PortofolioOptimization,
+) -> Result<Vec<(&'static str, OptimizationResults)>, QuarsError> {
+    if po.cash_ticker.is_some() {
+        return Err(QuarsError::Config(
+            "Comparing methods is not supported with a cash_ticker, which only the tau-based methods use"
+                .to_string(),
+        ));
+    }
+    let risk_free = risk_free_return(stats, po)?;
+    MvoOptMethod::registry(po)?
+        .into_iter()
+        .map(|method| {
+            Ok((
+                method.name(),
+                optimize_with_method(stats, method, risk_free, n_points, po)?,
+            ))
+        })
+        .collect()
+}
+
+/// Michaud resampled efficient frontier. The optimizer runs on `n_resamples` bootstrap
+/// resamples of the return periods, drawn with replacement, and the weights are averaged
+/// across the runs. Note that the means are re-estimated on every resample, overriding views.
+/// Each resample draws from its own sub-stream of a seed drawn from `rng`, so the frontier
+/// does not depend on the number of threads the resamples run on.
+pub fn resampled_frontier(
+    stats: &PortfolioStats,
+    n_points: usize,
+    po: &PortofolioOptimization,
+    n_resamples: usize,
+    rng: &mut StdRng,
+) -> Result<OptimizationResults, QuarsError> {
+    let n_periods = stats.returns_matrix.ncols();
+    let seed: u64 = rng.gen();
+    let samples: Vec<Vec<usize>> = (0..n_resamples)
+        .into_par_iter()
+        .map(|i| {
+            let mut rng = utils::substream(seed, i as u64);
+            (0..n_periods)
+                .map(|_| rng.gen_range(0..n_periods))
+                .collect()
+        })
+        .collect();
+    resampled_frontier_from(stats, n_points, po, &samples)
+}
+
+/// Resampled frontier over the given resamples, each a list of period indices, optimized in
+/// parallel. Frontier points are matched on their rank along the frontier, and the averaged
+/// portfolios are evaluated with the full-sample statistics.
+pub fn resampled_frontier_from(
+    stats: &PortfolioStats,
+    n_points: usize,
+    po: &PortofolioOptimization,
+    samples: &[Vec<usize>],
+) -> Result<OptimizationResults, QuarsError> {
+    if samples.is_empty() {
+        return Err(QuarsError::Config(
+            "The resampled frontier needs at least one resample.".to_string(),
+        ));
+    }
+    let runs = samples
+        .par_iter()
+        .map(|sample| {
+            utils::check_cancelled()?;
+            optimize_portfolio(&stats.resample_periods(sample)?, n_points, po)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let n_runs = runs.len() as f64;
+    let average = |weights: Vec<&[f64]>| -> Array1<f64> {
+        weights
+            .into_iter()
+            .fold(Array1::zeros(stats.assets.len()), |acc, w| {
+                acc + Array1::from(w.to_vec())
+            })
+            / n_runs
+    };
+
+    let risk_free = risk_free_return(stats, po)?;
+
+    let frontier = (0..n_points)
+        .map(|i| {
+            let risky = average(
+                runs.iter()
+                    .map(|r| &r.frontier[i].risky_weights[..])
+                    .collect(),
+            );
+            let risk_free_weight = runs
+                .iter()
+                .map(|r| r.frontier[i].risk_free_weight)
+                .sum::<f64>()
+                / n_runs;
+            let expected_return = risk_free_weight * risk_free + stats.mean_returns.dot(&risky);
+            let portfolio_std = portfolio_variance(&risky, &stats.covariance)?.sqrt();
+            // The all risk-free point has no Sharpe ratio, as in `cal_frontier`
+            let sharpe_ratio = if risk_free_weight < 1.0 {
+                sharpe_ratio(expected_return, risk_free, portfolio_std).unwrap_or(0.0)
+            } else {
+                0.0
+            };
+            Ok(FrontierPoint {
+                risk_free_weight,
+                risky_weights: risky.to_vec(),
+                expected_return,
+                portfolio_std,
+                sharpe_ratio,
+            })
+        })
+        .collect::<Result<Vec<_>, QuarsError>>()?;
+
+    let optimal = average(
+        runs.iter()
+            .map(|r| &r.optimal_risky_portfolio[..])
+            .collect(),
+    );
+    let optimal_risky_return = stats.mean_returns.dot(&optimal);
+    let optimal_risky_std = portfolio_variance(&optimal, &stats.covariance)?.sqrt();
+    Ok(OptimizationResults {
+        frontier,
+        optimal_risky_portfolio: optimal.to_vec(),
+        optimal_risky_return,
+        optimal_risky_std,
+        max_sharpe: sharpe_ratio(optimal_risky_return, risk_free, optimal_risky_std).unwrap_or(0.0),
+        risk_free_weight: runs.iter().map(|r| r.risk_free_weight).sum::<f64>() / n_runs,
+        near_optimal: None,
+        certainty_equivalent: None,
+    })
+}
+
+/// Constraints of the minimum tracking error portfolio
+#[derive(Debug, Default, Clone)]
+pub struct TrackingConstraints {
+    pub min_weight: Option<f64>,
+    pub max_weight: Option<f64>,
+    // Largest number of assets held
+    pub max_assets: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TrackingPortfolio {
+    pub weights: Vec<f64>,
+    // Per-period standard deviation of the active returns (w - w_target)ᵀr
+    pub tracking_error: f64,
+    // Iterations of the QP solver over its runs, zero for the closed-form solution
+    pub iterations: usize,
+}
+
+/// Portfolio closest to the target weights in tracking error, min (w - w_t)ᵀΣ(w - w_t)
+/// subject to 1ᵀw = 1 and the constraints. Without constraints the solution is closed-form,
+/// w = w_t + Σ⁻¹1 (1 - 1ᵀw_t) / (1ᵀΣ⁻¹1), i.e. the target itself when it is fully invested.
+/// Bounds go through the QP solver, and the cardinality limit keeps the largest positions of
+/// the bounded solution and solves again on those alone. `warm_start` seeds the solver with
+/// prior weights, e.g. the previous rebalance's solution.
+pub fn optimize_min_tracking_error(
+    stats: &PortfolioStats,
+    target_weights: &[f64],
+    constraints: &TrackingConstraints,
+    warm_start: Option<&[f64]>,
+) -> Result<TrackingPortfolio, QuarsError> {
+    let n = stats.assets.len();
+    if target_weights.len() != n {
+        return Err(QuarsError::Config(format!(
+            "Got {} target weights for {} assets",
+            target_weights.len(),
+            n
+        )));
+    }
+    let target = Array1::from(target_weights.to_vec());
+    let cov = &stats.covariance;
+    // ½xᵀQx + cᵀx with Q = 2Σ and c = -2Σw_t is the tracking error up to a constant
+    let q = cov * 2.0;
+    let c = -q.dot(&target);
+    let mut lower = Array1::from_elem(n, constraints.min_weight.unwrap_or(f64::NEG_INFINITY));
+    let mut upper = Array1::from_elem(n, constraints.max_weight.unwrap_or(f64::INFINITY));
+
+    let mut iterations = 0;
+    let mut weights = if constraints.min_weight.is_none()
+        && constraints.max_weight.is_none()
+        && constraints.max_assets.is_none()
+    {
+        let cov_inv = invert_covariance(cov)?;
+        let direction = cov_inv.dot(&Array1::<f64>::ones(n));
+        &target + &(&direction * ((1.0 - target.sum()) / direction.sum()))
+    } else {
+        let warm_start = warm_start.map(|w| Array1::from(w.to_vec()));
+        let solution = utils::timed("qp solver", || {
+            minimize_budget_qp(&q, &c, &lower, &upper, warm_start.as_ref())
+        })?;
+        iterations += solution.iterations;
+        solution.x
+    };
+
+    if let Some(max_assets) = constraints.max_assets {
+        if max_assets == 0 {
+            return Err(QuarsError::Config(
+                "max_assets must be at least 1.".to_string(),
+            ));
         }
-        MvoOptMethod::NearOptimal { theta, tau } => {
-            optimize_near_optimal(stats, po.risk_free_rate, tau, theta, n_points)
+        let held = weights.iter().filter(|w| w.abs() > 1e-12).count();
+        if held > max_assets {
+            let mut by_size: Vec<usize> = (0..n).collect();
+            by_size.sort_by(|&a, &b| weights[b].abs().total_cmp(&weights[a].abs()));
+            for &i in &by_size[max_assets..] {
+                lower[i] = 0.0;
+                upper[i] = 0.0;
+            }
+            // The kept positions barely move, so the first solution is a good starting point
+            let solution = utils::timed("qp solver", || {
+                minimize_budget_qp(&q, &c, &lower, &upper, Some(&weights))
+            })?;
+            iterations += solution.iterations;
+            weights = solution.x;
         }
     }
+
+    let active = &weights - &target;
+    Ok(TrackingPortfolio {
+        tracking_error: portfolio_variance(&active, cov)?.sqrt(),
+        weights: weights.to_vec(),
+        iterations,
+    })
+}
+
+/// Point of the active frontier, a fully invested portfolio with the least tracking error
+/// for its active return over the benchmark, per period
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveFrontierPoint {
+    pub weights: Vec<f64>,
+    // Weights minus the benchmark weights
+    pub active_weights: Vec<f64>,
+    pub active_return: f64,
+    pub tracking_error: f64,
+}
+
+/// Active efficient frontier, the benchmark-relative analog of `min_variance_frontier`: for
+/// target active returns α the fully invested portfolio minimizing the tracking error
+/// (w - b)ᵀΣ(w - b) subject to μᵀ(w - b) = α, without bounds. With A = 1ᵀΣ⁻¹1, B = 1ᵀΣ⁻¹μ,
+/// C = μᵀΣ⁻¹μ, D = AC - B² and s = 1 - 1ᵀb the active weights are
+/// ((Cs - Bα)Σ⁻¹1 + (Aα - Bs)Σ⁻¹μ) / D and TE² = (Cs² - 2Bsα + Aα²) / D. The sweep runs from
+/// the minimum tracking error, at α = Bs/A (zero, i.e. the benchmark itself, when the
+/// benchmark is fully invested), to the active return of holding only the best asset.
+pub fn optimize_active_frontier(
+    stats: &PortfolioStats,
+    benchmark_weights: &[f64],
+    n_points: usize,
+) -> Result<Vec<ActiveFrontierPoint>, QuarsError> {
+    let n = stats.assets.len();
+    if benchmark_weights.len() != n {
+        return Err(QuarsError::Config(format!(
+            "Got {} benchmark weights for {} assets",
+            benchmark_weights.len(),
+            n
+        )));
+    }
+    if n_points < 2 {
+        return Err(QuarsError::Config(
+            "The active frontier needs at least two points.".to_string(),
+        ));
+    }
+    let mean = &stats.mean_returns;
+    let benchmark = Array1::from(benchmark_weights.to_vec());
+    let cov_inv = invert_covariance(&stats.covariance)?;
+    let inv_ones = cov_inv.sum_axis(Axis(1));
+    let inv_mean = cov_inv.dot(mean);
+    let a = inv_ones.sum();
+    let b = inv_mean.sum();
+    let c = mean.dot(&inv_mean);
+    let d = a * c - b * b;
+    let s = 1.0 - benchmark.sum();
+    let min_active = b * s / a;
+    let max_active = mean.fold(f64::NEG_INFINITY, |acc, &m| acc.max(m)) - mean.dot(&benchmark);
+    if d <= 0.0 || max_active <= min_active {
+        return Err(QuarsError::Singular(
+            "Degenerate active frontier, no asset beats the minimum tracking error portfolio."
+                .to_string(),
+        ));
+    }
+    Ok((0..n_points)
+        .map(|i| {
+            let alpha = min_active + (max_active - min_active) * i as f64 / (n_points - 1) as f64;
+            let active = (&inv_ones * (c * s - b * alpha) + &inv_mean * (a * alpha - b * s)) / d;
+            let variance = (c * s * s - 2.0 * b * s * alpha + a * alpha * alpha) / d;
+            ActiveFrontierPoint {
+                weights: (&benchmark + &active).to_vec(),
+                active_weights: active.to_vec(),
+                active_return: alpha,
+                tracking_error: variance.max(0.0).sqrt(),
+            }
+        })
+        .collect())
 }
 
 fn optimize_risk_adjusted(
     stats: &PortfolioStats,
-    risk_free_rate: f64,
+    risk_free: f64,
     tau: f64,
     n_points: usize,
-) -> Result<OptimizationResults, Box<dyn Error>> {
+    explain: bool,
+) -> Result<OptimizationResults, QuarsError> {
     let n = stats.assets.len();
     let mean = stats.mean_returns.clone();
     let cov = stats.covariance.clone();
-    let daily_risk_free = annual_to_daily_rate(risk_free_rate);
-    let cov_inv: Array2<f64> = cov.clone().inv_into()?;
+    let cov_inv = invert_covariance(&cov)?;
     let ones = Array1::<f64>::ones(n);
-    let excess = &mean - ones.mapv(|_| daily_risk_free);
+    let excess = &mean - ones.mapv(|_| risk_free);
     let A = ones.dot(&cov_inv.dot(&ones));
     let B = ones.dot(&cov_inv.dot(&excess));
     let lambda_multiplier = (B - 2.0 * tau) / A;
     let factor = 1.0 / (2.0 * tau);
     let optimal_risky = cov_inv.dot(&(&excess - ones.mapv(|_| lambda_multiplier))) * factor;
+    if explain {
+        println!("[explain] Risk-adjusted MVO, tau = {}", tau);
+        println!("[explain]   risk-free rate per period = {:.6e}", risk_free);
+        println!("[explain]   cond(Σ) = {:.4e}", condition_number(&cov)?);
+        println!("[explain]   A = 1ᵀΣ⁻¹1 = {:.6e}", A);
+        println!("[explain]   B = 1ᵀΣ⁻¹(μ - r_f) = {:.6e}", B);
+        println!("[explain]   λ = (B - 2τ) / A = {:.6e}", lambda_multiplier);
+        println!(
+            "[explain]   unconstrained weights Σ⁻¹(μ - r_f) / 2τ = {:?}",
+            (cov_inv.dot(&excess) * factor).to_vec()
+        );
+        println!(
+            "[explain]   budget-constrained weights Σ⁻¹(μ - r_f - λ) / 2τ = {:?}",
+            optimal_risky.to_vec()
+        );
+    }
     let sum_weights = optimal_risky.sum();
     if (sum_weights - 1.0).abs() > 1e-6 {
-        return Err("Optimal risky weights do not sum to 1.".into());
+        return Err(QuarsError::Infeasible(
+            "Optimal risky weights do not sum to 1.".to_string(),
+        ));
     }
     let optimal_risky_return = mean.dot(&optimal_risky);
-    let variance_risky = optimal_risky.dot(&cov.dot(&optimal_risky));
-    let optimal_risky_std = variance_risky.sqrt();
-    let max_sharpe = (optimal_risky_return - daily_risk_free) / optimal_risky_std;
-    let max_leverage = 2.0;
-    let lambda_step = max_leverage / (n_points as f64 - 1.0);
-    let mut frontier = Vec::with_capacity(n_points);
-    for i in 0..n_points {
-        let leverage = i as f64 * lambda_step;
-        let risk_free_weight = 1.0 - leverage;
-        let scaled_risky: Vec<f64> = optimal_risky.mapv(|w| leverage * w).to_vec();
-        let portfolio_return = daily_risk_free + leverage * (optimal_risky_return - daily_risk_free);
-        let portfolio_std = leverage * optimal_risky_std;
-        let sharpe_ratio = if leverage > 0.0 {
-            (portfolio_return - daily_risk_free) / portfolio_std
-        } else {
-            0.0
-        };
-        frontier.push(FrontierPoint {
-            risk_free_weight,
-            risky_weights: scaled_risky,
-            expected_return: portfolio_return,
-            portfolio_std,
-            sharpe_ratio,
-        });
-    }
+    let optimal_risky_std = portfolio_variance(&optimal_risky, &cov)?.sqrt();
+    let max_sharpe =
+        sharpe_ratio(optimal_risky_return, risk_free, optimal_risky_std).unwrap_or(0.0);
+    let frontier = cal_frontier(
+        &optimal_risky,
+        optimal_risky_return,
+        optimal_risky_std,
+        risk_free,
+        n_points,
+    );
     Ok(OptimizationResults {
         frontier,
         optimal_risky_portfolio: optimal_risky.to_vec(),
         optimal_risky_return,
         optimal_risky_std,
         max_sharpe,
+        risk_free_weight: 0.0,
+        near_optimal: None,
+        certainty_equivalent: None,
     })
 }
 
-/// Near-optimality method implementation.
-/// Step 1: Compute classic MVO
-/// Use a simple closed-form approximation assuming an unconstrained problem:
-/// x_mvo ∝ Σ⁻¹ * μ, then normalize so that 1ᵀx = 1.
-fn optimize_near_optimal(
+/// Response of an asset's risk-adjusted weight to its own expected return
+#[derive(Debug, Clone, Serialize)]
+pub struct ReturnSensitivity {
+    pub asset: String,
+    pub weight: f64,
+    // Weights with the asset's mean return bumped up and down
+    pub weight_up: f64,
+    pub weight_down: f64,
+    // Central difference ∂w_i/∂μ_i, per unit of per-period return
+    pub gradient: f64,
+}
+
+/// What-if report of the risk-adjusted portfolio: each asset's per-period mean return is
+/// bumped by ±`bump` in turn, the portfolio re-optimized and the asset's own weight compared.
+/// The weights are linear in μ, so the gradient is exact, (Σ⁻¹_ii - (Σ⁻¹1)_i² / 1ᵀΣ⁻¹1) / 2τ,
+/// and largest for the assets of least variance. `risk_free` is per period.
+pub fn return_sensitivity(
+    stats: &PortfolioStats,
+    risk_free: f64,
+    tau: f64,
+    bump: f64,
+) -> Result<Vec<ReturnSensitivity>, QuarsError> {
+    if !bump.is_finite() || bump <= 0.0 {
+        return Err(QuarsError::Config(format!(
+            "The return sensitivity bump must be positive, got {}",
+            bump
+        )));
+    }
+    let optimize = |stats: &PortfolioStats| {
+        optimize_risk_adjusted(stats, risk_free, tau, 2, false)
+            .map(|results| results.optimal_risky_portfolio)
+    };
+    let base = optimize(stats)?;
+    let mut bumped = PortfolioStats {
+        assets: stats.assets.clone(),
+        mean_returns: stats.mean_returns.clone(),
+        covariance: stats.covariance.clone(),
+        returns_matrix: stats.returns_matrix.clone(),
+        dates: stats.dates.clone(),
+    };
+    let mut sensitivities = Vec::with_capacity(stats.assets.len());
+    for (i, asset) in stats.assets.iter().enumerate() {
+        bumped.mean_returns[i] = stats.mean_returns[i] + bump;
+        let weight_up = optimize(&bumped)?[i];
+        bumped.mean_returns[i] = stats.mean_returns[i] - bump;
+        let weight_down = optimize(&bumped)?[i];
+        bumped.mean_returns[i] = stats.mean_returns[i];
+        sensitivities.push(ReturnSensitivity {
+            asset: asset.clone(),
+            weight: base[i],
+            weight_up,
+            weight_down,
+            gradient: (weight_up - weight_down) / (2.0 * bump),
+        });
+    }
+    Ok(sensitivities)
+}
+
+/// Risk-adjusted allocation at one risk-free rate
+#[derive(Debug, Clone, Serialize)]
+pub struct RateScenario {
+    // Annual risk-free rate
+    pub risk_free_rate: f64,
+    // Fully invested risky portfolio and its per-period return and Sharpe ratio
+    pub weights: Vec<f64>,
+    pub risky_return: f64,
+    pub sharpe_ratio: f64,
+    // Share of the risky portfolio on the CAL chosen with the risk aversion τ,
+    // (μ_p - r_f) / 2τσ_p², the rest held at the risk-free rate
+    pub risky_share: f64,
+    // Per-period return r_f + y(μ_p - r_f) of that mix
+    pub expected_return: f64,
+}
+
+/// Response of the risk-adjusted allocation to a parallel shift of the risk-free rate
+#[derive(Debug, Clone, Serialize)]
+pub struct RateSensitivity {
+    pub base: RateScenario,
+    pub up: RateScenario,
+    pub down: RateScenario,
+    // Central differences per unit of annual rate
+    pub weight_gradients: Vec<f64>,
+    pub risky_share_gradient: f64,
+    pub return_gradient: f64,
+}
+
+/// What-if report of the rate assumption: the risk-adjusted portfolio is re-optimized with
+/// the annual risk-free rate shifted by ±`bump`. The budget constraint absorbs a parallel
+/// shift of the excess returns, so the risky weights do not move; the rate moves the Sharpe
+/// ratio and, through it, the split between the risky portfolio and the risk-free asset.
+pub fn rate_sensitivity(
     stats: &PortfolioStats,
     risk_free_rate: f64,
+    periods_per_year: f64,
+    compounding: Compounding,
     tau: f64,
-    theta: f64,
-    n_points: usize,
-) -> Result<OptimizationResults, Box<dyn Error>> {
-    let n = stats.assets.len();
-    let mean = stats.mean_returns.clone();
-    let cov = stats.covariance.clone();
-    let cov_inv: Array2<f64> = cov.clone().inv_into()?;
-    let daily_risk_free = annual_to_daily_rate(risk_free_rate);
-    let x_mvo_unnorm = cov_inv.dot(&mean);
-    let sum_x = x_mvo_unnorm.sum();
-    let x_mvo = x_mvo_unnorm.mapv(|val| val / sum_x);
-    // Compute ex ante utility based on standart MVO: ε = μᵀx_mvo - ½γ x_mvoᵀΣx_mvo
-    let epsilon = mean.dot(&x_mvo) - 0.5 * tau * x_mvo.dot(&cov.dot(&x_mvo));
-    dbg!(&mean);
-    dbg!(&cov);
-    // Minimize concentration (xᵀx)
-    // As noted in the paper, near-optimality approach is not strictly linear and a solution for the
-    // minimization of the objective function, such that the constraining portofolio utility is greater
-    // or equal to a preset percentace (Theta) of the standard optimized portfolio.
-    // A computationally cheap and easy initial solution to that is to initialize an equal-weight portofolio and blend with the
-    // classic MVO one, until the utility is at least θε
-    // Cons: depending on the picked assets, most of the cases will lead to an equal-weighted portofolio, since the variance in
-    // weights due to the the brute-forced alpha is very low.
-    // Convex quadratic solver could lead to optimal weights.
-    let x_equal = Array1::from_elem(n, 1.0 / n as f64);
-    let mut best_blend = x_mvo.clone();
+    bump: f64,
+) -> Result<RateSensitivity, QuarsError> {
+    if !bump.is_finite() || bump <= 0.0 {
+        return Err(QuarsError::Config(format!(
+            "The rate sensitivity bump must be positive, got {}",
+            bump
+        )));
+    }
+    let scenario = |rate: f64| -> Result<RateScenario, QuarsError> {
+        let risk_free = annual_to_period_rate(rate, periods_per_year, compounding);
+        let results = optimize_risk_adjusted(stats, risk_free, tau, 2, false)?;
+        let variance = results.optimal_risky_std.powi(2);
+        if variance < MIN_VOLATILITY * MIN_VOLATILITY {
+            return Err(QuarsError::Singular(
+                "The risk-adjusted portfolio carries no risk, the rate sensitivity is undefined."
+                    .to_string(),
+            ));
+        }
+        let risky_share = (results.optimal_risky_return - risk_free) / (2.0 * tau * variance);
+        Ok(RateScenario {
+            risk_free_rate: rate,
+            weights: results.optimal_risky_portfolio,
+            risky_return: results.optimal_risky_return,
+            sharpe_ratio: results.max_sharpe,
+            risky_share,
+            expected_return: risk_free + risky_share * (results.optimal_risky_return - risk_free),
+        })
+    };
+    let base = scenario(risk_free_rate)?;
+    let up = scenario(risk_free_rate + bump)?;
+    let down = scenario(risk_free_rate - bump)?;
+    let gradient = |up: f64, down: f64| (up - down) / (2.0 * bump);
+    Ok(RateSensitivity {
+        weight_gradients: up
+            .weights
+            .iter()
+            .zip(&down.weights)
+            .map(|(&w_up, &w_down)| gradient(w_up, w_down))
+            .collect(),
+        risky_share_gradient: gradient(up.risky_share, down.risky_share),
+        return_gradient: gradient(up.expected_return, down.expected_return),
+        base,
+        up,
+        down,
+    })
+}
 
-    // Initialize concentration with the classic mvo weights
-    let mut weights_concentration = x_mvo.t().dot(&x_mvo);
+/// Criterion the walk-forward selection of τ ranks the out-of-sample performance by
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TauObjective {
+    // Highest Sharpe ratio
+    Sharpe,
+    // Shallowest maximum drawdown
+    Drawdown,
+}
 
-    // Set an arbitrary parameter alpha_0 equal to 1, minimizing at each iteration
-    // TODO, implement convex quadratic optimization
-    for i in 0..=100 {
-        let alpha = i as f64 / 100.0;
-        let x_blend = &x_equal * (1.0 - alpha) + &x_mvo * alpha;
-        let utility = mean.dot(&x_blend) - 0.5 * tau * x_blend.dot(&cov.dot(&x_blend));
-        // (x_blend.sum() - 1.0).abs() < 1e-6: for floating point tolerance
-        if utility >= theta * epsilon
-            && (x_blend.sum() - 1.0).abs() < 1e-6
-        {
-            // xᵀx
-            let concentration = x_blend.t().dot(&x_blend);
-            if concentration < weights_concentration {
-                print!("Concentraton: {:?}, best x blend {:?}, utility {:?}", concentration, x_blend, utility);
-                weights_concentration = concentration;
-                best_blend = x_blend;
-            }
+impl TauObjective {
+    pub fn from_config(objective: &str) -> Result<Self, QuarsError> {
+        match objective {
+            "sharpe" => Ok(Self::Sharpe),
+            "drawdown" => Ok(Self::Drawdown),
+            other => Err(QuarsError::Config(format!(
+                "Unknown tau_objective '{}', use sharpe or drawdown",
+                other
+            ))),
         }
     }
+}
 
-    // repeat for inverse case
-    for i in 0..=100 {
-        let alpha = i as f64 / 100.0;
-        let x_blend = &x_equal * alpha + &x_mvo * (1.0 - alpha);
-        let utility = mean.dot(&x_blend) - 0.5 * tau * x_blend.dot(&cov.dot(&x_blend));
-        // (x_blend.sum() - 1.0).abs() < 1e-6: for floating point tolerance
-        if utility >= theta * epsilon
-            && (x_blend.sum() - 1.0).abs() < 1e-6
-        {
-            // xᵀx
-            let concentration = x_blend.t().dot(&x_blend);
-            if concentration < weights_concentration {
-                print!("Concentraton: {:?}, best x blend {:?}, utility {:?}", concentration, x_blend, utility);
-                weights_concentration = concentration;
-                best_blend = x_blend;
-            }
-        }
+/// Out-of-sample performance of the risk-adjusted portfolio with one τ, per period
+#[derive(Debug, Clone, Serialize)]
+pub struct WalkForward {
+    pub tau: f64,
+    pub mean_return: f64,
+    pub volatility: f64,
+    // None when the returns carry no risk
+    pub sharpe_ratio: Option<f64>,
+    pub max_drawdown: f64,
+    // Returns from the first rebalance with a full estimation window on
+    pub returns: Vec<f64>,
+}
+
+/// Rolling backtest of the risk-adjusted method with `tau`: at each rebalance the portfolio is
+/// optimized on the `window` returns before it only, and held over the returns up to the next
+/// rebalance. The rebalances with a shorter history are skipped. `risk_free` is per period.
+pub fn walk_forward(
+    stats: &PortfolioStats,
+    rebalances: &[usize],
+    window: usize,
+    tau: f64,
+    risk_free: f64,
+) -> Result<WalkForward, QuarsError> {
+    let n_periods = stats.returns_matrix.ncols();
+    let starts: Vec<usize> = rebalances
+        .iter()
+        .copied()
+        .filter(|&t| t >= window && t < n_periods)
+        .collect();
+    if window < 2 || starts.is_empty() {
+        return Err(QuarsError::InsufficientData(format!(
+            "No rebalance has {} returns before it to estimate on",
+            window
+        )));
     }
-    let optimal_risky = best_blend;
-    let optimal_risky_return = mean.dot(&optimal_risky);
-    let variance_risky = optimal_risky.dot(&cov.dot(&optimal_risky));
-    let optimal_risky_std = variance_risky.sqrt();
-    let max_sharpe = (optimal_risky_return - daily_risk_free) / optimal_risky_std;
+    let mut returns = Vec::with_capacity(n_periods - starts[0]);
+    for (k, &start) in starts.iter().enumerate() {
+        let end = starts.get(k + 1).copied().unwrap_or(n_periods);
+        let estimation: Vec<usize> = (start - window..start).collect();
+        let weights = optimize_risk_adjusted(
+            &stats.resample_periods(&estimation)?,
+            risk_free,
+            tau,
+            2,
+            false,
+        )?
+        .optimal_risky_portfolio;
+        returns.extend(compute_portfolio_returns(
+            &stats.returns_matrix.slice(s![.., start..end]).to_owned(),
+            &weights,
+        ));
+    }
+    let n = returns.len() as f64;
+    let mean_return = returns.iter().sum::<f64>() / n;
+    let volatility = if returns.len() > 1 {
+        (returns
+            .iter()
+            .map(|r| (r - mean_return).powi(2))
+            .sum::<f64>()
+            / (n - 1.0))
+            .sqrt()
+    } else {
+        0.0
+    };
+    Ok(WalkForward {
+        tau,
+        mean_return,
+        volatility,
+        sharpe_ratio: (volatility >= MIN_VOLATILITY)
+            .then(|| (mean_return - risk_free) / volatility),
+        max_drawdown: max_drawdown(&returns),
+        returns,
+    })
+}
 
-    let max_leverage = 2.0;
-    let lambda_step = max_leverage / (n_points as f64 - 1.0);
-    let mut frontier = Vec::with_capacity(n_points);
-    for i in 0..n_points {
-        let leverage = i as f64 * lambda_step;
-        let risk_free_weight = 1.0 - leverage;
-        let scaled_risky: Vec<f64> = optimal_risky.mapv(|w| leverage * w).to_vec();
-        let portfolio_return = daily_risk_free + leverage * (optimal_risky_return - daily_risk_free);
-        let portfolio_std = leverage * optimal_risky_std;
-        let sharpe_ratio = if leverage > 0.0 {
-            (portfolio_return - daily_risk_free) / portfolio_std
-        } else {
-            0.0
-        };
-        frontier.push(FrontierPoint {
-            risk_free_weight,
-            risky_weights: scaled_risky,
-            expected_return: portfolio_return,
-            portfolio_std,
-            sharpe_ratio,
-        });
+/// Walk-forward selection of τ: runs `walk_forward` for each τ of the grid and returns the
+/// index of the best one by `objective`, with every run. Each τ is judged on returns earned
+/// after the windows its portfolios were estimated on.
+pub fn select_tau(
+    stats: &PortfolioStats,
+    rebalances: &[usize],
+    window: usize,
+    taus: &[f64],
+    objective: TauObjective,
+    risk_free: f64,
+) -> Result<(usize, Vec<WalkForward>), QuarsError> {
+    if let Some(tau) = taus.iter().find(|tau| !tau.is_finite() || **tau <= 0.0) {
+        return Err(QuarsError::Config(format!(
+            "The tau grid must hold positive values, got {}",
+            tau
+        )));
     }
+    let runs = taus
+        .iter()
+        .map(|&tau| walk_forward(stats, rebalances, window, tau, risk_free))
+        .collect::<Result<Vec<_>, _>>()?;
+    let score = |run: &WalkForward| match objective {
+        TauObjective::Sharpe => run.sharpe_ratio.unwrap_or(f64::NEG_INFINITY),
+        TauObjective::Drawdown => run.max_drawdown,
+    };
+    let best = (0..runs.len())
+        .max_by(|&a, &b| score(&runs[a]).total_cmp(&score(&runs[b])))
+        .ok_or_else(|| QuarsError::Config("The tau grid is empty".to_string()))?;
+    Ok((best, runs))
+}
 
+/// Exponential utility (CARA) investor: with normal returns, maximizing the expected utility
+/// -exp(-γW) amounts to maximizing the certainty equivalent μᵀx - (γ/2)xᵀΣx. Under the budget
+/// 1ᵀx = 1 the solution is x = Σ⁻¹(μ - λ1)/γ with λ = (1ᵀΣ⁻¹μ - γ) / 1ᵀΣ⁻¹1. The frontier is
+/// the CAL through the resulting portfolio.
+pub fn optimize_cara(
+    stats: &PortfolioStats,
+    gamma: f64,
+    risk_free: f64,
+    n_points: usize,
+    explain: bool,
+) -> Result<OptimizationResults, QuarsError> {
+    if gamma <= 0.0 {
+        return Err(QuarsError::Config(format!(
+            "The CARA risk aversion must be positive, got {}",
+            gamma
+        )));
+    }
+    let n = stats.assets.len();
+    let mean = &stats.mean_returns;
+    let cov_inv = invert_covariance(&stats.covariance)?;
+    let ones = Array1::<f64>::ones(n);
+    let a = ones.dot(&cov_inv.dot(&ones));
+    let b = ones.dot(&cov_inv.dot(mean));
+    let lambda = (b - gamma) / a;
+    let weights = cov_inv.dot(&mean.mapv(|m| m - lambda)) / gamma;
+    let certainty_equivalent =
+        mean.dot(&weights) - 0.5 * gamma * portfolio_variance(&weights, &stats.covariance)?;
+    if explain {
+        println!("[explain] CARA utility, gamma = {}", gamma);
+        println!("[explain]   1ᵀΣ⁻¹1 = {:.6e}, 1ᵀΣ⁻¹μ = {:.6e}", a, b);
+        println!("[explain]   λ = (1ᵀΣ⁻¹μ - γ) / 1ᵀΣ⁻¹1 = {:.6e}", lambda);
+        println!(
+            "[explain]   certainty equivalent μᵀx - (γ/2)xᵀΣx = {:.6e}",
+            certainty_equivalent
+        );
+    }
     Ok(OptimizationResults {
-        frontier,
-        optimal_risky_portfolio: optimal_risky.to_vec(),
-        optimal_risky_return,
-        optimal_risky_std,
-        max_sharpe,
+        certainty_equivalent: Some(certainty_equivalent),
+        ..heuristic_results(stats, weights, risk_free, n_points)?
     })
 }
 
-pub fn annual_to_daily_rate(r_annual: f64) -> f64 {
-    (1.0 + r_annual).powf(1.0 / 252.0) - 1.0
-}
\ No newline at end of file
+/// Point of the return, risk and concentration frontier
+#[derive(Debug, Clone, Serialize)]
+pub struct ConcentrationPoint {
+    // Per-period concentration penalty δ
+    pub penalty: f64,
+    pub weights: Vec<f64>,
+    pub expected_return: f64,
+    pub portfolio_std: f64,
+    // xᵀx, from 1/n for equal weights up to 1 for a single asset
+    pub concentration: f64,
+    // No other point of the sweep is at least as good on all three objectives and better on one
+    pub pareto_efficient: bool,
+}
+
+/// Multi-objective sweep over return, risk and concentration: for each penalty δ the fully
+/// invested portfolio maximizing μᵀx - (γ/2)xᵀΣx - δxᵀx, x = M⁻¹(μ - λ1) with M = γΣ + 2δI
+/// and λ = (1ᵀM⁻¹μ - 1) / 1ᵀM⁻¹1. δ = 0 gives the mean-variance portfolio of `optimize_cara`,
+/// and a growing δ pulls the weights toward equal weights, lowering xᵀx monotonically. This is
+/// the principled form of the near-optimal blend. The points follow the order of the penalties.
+pub fn concentration_frontier(
+    stats: &PortfolioStats,
+    gamma: f64,
+    penalties: &[f64],
+) -> Result<Vec<ConcentrationPoint>, QuarsError> {
+    if gamma <= 0.0 {
+        return Err(QuarsError::Config(format!(
+            "The risk aversion must be positive, got {}",
+            gamma
+        )));
+    }
+    if let Some(penalty) = penalties.iter().find(|p| !p.is_finite() || **p < 0.0) {
+        return Err(QuarsError::Config(format!(
+            "Concentration penalties must be non-negative, got {}",
+            penalty
+        )));
+    }
+    let n = stats.assets.len();
+    let mean = &stats.mean_returns;
+    let ones = Array1::<f64>::ones(n);
+    let mut points = Vec::with_capacity(penalties.len());
+    for &penalty in penalties {
+        let m = &stats.covariance * gamma + Array2::<f64>::eye(n) * (2.0 * penalty);
+        let m_inv = utils::timed("covariance inversion", || m.inv_into())?;
+        let m_inv_ones = m_inv.dot(&ones);
+        let lambda = (m_inv_ones.dot(mean) - 1.0) / m_inv_ones.sum();
+        let weights = m_inv.dot(&mean.mapv(|m| m - lambda));
+        points.push(ConcentrationPoint {
+            penalty,
+            expected_return: mean.dot(&weights),
+            portfolio_std: portfolio_variance(&weights, &stats.covariance)?.sqrt(),
+            concentration: weights.dot(&weights),
+            weights: weights.to_vec(),
+            pareto_efficient: true,
+        });
+    }
+    let objectives: Vec<[f64; 3]> = points
+        .iter()
+        .map(|pt| [-pt.expected_return, pt.portfolio_std, pt.concentration])
+        .collect();
+    for (point, own) in points.iter_mut().zip(&objectives) {
+        point.pareto_efficient = !objectives.iter().any(|other| {
+            other.iter().zip(own).all(|(o, s)| o <= s) && other.iter().zip(own).any(|(o, s)| o < s)
+        });
+    }
+    Ok(points)
+}
+
+/// Post-modern portfolio theory allocation: the fully invested portfolio of least downside
+/// variance xᵀSx, with S the semi-covariance of the returns below the per-period
+/// `downside_target`, i.e. x = S⁻¹1 / 1ᵀS⁻¹1. S is often singular, e.g. when an asset rarely
+/// falls below the target, so it goes through the nearest positive definite fix first. The
+/// frontier is the CAL through the resulting portfolio.
+pub fn optimize_min_semivariance(
+    stats: &PortfolioStats,
+    downside_target: f64,
+    risk_free: f64,
+    n_points: usize,
+    explain: bool,
+) -> Result<OptimizationResults, QuarsError> {
+    let semi_cov = semi_covariance(&stats.returns_matrix, downside_target)?;
+    let semi_cov = nearest_positive_definite(&semi_cov)?;
+    let direction = invert_covariance(&semi_cov)?.dot(&Array1::<f64>::ones(stats.assets.len()));
+    let weights = &direction / direction.sum();
+    if explain {
+        println!(
+            "[explain] Minimum semivariance below {:.6e} per period",
+            downside_target
+        );
+        println!(
+            "[explain]   downside deviation √(xᵀSx) = {:.6e}",
+            portfolio_variance(&weights, &semi_cov)?.sqrt()
+        );
+    }
+    heuristic_results(stats, weights, risk_free, n_points)
+}
+
+/// Minimum correlation algorithm (Varadi, Kapler and Bee), a diversification heuristic that
+/// needs no matrix inversion. Each asset's average correlation with the rest of the universe
+/// is standardized across assets and mapped through 1 - Φ(z), so that the least correlated
+/// assets score highest, and the scores are scaled by inverse volatility. The frontier is the
+/// CAL through the resulting portfolio.
+pub fn optimize_min_correlation(
+    stats: &PortfolioStats,
+    risk_free: f64,
+    n_points: usize,
+    explain: bool,
+) -> Result<OptimizationResults, QuarsError> {
+    let n = stats.assets.len();
+    let vols = asset_volatilities(stats, "min-correlation")?;
+    let weights = if n < 2 {
+        Array1::ones(n)
+    } else {
+        let corr = stats.correlation();
+        // The diagonal holds the unit self-correlations
+        let avg_corr = (corr.sum_axis(Axis(1)) - 1.0) / (n - 1) as f64;
+        let corr_mean = avg_corr.mean().unwrap_or(0.0);
+        let corr_std = avg_corr.std(0.0);
+        // All assets score the same when their average correlations do not vary
+        let scores = if corr_std < 1e-12 {
+            Array1::from_elem(n, 0.5)
+        } else {
+            avg_corr.mapv(|c| 1.0 - normal_cdf((c - corr_mean) / corr_std))
+        };
+        if explain {
+            println!("[explain] Minimum correlation algorithm");
+            println!("[explain]   average correlations = {:?}", avg_corr.to_vec());
+            println!("[explain]   scores 1 - Φ(z) = {:?}", scores.to_vec());
+        }
+        scores / &vols
+    };
+    heuristic_results(stats, &weights / weights.sum(), risk_free, n_points)
+}
+
+/// Inverse volatility weighting, x_i ∝ 1/σ_i, a naive risk parity that ignores correlations.
+/// The frontier is the CAL through the resulting portfolio.
+pub fn optimize_inverse_volatility(
+    stats: &PortfolioStats,
+    risk_free: f64,
+    n_points: usize,
+    explain: bool,
+) -> Result<OptimizationResults, QuarsError> {
+    let inverse_vols = asset_volatilities(stats, "inverse-vol")?.mapv(|v| 1.0 / v);
+    let weights = &inverse_vols / inverse_vols.sum();
+    if explain {
+        println!("[explain] Inverse volatility weighting");
+        println!("[explain]   1/σ = {:?}", inverse_vols.to_vec());
+    }
+    heuristic_results(stats, weights, risk_free, n_points)
+}
+
+/// Global minimum-variance portfolio x = Σ⁻¹1 / 1ᵀΣ⁻¹1, fully invested and short sales
+/// allowed, which ignores the mean returns altogether. The frontier is the CAL through it.
+pub fn optimize_min_variance(
+    stats: &PortfolioStats,
+    risk_free: f64,
+    n_points: usize,
+    explain: bool,
+) -> Result<OptimizationResults, QuarsError> {
+    let inv_ones = invert_covariance(&stats.covariance)?.dot(&Array1::ones(stats.assets.len()));
+    let weights = &inv_ones / inv_ones.sum();
+    if explain {
+        println!("[explain] Global minimum variance");
+        println!("[explain]   Σ⁻¹1 = {:?}", inv_ones.to_vec());
+    }
+    heuristic_results(stats, weights, risk_free, n_points)
+}
+
+// Iteration budget and tolerance on the spread of the risk contributions of risk parity
+const RISK_PARITY_MAX_ITERATIONS: usize = 10_000;
+const RISK_PARITY_TOLERANCE: f64 = 1e-10;
+
+/// Risk parity, the long-only portfolio whose assets contribute equally to its variance,
+/// x_i (Σx)_i = xᵀΣx / n. Unlike inverse volatility it accounts for the correlations. The
+/// weights minimize ½xᵀΣx - (1/n) Σ ln x_i, solved by cyclical coordinate descent, each x_i the
+/// positive root of Σ_ii x_i² + (Σx - Σ_ii x_i)_i x_i - 1/n = 0, and are then normalized.
+/// The frontier is the CAL through the resulting portfolio.
+pub fn optimize_risk_parity(
+    stats: &PortfolioStats,
+    risk_free: f64,
+    n_points: usize,
+    explain: bool,
+) -> Result<OptimizationResults, QuarsError> {
+    let n = stats.assets.len();
+    let cov = &stats.covariance;
+    let vols = asset_volatilities(stats, "risk-parity")?;
+    let budget = 1.0 / n as f64;
+    // Inverse volatility, exact for uncorrelated assets
+    let mut x = vols.mapv(|v| 1.0 / v);
+    let mut iterations = 0;
+    loop {
+        for i in 0..n {
+            let others = cov.row(i).dot(&x) - cov[[i, i]] * x[i];
+            x[i] = (-others + (others * others + 4.0 * cov[[i, i]] * budget).sqrt())
+                / (2.0 * cov[[i, i]]);
+        }
+        iterations += 1;
+        let contributions = &x * &cov.dot(&x);
+        let total = contributions.sum();
+        let spread = contributions
+            .iter()
+            .fold(0.0_f64, |acc, c| acc.max((c / total - budget).abs()));
+        if spread < RISK_PARITY_TOLERANCE {
+            break;
+        }
+        if iterations == RISK_PARITY_MAX_ITERATIONS {
+            return Err(QuarsError::Infeasible(format!(
+                "Risk parity did not converge in {} iterations, the risk contributions are still {:e} apart",
+                iterations, spread
+            )));
+        }
+    }
+    let weights = &x / x.sum();
+    if explain {
+        println!(
+            "[explain] Risk parity, {} coordinate descent sweeps",
+            iterations
+        );
+        println!(
+            "[explain]   risk contributions x_i(Σx)_i / xᵀΣx = {:?}",
+            (&weights * &cov.dot(&weights) / portfolio_variance(&weights, cov)?).to_vec()
+        );
+    }
+    heuristic_results(stats, weights, risk_free, n_points)
+}
+
+// Inverse of a covariance matrix, timed as its own stage since it dominates on large universes
+fn invert_covariance(cov: &Array2<f64>) -> Result<Array2<f64>, QuarsError> {
+    Ok(utils::timed("covariance inversion", || {
+        cov.clone().inv_into()
+    })?)
+}
+
+// Asset volatilities, which the heuristic methods divide by, so they must be non-zero
+fn asset_volatilities(stats: &PortfolioStats, method: &str) -> Result<Array1<f64>, QuarsError> {
+    let vols = stats.asset_volatilities();
+    if vols.iter().any(|&v| !v.is_finite() || v < MIN_VOLATILITY) {
+        return Err(QuarsError::InsufficientData(format!(
+            "The {} method needs a non-zero volatility for every asset.",
+            method
+        )));
+    }
+    Ok(vols)
+}
+
+// Results of a single fully invested portfolio, e.g. a heuristic method's, with the CAL
+// through it
+fn heuristic_results(
+    stats: &PortfolioStats,
+    optimal_risky: Array1<f64>,
+    risk_free: f64,
+    n_points: usize,
+) -> Result<OptimizationResults, QuarsError> {
+    let optimal_risky_return = stats.mean_returns.dot(&optimal_risky);
+    let optimal_risky_std = portfolio_variance(&optimal_risky, &stats.covariance)?.sqrt();
+    let max_sharpe =
+        sharpe_ratio(optimal_risky_return, risk_free, optimal_risky_std).unwrap_or(0.0);
+    let frontier = cal_frontier(
+        &optimal_risky,
+        optimal_risky_return,
+        optimal_risky_std,
+        risk_free,
+        n_points,
+    );
+    Ok(OptimizationResults {
+        frontier,
+        optimal_risky_portfolio: optimal_risky.to_vec(),
+        optimal_risky_return,
+        optimal_risky_std,
+        max_sharpe,
+        risk_free_weight: 0.0,
+        near_optimal: None,
+        certainty_equivalent: None,
+    })
+}
+
+/// Optimization with an explicit cash asset in the universe. The cash asset is the risk-free
+/// leg, with its mean return as the risk-free rate: the configured method runs on the risky
+/// assets alone, against that rate, and the CAL through its portfolio starts at the cash mean.
+/// The risky exposure on the CAL maximizes (μ_p - r_f)y - τσ_p²y² with the method's risk
+/// aversion, y = (μ_p - r_f) / (2τσ_p²), and cash absorbs the rest, 1 - y. The risky weights
+/// are embedded back into the full universe with a zero cash weight, the cash allocation is
+/// only `risk_free_weight`.
+fn optimize_with_cash(
+    stats: &PortfolioStats,
+    cash_ticker: &str,
+    opt_method: MvoOptMethod,
+    n_points: usize,
+    po: &PortofolioOptimization,
+) -> Result<OptimizationResults, QuarsError> {
+    let tau = opt_method.tau().ok_or_else(|| {
+        QuarsError::Config(
+            "cash_ticker requires a risk aversion, use a tau-based sub_method".to_string(),
+        )
+    })?;
+    let cash_idx = stats
+        .assets
+        .iter()
+        .position(|asset| asset == cash_ticker)
+        .ok_or_else(|| {
+            QuarsError::Config(format!("Cash ticker {} not found in the data", cash_ticker))
+        })?;
+    let risky_idx: Vec<usize> = (0..stats.assets.len()).filter(|&i| i != cash_idx).collect();
+    if risky_idx.is_empty() {
+        return Err(QuarsError::Config(
+            "At least one risky asset is needed besides the cash asset.".to_string(),
+        ));
+    }
+    let risky = stats.subset(&risky_idx);
+    let risk_free = stats.mean_returns[cash_idx];
+    check_positive_definite(&risky.covariance)?;
+    let results = optimize_with_method(&risky, opt_method, risk_free, n_points, po)?;
+
+    let variance = results.optimal_risky_std.powi(2);
+    if variance < MIN_VOLATILITY * MIN_VOLATILITY {
+        return Err(QuarsError::Singular(
+            "The risky portfolio carries no risk, the cash allocation is undefined.".to_string(),
+        ));
+    }
+    let exposure = (results.optimal_risky_return - risk_free) / (2.0 * tau * variance);
+
+    // Embed the risky weights back into the full universe, none in cash
+    let embed = |weights: &[f64]| -> Vec<f64> {
+        let mut full = vec![0.0; stats.assets.len()];
+        for (w, &i) in weights.iter().zip(risky_idx.iter()) {
+            full[i] = *w;
+        }
+        full
+    };
+    Ok(OptimizationResults {
+        frontier: results
+            .frontier
+            .into_iter()
+            .map(|mut pt| {
+                pt.risky_weights = embed(&pt.risky_weights);
+                pt
+            })
+            .collect(),
+        optimal_risky_portfolio: embed(&results.optimal_risky_portfolio),
+        risk_free_weight: 1.0 - exposure,
+        ..results
+    })
+}
+
+/// Near-optimality method implementation.
+/// Step 1: Compute classic MVO
+/// Use a simple closed-form approximation assuming an unconstrained problem:
+/// x_mvo ∝ Σ⁻¹ * μ, then normalize so that 1ᵀx = 1.
+fn optimize_near_optimal(
+    stats: &PortfolioStats,
+    risk_free: f64,
+    tau: f64,
+    theta: f64,
+    blend_steps: usize,
+    n_points: usize,
+    explain: bool,
+) -> Result<OptimizationResults, QuarsError> {
+    let n = stats.assets.len();
+    let mean = stats.mean_returns.clone();
+    let cov = stats.covariance.clone();
+    let cov_inv = invert_covariance(&cov)?;
+    let x_mvo_unnorm = cov_inv.dot(&mean);
+    let sum_x = x_mvo_unnorm.sum();
+    let x_mvo = x_mvo_unnorm.mapv(|val| val / sum_x);
+    // Compute ex ante utility based on standart MVO: ε = μᵀx_mvo - ½γ x_mvoᵀΣx_mvo
+    let epsilon = mean.dot(&x_mvo) - 0.5 * tau * portfolio_variance(&x_mvo, &cov)?;
+    if explain {
+        println!(
+            "[explain] Near-optimal MVO, tau = {}, theta = {}",
+            tau, theta
+        );
+        println!("[explain]   cond(Σ) = {:.4e}", condition_number(&cov)?);
+        println!("[explain]   mean returns μ = {:?}", mean.to_vec());
+        println!(
+            "[explain]   unnormalized weights Σ⁻¹μ = {:?}",
+            x_mvo_unnorm.to_vec()
+        );
+        println!("[explain]   classic MVO weights = {:?}", x_mvo.to_vec());
+        println!(
+            "[explain]   MVO utility ε = {:.6e}, required θε = {:.6e}",
+            epsilon,
+            theta * epsilon
+        );
+    }
+    // Minimize concentration (xᵀx)
+    // As noted in the paper, near-optimality approach is not strictly linear and a solution for the
+    // minimization of the objective function, such that the constraining portofolio utility is greater
+    // or equal to a preset percentace (Theta) of the standard optimized portfolio.
+    // A computationally cheap and easy initial solution to that is to initialize an equal-weight portofolio and blend with the
+    // classic MVO one, until the utility is at least θε
+    // Cons: depending on the picked assets, most of the cases will lead to an equal-weighted portofolio, since the variance in
+    // weights due to the the brute-forced alpha is very low.
+    // Convex quadratic solver could lead to optimal weights.
+    let x_equal = Array1::from_elem(n, 1.0 / n as f64);
+    let utility = |x: &Array1<f64>| mean.dot(x) - 0.5 * tau * x.dot(&cov.dot(x));
+    let blend = blend_search(&x_equal, &x_mvo, blend_steps, theta * epsilon, utility);
+    if explain {
+        println!(
+            "[explain]   {} blend steps, chosen alpha = {:.6}, utility {:.6e} (required {:.6e}), concentration {:.6}",
+            blend_steps,
+            blend.alpha,
+            blend.utility,
+            blend.required_utility,
+            blend.concentration
+        );
+    }
+    // TODO, implement convex quadratic optimization
+    let optimal_risky = &x_equal * (1.0 - blend.alpha) + &x_mvo * blend.alpha;
+    let optimal_risky_return = mean.dot(&optimal_risky);
+    let optimal_risky_std = portfolio_variance(&optimal_risky, &cov)?.sqrt();
+    let max_sharpe =
+        sharpe_ratio(optimal_risky_return, risk_free, optimal_risky_std).unwrap_or(0.0);
+
+    let frontier = cal_frontier(
+        &optimal_risky,
+        optimal_risky_return,
+        optimal_risky_std,
+        risk_free,
+        n_points,
+    );
+
+    Ok(OptimizationResults {
+        frontier,
+        optimal_risky_portfolio: optimal_risky.to_vec(),
+        optimal_risky_return,
+        optimal_risky_std,
+        max_sharpe,
+        risk_free_weight: 0.0,
+        near_optimal: Some(blend),
+        certainty_equivalent: None,
+    })
+}
+
+/// Grid search over blends x(α) = (1 - α)·x_equal + α·x_mvo, α = i / steps, for the least
+/// concentrated (min xᵀx) blend whose utility reaches `required_utility`. The utility is
+/// concave in α, so the feasible blends form one run of the grid: the search sweeps in from
+/// the equal-weight end and from the MVO end to the first feasible blend of each, and only
+/// the run between them is scanned. Falls back to the MVO portfolio (α = 1) when no blend is
+/// less concentrated.
+fn blend_search(
+    x_equal: &Array1<f64>,
+    x_mvo: &Array1<f64>,
+    steps: usize,
+    required_utility: f64,
+    utility: impl Fn(&Array1<f64>) -> f64,
+) -> NearOptimalBlend {
+    let mut best = NearOptimalBlend {
+        alpha: 1.0,
+        utility: utility(x_mvo),
+        required_utility,
+        concentration: x_mvo.dot(x_mvo),
+    };
+    let steps = steps.max(1);
+    let alpha = |i: usize| i as f64 / steps as f64;
+    let blend = |i: usize| x_equal * (1.0 - alpha(i)) + x_mvo * alpha(i);
+    // (x_blend.sum() - 1.0).abs() < 1e-6: for floating point tolerance
+    let feasible = |i: usize| {
+        let x_blend = blend(i);
+        utility(&x_blend) >= required_utility && (x_blend.sum() - 1.0).abs() < 1e-6
+    };
+    let Some(first) = (0..=steps).find(|&i| feasible(i)) else {
+        return best;
+    };
+    let last = (first..=steps)
+        .rev()
+        .find(|&i| feasible(i))
+        .unwrap_or(first);
+    for i in first..=last {
+        let x_blend = blend(i);
+        let concentration = x_blend.dot(&x_blend);
+        if concentration < best.concentration {
+            best = NearOptimalBlend {
+                alpha: alpha(i),
+                utility: utility(&x_blend),
+                required_utility,
+                concentration,
+            };
+        }
+    }
+    best
+}
+
+/// Placement of the target returns along the minimum-variance frontier
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrontierSpacing {
+    // Evenly spaced target returns
+    Uniform,
+    // Denser where the frontier bends, around the minimum-variance vertex
+    Adaptive,
+}
+
+impl FrontierSpacing {
+    pub fn from_config(spacing: &str) -> Result<Self, QuarsError> {
+        match spacing {
+            "uniform" => Ok(Self::Uniform),
+            "adaptive" => Ok(Self::Adaptive),
+            other => Err(QuarsError::Config(format!(
+                "Unknown frontier_spacing '{}', use uniform or adaptive",
+                other
+            ))),
+        }
+    }
+}
+
+// Fine grid resolution, per requested point, of the adaptive curvature estimate
+const ADAPTIVE_OVERSAMPLING: usize = 20;
+
+/// Constants (A, B, C) = (1ᵀΣ⁻¹1, μᵀΣ⁻¹1, μᵀΣ⁻¹μ) parameterizing the minimum-variance frontier
+/// of the risky assets, fully invested and unconstrained. Its vertex, the global
+/// minimum-variance portfolio, has return B/A and variance 1/A, and `frontier_variance` gives
+/// the variance at any other target return.
+pub fn frontier_constants(stats: &PortfolioStats) -> Result<(f64, f64, f64), QuarsError> {
+    let (inv_ones, inv_mean) = frontier_directions(stats)?;
+    Ok(constants_of(stats, &inv_ones, &inv_mean))
+}
+
+/// Variance σ²(m) = (Am² - 2Bm + C) / (AC - B²) of the minimum-variance portfolio with expected
+/// return m, from the constants of `frontier_constants`
+pub fn frontier_variance((a, b, c): (f64, f64, f64), target_return: f64) -> f64 {
+    let m = target_return;
+    ((a * m * m - 2.0 * b * m + c) / (a * c - b * b)).max(0.0)
+}
+
+// Σ⁻¹1 and Σ⁻¹μ, which span the minimum-variance portfolios
+fn frontier_directions(stats: &PortfolioStats) -> Result<(Array1<f64>, Array1<f64>), QuarsError> {
+    let cov_inv = invert_covariance(&stats.covariance)?;
+    let ones = Array1::<f64>::ones(stats.assets.len());
+    Ok((cov_inv.dot(&ones), cov_inv.dot(&stats.mean_returns)))
+}
+
+fn constants_of(
+    stats: &PortfolioStats,
+    inv_ones: &Array1<f64>,
+    inv_mean: &Array1<f64>,
+) -> (f64, f64, f64) {
+    let mean = &stats.mean_returns;
+    (inv_ones.sum(), mean.dot(inv_ones), mean.dot(inv_mean))
+}
+
+/// Efficient part of the minimum-variance frontier of the risky assets alone, fully invested
+/// and unconstrained, from the global minimum-variance portfolio up to the highest asset mean.
+/// With the constants (A, B, C) of `frontier_constants` and D = AC - B², the portfolio with
+/// expected return m is x = Σ⁻¹((C - Bm)1 + (Am - B)μ) / D, of variance `frontier_variance`.
+pub fn min_variance_frontier(
+    stats: &PortfolioStats,
+    risk_free: f64,
+    n_points: usize,
+    spacing: FrontierSpacing,
+) -> Result<Vec<FrontierPoint>, QuarsError> {
+    if n_points < 2 {
+        return Err(QuarsError::Config(
+            "The minimum-variance frontier needs at least two points.".to_string(),
+        ));
+    }
+    let (inv_ones, inv_mean) = frontier_directions(stats)?;
+    let constants = constants_of(stats, &inv_ones, &inv_mean);
+    let (a, b, c) = constants;
+    let d = a * c - b * b;
+    let min_variance_return = b / a;
+    let max_return = stats
+        .mean_returns
+        .fold(f64::NEG_INFINITY, |acc, &m| acc.max(m));
+    if d <= 0.0 || max_return <= min_variance_return {
+        return Err(QuarsError::Singular(
+            "Degenerate minimum-variance frontier, the expected returns are equal.".to_string(),
+        ));
+    }
+    let variance = |m: f64| frontier_variance(constants, m);
+
+    let targets = match spacing {
+        FrontierSpacing::Uniform => (0..n_points)
+            .map(|i| {
+                min_variance_return
+                    + (max_return - min_variance_return) * i as f64 / (n_points - 1) as f64
+            })
+            .collect(),
+        FrontierSpacing::Adaptive => {
+            curvature_grid(min_variance_return, max_return, n_points, |m| {
+                variance(m).sqrt()
+            })
+        }
+    };
+    Ok(targets
+        .into_iter()
+        .map(|m| {
+            let weights = (&inv_ones * (c - b * m) + &inv_mean * (a * m - b)) / d;
+            let std = variance(m).sqrt();
+            FrontierPoint {
+                risk_free_weight: 0.0,
+                risky_weights: weights.to_vec(),
+                expected_return: m,
+                portfolio_std: std,
+                sharpe_ratio: sharpe_ratio(m, risk_free, std).unwrap_or(0.0),
+            }
+        })
+        .collect())
+}
+
+/// Efficient minimum-variance portfolios of the risky assets alone at the given per-period
+/// volatilities σ, instead of evenly spaced returns. With the constants of `frontier_constants`
+/// the efficient return at σ solves `frontier_variance`, m = B/A + √(D(Aσ² - 1))/A, which
+/// requires σ to be at least that of the global minimum-variance portfolio, √(1/A).
+pub fn min_variance_points_at_volatilities(
+    stats: &PortfolioStats,
+    risk_free: f64,
+    volatilities: &[f64],
+) -> Result<Vec<FrontierPoint>, QuarsError> {
+    let (inv_ones, inv_mean) = frontier_directions(stats)?;
+    let (a, b, c) = constants_of(stats, &inv_ones, &inv_mean);
+    let d = a * c - b * b;
+    if d <= 0.0 {
+        return Err(QuarsError::Singular(
+            "Degenerate minimum-variance frontier, the expected returns are equal.".to_string(),
+        ));
+    }
+    let min_std = (1.0 / a).sqrt();
+    volatilities
+        .iter()
+        .map(|&std| {
+            if std < min_std * (1.0 - 1e-12) {
+                return Err(QuarsError::Infeasible(format!(
+                    "Target volatility {:.3e} is below that of the global minimum-variance portfolio, {:.3e}",
+                    std, min_std
+                )));
+            }
+            let m = b / a + (d * (a * std * std - 1.0)).max(0.0).sqrt() / a;
+            let weights = (&inv_ones * (c - b * m) + &inv_mean * (a * m - b)) / d;
+            Ok(FrontierPoint {
+                risk_free_weight: 0.0,
+                risky_weights: weights.to_vec(),
+                expected_return: m,
+                portfolio_std: std,
+                sharpe_ratio: sharpe_ratio(m, risk_free, std).unwrap_or(0.0),
+            })
+        })
+        .collect()
+}
+
+/// Reference portfolio with its risk and return metrics, per period
+#[derive(Debug, Clone, Serialize)]
+pub struct SpecialPortfolio {
+    pub weights: Vec<f64>,
+    pub expected_return: f64,
+    pub portfolio_std: f64,
+    // None when the portfolio carries no risk
+    pub sharpe_ratio: Option<f64>,
+    pub diversification_ratio: Option<f64>,
+}
+
+impl SpecialPortfolio {
+    fn new(
+        stats: &PortfolioStats,
+        weights: Array1<f64>,
+        risk_free: f64,
+    ) -> Result<Self, QuarsError> {
+        let expected_return = stats.mean_returns.dot(&weights);
+        let portfolio_std = portfolio_variance(&weights, &stats.covariance)?.sqrt();
+        let weights = weights.to_vec();
+        Ok(Self {
+            sharpe_ratio: (portfolio_std >= MIN_VOLATILITY)
+                .then(|| (expected_return - risk_free) / portfolio_std),
+            diversification_ratio: diversification_ratio(&weights, &stats.covariance),
+            weights,
+            expected_return,
+            portfolio_std,
+        })
+    }
+}
+
+/// The fully invested reference portfolios of a universe
+#[derive(Debug, Clone, Serialize)]
+pub struct SpecialPortfolios {
+    // Global minimum-variance portfolio Σ⁻¹1 / 1ᵀΣ⁻¹1
+    pub min_variance: SpecialPortfolio,
+    // Unconstrained tangency portfolio Σ⁻¹(μ - r_f) / 1ᵀΣ⁻¹(μ - r_f)
+    pub max_sharpe: SpecialPortfolio,
+    // Highest return without leverage or shorts, all in the asset with the highest mean
+    pub max_return: SpecialPortfolio,
+    pub equal_weight: SpecialPortfolio,
+}
+
+impl SpecialPortfolios {
+    /// The portfolios with their display names, in report order
+    pub fn named(&self) -> [(&'static str, &SpecialPortfolio); 4] {
+        [
+            ("Minimum Variance", &self.min_variance),
+            ("Maximum Sharpe", &self.max_sharpe),
+            ("Maximum Return", &self.max_return),
+            ("Equal Weight", &self.equal_weight),
+        ]
+    }
+}
+
+/// Computes the minimum-variance, maximum-Sharpe, maximum-return and equal-weight portfolios
+/// of the statistics in one call. `risk_free` is per period. The maximum-Sharpe portfolio is
+/// undefined, and an error returned, when the risk-free rate is at or above the return of
+/// the minimum-variance portfolio, where the capital allocation line no longer touches the
+/// upper frontier.
+pub fn optimize_all_special(
+    stats: &PortfolioStats,
+    risk_free: f64,
+) -> Result<SpecialPortfolios, QuarsError> {
+    let n = stats.assets.len();
+    let cov_inv = invert_covariance(&stats.covariance)?;
+    let inv_ones = cov_inv.sum_axis(Axis(1));
+    let inv_excess = cov_inv.dot(&stats.mean_returns.mapv(|m| m - risk_free));
+    if inv_excess.sum() <= 0.0 {
+        return Err(QuarsError::Infeasible(
+            "No maximum-Sharpe portfolio, the risk-free rate is at or above the minimum-variance return."
+                .to_string(),
+        ));
+    }
+    let best = stats
+        .mean_returns
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .map_or(0, |(i, _)| i);
+    let mut max_return = Array1::<f64>::zeros(n);
+    max_return[best] = 1.0;
+    Ok(SpecialPortfolios {
+        min_variance: SpecialPortfolio::new(stats, &inv_ones / inv_ones.sum(), risk_free)?,
+        max_sharpe: SpecialPortfolio::new(stats, &inv_excess / inv_excess.sum(), risk_free)?,
+        max_return: SpecialPortfolio::new(stats, max_return, risk_free)?,
+        equal_weight: SpecialPortfolio::new(
+            stats,
+            Array1::from_elem(n, 1.0 / n as f64),
+            risk_free,
+        )?,
+    })
+}
+
+// Target returns in [lo, hi] for the curve (risk(m), m), denser where it bends. The points
+// split the curve evenly by arc length plus turning angle, measured on a fine grid with both
+// axes scaled to unit range, so flat stretches keep some points and the bends get the rest.
+fn curvature_grid(lo: f64, hi: f64, n_points: usize, risk: impl Fn(f64) -> f64) -> Vec<f64> {
+    let n_fine = n_points * ADAPTIVE_OVERSAMPLING;
+    let params: Vec<f64> = (0..=n_fine)
+        .map(|k| lo + (hi - lo) * k as f64 / n_fine as f64)
+        .collect();
+    let risks: Vec<f64> = params.iter().map(|&m| risk(m)).collect();
+    let risk_min = risks.iter().copied().fold(f64::INFINITY, f64::min);
+    let risk_range = risks.iter().copied().fold(f64::NEG_INFINITY, f64::max) - risk_min;
+    let risk_range = if risk_range > 0.0 { risk_range } else { 1.0 };
+    let points: Vec<(f64, f64)> = params
+        .iter()
+        .zip(&risks)
+        .map(|(&m, &s)| ((s - risk_min) / risk_range, (m - lo) / (hi - lo)))
+        .collect();
+    let segments: Vec<(f64, f64)> = points
+        .windows(2)
+        .map(|pair| (pair[1].0 - pair[0].0, pair[1].1 - pair[0].1))
+        .collect();
+    let turning: Vec<f64> = segments
+        .windows(2)
+        .map(|pair| {
+            let (u, v) = (pair[0], pair[1]);
+            (u.0 * v.1 - u.1 * v.0).atan2(u.0 * v.0 + u.1 * v.1).abs()
+        })
+        .collect();
+
+    // Cumulative measure at each fine grid point, each turn counted on both of its segments
+    // so the bends weigh twice their angle against the arc length
+    let mut cumulative = vec![0.0; params.len()];
+    for (k, (dx, dy)) in segments.iter().enumerate() {
+        let before = if k > 0 { turning[k - 1] } else { 0.0 };
+        let after = turning.get(k).copied().unwrap_or(0.0);
+        cumulative[k + 1] = cumulative[k] + dx.hypot(*dy) + (before + after);
+    }
+    let total = cumulative[n_fine];
+
+    let mut targets = Vec::with_capacity(n_points);
+    let mut k = 0;
+    for i in 0..n_points {
+        let level = total * i as f64 / (n_points - 1) as f64;
+        while k + 1 < n_fine && cumulative[k + 1] < level {
+            k += 1;
+        }
+        let width = cumulative[k + 1] - cumulative[k];
+        let t = if width > 0.0 {
+            ((level - cumulative[k]) / width).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        targets.push(params[k] + t * (params[k + 1] - params[k]));
+    }
+    targets
+}
+
+/// Per-period return of the risk-free leg, the cash asset's mean when one is configured
+pub fn risk_free_return(
+    stats: &PortfolioStats,
+    po: &PortofolioOptimization,
+) -> Result<f64, QuarsError> {
+    match &po.cash_ticker {
+        Some(cash_ticker) => {
+            let cash_idx = stats
+                .assets
+                .iter()
+                .position(|asset| asset == cash_ticker)
+                .ok_or_else(|| {
+                    QuarsError::Config(format!("Cash ticker {} not found in the data", cash_ticker))
+                })?;
+            Ok(stats.mean_returns[cash_idx])
+        }
+        None => Ok(annual_to_period_rate(
+            po.risk_free_rate,
+            po.periods_per_year,
+            Compounding::from_config(&po.compounding)?,
+        )),
+    }
+}
+
+/// Capital allocation line: blends of the risk-free asset and the tangency portfolio,
+/// from fully in the risk-free asset up to 2x leverage on the tangency portfolio
+fn cal_frontier(
+    optimal_risky: &Array1<f64>,
+    optimal_risky_return: f64,
+    optimal_risky_std: f64,
+    risk_free: f64,
+    n_points: usize,
+) -> Vec<FrontierPoint> {
+    let lambda_step = MAX_LEVERAGE / (n_points as f64 - 1.0);
+    // Every levered point on the CAL shares the tangency Sharpe ratio
+    let tangency_sharpe = sharpe_ratio(optimal_risky_return, risk_free, optimal_risky_std);
+    let mut frontier = Vec::with_capacity(n_points);
+    for i in 0..n_points {
+        let leverage = i as f64 * lambda_step;
+        let risk_free_weight = 1.0 - leverage;
+        let scaled_risky: Vec<f64> = optimal_risky.mapv(|w| leverage * w).to_vec();
+        let portfolio_return = risk_free + leverage * (optimal_risky_return - risk_free);
+        let portfolio_std = leverage * optimal_risky_std;
+        let sharpe_ratio = if leverage > 0.0 {
+            tangency_sharpe.unwrap_or(0.0)
+        } else {
+            0.0
+        };
+        frontier.push(FrontierPoint {
+            risk_free_weight,
+            risky_weights: scaled_risky,
+            expected_return: portfolio_return,
+            portfolio_std,
+            sharpe_ratio,
+        });
+    }
+    frontier
+}
+
+/// Expected return of the CAL portfolio holding `risk_free_weight` in the risk-free asset and
+/// the rest in the tangency portfolio, the risk-free weight earning the lending rate when
+/// positive and costing the borrowing rate when negative. All returns are per period.
+pub fn cal_return(
+    risk_free_weight: f64,
+    tangency_return: f64,
+    lending: f64,
+    borrowing: f64,
+) -> f64 {
+    let rate = if risk_free_weight >= 0.0 {
+        lending
+    } else {
+        borrowing
+    };
+    risk_free_weight * rate + (1.0 - risk_free_weight) * tangency_return
+}
+
+/// Points of the capital allocation line at the given per-period volatilities instead of
+/// evenly spaced leverages: the tangency portfolio of `results` levered by σ / σ_T, which may
+/// exceed `MAX_LEVERAGE` for a high target. The leverage past the tangency portfolio is
+/// financed at the `borrowing` rate.
+pub fn cal_points_at_volatilities(
+    results: &OptimizationResults,
+    risk_free: f64,
+    borrowing: f64,
+    volatilities: &[f64],
+) -> Result<Vec<FrontierPoint>, QuarsError> {
+    if results.optimal_risky_std < MIN_VOLATILITY {
+        return Err(QuarsError::Singular(
+            "The tangency portfolio carries no risk, no leverage reaches a target volatility."
+                .to_string(),
+        ));
+    }
+    let tangency_sharpe = sharpe_ratio(
+        results.optimal_risky_return,
+        risk_free,
+        results.optimal_risky_std,
+    );
+    volatilities
+        .iter()
+        .map(|&std| {
+            if std < 0.0 {
+                return Err(QuarsError::Config(format!(
+                    "Target volatilities must be non-negative, got {}",
+                    std
+                )));
+            }
+            let leverage = std / results.optimal_risky_std;
+            let expected_return = cal_return(
+                1.0 - leverage,
+                results.optimal_risky_return,
+                risk_free,
+                borrowing,
+            );
+            Ok(FrontierPoint {
+                risk_free_weight: 1.0 - leverage,
+                risky_weights: results
+                    .optimal_risky_portfolio
+                    .iter()
+                    .map(|w| leverage * w)
+                    .collect(),
+                expected_return,
+                portfolio_std: std,
+                sharpe_ratio: if leverage > 1.0 {
+                    sharpe_ratio(expected_return, risk_free, std).unwrap_or(0.0)
+                } else if leverage > 0.0 {
+                    tangency_sharpe.unwrap_or(0.0)
+                } else {
+                    0.0
+                },
+            })
+        })
+        .collect()
+}
+
+/// Condition number of a symmetric covariance matrix, the ratio of its extreme eigenvalues
+pub fn condition_number(cov: &Array2<f64>) -> Result<f64, QuarsError> {
+    let (eigenvalues, _) = cov.eigh(UPLO::Lower)?;
+    let max = eigenvalues.iter().cloned().fold(f64::MIN, f64::max);
+    let min = eigenvalues.iter().cloned().fold(f64::MAX, f64::min);
+    if min <= 0.0 {
+        return Ok(f64::INFINITY);
+    }
+    Ok(max / min)
+}
+
+// Leverage on the tangency portfolio at the far end of the capital allocation line
+pub const MAX_LEVERAGE: f64 = 2.0;
+
+// Volatilities below this are treated as zero when computing Sharpe ratios
+const MIN_VOLATILITY: f64 = 1e-12;
+
+/// Sharpe ratio (r - r_f) / σ, None (with a warning) when the volatility is zero or near zero
+/// so that no inf/NaN ratio leaks into the frontier and plots
+pub fn sharpe_ratio(expected_return: f64, risk_free: f64, std: f64) -> Option<f64> {
+    if !std.is_finite() || std < MIN_VOLATILITY {
+        warn!(
+            "volatility {:e} is (near) zero or invalid, Sharpe ratio is undefined",
+            std
+        );
+        return None;
+    }
+    Some((expected_return - risk_free) / std)
+}
+
+/// Risk aversion implied by holding `weights`, γ = (μ - r_f·1)ᵀx / xᵀΣx, the γ for which x
+/// maximizes the unconstrained utility (μ - r_f·1)ᵀx - (γ/2)xᵀΣx, i.e. twice the τ of the
+/// risk-adjusted objective. It is the same per period and annualized. None when the portfolio
+/// has (near) zero variance.
+pub fn implied_risk_aversion(
+    stats: &PortfolioStats,
+    weights: &[f64],
+    risk_free: f64,
+) -> Option<f64> {
+    let weights = Array1::from(weights.to_vec());
+    let variance = portfolio_variance(&weights, &stats.covariance).ok()?;
+    if variance.sqrt() < MIN_VOLATILITY {
+        return None;
+    }
+    Some(stats.mean_returns.mapv(|m| m - risk_free).dot(&weights) / variance)
+}
+
+/// Reverse optimization: the expected excess returns μ - r_f·1 = γΣw under which `weights`
+/// maximize the utility (μ - r_f·1)ᵀx - (γ/2)xᵀΣx, e.g. the equilibrium returns implied by
+/// market-cap weights that serve as the Black-Litterman prior. Fully invested weights are
+/// also the risk-adjusted portfolio of these returns with τ = γ/2.
+pub fn reverse_optimize(
+    covariance: &Array2<f64>,
+    weights: &[f64],
+    risk_aversion: f64,
+) -> Result<Array1<f64>, QuarsError> {
+    if weights.len() != covariance.nrows() {
+        return Err(QuarsError::Config(format!(
+            "Got {} weights for {} assets",
+            weights.len(),
+            covariance.nrows()
+        )));
+    }
+    Ok(covariance.dot(&Array1::from(weights.to_vec())) * risk_aversion)
+}
+
+/// Compounding convention of an annual rate, over n periods a year
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compounding {
+    // (1 + r)^(1/n) - 1 per period
+    Discrete,
+    // r/n in log terms, i.e. a period return of e^(r/n) - 1
+    Continuous,
+}
+
+impl Compounding {
+    pub fn from_config(compounding: &str) -> Result<Self, QuarsError> {
+        match compounding {
+            "discrete" => Ok(Self::Discrete),
+            "continuous" => Ok(Self::Continuous),
+            other => Err(QuarsError::Config(format!(
+                "Unknown compounding '{}', use discrete or continuous",
+                other
+            ))),
+        }
+    }
+}
+
+/// Converts an annual rate to the rate of one of `periods_per_year` periods
+pub fn annual_to_period_rate(
+    r_annual: f64,
+    periods_per_year: f64,
+    compounding: Compounding,
+) -> f64 {
+    match compounding {
+        Compounding::Discrete => (1.0 + r_annual).powf(1.0 / periods_per_year) - 1.0,
+        Compounding::Continuous => (r_annual / periods_per_year).exp_m1(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Config, File, FileFormat};
+    use ndarray::array;
+    use rand::SeedableRng;
+
+    // Optimizer settings of a config with the given extra lines
+    fn settings(extra: &str) -> PortofolioOptimization {
+        let toml = format!(
+            "method = \"mvo\"\nsub_method = \"risk-adjusted\"\nrisk_free_rate = 0.0\nparams = [0.5]\n{}",
+            extra
+        );
+        Config::builder()
+            .add_source(File::from_str(&toml, FileFormat::Toml))
+            .build()
+            .and_then(Config::try_deserialize)
+            .unwrap()
+    }
+
+    // Assets of the given covariance and zero means, without return series
+    fn covariance_stats(covariance: Array2<f64>) -> PortfolioStats {
+        let n = covariance.nrows();
+        PortfolioStats {
+            assets: (0..n).map(|i| format!("asset_{}", i)).collect(),
+            mean_returns: Array1::zeros(n),
+            covariance,
+            returns_matrix: Array2::zeros((n, 0)),
+            dates: Vec::new(),
+        }
+    }
+
+    fn assert_weights(actual: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!(
+                (a - e).abs() < tolerance,
+                "{:?} differs from {:?}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn min_tracking_error_without_constraints_is_the_target() {
+        let stats = stats_with_cash().subset(&[0, 1]);
+        let target = [0.7, 0.3];
+        let portfolio =
+            optimize_min_tracking_error(&stats, &target, &TrackingConstraints::default(), None)
+                .unwrap();
+        assert_weights(&portfolio.weights, &target, 1e-12);
+        assert!(portfolio.tracking_error < 1e-9);
+    }
+
+    #[test]
+    fn min_tracking_error_with_bounds_and_cardinality_solves_the_qp() {
+        // With equal variances the excess over a cap, or the weight of a dropped asset, is
+        // spread evenly over the others
+        let stats = covariance_stats(Array2::eye(3) * 0.01);
+        let capped = TrackingConstraints {
+            max_weight: Some(0.5),
+            ..TrackingConstraints::default()
+        };
+        let portfolio =
+            optimize_min_tracking_error(&stats, &[0.7, 0.2, 0.1], &capped, None).unwrap();
+        assert_weights(&portfolio.weights, &[0.5, 0.3, 0.2], 1e-6);
+        assert!((portfolio.tracking_error - (0.01f64 * (0.04 + 0.01 + 0.01)).sqrt()).abs() < 1e-6);
+
+        let two_assets = TrackingConstraints {
+            max_assets: Some(2),
+            ..TrackingConstraints::default()
+        };
+        let portfolio =
+            optimize_min_tracking_error(&stats, &[0.5, 0.3, 0.2], &two_assets, None).unwrap();
+        assert_weights(&portfolio.weights, &[0.6, 0.4, 0.0], 1e-6);
+    }
+
+    #[test]
+    fn min_correlation_weights_uncorrelated_assets_equally_and_down_weights_a_correlated_pair() {
+        let results =
+            optimize_min_correlation(&covariance_stats(Array2::eye(2) * 0.01), 0.0, 3, false)
+                .unwrap();
+        assert_weights(&results.optimal_risky_portfolio, &[0.5, 0.5], 1e-12);
+
+        // Equal volatilities of 10%, the first two assets 90% correlated
+        let stats = covariance_stats(array![
+            [0.01, 0.009, 0.0],
+            [0.009, 0.01, 0.0],
+            [0.0, 0.0, 0.01]
+        ]);
+        let weights = optimize_min_correlation(&stats, 0.0, 3, false)
+            .unwrap()
+            .optimal_risky_portfolio;
+        assert!((weights[0] - weights[1]).abs() < 1e-12);
+        assert!(weights[0] < weights[2], "{:?}", weights);
+        assert!((weights.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn a_loaded_covariance_gives_the_closed_form_tangency_portfolio() {
+        // Annualized, BBB first to check the rows are matched to the assets by name
+        let path = std::env::temp_dir().join(format!("quars-{}-cov.csv", std::process::id()));
+        std::fs::write(&path, ",BBB,AAA\nBBB,0.04,0.004\nAAA,0.004,0.01\n").unwrap();
+        let (assets, covariance) = crate::data::read_asset_matrix(path.to_str().unwrap()).unwrap();
+
+        let mut stats = covariance_stats(Array2::zeros((2, 2)));
+        stats.assets = vec!["AAA".to_string(), "BBB".to_string()];
+        let periods_per_year = 252.0;
+        stats
+            .override_covariance(&assets, &covariance, periods_per_year)
+            .unwrap();
+        let expected = [("aaa", 0.05), ("bbb", 0.10)]
+            .into_iter()
+            .map(|(asset, mean)| (asset.to_string(), mean))
+            .collect();
+        stats
+            .override_mean_returns(&expected, periods_per_year)
+            .unwrap();
+
+        // Σ⁻¹μ ∝ [0.04·0.05 - 0.004·0.10, -0.004·0.05 + 0.01·0.10] = [0.0016, 0.0008]
+        let tangency = optimize_all_special(&stats, 0.0).unwrap().max_sharpe;
+        assert_weights(&tangency.weights, &[2.0 / 3.0, 1.0 / 3.0], 1e-12);
+
+        let asymmetric = array![[0.04, 0.004], [0.0, 0.01]];
+        assert!(matches!(
+            stats.override_covariance(&assets, &asymmetric, periods_per_year),
+            Err(QuarsError::Parse(_))
+        ));
+    }
+
+    #[test]
+    fn annual_rates_are_converted_with_the_periods_of_the_timeframe() {
+        let daily = annual_to_period_rate(0.05, 252.0, Compounding::Discrete);
+        assert!((daily - 1.05f64.powf(1.0 / 252.0) + 1.0).abs() < 1e-15);
+        let monthly = annual_to_period_rate(0.12, 12.0, Compounding::Discrete);
+        assert!((monthly - 1.12f64.powf(1.0 / 12.0) + 1.0).abs() < 1e-15);
+        let continuous = annual_to_period_rate(0.12, 12.0, Compounding::Continuous);
+        assert!((continuous - 0.01f64.exp_m1()).abs() < 1e-15);
+        // Compounding the period rate back over the year recovers the annual rate
+        assert!(((1.0 + monthly).powi(12) - 1.12).abs() < 1e-12);
+        assert!(((1.0 + continuous).ln() * 12.0 - 0.12).abs() < 1e-12);
+
+        let mut po = settings("compounding = \"continuous\"");
+        po.risk_free_rate = 0.12;
+        po.periods_per_year = 12.0;
+        let stats = covariance_stats(Array2::eye(2) * 0.01);
+        assert_eq!(risk_free_return(&stats, &po).unwrap(), continuous);
+    }
+
+    #[test]
+    fn a_cash_floor_caps_the_risky_allocation_and_holds_the_cal_point_at_the_floor() {
+        let mut stats = covariance_stats(Array2::from_diag(&array![0.0004, 0.0009]));
+        stats.mean_returns = array![0.001, 0.002];
+        let risk_free = 0.0001;
+        let mut results = optimize_risk_adjusted(&stats, risk_free, 0.5, 11, false).unwrap();
+        let held = results
+            .apply_min_cash_weight(0.1, risk_free)
+            .unwrap()
+            .unwrap();
+        for pt in &results.frontier {
+            assert!(
+                pt.risky_weights.iter().sum::<f64>() <= 0.9 + 1e-12,
+                "{:?}",
+                pt
+            );
+        }
+        assert_eq!(results.risk_free_weight, 0.1);
+        assert_eq!(held.risk_free_weight, 0.1);
+        let expected: Vec<f64> = results
+            .optimal_risky_portfolio
+            .iter()
+            .map(|w| 0.9 * w)
+            .collect();
+        assert_weights(&held.risky_weights, &expected, 1e-15);
+        let expected_return = 0.1 * risk_free + 0.9 * results.optimal_risky_return;
+        assert!((held.expected_return - expected_return).abs() < 1e-15);
+        assert!((held.portfolio_std - 0.9 * results.optimal_risky_std).abs() < 1e-15);
+        // The CAL keeps the tangency Sharpe ratio
+        assert!((held.sharpe_ratio - results.max_sharpe).abs() < 1e-12);
+
+        // A floor below the cash already held does not bind
+        results.risk_free_weight = 0.3;
+        assert!(results
+            .apply_min_cash_weight(0.1, risk_free)
+            .unwrap()
+            .is_none());
+        assert_eq!(results.risk_free_weight, 0.3);
+    }
+
+    #[test]
+    fn the_concentration_penalty_starts_at_mean_variance_and_lowers_the_concentration() {
+        let mut stats = covariance_stats(array![
+            [0.0004, 0.0001, 0.0],
+            [0.0001, 0.0009, 0.0002],
+            [0.0, 0.0002, 0.0001]
+        ]);
+        stats.mean_returns = array![0.001, 0.002, 0.0005];
+        let gamma = 4.0;
+        let penalties = [0.0, 1e-5, 1e-4, 1e-3, 1e-2];
+        let points = concentration_frontier(&stats, gamma, &penalties).unwrap();
+
+        let cara = optimize_cara(&stats, gamma, 0.0, 3, false).unwrap();
+        assert_weights(&points[0].weights, &cara.optimal_risky_portfolio, 1e-9);
+        for pair in points.windows(2) {
+            assert!(
+                pair[1].concentration < pair[0].concentration,
+                "{} then {}",
+                pair[0].concentration,
+                pair[1].concentration
+            );
+        }
+        // Toward equal weights, xᵀx = 1/n
+        assert!(points[4].concentration - 1.0 / 3.0 < 0.01);
+    }
+
+    #[test]
+    fn the_comparison_has_one_row_per_registered_method() {
+        let po = settings("");
+        let registry = MvoOptMethod::registry(&po).unwrap();
+        let names: Vec<&str> = registry.iter().map(MvoOptMethod::name).collect();
+        // Each method once, reachable by its sub_method name
+        for (i, name) in names.iter().enumerate() {
+            assert!(!names[..i].contains(name), "{} registered twice", name);
+            let mut configured = settings("");
+            configured.sub_method = name.to_string();
+            configured.params = vec![0.5, 0.95];
+            assert_eq!(
+                MvoOptMethod::from_config(&configured).unwrap().name(),
+                *name
+            );
+        }
+        assert!(names.contains(&"min-variance") && names.contains(&"risk-parity"));
+
+        let stats = stats_with_cash().subset(&[0, 1]);
+        let comparison = compare_methods(&stats, 5, &po).unwrap();
+        let compared: Vec<&str> = comparison.iter().map(|(name, _)| *name).collect();
+        assert_eq!(compared, names);
+    }
+
+    #[test]
+    fn risk_parity_equalizes_the_risk_contributions() {
+        let stats = covariance_stats(array![
+            [0.0004, 0.0003, 0.0],
+            [0.0003, 0.0009, 0.0001],
+            [0.0, 0.0001, 0.0001]
+        ]);
+        let weights = Array1::from(
+            optimize_risk_parity(&stats, 0.0, 3, false)
+                .unwrap()
+                .optimal_risky_portfolio,
+        );
+        assert!((weights.sum() - 1.0).abs() < 1e-12);
+        assert!(weights.iter().all(|&w| w > 0.0));
+        let contributions = &weights * &stats.covariance.dot(&weights);
+        let total = contributions.sum();
+        assert_weights(&(contributions / total).to_vec(), &[1.0 / 3.0; 3], 1e-9);
+
+        // Uncorrelated assets are weighted by inverse volatility
+        let stats = covariance_stats(Array2::from_diag(&array![0.0004, 0.0016]));
+        let weights = optimize_risk_parity(&stats, 0.0, 3, false)
+            .unwrap()
+            .optimal_risky_portfolio;
+        assert_weights(&weights, &[2.0 / 3.0, 1.0 / 3.0], 1e-12);
+    }
+
+    #[test]
+    fn min_variance_is_the_global_minimum_variance_portfolio() {
+        let mut stats = covariance_stats(array![[0.0004, 0.0001], [0.0001, 0.0009]]);
+        stats.mean_returns = array![0.001, 0.002];
+        let weights = optimize_min_variance(&stats, 0.0, 3, false)
+            .unwrap()
+            .optimal_risky_portfolio;
+        // Σ⁻¹1 ∝ [0.0009 - 0.0001, 0.0004 - 0.0001]
+        assert_weights(&weights, &[8.0 / 11.0, 3.0 / 11.0], 1e-12);
+        let special = optimize_all_special(&stats, 0.0).unwrap();
+        assert_weights(&weights, &special.min_variance.weights, 1e-12);
+    }
+
+    #[test]
+    fn the_active_frontier_starts_at_the_benchmark() {
+        let mut stats = covariance_stats(array![
+            [0.0004, 0.0001, 0.0],
+            [0.0001, 0.0009, 0.0002],
+            [0.0, 0.0002, 0.0001]
+        ]);
+        stats.mean_returns = array![0.001, 0.002, 0.0005];
+        let benchmark = [0.5, 0.3, 0.2];
+        let frontier = optimize_active_frontier(&stats, &benchmark, 7).unwrap();
+        assert_eq!(frontier.len(), 7);
+        let start = &frontier[0];
+        assert!(start.active_return.abs() < 1e-15);
+        assert!(start.tracking_error < 1e-9);
+        assert_weights(&start.weights, &benchmark, 1e-12);
+        for pair in frontier.windows(2) {
+            assert!(pair[1].active_return > pair[0].active_return);
+            assert!(pair[1].tracking_error > pair[0].tracking_error);
+        }
+    }
+
+    // Two risky assets and a cash asset with a constant return
+    fn stats_with_cash() -> PortfolioStats {
+        let returns = array![
+            [0.010, -0.004, 0.006, 0.002, -0.001, 0.008, -0.003, 0.005],
+            [0.002, 0.004, -0.006, 0.007, 0.003, -0.002, 0.006, 0.001],
+            [0.0002, 0.0002, 0.0002, 0.0002, 0.0002, 0.0002, 0.0002, 0.0002],
+        ];
+        let assets = ["AAA", "BBB", "CASH"].map(String::from).to_vec();
+        let dates = (1..=returns.ncols()).map(|t| t.to_string()).collect();
+        PortfolioStats::from_returns(assets, returns, dates).unwrap()
+    }
+
+    #[test]
+    fn constant_returns_give_no_nan_or_infinite_sharpe_ratio() {
+        let returns = array![[0.001, 0.001, 0.001, 0.001]];
+        let dates = (1..=4).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(vec!["AAA".to_string()], returns, dates).unwrap();
+        assert_eq!(sharpe_ratio(0.001, 0.0002, 0.0), None);
+        let results = heuristic_results(&stats, array![1.0], 0.0002, 5).unwrap();
+        assert_eq!(results.optimal_risky_std, 0.0);
+        assert!(results.max_sharpe.is_finite());
+        assert!(results
+            .frontier
+            .iter()
+            .all(|pt| pt.sharpe_ratio.is_finite() && pt.expected_return.is_finite()));
+    }
+
+    #[test]
+    fn finer_blend_grids_never_increase_the_concentration() {
+        let mean = array![0.012, 0.004, 0.001];
+        let cov = array![
+            [0.0009, 0.0001, 0.0],
+            [0.0001, 0.0004, 0.0],
+            [0.0, 0.0, 0.0001]
+        ];
+        let utility = |x: &Array1<f64>| mean.dot(x) - 0.5 * 2.0 * x.dot(&cov.dot(x));
+        let x_equal = Array1::from_elem(3, 1.0 / 3.0);
+        let x_mvo = array![0.9, 0.3, -0.2];
+        let required = 0.95 * utility(&x_mvo);
+        let mut previous = f64::INFINITY;
+        for steps in [3, 6, 12, 24, 48, 96, 192] {
+            let blend = blend_search(&x_equal, &x_mvo, steps, required, utility);
+            assert!(blend.utility >= required);
+            assert!(
+                blend.concentration <= previous,
+                "{} steps: {} above {}",
+                steps,
+                blend.concentration,
+                previous
+            );
+            previous = blend.concentration;
+        }
+        assert!(previous < x_mvo.dot(&x_mvo));
+    }
+
+    #[test]
+    fn cash_absorbs_the_residual_allocation() {
+        let stats = stats_with_cash();
+        let po = settings("cash_ticker = \"CASH\"");
+        let results = optimize_portfolio(&stats, 5, &po).unwrap();
+        let cash_mean = stats.mean_returns[2];
+
+        // The configured method on the risky assets alone, against the cash mean
+        let risky = stats.subset(&[0, 1]);
+        let method = MvoOptMethod::from_config(&po).unwrap();
+        let expected = optimize_with_method(&risky, method, cash_mean, 5, &po).unwrap();
+        assert_eq!(
+            results.optimal_risky_portfolio,
+            [&expected.optimal_risky_portfolio[..], &[0.0]].concat()
+        );
+        let exposure = (expected.optimal_risky_return - cash_mean)
+            / (2.0 * 0.5 * expected.optimal_risky_std.powi(2));
+        assert!((results.risk_free_weight - (1.0 - exposure)).abs() < 1e-9);
+        // The CAL starts at the cash mean and never holds cash as a risky weight
+        assert!((results.frontier[0].expected_return - cash_mean).abs() < 1e-15);
+        assert!(results.frontier.iter().all(|pt| pt.risky_weights[2] == 0.0));
+    }
+
+    #[test]
+    fn the_resampled_frontier_does_not_depend_on_the_thread_count() {
+        let stats = stats_with_cash().subset(&[0, 1]);
+        let po = settings("");
+        let on_threads = |threads: usize| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    let mut rng = StdRng::seed_from_u64(42);
+                    resampled_frontier(&stats, 10, &po, 20, &mut rng).unwrap()
+                })
+        };
+        let single = on_threads(1);
+        let parallel = on_threads(4);
+        assert_eq!(
+            single.optimal_risky_portfolio,
+            parallel.optimal_risky_portfolio
+        );
+        for (a, b) in single.frontier.iter().zip(&parallel.frontier) {
+            assert_eq!(a.risky_weights, b.risky_weights);
+        }
+    }
+
+    #[test]
+    fn the_frontier_constants_give_the_variance_of_the_frontier_portfolios() {
+        let mut stats = covariance_stats(array![
+            [0.04, 0.006, 0.002],
+            [0.006, 0.09, 0.01],
+            [0.002, 0.01, 0.01]
+        ]);
+        stats.mean_returns = array![0.08, 0.12, 0.03];
+        let (a, b, c) = frontier_constants(&stats).unwrap();
+        let frontier = min_variance_frontier(&stats, 0.0, 10, FrontierSpacing::Uniform).unwrap();
+        // The frontier starts at the global minimum-variance portfolio, of return B/A and
+        // variance 1/A
+        let min_variance = optimize_min_variance(&stats, 0.0, 2, false).unwrap();
+        assert_weights(
+            &frontier[0].risky_weights,
+            &min_variance.optimal_risky_portfolio,
+            1e-12,
+        );
+        assert!((frontier[0].expected_return - b / a).abs() < 1e-12);
+        for pt in &frontier {
+            let weights = Array1::from(pt.risky_weights.clone());
+            let variance = weights.dot(&stats.covariance.dot(&weights));
+            assert!((weights.sum() - 1.0).abs() < 1e-12);
+            assert!((weights.dot(&stats.mean_returns) - pt.expected_return).abs() < 1e-12);
+            assert!(
+                (variance - frontier_variance((a, b, c), pt.expected_return)).abs() < 1e-12,
+                "{} vs {}",
+                variance,
+                frontier_variance((a, b, c), pt.expected_return)
+            );
+        }
+        assert!((frontier_variance((a, b, c), b / a) - 1.0 / a).abs() < 1e-12);
+    }
+
+    #[test]
+    fn the_implied_returns_make_the_weights_the_risk_adjusted_portfolio() {
+        let mut stats = covariance_stats(array![
+            [0.04, 0.006, 0.002],
+            [0.006, 0.09, 0.01],
+            [0.002, 0.01, 0.01]
+        ]);
+        let weights = [0.5, 0.3, 0.2];
+        let (risk_free, risk_aversion) = (0.001, 3.0);
+        let excess = reverse_optimize(&stats.covariance, &weights, risk_aversion).unwrap();
+        stats.mean_returns = excess + risk_free;
+        let results =
+            optimize_risk_adjusted(&stats, risk_free, risk_aversion / 2.0, 2, false).unwrap();
+        assert_weights(&results.optimal_risky_portfolio, &weights, 1e-10);
+        assert!(matches!(
+            reverse_optimize(&stats.covariance, &weights[..2], risk_aversion),
+            Err(QuarsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn an_indefinite_covariance_fails_the_positive_definite_check_with_its_remedies() {
+        // Correlations no return series can have, e.g. of a hand-edited covariance file
+        let stats = covariance_stats(array![
+            [0.04, 0.05, 0.0],
+            [0.05, 0.04, 0.0],
+            [0.0, 0.0, 0.01]
+        ]);
+        let err = optimize_portfolio(&stats, 10, &settings("")).unwrap_err();
+        assert!(
+            matches!(&err, QuarsError::Singular(msg)
+                if msg.contains("smallest eigenvalue is -1.000e-2")
+                    && msg.contains("covariance_ridge")),
+            "{}",
+            err
+        );
+        assert!(optimize_portfolio(&stats.subset(&[0, 2]), 10, &settings("")).is_ok());
+    }
+
+    #[test]
+    fn the_frontier_portfolios_at_target_volatilities_have_those_volatilities() {
+        let mut stats = covariance_stats(array![
+            [0.04, 0.006, 0.002],
+            [0.006, 0.09, 0.01],
+            [0.002, 0.01, 0.01]
+        ]);
+        stats.mean_returns = array![0.08, 0.12, 0.03];
+        let std_of = |weights: &[f64]| {
+            let weights = Array1::from(weights.to_vec());
+            weights.dot(&stats.covariance.dot(&weights)).sqrt()
+        };
+        let (a, b, _) = frontier_constants(&stats).unwrap();
+        let volatilities = [(1.0 / a).sqrt(), 0.15, 0.25];
+        let risky = min_variance_points_at_volatilities(&stats, 0.01, &volatilities).unwrap();
+        for (pt, vol) in risky.iter().zip(volatilities) {
+            assert!((std_of(&pt.risky_weights) - vol).abs() < 1e-12);
+            assert!((pt.risky_weights.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+            // On the efficient branch, above the return of the vertex
+            assert!(pt.expected_return >= b / a - 1e-12);
+        }
+        assert!(matches!(
+            min_variance_points_at_volatilities(&stats, 0.01, &[0.5 * (1.0 / a).sqrt()]),
+            Err(QuarsError::Infeasible(_))
+        ));
+
+        let results = optimize_portfolio(&stats, 10, &settings("")).unwrap();
+        let vols = [
+            0.0,
+            0.5 * results.optimal_risky_std,
+            2.0 * results.optimal_risky_std,
+        ];
+        let cal = cal_points_at_volatilities(&results, 0.01, 0.01, &vols).unwrap();
+        for (pt, vol) in cal.iter().zip(vols) {
+            assert!((std_of(&pt.risky_weights) - vol).abs() < 1e-12);
+            let leverage = vol / results.optimal_risky_std;
+            assert!((pt.risk_free_weight - (1.0 - leverage)).abs() < 1e-12);
+            let expected = (1.0 - leverage) * 0.01 + leverage * results.optimal_risky_return;
+            assert!((pt.expected_return - expected).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn walk_forward_portfolios_see_only_the_returns_before_their_rebalance() {
+        let returns = array![
+            [
+                0.010, -0.004, 0.006, 0.002, -0.001, 0.008, -0.003, 0.005, 0.004, -0.006, 0.007,
+                0.001
+            ],
+            [
+                0.002, 0.004, -0.006, 0.007, 0.003, -0.002, 0.006, 0.001, -0.003, 0.005, 0.000,
+                0.002
+            ],
+        ];
+        let assets = ["AAA", "BBB"].map(String::from).to_vec();
+        let dates = (1..=returns.ncols()).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(assets, returns.clone(), dates).unwrap();
+        // The rebalance at 2 has too short a history and is skipped
+        let run = walk_forward(&stats, &[2, 4, 8], 4, 0.5, 0.0001).unwrap();
+        assert_eq!(run.returns.len(), 8);
+        let first = optimize_risk_adjusted(
+            &stats.resample_periods(&[0, 1, 2, 3]).unwrap(),
+            0.0001,
+            0.5,
+            2,
+            false,
+        )
+        .unwrap()
+        .optimal_risky_portfolio;
+        for t in 4..8 {
+            let expected = first[0] * returns[[0, t]] + first[1] * returns[[1, t]];
+            assert!((run.returns[t - 4] - expected).abs() < 1e-15);
+        }
+        // A later return leaves the earlier ones as they were
+        let mut shocked = stats.clone();
+        shocked.returns_matrix[[0, 11]] = -0.5;
+        let shocked_run = walk_forward(&shocked, &[2, 4, 8], 4, 0.5, 0.0001).unwrap();
+        assert_eq!(shocked_run.returns[..7], run.returns[..7]);
+
+        let taus = [0.1, 0.5, 5.0];
+        let (best, runs) =
+            select_tau(&stats, &[4, 8], 4, &taus, TauObjective::Sharpe, 0.0001).unwrap();
+        assert_eq!(runs.len(), taus.len());
+        assert!(runs
+            .iter()
+            .all(|run| run.sharpe_ratio <= runs[best].sharpe_ratio));
+        assert!(matches!(
+            select_tau(
+                &stats,
+                &[4, 8],
+                4,
+                &[0.5, 0.0],
+                TauObjective::Sharpe,
+                0.0001
+            ),
+            Err(QuarsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn the_borrowing_rate_lowers_only_the_leveraged_part_of_the_cal() {
+        let stats = stats_with_cash().subset(&[0, 1]);
+        let mut po = settings("");
+        po.risk_free_rate = 0.025;
+        let lending = risk_free_return(&stats, &po).unwrap();
+        let borrowing = 3.0 * lending;
+        let lent = optimize_portfolio(&stats, 20, &po).unwrap();
+        assert!(lent.frontier.iter().any(|pt| pt.risk_free_weight < 0.0));
+        let mut borrowed = optimize_portfolio(&stats, 20, &po).unwrap();
+        borrowed.apply_borrowing_rate(lending, borrowing).unwrap();
+        let tangency_sharpe =
+            sharpe_ratio(lent.optimal_risky_return, lending, lent.optimal_risky_std).unwrap();
+        for (before, after) in lent.frontier.iter().zip(&borrowed.frontier) {
+            let expected = cal_return(
+                before.risk_free_weight,
+                lent.optimal_risky_return,
+                lending,
+                borrowing,
+            );
+            if before.risk_free_weight >= 0.0 {
+                assert_eq!(after.expected_return, before.expected_return);
+                assert_eq!(after.sharpe_ratio, before.sharpe_ratio);
+            } else {
+                assert!(after.expected_return < before.expected_return);
+                assert!(after.sharpe_ratio < tangency_sharpe);
+            }
+            assert!((after.expected_return - expected).abs() < 1e-12);
+        }
+        assert!(matches!(
+            borrowed.apply_borrowing_rate(borrowing, lending),
+            Err(QuarsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn a_min_trade_weight_rebuilds_the_cal_on_the_cleaned_tangency() {
+        let mut stats = covariance_stats(array![
+            [0.04, 0.006, 0.002],
+            [0.006, 0.09, 0.01],
+            [0.002, 0.01, 0.01]
+        ]);
+        stats.mean_returns = array![0.08, 0.12, 0.03];
+        // Risk averse enough to hold little of the volatile asset, about 2%
+        let mut po = settings("");
+        po.params = vec![20.0];
+        let mut results = optimize_portfolio(&stats, 10, &po).unwrap();
+        let risk_free = 0.001;
+        let dropped = results
+            .apply_min_trade_weight(0.05, &stats, risk_free)
+            .unwrap();
+        assert_eq!(dropped.iter().map(|(i, _)| *i).collect::<Vec<_>>(), [1]);
+        let tangency = &results.optimal_risky_portfolio;
+        assert_eq!(tangency[1], 0.0);
+        assert_eq!(results.frontier.len(), 10);
+        for pt in &results.frontier {
+            let exposure = 1.0 - pt.risk_free_weight;
+            let expected: Vec<f64> = tangency.iter().map(|w| exposure * w).collect();
+            assert_weights(&pt.risky_weights, &expected, 1e-15);
+            let weights = Array1::from(pt.risky_weights.clone());
+            let variance = weights.dot(&stats.covariance.dot(&weights));
+            assert!((pt.portfolio_std - variance.sqrt()).abs() < 1e-12);
+            let expected_return =
+                pt.risk_free_weight * risk_free + weights.dot(&stats.mean_returns);
+            assert!((pt.expected_return - expected_return).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn frontier_lookups_hit_grid_points_and_interpolate_between_them() {
+        let stats = stats_with_cash().subset(&[0, 1]);
+        let results = optimize_portfolio(&stats, 10, &settings("")).unwrap();
+        let grid = &results.frontier[3];
+        let exact = results.weights_for_return(grid.expected_return).unwrap();
+        assert_weights(&exact.risky_weights, &grid.risky_weights, 1e-12);
+        assert!((exact.portfolio_std - grid.portfolio_std).abs() < 1e-12);
+
+        let (lo, hi) = (&results.frontier[3], &results.frontier[4]);
+        let mid = results
+            .weights_for_risk(0.5 * (lo.portfolio_std + hi.portfolio_std))
+            .unwrap();
+        let expected: Vec<f64> = lo
+            .risky_weights
+            .iter()
+            .zip(&hi.risky_weights)
+            .map(|(a, b)| 0.5 * (a + b))
+            .collect();
+        assert_weights(&mid.risky_weights, &expected, 1e-12);
+        assert!(
+            (mid.expected_return - 0.5 * (lo.expected_return + hi.expected_return)).abs() < 1e-12
+        );
+
+        let last = results.frontier.last().unwrap();
+        let clamped = results.weights_for_risk(10.0 * last.portfolio_std).unwrap();
+        assert_eq!(clamped.risky_weights, last.risky_weights);
+        let empty = OptimizationResults {
+            frontier: Vec::new(),
+            ..results
+        };
+        assert!(matches!(
+            empty.weights_for_return(0.0),
+            Err(QuarsError::InsufficientData(_))
+        ));
+    }
+
+    #[test]
+    fn a_single_resample_of_every_period_is_the_plain_frontier() {
+        let stats = stats_with_cash().subset(&[0, 1]);
+        let po = settings("");
+        let plain = optimize_portfolio(&stats, 10, &po).unwrap();
+        let every_period: Vec<usize> = (0..stats.returns_matrix.ncols()).collect();
+        let resampled = resampled_frontier_from(&stats, 10, &po, &[every_period]).unwrap();
+        assert_weights(
+            &resampled.optimal_risky_portfolio,
+            &plain.optimal_risky_portfolio,
+            1e-12,
+        );
+        assert!((resampled.optimal_risky_std - plain.optimal_risky_std).abs() < 1e-12);
+        assert_eq!(resampled.frontier.len(), plain.frontier.len());
+        for (r, p) in resampled.frontier.iter().zip(&plain.frontier) {
+            assert_weights(&r.risky_weights, &p.risky_weights, 1e-12);
+            assert!((r.risk_free_weight - p.risk_free_weight).abs() < 1e-12);
+            assert!((r.expected_return - p.expected_return).abs() < 1e-12);
+            assert!((r.portfolio_std - p.portfolio_std).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn the_implied_risk_aversion_of_the_risk_adjusted_allocation_round_trips() {
+        let mut stats = covariance_stats(array![[0.0004, 0.0001], [0.0001, 0.0009]]);
+        stats.mean_returns = array![0.001, 0.0015];
+        let gamma = 3.0;
+        // gamma -> tau
+        let tau = MvoOptMethod::Cara { gamma }.tau().unwrap();
+        let mut po = settings("");
+        po.risk_free_rate = 0.02;
+        po.params = vec![tau];
+        let results = optimize_portfolio(&stats, 5, &po).unwrap();
+        let risk_free = risk_free_return(&stats, &po).unwrap();
+
+        // tau -> gamma, the tangency portfolio scaled by the exposure the risk aversion takes
+        let exposure = (results.optimal_risky_return - risk_free)
+            / (2.0 * tau * results.optimal_risky_std.powi(2));
+        let allocation: Vec<f64> = results
+            .optimal_risky_portfolio
+            .iter()
+            .map(|w| w * exposure)
+            .collect();
+        let implied = implied_risk_aversion(&stats, &allocation, risk_free).unwrap();
+        assert!((implied - gamma).abs() < 1e-9, "{}", implied);
+
+        let mut cara = settings("");
+        cara.sub_method = "cara".to_string();
+        cara.params = vec![implied];
+        let tau_back = MvoOptMethod::from_config(&cara).unwrap().tau().unwrap();
+        assert!((tau_back - tau).abs() < 1e-9, "{}", tau_back);
+    }
+
+    #[test]
+    fn missing_params_and_unknown_sub_methods_are_config_errors() {
+        for (sub_method, params) in [
+            ("risk-adjusted", vec![]),
+            ("near-optimal", vec![0.5]),
+            ("cara", vec![]),
+            ("max-sharpe", vec![0.5, 0.95]),
+        ] {
+            let mut po = settings("");
+            po.sub_method = sub_method.to_string();
+            po.params = params;
+            assert!(
+                matches!(MvoOptMethod::from_config(&po), Err(QuarsError::Config(_))),
+                "{}",
+                sub_method
+            );
+        }
+        // The methods without params need none
+        let mut po = settings("");
+        po.sub_method = "min-variance".to_string();
+        po.params = vec![];
+        assert_eq!(
+            MvoOptMethod::from_config(&po).unwrap().name(),
+            "min-variance"
+        );
+    }
+
+    #[test]
+    fn a_large_ridge_drives_the_min_variance_weights_to_equal_weights() {
+        let mut stats = covariance_stats(array![
+            [0.0004, 0.0001, 0.0],
+            [0.0001, 0.0009, 0.0002],
+            [0.0, 0.0002, 0.0025]
+        ]);
+        stats.mean_returns = array![0.001, 0.002, 0.003];
+        let distance_to_equal = |lambda: f64| {
+            let mut ridged = stats.clone();
+            ridged.add_covariance_ridge(lambda).unwrap();
+            let weights = optimize_min_variance(&ridged, 0.0, 5, false)
+                .unwrap()
+                .optimal_risky_portfolio;
+            weights
+                .iter()
+                .map(|w| (w - 1.0 / 3.0).abs())
+                .fold(0.0, f64::max)
+        };
+        let distances: Vec<f64> = [0.0, 1e-4, 1e-3, 1e-2, 10.0]
+            .iter()
+            .map(|&lambda| distance_to_equal(lambda))
+            .collect();
+        for pair in distances.windows(2) {
+            assert!(pair[1] < pair[0], "{:?}", distances);
+        }
+        assert!(distances[0] > 0.1, "{:?}", distances);
+        assert!(distances[4] < 1e-3, "{:?}", distances);
+    }
+
+    #[test]
+    fn overriding_the_means_moves_the_tangency_portfolio_on_the_same_risk() {
+        let mut stats = covariance_stats(array![
+            [0.0004, 0.0001, 0.0],
+            [0.0001, 0.0009, 0.0002],
+            [0.0, 0.0002, 0.0016]
+        ]);
+        stats.mean_returns = array![0.0004, 0.0006, 0.0008];
+        let po = settings("");
+        let historical = optimize_portfolio(&stats, 5, &po).unwrap();
+
+        // Annualized views, favoring the first asset
+        let views: std::collections::HashMap<String, f64> =
+            [("ASSET_0", 0.30), ("asset_1", 0.05), ("asset_2", 0.02)]
+                .into_iter()
+                .map(|(asset, view)| (asset.to_string(), view))
+                .collect();
+        let mut overridden = stats.clone();
+        overridden.override_mean_returns(&views, 252.0).unwrap();
+        assert!((overridden.mean_returns[0] - 0.30 / 252.0).abs() < 1e-15);
+        assert_eq!(overridden.covariance, stats.covariance);
+        let with_views = optimize_portfolio(&overridden, 5, &po).unwrap();
+        assert!(
+            with_views.optimal_risky_portfolio[0] > historical.optimal_risky_portfolio[0] + 0.1,
+            "{:?} then {:?}",
+            historical.optimal_risky_portfolio,
+            with_views.optimal_risky_portfolio
+        );
+        // Its risk is still measured on the historical covariance
+        let weights = Array1::from(with_views.optimal_risky_portfolio.clone());
+        let variance = portfolio_variance(&weights, &stats.covariance).unwrap();
+        assert!((with_views.optimal_risky_std - variance.sqrt()).abs() < 1e-12);
+
+        let partial: std::collections::HashMap<String, f64> =
+            [("asset_0".to_string(), 0.1)].into_iter().collect();
+        assert!(matches!(
+            stats.clone().override_mean_returns(&partial, 252.0),
+            Err(QuarsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn a_tight_volatility_cap_trims_the_frontier_points_above_it() {
+        let mut stats = covariance_stats(array![[0.0004, 0.0001], [0.0001, 0.0009]]);
+        stats.mean_returns = array![0.0004, 0.0008];
+        let results = optimize_portfolio(&stats, 20, &settings("")).unwrap();
+        let annual_vol = |std: f64| std * 252f64.sqrt();
+        let vols: Vec<f64> = results
+            .frontier
+            .iter()
+            .map(|pt| annual_vol(pt.portfolio_std))
+            .collect();
+        // Halfway along the frontier's volatilities
+        let cap = (vols.iter().cloned().fold(f64::INFINITY, f64::min)
+            + vols.iter().cloned().fold(0.0, f64::max))
+            / 2.0;
+
+        let mut capped = optimize_portfolio(&stats, 20, &settings("")).unwrap();
+        capped.truncate_frontier(None, Some(cap), 252.0).unwrap();
+        assert!(!capped.frontier.is_empty());
+        assert!(capped.frontier.len() < results.frontier.len());
+        assert!(capped
+            .frontier
+            .iter()
+            .all(|pt| annual_vol(pt.portfolio_std) <= cap));
+        assert_eq!(
+            capped.frontier.len(),
+            vols.iter().filter(|vol| **vol <= cap).count()
+        );
+
+        // Below the risk-free return, no point is left
+        let mut none_left = optimize_portfolio(&stats, 20, &settings("")).unwrap();
+        assert!(matches!(
+            none_left.truncate_frontier(Some(-1.0), None, 252.0),
+            Err(QuarsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn inverse_volatility_weights_equal_vols_equally_and_a_riskier_asset_less() {
+        // Correlations play no part
+        let equal = covariance_stats(array![[0.01, 0.008], [0.008, 0.01]]);
+        let weights = optimize_inverse_volatility(&equal, 0.0, 3, false)
+            .unwrap()
+            .optimal_risky_portfolio;
+        assert_weights(&weights, &[0.5, 0.5], 1e-12);
+
+        // Volatilities of 10%, 20% and 40%: weights ∝ 1/σ, i.e. 4:2:1
+        let stats = covariance_stats(array![[0.01, 0.0, 0.0], [0.0, 0.04, 0.0], [0.0, 0.0, 0.16]]);
+        let weights = optimize_inverse_volatility(&stats, 0.0, 3, false)
+            .unwrap()
+            .optimal_risky_portfolio;
+        assert_weights(&weights, &[4.0 / 7.0, 2.0 / 7.0, 1.0 / 7.0], 1e-12);
+        assert!((weights[0] * 0.1 - weights[1] * 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn the_cara_closed_form_matches_a_numerical_maximization() {
+        let mut stats = covariance_stats(array![
+            [0.0004, 0.0001, 0.0],
+            [0.0001, 0.0009, 0.0002],
+            [0.0, 0.0002, 0.0016]
+        ]);
+        stats.mean_returns = array![0.0004, 0.0007, 0.0009];
+        let gamma = 3.0;
+        let utility = |x: &Array1<f64>| {
+            stats.mean_returns.dot(x) - 0.5 * gamma * x.dot(&stats.covariance.dot(x))
+        };
+
+        // Gradient ascent within the budget, the gradient μ - γΣx projected onto Σx = 0
+        let mut x = Array1::from_elem(3, 1.0 / 3.0);
+        for _ in 0..5_000 {
+            let gradient = &stats.mean_returns - &(stats.covariance.dot(&x) * gamma);
+            let projected = &gradient - gradient.mean().unwrap();
+            x = x + projected * 50.0;
+        }
+
+        let results = optimize_cara(&stats, gamma, 0.0, 3, false).unwrap();
+        assert_weights(&results.optimal_risky_portfolio, &x.to_vec(), 1e-8);
+        let closed_form = Array1::from(results.optimal_risky_portfolio.clone());
+        assert!((closed_form.sum() - 1.0).abs() < 1e-12);
+        assert!((results.certainty_equivalent.unwrap() - utility(&x)).abs() < 1e-12);
+        // Any other fully invested portfolio has a lower utility
+        let shifted = &closed_form + &array![0.01, -0.01, 0.0];
+        assert!(utility(&shifted) < utility(&closed_form));
+    }
+
+    #[test]
+    fn adaptive_spacing_concentrates_the_points_at_the_bend_of_the_frontier() {
+        let mut stats = covariance_stats(array![
+            [0.0004, 0.0001, 0.0],
+            [0.0001, 0.0009, 0.0002],
+            [0.0, 0.0002, 0.0016]
+        ]);
+        stats.mean_returns = array![0.0002, 0.0006, 0.0012];
+        let returns = |spacing| -> Vec<f64> {
+            min_variance_frontier(&stats, 0.0, 21, spacing)
+                .unwrap()
+                .iter()
+                .map(|pt| pt.expected_return)
+                .collect()
+        };
+        let uniform = returns(FrontierSpacing::Uniform);
+        let adaptive = returns(FrontierSpacing::Adaptive);
+        assert_eq!(adaptive.len(), uniform.len());
+        // Same span, from the minimum-variance vertex to the highest mean
+        assert!((adaptive[0] - uniform[0]).abs() < 1e-15);
+        assert!((adaptive[20] - uniform[20]).abs() < 1e-15);
+
+        // The frontier bends most at the vertex and straightens out above it
+        let near_vertex = |targets: &[f64]| {
+            let bound = uniform[0] + 0.2 * (uniform[20] - uniform[0]);
+            targets.iter().filter(|m| **m <= bound + 1e-15).count()
+        };
+        assert!(
+            near_vertex(&adaptive) > near_vertex(&uniform),
+            "{} then {}",
+            near_vertex(&uniform),
+            near_vertex(&adaptive)
+        );
+        let first_gap = adaptive[1] - adaptive[0];
+        let last_gap = adaptive[20] - adaptive[19];
+        assert!(first_gap < last_gap, "{} then {}", first_gap, last_gap);
+    }
+
+    #[test]
+    fn symmetric_returns_have_their_min_semivariance_close_to_the_min_variance_portfolio() {
+        use rand::Rng;
+        use rand_distr::StandardNormal;
+        // One-factor normal draws, each mirrored around the mean so the sample is symmetric
+        let (betas, idiosyncratic, mean) = ([0.008, 0.012, 0.015], [0.006, 0.008, 0.014], 0.0005);
+        let mut rng = StdRng::seed_from_u64(5);
+        let n_draws = 5_000;
+        let mut returns = Array2::<f64>::zeros((3, 2 * n_draws));
+        for t in 0..n_draws {
+            let factor: f64 = rng.sample(StandardNormal);
+            for i in 0..3 {
+                let noise: f64 = rng.sample(StandardNormal);
+                let deviation = betas[i] * factor + idiosyncratic[i] * noise;
+                returns[[i, 2 * t]] = mean + deviation;
+                returns[[i, 2 * t + 1]] = mean - deviation;
+            }
+        }
+        let dates = (0..2 * n_draws).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(
+            ["A", "B", "C"].map(String::from).to_vec(),
+            returns,
+            dates,
+        )
+        .unwrap();
+
+        let min_variance = optimize_min_variance(&stats, 0.0, 3, false).unwrap();
+        let min_semivariance = optimize_min_semivariance(&stats, mean, 0.0, 3, false).unwrap();
+        // The downside co-movements differ from half the covariance by the periods the assets
+        // move apart, the two portfolios are close in risk rather than identical
+        let excess_risk = min_semivariance.optimal_risky_std / min_variance.optimal_risky_std;
+        assert!((1.0..1.02).contains(&excess_risk), "{}", excess_risk);
+
+        // Assets always moving the same way: the semi-covariance is exactly half the covariance
+        let mut returns = Array2::<f64>::zeros((3, 2 * n_draws));
+        for t in 0..n_draws {
+            let factor: f64 = rng.sample(StandardNormal);
+            for i in 0..3 {
+                let deviation = factor * (betas[i] + idiosyncratic[i] * rng.gen::<f64>());
+                returns[[i, 2 * t]] = mean + deviation;
+                returns[[i, 2 * t + 1]] = mean - deviation;
+            }
+        }
+        let dates = (0..2 * n_draws).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(
+            ["A", "B", "C"].map(String::from).to_vec(),
+            returns,
+            dates,
+        )
+        .unwrap();
+        assert_weights(
+            &optimize_min_semivariance(&stats, mean, 0.0, 3, false)
+                .unwrap()
+                .optimal_risky_portfolio,
+            &optimize_min_variance(&stats, 0.0, 3, false)
+                .unwrap()
+                .optimal_risky_portfolio,
+            1e-6,
+        );
+    }
+
+    #[test]
+    fn a_more_volatile_asset_is_less_sensitive_to_its_expected_return() {
+        // Uncorrelated, 1%, 2% and 3% daily volatility
+        let mut stats = covariance_stats(array![
+            [0.0001, 0.0, 0.0],
+            [0.0, 0.0004, 0.0],
+            [0.0, 0.0, 0.0009]
+        ]);
+        stats.mean_returns = array![0.0003, 0.0005, 0.0007];
+        let sensitivities = return_sensitivity(&stats, 0.0, 2.0, 1e-5).unwrap();
+        let gradients: Vec<f64> = sensitivities.iter().map(|s| s.gradient).collect();
+        assert!(gradients.iter().all(|g| *g > 0.0), "{:?}", gradients);
+        assert!(
+            gradients[0] > gradients[1] && gradients[1] > gradients[2],
+            "{:?}",
+            gradients
+        );
+        for s in &sensitivities {
+            assert!(
+                s.weight_down < s.weight && s.weight < s.weight_up,
+                "{:?}",
+                s
+            );
+        }
+        assert!(return_sensitivity(&stats, 0.0, 2.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn the_min_variance_portfolio_has_the_lowest_volatility_of_the_special_portfolios() {
+        let mut stats = covariance_stats(array![
+            [0.0001, 0.00002, 0.0],
+            [0.00002, 0.0004, 0.0001],
+            [0.0, 0.0001, 0.0009],
+        ]);
+        stats.mean_returns = array![0.0002, 0.0006, 0.0010];
+        let special = optimize_all_special(&stats, 0.0001).unwrap();
+
+        let min_std = special.min_variance.portfolio_std;
+        let max_sharpe = special.max_sharpe.sharpe_ratio.unwrap();
+        for (name, portfolio) in special.named() {
+            assert!(
+                (portfolio.weights.iter().sum::<f64>() - 1.0).abs() < 1e-12,
+                "{}",
+                name
+            );
+            assert!(portfolio.portfolio_std >= min_std - 1e-15, "{}", name);
+            assert!(
+                portfolio.sharpe_ratio.unwrap() <= max_sharpe + 1e-12,
+                "{}",
+                name
+            );
+        }
+        assert_weights(&special.max_return.weights, &[0.0, 0.0, 1.0], 1e-12);
+        assert!(special.equal_weight.portfolio_std > min_std);
+
+        // At or above the minimum-variance return the tangency portfolio does not exist
+        assert!(matches!(
+            optimize_all_special(&stats, special.min_variance.expected_return),
+            Err(QuarsError::Infeasible(_))
+        ));
+    }
+
+    #[test]
+    fn a_higher_rate_keeps_the_risky_weights_and_moves_the_mix_to_the_risk_free_asset() {
+        let mut stats = covariance_stats(array![[0.0001, 0.00002], [0.00002, 0.0004]]);
+        stats.mean_returns = array![0.0004, 0.0008];
+        let sensitivity =
+            rate_sensitivity(&stats, 0.02, 252.0, Compounding::Discrete, 2.0, 0.01).unwrap();
+
+        // A parallel shift of the excess returns is absorbed by the budget constraint
+        assert_weights(&sensitivity.up.weights, &sensitivity.base.weights, 1e-9);
+        assert_weights(&sensitivity.down.weights, &sensitivity.base.weights, 1e-9);
+        assert!(sensitivity.weight_gradients.iter().all(|g| g.abs() < 1e-6));
+        // A higher rate narrows the excess return of the same risky portfolio
+        assert!(sensitivity.up.sharpe_ratio < sensitivity.base.sharpe_ratio);
+        assert!(sensitivity.base.sharpe_ratio < sensitivity.down.sharpe_ratio);
+        assert!(sensitivity.up.risky_share < sensitivity.base.risky_share);
+        assert!(sensitivity.base.risky_share < sensitivity.down.risky_share);
+        assert!(sensitivity.risky_share_gradient < 0.0);
+
+        assert!(matches!(
+            rate_sensitivity(&stats, 0.02, 252.0, Compounding::Discrete, 2.0, 0.0),
+            Err(QuarsError::Config(_))
+        ));
+    }
+}
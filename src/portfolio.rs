@@ -1,55 +1,694 @@
+use crate::config::{BacktestSettings, SyntheticAsset};
 use crate::data::HistoricalData;
-use ndarray::{Array1, Array2, Axis};
-use std::collections::HashMap;
-use std::error::Error;
+use crate::error::QuarsError;
+use crate::math::statistics::normal_quantile;
+use crate::utils::{self, parse_date};
+use chrono::Datelike;
+use ndarray::{s, Array1, Array2, ArrayView1, ArrayView2, Axis};
+use ndarray_linalg::{Cholesky, Eigh, Inverse, UPLO};
+use rand::rngs::StdRng;
+use rand::Rng;
+use rand_distr::StandardNormal;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use tracing::warn;
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct PortfolioStats {
     pub assets: Vec<String>,
     pub mean_returns: Array1<f64>,
     pub covariance: Array2<f64>,
     pub returns_matrix: Array2<f64>, // shape: (n_assets, n_samples)
+    pub dates: Vec<String>,          // date of each return sample (end of the period)
 }
 
-pub fn calculate_portfolio_stats(data: &HistoricalData) -> Result<PortfolioStats, Box<dyn Error>> {
-    // Group prices by asset
-    let mut asset_prices: HashMap<String, Vec<f64>> = HashMap::new();
+impl PortfolioStats {
+    /// Covariance scaled from the native sampling frequency to annual, Σ·periods_per_year.
+    /// The optimizer keeps working on the native scale, this is meant for reporting.
+    pub fn annualized_covariance(&self, periods_per_year: f64) -> Array2<f64> {
+        &self.covariance * periods_per_year
+    }
+
+    /// Statistics restricted to the assets at the given indices, in that order
+    pub fn subset(&self, indices: &[usize]) -> PortfolioStats {
+        PortfolioStats {
+            assets: indices.iter().map(|&i| self.assets[i].clone()).collect(),
+            mean_returns: self.mean_returns.select(Axis(0), indices),
+            covariance: self
+                .covariance
+                .select(Axis(0), indices)
+                .select(Axis(1), indices),
+            returns_matrix: self.returns_matrix.select(Axis(0), indices),
+            dates: self.dates.clone(),
+        }
+    }
+
+    /// Appends synthetic assets, linear combinations Σ wᵢ·assetᵢ of the assets (tickers
+    /// case-insensitive, earlier synthetic assets included), with the combined returns wᵀr,
+    /// mean wᵀμ and covariances Σw, consistent with an overridden covariance. The constituents
+    /// of the definitions with `drop_constituents` are then removed.
+    pub fn add_synthetic_assets(&mut self, synthetic: &[SyntheticAsset]) -> Result<(), QuarsError> {
+        let mut dropped = Vec::new();
+        for definition in synthetic {
+            let name = &definition.name;
+            if self.assets.iter().any(|a| a.eq_ignore_ascii_case(name)) {
+                return Err(QuarsError::Config(format!(
+                    "Synthetic asset {} is already an asset of the data",
+                    name
+                )));
+            }
+            if definition.weights.is_empty() {
+                return Err(QuarsError::Config(format!(
+                    "Synthetic asset {} has no constituents",
+                    name
+                )));
+            }
+            let n = self.assets.len();
+            let mut weights = Array1::<f64>::zeros(n);
+            for (ticker, weight) in &definition.weights {
+                let idx = self
+                    .assets
+                    .iter()
+                    .position(|a| a.eq_ignore_ascii_case(ticker))
+                    .ok_or_else(|| {
+                        QuarsError::Config(format!(
+                            "Constituent {} of synthetic asset {} is not in the data",
+                            ticker, name
+                        ))
+                    })?;
+                weights[idx] += weight;
+                if definition.drop_constituents {
+                    dropped.push(idx);
+                }
+            }
+
+            let covariances = self.covariance.dot(&weights);
+            let variance = weights.dot(&covariances);
+            self.covariance =
+                Array2::from_shape_fn((n + 1, n + 1), |(i, j)| match (i == n, j == n) {
+                    (false, false) => self.covariance[[i, j]],
+                    (true, false) => covariances[j],
+                    (false, true) => covariances[i],
+                    (true, true) => variance,
+                });
+            self.mean_returns = self
+                .mean_returns
+                .iter()
+                .copied()
+                .chain(std::iter::once(weights.dot(&self.mean_returns)))
+                .collect();
+            self.returns_matrix
+                .push_row(weights.dot(&self.returns_matrix).view())
+                .map_err(|e| QuarsError::InsufficientData(e.to_string()))?;
+            self.assets.push(name.clone());
+        }
+        if !dropped.is_empty() {
+            let kept: Vec<usize> = (0..self.assets.len())
+                .filter(|i| !dropped.contains(i))
+                .collect();
+            *self = self.subset(&kept);
+        }
+        Ok(())
+    }
+
+    /// Keeps the `n` assets ranking highest by `metric`, computed from the returns matrix with
+    /// the per-period `risk_free_rate` for the Sharpe ratio, and the `keep` tickers
+    /// (case-insensitive) unranked, in their original order. Returns the statistics and the
+    /// selected ranked assets with their score.
+    pub fn preselect(
+        &self,
+        metric: Preselection,
+        n: usize,
+        risk_free_rate: f64,
+        keep: &[&str],
+    ) -> Result<(PortfolioStats, Vec<(String, f64)>), QuarsError> {
+        if n == 0 {
+            return Err(QuarsError::Config(
+                "preselect_n must keep at least one asset".to_string(),
+            ));
+        }
+        if self.returns_matrix.ncols() < 2 {
+            return Err(QuarsError::InsufficientData(
+                "Pre-selecting assets needs a return series of at least two periods".to_string(),
+            ));
+        }
+        let scores: Vec<f64> = self
+            .returns_matrix
+            .rows()
+            .into_iter()
+            .map(|returns| match metric {
+                Preselection::MeanReturn => returns.mean().unwrap_or(0.0),
+                Preselection::Momentum => returns.fold(1.0, |acc, r| acc * (1.0 + r)) - 1.0,
+                Preselection::Sharpe => {
+                    let std = returns.std(1.0);
+                    if std > 0.0 {
+                        (returns.mean().unwrap_or(0.0) - risk_free_rate) / std
+                    } else {
+                        f64::NEG_INFINITY
+                    }
+                }
+            })
+            .collect();
+        let is_kept = |i: usize| keep.iter().any(|k| k.eq_ignore_ascii_case(&self.assets[i]));
+        let mut ranked: Vec<usize> = (0..self.assets.len()).filter(|&i| !is_kept(i)).collect();
+        ranked.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        ranked.truncate(n);
+        let mut indices: Vec<usize> = (0..self.assets.len())
+            .filter(|&i| is_kept(i) || ranked.contains(&i))
+            .collect();
+        indices.sort_unstable();
+        let selected = ranked
+            .iter()
+            .map(|&i| (self.assets[i].clone(), scores[i]))
+            .collect();
+        Ok((self.subset(&indices), selected))
+    }
+
+    /// Replaces the historical mean returns with user views, keeping the estimated covariance.
+    /// `expected` maps tickers (case-insensitive) to annualized expected returns and must
+    /// cover every asset.
+    pub fn override_mean_returns(
+        &mut self,
+        expected: &HashMap<String, f64>,
+        periods_per_year: f64,
+    ) -> Result<(), QuarsError> {
+        let lookup: HashMap<String, f64> = expected
+            .iter()
+            .map(|(asset, value)| (asset.to_uppercase(), *value))
+            .collect();
+        let mut means = Array1::<f64>::zeros(self.assets.len());
+        let mut missing = Vec::new();
+        for (i, asset) in self.assets.iter().enumerate() {
+            match lookup.get(&asset.to_uppercase()) {
+                Some(value) => means[i] = value / periods_per_year,
+                None => missing.push(asset.clone()),
+            }
+        }
+        if !missing.is_empty() {
+            return Err(QuarsError::Config(format!(
+                "Expected returns are missing for {:?}",
+                missing
+            )));
+        }
+        if lookup.len() > self.assets.len() {
+            warn!("expected returns given for assets that are not in the data");
+        }
+        self.mean_returns = means;
+        Ok(())
+    }
+
+    /// Shrinks the mean returns toward a common target, μ_s = (1 - w)μ + w·μ₀1, see
+    /// `MeanShrinkage`. Without an intensity w it is estimated as Jorion's (1986) Bayes-Stein
+    /// intensity w = (N + 2) / (N + 2 + T (μ - μ₀1)ᵀΣ⁻¹(μ - μ₀1)), N assets and T periods,
+    /// which shrinks more the noisier the means are relative to their spread. Returns the
+    /// intensity and the per-period target.
+    pub fn shrink_mean_returns(
+        &mut self,
+        target: MeanShrinkage,
+        intensity: Option<f64>,
+    ) -> Result<(f64, f64), QuarsError> {
+        let n = self.assets.len() as f64;
+        let target_mean = match target {
+            MeanShrinkage::GrandMean => self.mean_returns.mean().unwrap_or(0.0),
+            // Expected return of the global minimum-variance portfolio Σ⁻¹1 / 1ᵀΣ⁻¹1
+            MeanShrinkage::MinVariance => {
+                let gmv = self.covariance.inv()?.sum_axis(Axis(1));
+                gmv.dot(&self.mean_returns) / gmv.sum()
+            }
+        };
+        let deviation = self.mean_returns.mapv(|m| m - target_mean);
+        let intensity = match intensity {
+            Some(intensity) if !(0.0..=1.0).contains(&intensity) => {
+                return Err(QuarsError::Config(format!(
+                    "mean_shrinkage_intensity must lie in [0, 1], got {}",
+                    intensity
+                )))
+            }
+            Some(intensity) => intensity,
+            None => {
+                let distance = deviation.dot(&self.covariance.inv()?.dot(&deviation));
+                let periods = self.returns_matrix.ncols() as f64;
+                (n + 2.0) / (n + 2.0 + periods * distance)
+            }
+        };
+        self.mean_returns = &self.mean_returns - &(deviation * intensity);
+        Ok((intensity, target_mean))
+    }
+
+    /// Tilts the means toward recent momentum, μ = (1 - w)·μ + w·m with m the trailing return
+    /// of each asset compounded over the last `lookback` periods, ∏(1 + r) - 1, converted to
+    /// the per-period rate (∏(1 + r))^(1/lookback) - 1 that compounds to it.
+    pub fn apply_momentum_tilt(&mut self, weight: f64, lookback: usize) -> Result<(), QuarsError> {
+        if !(0.0..=1.0).contains(&weight) {
+            return Err(QuarsError::Config(format!(
+                "The momentum tilt weight must lie in [0, 1], got {}",
+                weight
+            )));
+        }
+        let n_obs = self.returns_matrix.ncols();
+        if lookback == 0 || lookback > n_obs {
+            return Err(QuarsError::InsufficientData(format!(
+                "The momentum lookback of {} periods must lie within the {} returns",
+                lookback, n_obs
+            )));
+        }
+        let momentum = trailing_momentum(self.returns_matrix.view(), lookback);
+        self.mean_returns = &self.mean_returns * (1.0 - weight) + momentum * weight;
+        Ok(())
+    }
+
+    /// Ridge regularization Σ + λI of the covariance, λ per period. It bounds the smallest
+    /// eigenvalue away from zero so that a near-singular estimate inverts stably, and as λ
+    /// outgrows the covariance the optimal weights tend to those of λI, i.e. equal weights.
+    pub fn add_covariance_ridge(&mut self, lambda: f64) -> Result<(), QuarsError> {
+        if !lambda.is_finite() || lambda < 0.0 {
+            return Err(QuarsError::Config(
+                "The covariance ridge must be non-negative.".to_string(),
+            ));
+        }
+        self.covariance.diag_mut().mapv_inplace(|v| v + lambda);
+        Ok(())
+    }
+
+    /// Replaces the estimated covariance with an externally supplied annualized one. The
+    /// matrix must be symmetric and cover exactly the assets of the data, its rows and columns
+    /// are matched to them by name (case-insensitive).
+    pub fn override_covariance(
+        &mut self,
+        assets: &[String],
+        covariance: &Array2<f64>,
+        periods_per_year: f64,
+    ) -> Result<(), QuarsError> {
+        let n = self.assets.len();
+        if covariance.nrows() != assets.len() || covariance.ncols() != assets.len() {
+            return Err(QuarsError::Parse(
+                "The covariance matrix must be square over its assets.".to_string(),
+            ));
+        }
+        let scale = covariance.iter().fold(0.0_f64, |acc, v| acc.max(v.abs()));
+        if covariance
+            .indexed_iter()
+            .any(|((i, j), v)| (v - covariance[[j, i]]).abs() > 1e-10 * scale.max(1.0))
+        {
+            return Err(QuarsError::Parse(
+                "The covariance matrix is not symmetric.".to_string(),
+            ));
+        }
+        if covariance.diag().iter().any(|&v| v < 0.0) {
+            return Err(QuarsError::Parse(
+                "The covariance matrix has a negative variance.".to_string(),
+            ));
+        }
+
+        let positions: HashMap<String, usize> = assets
+            .iter()
+            .enumerate()
+            .map(|(i, asset)| (asset.to_uppercase(), i))
+            .collect();
+        let missing: Vec<&String> = self
+            .assets
+            .iter()
+            .filter(|asset| !positions.contains_key(&asset.to_uppercase()))
+            .collect();
+        if !missing.is_empty() || positions.len() != n {
+            return Err(QuarsError::Config(format!(
+                "The covariance matrix covers {:?}, the data {:?}",
+                assets, self.assets
+            )));
+        }
+        let order: Vec<usize> = self
+            .assets
+            .iter()
+            .map(|asset| positions[&asset.to_uppercase()])
+            .collect();
+        self.covariance = Array2::from_shape_fn((n, n), |(i, j)| covariance[[order[i], order[j]]])
+            / periods_per_year;
+        warn_if_indefinite(&self.covariance, "supplied")
+    }
+
+    /// Weights per asset from a ticker (case-insensitive) to weight map, zero for the assets
+    /// the map does not cover
+    pub fn weights_from_map(&self, weights: &HashMap<String, f64>) -> Vec<f64> {
+        let lookup: HashMap<String, f64> = weights
+            .iter()
+            .map(|(asset, value)| (asset.to_uppercase(), *value))
+            .collect();
+        let aligned: Vec<f64> = self
+            .assets
+            .iter()
+            .map(|asset| lookup.get(&asset.to_uppercase()).copied().unwrap_or(0.0))
+            .collect();
+        if aligned.iter().filter(|w| **w != 0.0).count()
+            < lookup.values().filter(|w| **w != 0.0).count()
+        {
+            warn!("weights given for assets that are not in the data are ignored");
+        }
+        aligned
+    }
+
+    /// Removes an asset (e.g. a benchmark fetched alongside the universe) from the statistics
+    /// and returns it separately as a return series aligned with `dates`
+    pub fn split_off_asset(&self, ticker: &str) -> Result<(PortfolioStats, Vec<f64>), QuarsError> {
+        let idx = self
+            .assets
+            .iter()
+            .position(|asset| asset.eq_ignore_ascii_case(ticker))
+            .ok_or_else(|| {
+                QuarsError::Config(format!(
+                    "{} not found in the data, add it to the tickers",
+                    ticker
+                ))
+            })?;
+        let rest: Vec<usize> = (0..self.assets.len()).filter(|&i| i != idx).collect();
+        if rest.is_empty() {
+            return Err(QuarsError::Config(format!(
+                "{} is the only asset in the data",
+                ticker
+            )));
+        }
+        Ok((self.subset(&rest), self.returns_matrix.row(idx).to_vec()))
+    }
+
+    /// Statistics of the returns in excess of a benchmark (alpha), r_i - r_b for every period.
+    /// The covariance is re-estimated on the active returns, i.e. it becomes the tracking
+    /// error covariance, and the benchmark mean is subtracted from the current means so that
+    /// overridden expected returns carry over.
+    pub fn excess_over(&self, benchmark: &[f64]) -> Result<PortfolioStats, QuarsError> {
+        if benchmark.len() != self.returns_matrix.ncols() {
+            return Err(QuarsError::InsufficientData(format!(
+                "Benchmark has {} returns, the assets have {}",
+                benchmark.len(),
+                self.returns_matrix.ncols()
+            )));
+        }
+        let benchmark = Array1::from(benchmark.to_vec());
+        let benchmark_mean = benchmark.mean().unwrap_or(0.0);
+        let returns_matrix = &self.returns_matrix - &benchmark;
+        Ok(PortfolioStats {
+            assets: self.assets.clone(),
+            mean_returns: &self.mean_returns - benchmark_mean,
+            covariance: compute_sample_covariance(&returns_matrix)?,
+            returns_matrix,
+            dates: self.dates.clone(),
+        })
+    }
+
+    /// Statistics re-estimated on the given return periods, e.g. a bootstrap resample.
+    /// Periods may repeat; mean and covariance are recomputed from the selected returns.
+    pub fn resample_periods(&self, periods: &[usize]) -> Result<PortfolioStats, QuarsError> {
+        Self::from_returns(
+            self.assets.clone(),
+            self.returns_matrix.select(Axis(1), periods),
+            periods.iter().map(|&t| self.dates[t].clone()).collect(),
+        )
+    }
+
+    /// Statistics of a returns matrix of shape (n_assets, n_samples), the sample mean and
+    /// covariance of its rows
+    pub fn from_returns(
+        assets: Vec<String>,
+        returns_matrix: Array2<f64>,
+        dates: Vec<String>,
+    ) -> Result<PortfolioStats, QuarsError> {
+        let mean_returns = returns_matrix.mean_axis(Axis(1)).ok_or_else(|| {
+            QuarsError::InsufficientData("Failed to compute mean returns".to_string())
+        })?;
+        Ok(PortfolioStats {
+            assets,
+            mean_returns,
+            covariance: compute_sample_covariance(&returns_matrix)?,
+            returns_matrix,
+            dates,
+        })
+    }
+
+    /// Statistics of the returns with each asset of `lags` shifted back by its number of
+    /// periods, for lead-lag studies: at date t a lagged asset carries its return of date
+    /// t - k, so the covariance measures how it co-moves with the others k periods later.
+    /// Every series is truncated to the common overlap, the first max(k) periods are dropped.
+    /// A lead is a lag of all the other assets.
+    pub fn lag_returns(&self, lags: &HashMap<String, usize>) -> Result<PortfolioStats, QuarsError> {
+        let mut asset_lags = vec![0; self.assets.len()];
+        for (ticker, &lag) in lags {
+            let idx = self
+                .assets
+                .iter()
+                .position(|asset| asset.eq_ignore_ascii_case(ticker))
+                .ok_or_else(|| {
+                    QuarsError::Config(format!(
+                        "{} has a return lag but is not in the data",
+                        ticker
+                    ))
+                })?;
+            asset_lags[idx] = lag;
+        }
+        let max_lag = asset_lags.iter().copied().max().unwrap_or(0);
+        let t = self.returns_matrix.ncols();
+        // The covariance needs two observations
+        if max_lag + 2 > t {
+            return Err(QuarsError::InsufficientData(format!(
+                "A return lag of {} periods leaves fewer than 2 of the {} returns",
+                max_lag, t
+            )));
+        }
+        let mut returns_matrix = Array2::<f64>::zeros((self.assets.len(), t - max_lag));
+        for (i, mut row) in returns_matrix.axis_iter_mut(Axis(0)).enumerate() {
+            let start = max_lag - asset_lags[i];
+            row.assign(&self.returns_matrix.slice(s![i, start..start + t - max_lag]));
+        }
+        let mean_returns = returns_matrix.mean_axis(Axis(1)).ok_or_else(|| {
+            QuarsError::InsufficientData("Failed to compute mean returns".to_string())
+        })?;
+        Ok(PortfolioStats {
+            assets: self.assets.clone(),
+            mean_returns,
+            covariance: compute_sample_covariance(&returns_matrix)?,
+            returns_matrix,
+            dates: self.dates[max_lag..].to_vec(),
+        })
+    }
+
+    /// Replaces the covariance with the realized covariance of the log-price increments,
+    /// ln(1 + r), see `realized_covariance`
+    pub fn use_realized_covariance(&mut self, lags: usize) -> Result<(), QuarsError> {
+        let log_increments = self.returns_matrix.mapv(f64::ln_1p);
+        self.covariance = realized_covariance(&log_increments, lags)?;
+        warn_if_indefinite(&self.covariance, "realized")
+    }
+
+    /// Replaces the means and covariance with those of the overlapping compounded returns over
+    /// `horizon` periods, see `horizon_returns` and `overlapping_covariance`, divided by the
+    /// horizon so that they stay on the per-period scale of the rest of the run. The returns
+    /// matrix keeps the single-period returns.
+    pub fn use_horizon_returns(&mut self, horizon: usize) -> Result<(), QuarsError> {
+        let overlapping = horizon_returns(&self.returns_matrix, horizon)?;
+        let mean_returns = overlapping.mean_axis(Axis(1)).ok_or_else(|| {
+            QuarsError::InsufficientData("Failed to compute mean returns".to_string())
+        })?;
+        self.covariance = overlapping_covariance(&overlapping, horizon)? / horizon as f64;
+        self.mean_returns = mean_returns / horizon as f64;
+        Ok(())
+    }
+
+    /// Correlation matrix, the covariance scaled by the asset volatilities
+    pub fn correlation(&self) -> Array2<f64> {
+        let vols = self.asset_volatilities();
+        let outer = vols
+            .clone()
+            .insert_axis(Axis(1))
+            .dot(&vols.insert_axis(Axis(0)));
+        &self.covariance / &outer
+    }
+
+    /// Per-asset volatilities, the square root of the covariance diagonal
+    pub fn asset_volatilities(&self) -> Array1<f64> {
+        self.covariance.diag().mapv(f64::sqrt)
+    }
+
+    /// Arithmetic mean returns scaled to annual, μ·periods_per_year
+    pub fn annualized_mean_returns(&self, periods_per_year: f64) -> Array1<f64> {
+        &self.mean_returns * periods_per_year
+    }
+}
+
+/// Common target the mean returns are shrunk toward
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MeanShrinkage {
+    // Equal-weighted average of the means
+    GrandMean,
+    // Expected return of the global minimum-variance portfolio, the Bayes-Stein target
+    MinVariance,
+}
+
+impl MeanShrinkage {
+    pub fn from_config(target: &str) -> Result<Self, QuarsError> {
+        match target {
+            "grand-mean" => Ok(Self::GrandMean),
+            "min-variance" => Ok(Self::MinVariance),
+            other => Err(QuarsError::Config(format!(
+                "Unknown mean_shrinkage '{}', use grand-mean or min-variance",
+                other
+            ))),
+        }
+    }
+}
+
+/// Metric the assets are ranked by to pre-select the universe
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preselection {
+    // Mean excess return over the risk-free rate per unit of volatility
+    Sharpe,
+    // Compounded return over the whole sample
+    Momentum,
+    // Arithmetic mean return
+    MeanReturn,
+}
+
+impl Preselection {
+    pub fn from_config(metric: &str) -> Result<Self, QuarsError> {
+        match metric {
+            "sharpe" => Ok(Self::Sharpe),
+            "momentum" => Ok(Self::Momentum),
+            "mean-return" => Ok(Self::MeanReturn),
+            other => Err(QuarsError::Config(format!(
+                "Unknown preselect_by '{}', use sharpe, momentum or mean-return",
+                other
+            ))),
+        }
+    }
+}
+
+// Chronological (date, price) series of each asset, restricted to the dates common to all
+// assets, with the assets in the order they first appear and those dates
+struct AlignedPrices {
+    assets: Vec<String>,
+    prices: HashMap<String, Vec<(String, f64)>>,
+    length: usize,
+    dates: Vec<String>,
+}
+
+fn align_prices(
+    data: &HistoricalData,
+    timeframe: &str,
+    strict_frequency: bool,
+) -> Result<AlignedPrices, QuarsError> {
+    // Group (date, price) observations by asset, keeping the assets in the order they first
+    // appear so that runs on the same data are reproducible
+    let mut asset_prices: HashMap<String, Vec<(String, f64)>> = HashMap::new();
+    let mut assets: Vec<String> = Vec::new();
     for record in data {
+        if !asset_prices.contains_key(&record.asset) {
+            assets.push(record.asset.clone());
+        }
         asset_prices
             .entry(record.asset.clone())
             .or_default()
-            .push(record.price);
+            .push((record.date.clone(), record.price));
+    }
+    // Brokers may return the most recent observation first
+    for series in asset_prices.values_mut() {
+        series.sort_by(|a, b| a.0.cmp(&b.0));
     }
 
-    let assets: Vec<String> = asset_prices.keys().cloned().collect();
     let n = assets.len();
     if n == 0 {
-        return Err("No assets found in data.".into());
+        return Err(QuarsError::InsufficientData(
+            "No assets found in data.".to_string(),
+        ));
     }
 
-    // Find the minimal length of price vector to handle partial data. This is simplified logic.
-    let mut min_len = usize::MAX;
-    for (_asset, prices) in asset_prices.iter() {
-        if prices.len() < min_len {
-            min_len = prices.len();
+    if asset_prices.values().any(|series| series.len() < 2) {
+        return Err(QuarsError::InsufficientData(
+            "Not enough data points to compute returns.".to_string(),
+        ));
+    }
+
+    // Every asset's series is checked, a single one at another frequency skews the returns
+    for asset in &assets {
+        let dates: Vec<String> = asset_prices[asset]
+            .iter()
+            .map(|(date, _)| date.clone())
+            .collect();
+        if let Some(warning) = check_frequency(&dates, timeframe)? {
+            let warning = format!("{}: {}", asset, warning);
+            if strict_frequency {
+                return Err(QuarsError::Config(warning));
+            }
+            warn!("{}", warning);
         }
     }
-    if min_len < 2 {
-        return Err("Not enough data points to compute returns.".into());
+
+    // Only the dates every asset has a price for are kept: assets on different trading
+    // calendars (crypto trading on weekends, equities not) would otherwise have the returns of
+    // different dates paired by position
+    let mut price_dates: Vec<String> = asset_prices[&assets[0]]
+        .iter()
+        .map(|(date, _)| date.clone())
+        .collect();
+    for asset in &assets[1..] {
+        let dates: HashSet<&str> = asset_prices[asset]
+            .iter()
+            .map(|(date, _)| date.as_str())
+            .collect();
+        price_dates.retain(|date| dates.contains(date.as_str()));
     }
+    price_dates.dedup();
+    if price_dates.len() < 2 {
+        return Err(QuarsError::InsufficientData(
+            "Not enough dates common to all assets to compute returns.".to_string(),
+        ));
+    }
+    let common: HashSet<&str> = price_dates.iter().map(String::as_str).collect();
+    for asset in &assets {
+        let Some(series) = asset_prices.get_mut(asset) else {
+            continue;
+        };
+        let n_prices = series.len();
+        series.retain(|(date, _)| common.contains(date.as_str()));
+        // A repeated date keeps its first price
+        series.dedup_by(|later, earlier| later.0 == earlier.0);
+        if series.len() < n_prices {
+            warn!(
+                "{}: dropped {} prices on dates not shared by all assets",
+                asset,
+                n_prices - series.len()
+            );
+        }
+    }
+    Ok(AlignedPrices {
+        assets,
+        prices: asset_prices,
+        length: price_dates.len(),
+        dates: price_dates,
+    })
+}
 
+pub fn calculate_portfolio_stats(
+    data: &HistoricalData,
+    timeframe: &str,
+    strict_frequency: bool,
+) -> Result<PortfolioStats, QuarsError> {
+    let AlignedPrices {
+        assets,
+        prices: asset_prices,
+        length: min_len,
+        dates: price_dates,
+    } = align_prices(data, timeframe, strict_frequency)?;
+    let n = assets.len();
     let t = min_len - 1;
     let mut returns_matrix = Array2::<f64>::zeros((n, t));
     for (i, asset) in assets.iter().enumerate() {
         let prices = &asset_prices[asset][0..min_len];
         for day in 0..(min_len - 1) {
-            let ret = (prices[day + 1] - prices[day]) / prices[day];
+            let ret = (prices[day + 1].1 - prices[day].1) / prices[day].1;
             returns_matrix[[i, day]] = ret;
         }
     }
 
-    let mean_returns = returns_matrix
-        .mean_axis(Axis(1))
-        .ok_or("Failed to compute mean returns")?;
+    let mean_returns = returns_matrix.mean_axis(Axis(1)).ok_or_else(|| {
+        QuarsError::InsufficientData("Failed to compute mean returns".to_string())
+    })?;
 
     // 4. Compute sample covariance
     //    Cov = 1/(T-1) * (R_centered * R_centered^T)
@@ -60,19 +699,353 @@ pub fn calculate_portfolio_stats(data: &HistoricalData) -> Result<PortfolioStats
         mean_returns,
         covariance,
         returns_matrix,
+        dates: price_dates[1..].to_vec(),
+    })
+}
+
+/// Statistics estimated in one pass over the aligned prices, each period's returns folded into
+/// a `CovarianceAccumulator` without materializing the returns matrix. The prices themselves
+/// are still held in memory, so this saves the (n_assets x T) returns matrix only. The means
+/// and covariance match `calculate_portfolio_stats` up to rounding, but the returns matrix and
+/// the dates are left empty, so nothing that needs the return series (VaR, drawdowns,
+/// resampling, ...) can run on them.
+pub fn calculate_streaming_stats(
+    data: &HistoricalData,
+    timeframe: &str,
+    strict_frequency: bool,
+) -> Result<PortfolioStats, QuarsError> {
+    let AlignedPrices {
+        assets,
+        prices: asset_prices,
+        length,
+        ..
+    } = align_prices(data, timeframe, strict_frequency)?;
+    let series: Vec<&[(String, f64)]> = assets
+        .iter()
+        .map(|asset| &asset_prices[asset][..length])
+        .collect();
+    let mut accumulator = CovarianceAccumulator::new(assets.len());
+    let mut returns = Array1::<f64>::zeros(assets.len());
+    for day in 0..length - 1 {
+        for (ret, prices) in returns.iter_mut().zip(&series) {
+            *ret = (prices[day + 1].1 - prices[day].1) / prices[day].1;
+        }
+        accumulator.push(returns.view());
+    }
+    Ok(PortfolioStats {
+        mean_returns: accumulator.mean().clone(),
+        covariance: accumulator.covariance()?,
+        returns_matrix: Array2::zeros((assets.len(), 0)),
+        dates: Vec::new(),
+        assets,
     })
 }
 
+/// Running mean and sample covariance of a stream of return vectors (Welford's algorithm):
+/// each observation x updates the mean and the co-moments C += (x - μ_old)(x - μ_new)ᵀ, which
+/// stays accurate where the textbook Σxxᵀ - nμμᵀ cancels catastrophically. Accumulators of
+/// separate chunks of a series merge into the one of the whole series, see `merge`.
+#[derive(Debug, Clone)]
+pub struct CovarianceAccumulator {
+    count: usize,
+    mean: Array1<f64>,
+    comoments: Array2<f64>,
+}
+
+impl CovarianceAccumulator {
+    pub fn new(n_assets: usize) -> Self {
+        Self {
+            count: 0,
+            mean: Array1::zeros(n_assets),
+            comoments: Array2::zeros((n_assets, n_assets)),
+        }
+    }
+
+    /// Accumulator of the observations in the columns of `observations`, its mean and
+    /// co-moments computed in one batch
+    pub fn from_columns(observations: ArrayView2<f64>) -> Self {
+        let mean = observations
+            .mean_axis(Axis(1))
+            .unwrap_or_else(|| Array1::zeros(observations.nrows()));
+        let centered = &observations - &mean.view().insert_axis(Axis(1));
+        Self {
+            count: observations.ncols(),
+            comoments: centered.dot(&centered.t()),
+            mean,
+        }
+    }
+
+    pub fn push(&mut self, observation: ArrayView1<f64>) {
+        self.count += 1;
+        let before = &observation - &self.mean;
+        self.mean.scaled_add(1.0 / self.count as f64, &before);
+        let after = &observation - &self.mean;
+        for ((i, j), c) in self.comoments.indexed_iter_mut() {
+            *c += before[i] * after[j];
+        }
+    }
+
+    /// Adds the observations of another accumulator (Chan, Golub and LeVeque's parallel
+    /// update): with δ = μ_b - μ_a, μ = μ_a + δ·n_b/n and C = C_a + C_b + δδᵀ·n_a·n_b/n, the
+    /// moments of both streams together, whatever the order they are merged in
+    pub fn merge(&mut self, other: &Self) {
+        if other.count == 0 {
+            return;
+        }
+        if self.count == 0 {
+            *self = other.clone();
+            return;
+        }
+        let count = (self.count + other.count) as f64;
+        let delta = &other.mean - &self.mean;
+        let weight = self.count as f64 * other.count as f64 / count;
+        for ((i, j), c) in self.comoments.indexed_iter_mut() {
+            *c += other.comoments[[i, j]] + delta[i] * delta[j] * weight;
+        }
+        self.mean.scaled_add(other.count as f64 / count, &delta);
+        self.count += other.count;
+    }
+
+    pub fn mean(&self) -> &Array1<f64> {
+        &self.mean
+    }
+
+    /// Sample covariance, C / (n - 1)
+    pub fn covariance(&self) -> Result<Array2<f64>, QuarsError> {
+        if self.count < 2 {
+            return Err(QuarsError::InsufficientData(
+                "Not enough observations to compute covariance.".to_string(),
+            ));
+        }
+        Ok(&self.comoments / (self.count as f64 - 1.0))
+    }
+}
+
+/// Infers the sampling frequency from the median spacing (in days) between consecutive
+/// dates and returns a warning message if it disagrees with the configured timeframe.
+pub fn check_frequency(dates: &[String], timeframe: &str) -> Result<Option<String>, QuarsError> {
+    let mut parsed = Vec::with_capacity(dates.len());
+    for date in dates {
+        // Intraday timestamps carry a time part after the date
+        parsed.push(parse_date(date.get(..10).unwrap_or(date))?);
+    }
+    let mut spacings: Vec<i64> = parsed
+        .windows(2)
+        .map(|w| (w[1] - w[0]).num_days().abs())
+        .collect();
+    if spacings.is_empty() {
+        return Ok(None);
+    }
+    spacings.sort_unstable();
+    let median = spacings[spacings.len() / 2];
+
+    let inferred = match median {
+        0 => "intraday",
+        1..=4 => "daily",
+        5..=10 => "weekly",
+        25..=35 => "monthly",
+        _ => "irregular",
+    };
+    let configured = timeframe.to_lowercase();
+    let consistent = match inferred {
+        // Any intraday interval ("5min", "1h", ...) is consistent with same-day spacing
+        "intraday" => !matches!(configured.as_str(), "daily" | "weekly" | "monthly"),
+        _ => inferred == configured,
+    };
+    if !consistent {
+        return Ok(Some(format!(
+            "configured timeframe is '{}' but the data looks {} (median spacing of {} days), annualized figures will be off",
+            configured, inferred, median
+        )));
+    }
+    Ok(None)
+}
+
+/// Realized covariance of (n_assets x n_samples) log-price increments with a Newey-West
+/// correction for autocorrelated increments, as bid-ask bounce and asynchronous trading
+/// produce in high-frequency data:
+///   Σ = Γ₀ + Σ_{k=1..L} (1 - k/(L+1)) (Γ_k + Γ_kᵀ),  Γ_k = 1/(T-1) Σ_t x_t x_{t-k}ᵀ
+/// with x_t the demeaned increments. The Bartlett weights keep Σ positive semi-definite.
+/// With zero lags this is the sample covariance.
+pub fn realized_covariance(
+    log_increments: &Array2<f64>,
+    lags: usize,
+) -> Result<Array2<f64>, QuarsError> {
+    let (_, n_obs) = log_increments.dim();
+    if lags >= n_obs {
+        return Err(QuarsError::InsufficientData(format!(
+            "{} autocorrelation lags need more than {} observations",
+            lags, n_obs
+        )));
+    }
+    let mut covariance = compute_sample_covariance(log_increments)?;
+    let mean = log_increments.mean_axis(Axis(1)).ok_or_else(|| {
+        QuarsError::InsufficientData("Failed to compute mean returns".to_string())
+    })?;
+    let centered = log_increments - &mean.insert_axis(Axis(1));
+    for k in 1..=lags {
+        let lead = centered.slice(s![.., k..]);
+        let lagged = centered.slice(s![.., ..n_obs - k]);
+        let gamma = lead.dot(&lagged.t()) / (n_obs as f64 - 1.0);
+        let weight = 1.0 - k as f64 / (lags as f64 + 1.0);
+        covariance = covariance + (&gamma + &gamma.t()) * weight;
+    }
+    Ok(covariance)
+}
+
+/// Overlapping compounded returns over `horizon` periods of (n_assets x T) single-period
+/// returns, one per window of consecutive periods: R_t = Π_{k<h} (1 + r_{t+k}) - 1 for
+/// t = 0..T-h. A horizon of one gives the returns back.
+pub fn horizon_returns(
+    returns_matrix: &Array2<f64>,
+    horizon: usize,
+) -> Result<Array2<f64>, QuarsError> {
+    if horizon == 0 {
+        return Err(QuarsError::Config(
+            "The return horizon must be at least one period.".to_string(),
+        ));
+    }
+    let (n_assets, n_obs) = returns_matrix.dim();
+    // Two windows at least, for a covariance
+    if n_obs < horizon + 1 {
+        return Err(QuarsError::InsufficientData(format!(
+            "A {}-period horizon needs more than {} returns",
+            horizon, n_obs
+        )));
+    }
+    let mut overlapping = Array2::<f64>::zeros((n_assets, n_obs - horizon + 1));
+    for (t, window) in rolling_windows(returns_matrix, horizon).enumerate() {
+        overlapping
+            .column_mut(t)
+            .assign(&window.map_axis(Axis(1), |returns| {
+                returns.fold(1.0, |growth, &r| growth * (1.0 + r)) - 1.0
+            }));
+    }
+    Ok(overlapping)
+}
+
+/// Windows of `window` consecutive periods sliding over the time axis of a (n_assets x T)
+/// returns matrix, as (n_assets x window) views in time order, the last one ending at the last
+/// period. There are T - window + 1 of them, and none when the window is empty or longer than
+/// the sample, so callers needing at least one window check the length themselves.
+pub fn rolling_windows(
+    returns_matrix: &Array2<f64>,
+    window: usize,
+) -> impl Iterator<Item = ArrayView2<'_, f64>> {
+    let n_windows = if window == 0 {
+        0
+    } else {
+        (returns_matrix.ncols() + 1).saturating_sub(window)
+    };
+    (0..n_windows).map(move |t| returns_matrix.slice(s![.., t..t + window]))
+}
+
+/// Covariance of (n_assets x N) overlapping returns over `horizon` periods, N = T - h + 1
+/// windows of T periods. The windows share h - 1 periods, so their sample mean moves with
+/// them and the plain 1/(N-1) estimator is biased low. As in the overlap correction of Lo and
+/// MacKinlay (1988) the denominator accounts for it, N - Σ_k c_k² / (N h) with c_k the number
+/// of windows holding period k, which is unbiased for serially uncorrelated periods. For
+/// h = 1 this is the sample covariance.
+pub fn overlapping_covariance(
+    overlapping: &Array2<f64>,
+    horizon: usize,
+) -> Result<Array2<f64>, QuarsError> {
+    let n_windows = overlapping.ncols();
+    if n_windows < 2 {
+        return Err(QuarsError::InsufficientData(
+            "Not enough observations to compute covariance.".to_string(),
+        ));
+    }
+    let n_obs = n_windows + horizon - 1;
+    let overlap: f64 = (0..n_obs)
+        .map(|k| (k + 1).min(horizon).min(n_windows).min(n_obs - k) as f64)
+        .map(|windows| windows * windows)
+        .sum();
+    let denominator = n_windows as f64 - overlap / (n_windows * horizon) as f64;
+    Ok(compute_sample_covariance(overlapping)? * ((n_windows as f64 - 1.0) / denominator))
+}
+
+/// Semi-covariance of the returns below a target B (Estrada):
+///   S_ij = 1/T Σ_t min(r_it - B, 0) · min(r_jt - B, 0)
+/// Only the shortfalls below the target count, so upside co-movements add no risk.
+pub fn semi_covariance(
+    returns_matrix: &Array2<f64>,
+    target: f64,
+) -> Result<Array2<f64>, QuarsError> {
+    let n_obs = returns_matrix.ncols();
+    if n_obs == 0 {
+        return Err(QuarsError::InsufficientData(
+            "Not enough observations to compute the semi-covariance.".to_string(),
+        ));
+    }
+    let shortfalls = returns_matrix.mapv(|r| (r - target).min(0.0));
+    Ok(shortfalls.dot(&shortfalls.t()) / n_obs as f64)
+}
+
+// Paths simulated from each sub-stream of the Monte Carlo VaR, fixed so the draws do not
+// depend on the number of threads
+const MONTE_CARLO_BLOCK: usize = 1_000;
+
+// Return series of at least two chunks of this many observations have their covariance
+// estimated chunk by chunk on separate threads, see `parallel_sample_covariance`
+const PARALLEL_CHUNK_LEN: usize = 20_000;
+
+// Smallest eigenvalue kept by `nearest_positive_definite`, relative to the largest one
+const MIN_RELATIVE_EIGENVALUE: f64 = 1e-8;
+
+/// Nearest positive definite matrix to a symmetric one, in the Frobenius norm up to the floor:
+/// the eigenvalues below a small fraction of the largest one are raised to it and the matrix
+/// is rebuilt from its eigenvectors.
+pub fn nearest_positive_definite(matrix: &Array2<f64>) -> Result<Array2<f64>, QuarsError> {
+    let symmetric = (matrix + &matrix.t()) / 2.0;
+    let (eigenvalues, eigenvectors) = symmetric.eigh(UPLO::Lower)?;
+    let largest = eigenvalues.fold(0.0f64, |acc, &v| acc.max(v));
+    if largest <= 0.0 {
+        return Err(QuarsError::Singular(
+            "The matrix has no positive eigenvalue.".to_string(),
+        ));
+    }
+    let floor = largest * MIN_RELATIVE_EIGENVALUE;
+    let clipped = eigenvalues.mapv(|v| v.max(floor));
+    let rebuilt = (&eigenvectors * &clipped).dot(&eigenvectors.t());
+    // Rounding leaves a slight asymmetry, which would trip the solvers' symmetry assumptions
+    Ok((&rebuilt + &rebuilt.t()) / 2.0)
+}
+
+/// Checks with a Cholesky factorization that a covariance is positive definite before an
+/// optimizer inverts it. A failure is reported with the smallest eigenvalue and the usual
+/// remedies instead of the bare linear algebra error.
+pub fn check_positive_definite(covariance: &Array2<f64>) -> Result<(), QuarsError> {
+    if covariance.cholesky(UPLO::Lower).is_ok() {
+        return Ok(());
+    }
+    let (eigenvalues, _) = covariance.eigh(UPLO::Lower)?;
+    let smallest = eigenvalues.fold(f64::INFINITY, |acc, &v| acc.min(v));
+    Err(QuarsError::Singular(format!(
+        "The covariance matrix of the {} assets is not positive definite, its smallest eigenvalue is {:.3e}. \
+         Use more observations than assets (an earlier start_date or a shorter timeframe), \
+         regularize it with a covariance_ridge, remove perfectly correlated assets (e.g. the same \
+         asset under two tickers) or reduce the universe (preselect_by and preselect_n).",
+        covariance.nrows(),
+        smallest
+    )))
+}
+
 /// Compute sample covariance from (n_assets x n_samples) returns
-fn compute_sample_covariance(returns: &Array2<f64>) -> Result<Array2<f64>, Box<dyn Error>> {
+fn compute_sample_covariance(returns: &Array2<f64>) -> Result<Array2<f64>, QuarsError> {
     let (n_assets, n_obs) = returns.dim();
     if n_obs < 2 {
-        return Err("Not enough observations to compute covariance.".into());
+        return Err(QuarsError::InsufficientData(
+            "Not enough observations to compute covariance.".to_string(),
+        ));
+    }
+    if n_obs >= 2 * PARALLEL_CHUNK_LEN {
+        return parallel_sample_covariance(returns.view(), PARALLEL_CHUNK_LEN);
     }
 
-    let means = returns
-        .mean_axis(Axis(1))
-        .ok_or("Could not compute means of returns matrix")?;
+    let means = returns.mean_axis(Axis(1)).ok_or_else(|| {
+        QuarsError::InsufficientData("Could not compute means of returns matrix".to_string())
+    })?;
 
     let mut centered = returns.clone();
     for i in 0..n_assets {
@@ -88,6 +1061,28 @@ fn compute_sample_covariance(returns: &Array2<f64>) -> Result<Array2<f64>, Box<d
     Ok(cov)
 }
 
+/// Sample covariance of the observations in the columns of `returns`, with the moments of
+/// each chunk of `chunk_len` observations computed on the rayon thread pool and merged in
+/// order, so the result does not depend on the number of threads. Matches the single-pass
+/// estimate up to rounding, whatever the chunk length.
+pub fn parallel_sample_covariance(
+    returns: ArrayView2<f64>,
+    chunk_len: usize,
+) -> Result<Array2<f64>, QuarsError> {
+    let chunks: Vec<ArrayView2<f64>> = returns
+        .axis_chunks_iter(Axis(1), chunk_len.max(1))
+        .collect();
+    let partials: Vec<CovarianceAccumulator> = chunks
+        .par_iter()
+        .map(|chunk| CovarianceAccumulator::from_columns(chunk.view()))
+        .collect();
+    let mut merged = CovarianceAccumulator::new(returns.nrows());
+    for partial in &partials {
+        merged.merge(partial);
+    }
+    merged.covariance()
+}
+
 /// Compute daily portfolio returns from each asset's returns_matrix and weights
 pub fn compute_portfolio_returns(returns_matrix: &Array2<f64>, weights: &[f64]) -> Vec<f64> {
     let (n_assets, n_samples) = returns_matrix.dim();
@@ -131,3 +1126,1425 @@ pub fn portfolio_cvar(returns: &[f64], alpha: f64) -> f64 {
     let tail = &sorted[0..idx];
     tail.iter().sum::<f64>() / tail.len() as f64
 }
+
+/// VaR of the portfolio under a multivariate normal model of the asset returns.
+/// Each path draws correlated asset returns r = μ + L·z, with L the Cholesky factor of the
+/// covariance, and the VaR is read off the simulated portfolio returns. The paths are
+/// simulated in blocks on the rayon thread pool, each block from its own sub-stream of a seed
+/// drawn from `rng`, so the VaR does not depend on the number of threads.
+pub fn monte_carlo_var(
+    stats: &PortfolioStats,
+    weights: &[f64],
+    n_paths: usize,
+    alpha: f64,
+    rng: &mut StdRng,
+) -> Result<f64, QuarsError> {
+    if n_paths == 0 {
+        return Err(QuarsError::Config(
+            "Monte Carlo VaR needs at least one path.".to_string(),
+        ));
+    }
+    let chol = stats.covariance.cholesky(UPLO::Lower)?;
+    let weights = Array1::from(weights.to_vec());
+    let n_assets = stats.assets.len();
+
+    let seed: u64 = rng.gen();
+    let blocks: Vec<Vec<f64>> = (0..n_paths.div_ceil(MONTE_CARLO_BLOCK))
+        .into_par_iter()
+        .map(|block| {
+            let mut rng = utils::substream(seed, block as u64);
+            let len = MONTE_CARLO_BLOCK.min(n_paths - block * MONTE_CARLO_BLOCK);
+            (0..len)
+                .map(|_| {
+                    let z: Array1<f64> =
+                        (0..n_assets).map(|_| rng.sample(StandardNormal)).collect();
+                    let asset_returns = &stats.mean_returns + &chol.dot(&z);
+                    asset_returns.dot(&weights)
+                })
+                .collect()
+        })
+        .collect();
+    Ok(portfolio_var(&blocks.concat(), alpha))
+}
+
+// Mean and standard deviation (population) of a return series
+fn mean_std(returns: &[f64]) -> (f64, f64) {
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let var = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / n;
+    (mean, var.sqrt())
+}
+
+// k-th standardized central moment E[((r - μ) / σ)^k], zero for a constant series
+fn standardized_moment(returns: &[f64], k: i32) -> f64 {
+    let (mean, std) = mean_std(returns);
+    if returns.is_empty() || std == 0.0 {
+        return 0.0;
+    }
+    returns
+        .iter()
+        .map(|r| ((r - mean) / std).powi(k))
+        .sum::<f64>()
+        / returns.len() as f64
+}
+
+/// Skewness of a return series, negative when the left tail is longer
+pub fn skewness(returns: &[f64]) -> f64 {
+    standardized_moment(returns, 3)
+}
+
+/// Excess kurtosis of a return series, zero for a normal distribution
+pub fn excess_kurtosis(returns: &[f64]) -> f64 {
+    standardized_moment(returns, 4) - 3.0
+}
+
+/// Parametric VaR under a normal distribution fitted to the returns, μ + z·σ with z the
+/// (1 - alpha) normal quantile. Like `portfolio_var` it is a return, negative for a loss.
+pub fn gaussian_var(returns: &[f64], alpha: f64) -> f64 {
+    let (mean, std) = mean_std(returns);
+    mean + normal_quantile(1.0 - alpha) * std
+}
+
+/// Modified VaR, the Gaussian VaR with the normal quantile adjusted for the skewness S and
+/// excess kurtosis K of the returns by the Cornish-Fisher expansion
+/// z_cf = z + (z² - 1)S/6 + (z³ - 3z)K/24 - (2z³ - 5z)S²/36
+pub fn cornish_fisher_var(returns: &[f64], alpha: f64) -> f64 {
+    let (mean, std) = mean_std(returns);
+    let s = skewness(returns);
+    let k = excess_kurtosis(returns);
+    let z = normal_quantile(1.0 - alpha);
+    let z_cf = z + (z.powi(2) - 1.0) * s / 6.0 + (z.powi(3) - 3.0 * z) * k / 24.0
+        - (2.0 * z.powi(3) - 5.0 * z) * s.powi(2) / 36.0;
+    mean + z_cf * std
+}
+
+/// Marginal and component VaR of a portfolio
+#[derive(Debug)]
+pub struct ComponentVar {
+    pub var: f64,
+    // Index of the return scenario the VaR is read at
+    pub scenario: usize,
+    // Sensitivity of the portfolio VaR to each asset weight
+    pub marginal: Vec<f64>,
+    // w_i * marginal_i, sums to the total VaR
+    pub component: Vec<f64>,
+}
+
+/// Index of the scenario whose return is the historical VaR, the order statistic
+/// `portfolio_var` reads
+fn var_scenario(returns: &[f64], alpha: f64) -> usize {
+    let mut order: Vec<usize> = (0..returns.len()).collect();
+    order.sort_by(|&a, &b| returns[a].total_cmp(&returns[b]));
+    let idx = ((1.0 - alpha) * order.len() as f64).ceil() as usize;
+    order[idx.min(order.len() - 1)]
+}
+
+/// Decompose the historical VaR into per-asset contributions.
+/// The marginal VaR of asset i is the expected return of asset i conditional on the
+/// portfolio return being at its VaR, i.e. E[r_i | r_p = VaR], estimated by the return of
+/// asset i in the scenario the VaR is read at. By Euler's theorem the component VaRs
+/// (w_i * marginal_i) then sum to the total VaR.
+pub fn component_var(
+    returns_matrix: &Array2<f64>,
+    weights: &[f64],
+    alpha: f64,
+) -> Result<ComponentVar, QuarsError> {
+    let port_returns = compute_portfolio_returns(returns_matrix, weights);
+    if port_returns.is_empty() {
+        return Err(QuarsError::InsufficientData(
+            "Not enough data points to compute component VaR.".to_string(),
+        ));
+    }
+    let scenario = var_scenario(&port_returns, alpha);
+    let marginal = returns_matrix.column(scenario).to_vec();
+    let component: Vec<f64> = weights
+        .iter()
+        .zip(marginal.iter())
+        .map(|(w, m)| w * m)
+        .collect();
+
+    Ok(ComponentVar {
+        var: port_returns[scenario],
+        scenario,
+        marginal,
+        component,
+    })
+}
+
+/// Attribution of the historical CVaR to the assets
+#[derive(Debug)]
+pub struct TailAttribution {
+    pub cvar: f64,
+    // Number of tail scenarios averaged
+    pub n_scenarios: usize,
+    // Average weighted return of each asset over the tail scenarios, sums to the CVaR
+    pub contribution: Vec<f64>,
+}
+
+/// Decompose the historical CVaR into per-asset contributions.
+/// The tail holds the worst (1 - alpha) scenarios of the portfolio return, the same ones
+/// `portfolio_cvar` averages, and the contribution of asset i is the average of w_i * r_i over
+/// exactly those scenarios. Unlike component VaR, which reads the sensitivities at the VaR
+/// scenario alone, this attributes the whole average tail loss.
+pub fn tail_attribution(
+    returns_matrix: &Array2<f64>,
+    weights: &[f64],
+    alpha: f64,
+) -> Result<TailAttribution, QuarsError> {
+    let port_returns = compute_portfolio_returns(returns_matrix, weights);
+    if port_returns.is_empty() {
+        return Err(QuarsError::InsufficientData(
+            "Not enough data points to compute the tail attribution.".to_string(),
+        ));
+    }
+    let n_tail = ((1.0 - alpha) * port_returns.len() as f64).ceil() as usize;
+    if n_tail == 0 {
+        return Err(QuarsError::Config(format!(
+            "No tail scenario at confidence level {}",
+            alpha
+        )));
+    }
+
+    // Scenarios ordered from the worst portfolio return, as sorted by `portfolio_cvar`
+    let mut order: Vec<usize> = (0..port_returns.len()).collect();
+    order.sort_by(|&a, &b| port_returns[a].total_cmp(&port_returns[b]));
+    let tail = if n_tail >= order.len() {
+        &order[order.len() - 1..]
+    } else {
+        &order[..n_tail]
+    };
+
+    let contribution: Vec<f64> = weights
+        .iter()
+        .enumerate()
+        .map(|(i, w)| {
+            tail.iter()
+                .map(|&t| w * returns_matrix[[i, t]])
+                .sum::<f64>()
+                / tail.len() as f64
+        })
+        .collect();
+
+    Ok(TailAttribution {
+        cvar: portfolio_cvar(&port_returns, alpha),
+        n_scenarios: tail.len(),
+        contribution,
+    })
+}
+
+/// Euler decomposition of the historical CVaR, checked against the CVaRs of the positions
+/// held alone
+#[derive(Debug)]
+pub struct CvarDecomposition {
+    pub cvar: f64,
+    // Number of tail scenarios averaged
+    pub n_scenarios: usize,
+    // Euler contribution of each asset, the average of w_i * r_i over the portfolio's tail
+    // scenarios, sums to the CVaR
+    pub contribution: Vec<f64>,
+    // CVaR of each position w_i * r_i on its own, over its own worst scenarios
+    pub standalone: Vec<f64>,
+    // CVaR less the sum of the standalone CVaRs, the tail loss the diversification saves
+    pub diversification_benefit: f64,
+    // Whether the CVaR is subadditive here, no worse than the sum of the standalone CVaRs
+    pub subadditive: bool,
+    // Whether the diversification reduced the tail risk, a strictly positive benefit
+    pub diversified: bool,
+}
+
+/// Decomposes the historical CVaR of the portfolio into the Euler contributions of the assets
+/// (see `tail_attribution`) and compares it with the sum of the standalone CVaRs of the
+/// positions w_i * r_i. CVaR is a coherent risk measure, so the portfolio's tail loss is at
+/// most the sum of the standalone ones: returns being negative in the tail, the CVaR is at
+/// least the sum, and the difference is the diversification benefit. Positions that never
+/// lose together, e.g. uncorrelated or negatively correlated assets, show a large benefit,
+/// perfectly correlated ones none.
+pub fn cvar_decomposition(
+    returns_matrix: &Array2<f64>,
+    weights: &[f64],
+    alpha: f64,
+) -> Result<CvarDecomposition, QuarsError> {
+    let tail = tail_attribution(returns_matrix, weights, alpha)?;
+    let standalone: Vec<f64> = weights
+        .iter()
+        .zip(returns_matrix.axis_iter(Axis(0)))
+        .map(|(w, returns)| {
+            let position: Vec<f64> = returns.iter().map(|r| w * r).collect();
+            portfolio_cvar(&position, alpha)
+        })
+        .collect();
+    let diversification_benefit = tail.cvar - standalone.iter().sum::<f64>();
+    Ok(CvarDecomposition {
+        cvar: tail.cvar,
+        n_scenarios: tail.n_scenarios,
+        contribution: tail.contribution,
+        standalone,
+        diversification_benefit,
+        // Up to the rounding of the sums
+        subadditive: diversification_benefit >= -1e-12,
+        diversified: diversification_benefit > 1e-12,
+    })
+}
+
+/// Exposure breakdown of a weight vector, per unit of capital
+#[derive(Debug, Clone, Serialize)]
+pub struct ExposureMetrics {
+    // Σ|w|
+    pub gross: f64,
+    // Σw
+    pub net: f64,
+    // Σ max(w, 0)
+    pub long: f64,
+    // Σ |min(w, 0)|
+    pub short: f64,
+    // Gross exposure relative to the invested capital (1.0 = unlevered)
+    pub leverage: f64,
+}
+
+pub fn exposure_metrics(weights: &[f64]) -> ExposureMetrics {
+    let long: f64 = weights.iter().filter(|w| **w > 0.0).sum();
+    let short: f64 = -weights.iter().filter(|w| **w < 0.0).sum::<f64>();
+    let gross = long + short;
+    ExposureMetrics {
+        gross,
+        net: long - short,
+        long,
+        short,
+        leverage: gross,
+    }
+}
+
+/// Rescales weights so that they sum to one (net exposure of 1)
+pub fn normalize_weights(weights: &[f64]) -> Result<Vec<f64>, QuarsError> {
+    let net: f64 = weights.iter().sum();
+    if net.abs() < 1e-12 {
+        return Err(QuarsError::Infeasible(
+            "Cannot normalize weights with zero net exposure.".to_string(),
+        ));
+    }
+    Ok(weights.iter().map(|w| w / net).collect())
+}
+
+/// Tilts weights toward high-score assets, w_i·exp(η·s_i) renormalized to sum to one. η = 0
+/// leaves fully invested weights unchanged, a large η concentrates the weight on the
+/// top-scored asset held. Short positions are scaled the same way, so a high score deepens
+/// a short.
+pub fn score_tilt(weights: &[f64], scores: &[f64], strength: f64) -> Result<Vec<f64>, QuarsError> {
+    if !strength.is_finite() || scores.iter().any(|s| !s.is_finite()) {
+        return Err(QuarsError::Config(
+            "Score tilt strength and scores must be finite".to_string(),
+        ));
+    }
+    // Shifted by the largest η·s of the assets held, the factors stay in (0, 1] whatever η
+    // and the one of the top-scored holding is 1, the normalization cancels the shift
+    let max_exponent = weights
+        .iter()
+        .zip(scores)
+        .filter(|(w, _)| **w != 0.0)
+        .map(|(_, s)| strength * s)
+        .fold(f64::NEG_INFINITY, f64::max);
+    // Assets not held stay out, their factor may overflow
+    let tilted: Vec<f64> = weights
+        .iter()
+        .zip(scores)
+        .map(|(&w, s)| {
+            if w == 0.0 {
+                0.0
+            } else {
+                w * (strength * s - max_exponent).exp()
+            }
+        })
+        .collect();
+    normalize_weights(&tilted)
+}
+
+/// Tradeable holdings of a target allocation, see `round_to_shares`
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareAllocation {
+    // Share count per asset, whole unless fractional shares are allowed, negative for shorts
+    pub shares: Vec<f64>,
+    // Value of each holding over the budget
+    pub realized_weights: Vec<f64>,
+    // Budget left uninvested
+    pub residual_cash: f64,
+}
+
+impl ShareAllocation {
+    /// Ex-ante tracking error of the realized weights to the targets, √(dᵀΣd) with d the
+    /// weight differences, the uninvested cash counting as riskless
+    pub fn tracking_error(
+        &self,
+        targets: &[f64],
+        covariance: &Array2<f64>,
+    ) -> Result<f64, QuarsError> {
+        let deviation: Array1<f64> = self
+            .realized_weights
+            .iter()
+            .zip(targets)
+            .map(|(realized, target)| realized - target)
+            .collect();
+        Ok(portfolio_variance(&deviation, covariance)?.sqrt())
+    }
+}
+
+/// Converts target weights into share counts at the given prices for a total budget. With
+/// fractional shares the targets are met exactly. Otherwise every position is first rounded
+/// toward zero, which keeps the cash non-negative, and the leftover cash then buys one share
+/// at a time of the asset whose weight error it reduces the most, until no affordable share
+/// reduces the squared deviation from the targets (a greedy largest-remainder rounding).
+pub fn round_to_shares(
+    weights: &[f64],
+    prices: &[f64],
+    budget: f64,
+    allow_fractional: bool,
+) -> Result<ShareAllocation, QuarsError> {
+    if weights.len() != prices.len() {
+        return Err(QuarsError::InsufficientData(format!(
+            "{} weights but {} prices",
+            weights.len(),
+            prices.len()
+        )));
+    }
+    if !budget.is_finite() || budget <= 0.0 {
+        return Err(QuarsError::Config(format!(
+            "The budget must be positive, got {}",
+            budget
+        )));
+    }
+    if let Some(price) = prices.iter().find(|p| !p.is_finite() || **p <= 0.0) {
+        return Err(QuarsError::InsufficientData(format!(
+            "Share prices must be positive, got {}",
+            price
+        )));
+    }
+
+    let targets: Vec<f64> = weights
+        .iter()
+        .zip(prices)
+        .map(|(w, p)| w * budget / p)
+        .collect();
+    let mut shares: Vec<f64> = if allow_fractional {
+        targets.clone()
+    } else {
+        targets.iter().map(|s| s.trunc()).collect()
+    };
+    let invested = |shares: &[f64]| -> f64 { shares.iter().zip(prices).map(|(s, p)| s * p).sum() };
+    let mut cash = budget - invested(&shares);
+    if !allow_fractional {
+        loop {
+            // Buying a share moves the value error d = s·p - w·budget to d + p, which lowers
+            // d² by -p(2d + p)
+            let best = (0..shares.len())
+                .filter(|&i| prices[i] <= cash)
+                .map(|i| {
+                    let error = (shares[i] - targets[i]) * prices[i];
+                    (i, -prices[i] * (2.0 * error + prices[i]))
+                })
+                .filter(|(_, reduction)| *reduction > 0.0)
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            let Some((i, _)) = best else {
+                break;
+            };
+            shares[i] += 1.0;
+            cash -= prices[i];
+        }
+        // Recomputed rather than accumulated, free of the rounding drift of the loop
+        cash = budget - invested(&shares);
+    }
+
+    Ok(ShareAllocation {
+        realized_weights: shares
+            .iter()
+            .zip(prices)
+            .map(|(s, p)| s * p / budget)
+            .collect(),
+        shares,
+        residual_cash: cash,
+    })
+}
+
+/// Annualized standard deviation of a return series
+pub fn realized_volatility(returns: &[f64], periods_per_year: f64) -> f64 {
+    let n = returns.len();
+    if n < 2 {
+        return 0.0;
+    }
+    let mean = returns.iter().sum::<f64>() / n as f64;
+    let var = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n as f64 - 1.0);
+    (var * periods_per_year).sqrt()
+}
+
+/// Leverage multipliers scaling the portfolio so its trailing realized volatility hits
+/// `target_vol` (annualized). The multiplier for period t only uses returns up to t-1, the
+/// first `window` periods stay unlevered (1.0) until enough history is available.
+pub fn volatility_target(
+    returns: &[f64],
+    target_vol: f64,
+    window: usize,
+    periods_per_year: f64,
+) -> Vec<f64> {
+    let mut multipliers = Vec::with_capacity(returns.len());
+    let mut current = 1.0;
+    for t in 0..returns.len() {
+        if window >= 2 && t >= window {
+            let trailing_vol = realized_volatility(&returns[t - window..t], periods_per_year);
+            // Keep the previous leverage when the trailing window carries no risk
+            if trailing_vol > 1e-12 {
+                current = target_vol / trailing_vol;
+            }
+        }
+        multipliers.push(current);
+    }
+    multipliers
+}
+
+/// Applies per-period leverage multipliers to a return series
+pub fn apply_leverage(returns: &[f64], multipliers: &[f64]) -> Vec<f64> {
+    returns
+        .iter()
+        .zip(multipliers.iter())
+        .map(|(r, m)| r * m)
+        .collect()
+}
+
+// Negative portfolio variance still taken as round-off, relative to |w|ᵀ|Σ||w|
+const NEGATIVE_VARIANCE_TOLERANCE: f64 = 1e-8;
+
+/// Warns once for a covariance with a negative eigenvalue, e.g. a supplied or realized one,
+/// whose portfolio variances `portfolio_variance` then clamps to zero without warning again
+pub fn warn_if_indefinite(covariance: &Array2<f64>, estimator: &str) -> Result<(), QuarsError> {
+    let (eigenvalues, _) = covariance.eigh(UPLO::Lower)?;
+    let smallest = eigenvalues.fold(f64::INFINITY, |acc, &v| acc.min(v));
+    if smallest < 0.0 {
+        warn!(
+            "the {} covariance is not positive semi-definite, its smallest eigenvalue is {:.3e}; slightly negative portfolio variances are clamped to zero",
+            estimator, smallest
+        );
+    }
+    Ok(())
+}
+
+/// Portfolio variance wᵀΣw. A covariance estimate that is not quite positive semi-definite
+/// (shrinkage, semi-covariance, pairwise or realized estimators) can make it slightly negative,
+/// which is round-off and clamped to zero, silently since it is called in loops and the
+/// estimators warn once per matrix (`warn_if_indefinite`); a materially negative variance means
+/// the matrix is no valid covariance and is an error rather than a NaN volatility downstream.
+pub fn portfolio_variance(
+    weights: &Array1<f64>,
+    covariance: &Array2<f64>,
+) -> Result<f64, QuarsError> {
+    let variance = weights.dot(&covariance.dot(weights));
+    if !variance.is_finite() {
+        return Err(QuarsError::InsufficientData(
+            "Portfolio variance is not finite, check the covariance for missing values."
+                .to_string(),
+        ));
+    }
+    if variance >= 0.0 {
+        return Ok(variance);
+    }
+    // Size of the terms summed, |w|ᵀ|Σ||w|, the scale of the round-off of the sum
+    let abs_weights = weights.mapv(f64::abs);
+    let scale = abs_weights.dot(&covariance.mapv(f64::abs).dot(&abs_weights));
+    if variance >= -NEGATIVE_VARIANCE_TOLERANCE * scale {
+        Ok(0.0)
+    } else {
+        Err(QuarsError::Singular(format!(
+            "Portfolio variance {:.3e} is negative, the covariance has materially negative eigenvalues; \
+             consider another covariance estimator or a covariance_ridge",
+            variance
+        )))
+    }
+}
+
+/// Diversification ratio (wᵀσ) / √(wᵀΣw): weighted average asset volatility over portfolio
+/// volatility. It equals 1 for a single asset and √n for an equal-weight portfolio of n
+/// uncorrelated equal-volatility assets. None when the portfolio has no risk.
+pub fn diversification_ratio(weights: &[f64], covariance: &Array2<f64>) -> Option<f64> {
+    let w = Array1::from(weights.to_vec());
+    let vols = covariance.diag().mapv(f64::sqrt);
+    let portfolio_std = portfolio_variance(&w, covariance).ok()?.sqrt();
+    if portfolio_std < 1e-12 {
+        return None;
+    }
+    Some(w.dot(&vols) / portfolio_std)
+}
+
+/// Underwater curve: relative distance of cumulative wealth from its running peak (≤ 0)
+pub fn drawdown_series(returns: &[f64]) -> Vec<f64> {
+    let mut wealth = 1.0;
+    let mut peak = 1.0;
+    returns
+        .iter()
+        .map(|r| {
+            wealth *= 1.0 + r;
+            peak = f64::max(peak, wealth);
+            wealth / peak - 1.0
+        })
+        .collect()
+}
+
+/// Largest peak-to-trough loss, as a negative fraction
+pub fn max_drawdown(returns: &[f64]) -> f64 {
+    drawdown_series(returns).into_iter().fold(0.0, f64::min)
+}
+
+/// Calendar on which the backtest rebalances
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RebalanceSchedule {
+    Monthly,
+    Quarterly,
+    // Rebalance once at least this many calendar days have passed
+    EveryNDays(u32),
+}
+
+impl RebalanceSchedule {
+    pub fn from_config(backtest: &BacktestSettings) -> Result<Self, QuarsError> {
+        match backtest.rebalance_schedule.as_str() {
+            "monthly" => Ok(Self::Monthly),
+            "quarterly" => Ok(Self::Quarterly),
+            "every-n-days" => match backtest.rebalance_days {
+                Some(days) if days > 0 => Ok(Self::EveryNDays(days)),
+                _ => Err(QuarsError::Config(
+                    "The every-n-days schedule needs rebalance_days > 0.".to_string(),
+                )),
+            },
+            other => Err(QuarsError::Config(format!(
+                "Unknown rebalance schedule: {}",
+                other
+            ))),
+        }
+    }
+}
+
+/// Indices of the rebalance dates in a chronological date series: the first date of each
+/// month or quarter, i.e. the first observation after the period-end close, or the first
+/// date at least n calendar days after the previous rebalance. The first date is always a
+/// rebalance, the initial allocation.
+pub fn rebalance_indices(
+    dates: &[String],
+    schedule: RebalanceSchedule,
+) -> Result<Vec<usize>, QuarsError> {
+    let mut indices = Vec::new();
+    let mut last_rebalance = None;
+    for (i, date) in dates.iter().enumerate() {
+        // Intraday timestamps carry a time part after the date
+        let date = parse_date(date.get(..10).unwrap_or(date))?;
+        let rebalance = match (last_rebalance, schedule) {
+            (None, _) => true,
+            (Some(last), _) if date < last => {
+                return Err(QuarsError::Parse(format!(
+                    "Dates are not in chronological order at {}",
+                    date
+                )))
+            }
+            (Some(last), RebalanceSchedule::Monthly) => {
+                (date.year(), date.month()) != (last.year(), last.month())
+            }
+            (Some(last), RebalanceSchedule::Quarterly) => {
+                (date.year(), date.month0() / 3) != (last.year(), last.month0() / 3)
+            }
+            (Some(last), RebalanceSchedule::EveryNDays(days)) => {
+                (date - last).num_days() >= days as i64
+            }
+        };
+        if rebalance {
+            indices.push(i);
+            last_rebalance = Some(date);
+        }
+    }
+    Ok(indices)
+}
+
+/// Per-period rate of each asset's return compounded over its last `lookback` periods,
+/// (∏(1 + r))^(1/lookback) - 1
+fn trailing_momentum(returns_matrix: ArrayView2<f64>, lookback: usize) -> Array1<f64> {
+    let n_obs = returns_matrix.ncols();
+    returns_matrix
+        .slice(s![.., n_obs - lookback..])
+        .map_axis(Axis(1), |returns| {
+            let growth: f64 = returns.iter().map(|r| 1.0 + r).product();
+            growth.powf(1.0 / lookback as f64) - 1.0
+        })
+}
+
+/// Aligns two dated series on their common dates, keeping the order of the first one
+pub fn align_series(
+    dates_a: &[String],
+    a: &[f64],
+    dates_b: &[String],
+    b: &[f64],
+) -> (Vec<String>, Vec<f64>, Vec<f64>) {
+    let lookup: HashMap<&str, f64> = dates_b
+        .iter()
+        .map(|d| d.as_str())
+        .zip(b.iter().cloned())
+        .collect();
+    let mut dates = Vec::new();
+    let mut aligned_a = Vec::new();
+    let mut aligned_b = Vec::new();
+    for (date, value) in dates_a.iter().zip(a.iter()) {
+        if let Some(other) = lookup.get(date.as_str()) {
+            dates.push(date.clone());
+            aligned_a.push(*value);
+            aligned_b.push(*other);
+        }
+    }
+    (dates, aligned_a, aligned_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::Record;
+    use ndarray::array;
+    use rand::SeedableRng;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!(
+            (actual - expected).abs() < 1e-12,
+            "{} differs from {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn equal_weights_of_uncorrelated_equal_vol_assets_diversify_by_sqrt_n() {
+        let n = 4;
+        let covariance = Array2::eye(n) * 0.0004;
+        let weights = vec![1.0 / n as f64; n];
+        assert_close(
+            diversification_ratio(&weights, &covariance).unwrap(),
+            (n as f64).sqrt(),
+        );
+        assert_close(
+            diversification_ratio(&[1.0, 0.0, 0.0, 0.0], &covariance).unwrap(),
+            1.0,
+        );
+    }
+
+    #[test]
+    fn component_var_of_two_assets_sums_to_var() {
+        let returns = array![
+            [0.01, -0.02, 0.03, -0.05, 0.02, 0.00, 0.01, -0.01, 0.04, -0.03],
+            [0.02, 0.01, -0.01, -0.03, 0.00, 0.01, -0.02, 0.02, 0.01, -0.04],
+        ];
+        // Portfolio returns 1.4, -0.8, 1.4, -4.2, 1.2, 0.4, -0.2, 0.2, 2.8, -3.4 (%): the
+        // 90% VaR is the second worst, the -3.4% of the last scenario
+        let decomposition = component_var(&returns, &[0.6, 0.4], 0.9).unwrap();
+        assert_eq!(decomposition.scenario, 9);
+        assert_close(decomposition.var, -0.034);
+        assert_close(decomposition.marginal[0], -0.03);
+        assert_close(decomposition.marginal[1], -0.04);
+        assert_close(decomposition.component[0], -0.018);
+        assert_close(decomposition.component[1], -0.016);
+        let total: f64 = decomposition.component.iter().sum();
+        assert_close(total, decomposition.var);
+        let port_returns = compute_portfolio_returns(&returns, &[0.6, 0.4]);
+        assert_close(decomposition.var, portfolio_var(&port_returns, 0.9));
+    }
+
+    #[test]
+    fn modified_var_of_left_skewed_returns_exceeds_the_gaussian_var() {
+        // Steady small gains and two crashes
+        let mut returns = vec![0.01; 18];
+        returns.extend([-0.12, -0.08]);
+        assert!(skewness(&returns) < 0.0);
+        let gaussian = gaussian_var(&returns, 0.95);
+        let modified = cornish_fisher_var(&returns, 0.95);
+        // Both are returns, the larger loss is the more negative
+        assert!(modified < gaussian, "{} vs {}", modified, gaussian);
+
+        // Without skewness or excess kurtosis the expansion leaves the quantile unchanged
+        let symmetric = [-0.02, 0.02, -0.02, 0.02];
+        assert_close(skewness(&symmetric), 0.0);
+        let k = excess_kurtosis(&symmetric);
+        let z = normal_quantile(0.05);
+        let (mean, std) = mean_std(&symmetric);
+        assert_close(
+            cornish_fisher_var(&symmetric, 0.95),
+            mean + (z + (z.powi(3) - 3.0 * z) * k / 24.0) * std,
+        );
+    }
+
+    #[test]
+    fn tail_contributions_sum_to_the_cvar() {
+        let returns = array![
+            [0.01, -0.02, 0.03, -0.05, 0.02, 0.00, 0.01, -0.01, 0.04, -0.03],
+            [0.02, 0.01, -0.01, -0.03, 0.00, 0.01, -0.02, 0.02, 0.01, -0.04],
+        ];
+        // The two worst portfolio returns, -4.2% and -3.4%, form the 80% tail
+        let attribution = tail_attribution(&returns, &[0.6, 0.4], 0.8).unwrap();
+        assert_eq!(attribution.n_scenarios, 2);
+        assert_close(attribution.cvar, -0.038);
+        assert_close(attribution.contribution[0], 0.6 * (-0.05 - 0.03) / 2.0);
+        assert_close(attribution.contribution[1], 0.4 * (-0.03 - 0.04) / 2.0);
+        let total: f64 = attribution.contribution.iter().sum();
+        assert_close(total, attribution.cvar);
+    }
+
+    #[test]
+    fn whole_shares_of_a_large_budget_match_the_target_weights() {
+        let weights = [0.5, 0.3, 0.2];
+        let prices = [187.3, 412.9, 95.15];
+        let allocation = round_to_shares(&weights, &prices, 1_000_000.0, false).unwrap();
+        assert!(allocation.shares.iter().all(|s| s.fract() == 0.0));
+        for (realized, target) in allocation.realized_weights.iter().zip(&weights) {
+            assert!(
+                (realized - target).abs() < 1e-3,
+                "{} vs {}",
+                realized,
+                target
+            );
+        }
+        assert!(allocation.residual_cash >= 0.0);
+        // Here less than the cheapest share is left over
+        assert!(allocation.residual_cash < 95.15);
+        let invested: f64 = allocation.realized_weights.iter().sum();
+        assert_close(invested + allocation.residual_cash / 1_000_000.0, 1.0);
+
+        // Whole shares of a small budget leave a visible gap, fractional ones none
+        let small = round_to_shares(&weights, &prices, 1_000.0, false).unwrap();
+        assert!((small.realized_weights[1] - 0.3).abs() > 1e-2);
+        let fractional = round_to_shares(&weights, &prices, 1_000.0, true).unwrap();
+        for (realized, target) in fractional.realized_weights.iter().zip(&weights) {
+            assert_close(*realized, *target);
+        }
+        assert_close(fractional.residual_cash, 0.0);
+    }
+
+    #[test]
+    fn slightly_negative_variances_are_clamped_and_material_ones_rejected() {
+        // Eigenvalues 2.0000000001 and -1e-10
+        let covariance = array![[1.0, 1.0 + 1e-10], [1.0 + 1e-10, 1.0]];
+        let hedge = array![1.0, -1.0];
+        assert!(hedge.dot(&covariance.dot(&hedge)) < 0.0);
+        assert_eq!(portfolio_variance(&hedge, &covariance).unwrap(), 0.0);
+        warn_if_indefinite(&covariance, "test").unwrap();
+
+        let indefinite = array![[1.0, 1.5], [1.5, 1.0]];
+        assert!(matches!(
+            portfolio_variance(&hedge, &indefinite),
+            Err(QuarsError::Singular(_))
+        ));
+        assert_close(
+            portfolio_variance(&array![0.5, 0.5], &covariance).unwrap(),
+            1.0 + 0.5e-10,
+        );
+    }
+
+    // Statistics of two correlated assets over ten periods
+    fn two_asset_stats() -> PortfolioStats {
+        let returns = array![
+            [0.01, -0.02, 0.03, -0.05, 0.02, 0.00, 0.01, -0.01, 0.04, -0.03],
+            [0.02, 0.01, -0.01, -0.03, 0.00, 0.01, -0.02, 0.02, 0.01, -0.04],
+        ];
+        let dates = (1..=10).map(|t| t.to_string()).collect();
+        PortfolioStats::from_returns(vec!["AAA".into(), "BBB".into()], returns, dates).unwrap()
+    }
+
+    #[test]
+    fn a_one_period_horizon_reproduces_the_single_period_statistics() {
+        let stats = two_asset_stats();
+        let mut horizon = two_asset_stats();
+        horizon.use_horizon_returns(1).unwrap();
+        for (a, e) in horizon.mean_returns.iter().zip(&stats.mean_returns) {
+            assert_close(*a, *e);
+        }
+        for (a, e) in horizon.covariance.iter().zip(&stats.covariance) {
+            assert_close(*a, *e);
+        }
+    }
+
+    #[test]
+    fn overlapping_returns_of_trending_prices_have_a_larger_covariance() {
+        // Slowly cycling returns, positively autocorrelated over a few periods
+        let returns = Array2::from_shape_fn((2, 60), |(i, t)| {
+            0.01 * (t as f64 / 5.0 + i as f64).sin() + 0.002
+        });
+        let dates = (1..=60).map(|t| t.to_string()).collect();
+        let stats =
+            PortfolioStats::from_returns(vec!["AAA".into(), "BBB".into()], returns, dates).unwrap();
+        let horizon = 5;
+        let overlapping = horizon_returns(&stats.returns_matrix, horizon).unwrap();
+        let covariance = overlapping_covariance(&overlapping, horizon).unwrap();
+        let mut per_period = stats.clone();
+        per_period.use_horizon_returns(horizon).unwrap();
+        for i in 0..2 {
+            // The horizon variance exceeds h single-period variances, the per-period one the
+            // single-period variance
+            assert!(covariance[[i, i]] > horizon as f64 * stats.covariance[[i, i]]);
+            assert!(per_period.covariance[[i, i]] > stats.covariance[[i, i]]);
+        }
+    }
+
+    #[test]
+    fn seeded_monte_carlo_var_is_reproducible() {
+        let stats = two_asset_stats();
+        let var = |seed: u64| {
+            let mut rng = StdRng::seed_from_u64(seed);
+            monte_carlo_var(
+                &stats,
+                &[0.6, 0.4],
+                2 * MONTE_CARLO_BLOCK + 7,
+                0.95,
+                &mut rng,
+            )
+            .unwrap()
+        };
+        assert_eq!(var(42).to_bits(), var(42).to_bits());
+        assert_ne!(var(42).to_bits(), var(43).to_bits());
+        let on_threads = |threads: usize| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build()
+                .unwrap()
+                .install(|| var(42))
+        };
+        assert_eq!(on_threads(1).to_bits(), on_threads(4).to_bits());
+    }
+
+    fn records(asset: &str, dates: &[String], prices: &[f64]) -> HistoricalData {
+        dates
+            .iter()
+            .zip(prices)
+            .map(|(date, price)| Record {
+                date: date.clone(),
+                asset: asset.to_string(),
+                price: *price,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn monthly_dates_under_a_daily_timeframe_warn() {
+        let monthly: Vec<String> = (1..=6).map(|m| format!("2021-{:02}-01", m)).collect();
+        let warning = check_frequency(&monthly, "daily").unwrap().unwrap();
+        assert!(warning.contains("monthly"), "{}", warning);
+        assert_eq!(check_frequency(&monthly, "monthly").unwrap(), None);
+    }
+
+    #[test]
+    fn frequency_is_checked_for_every_asset() {
+        let daily: Vec<String> = (1..=6).map(|d| format!("2021-01-{:02}", d)).collect();
+        let monthly: Vec<String> = (1..=6).map(|m| format!("2021-{:02}-01", m)).collect();
+        let prices = [100.0, 101.0, 99.0, 102.0, 103.0, 101.0];
+        let mut data = records("AAA", &daily, &prices);
+        data.extend(records("BBB", &monthly, &prices));
+        let err = calculate_portfolio_stats(&data, "daily", true).unwrap_err();
+        assert!(
+            matches!(&err, QuarsError::Config(msg) if msg.starts_with("BBB")),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn assets_on_different_calendars_are_aligned_on_their_common_dates() {
+        // Crypto trades every day, the equity on weekdays only
+        let every_day: Vec<String> = (1..=14).map(|d| format!("2024-01-{:02}", d)).collect();
+        let weekdays: Vec<String> = every_day
+            .iter()
+            .filter(|date| {
+                let weekday = parse_date(date).unwrap().weekday();
+                !matches!(weekday, chrono::Weekday::Sat | chrono::Weekday::Sun)
+            })
+            .cloned()
+            .collect();
+        let price_on = |date: &String, base: f64| base + date[8..].parse::<f64>().unwrap();
+        let mut data = records(
+            "BTC",
+            &every_day,
+            &every_day
+                .iter()
+                .map(|d| price_on(d, 100.0))
+                .collect::<Vec<_>>(),
+        );
+        data.extend(records(
+            "AAA",
+            &weekdays,
+            &weekdays
+                .iter()
+                .map(|d| price_on(d, 50.0))
+                .collect::<Vec<_>>(),
+        ));
+        for stats in [
+            calculate_portfolio_stats(&data, "daily", false).unwrap(),
+            calculate_streaming_stats(&data, "daily", false).unwrap(),
+        ] {
+            assert_eq!(stats.assets, ["BTC", "AAA"]);
+            let expected = |base: f64| -> Vec<f64> {
+                weekdays
+                    .windows(2)
+                    .map(|pair| price_on(&pair[1], base) / price_on(&pair[0], base) - 1.0)
+                    .collect()
+            };
+            for (i, base) in [100.0, 50.0].into_iter().enumerate() {
+                let mean = expected(base).iter().sum::<f64>() / (weekdays.len() - 1) as f64;
+                assert_close(stats.mean_returns[i], mean);
+            }
+            if !stats.dates.is_empty() {
+                assert_eq!(stats.dates, weekdays[1..]);
+                // Monday's return is taken over the weekend, from Friday's price
+                let monday = weekdays.iter().position(|d| d == "2024-01-08").unwrap();
+                assert_close(stats.returns_matrix[[0, monday - 1]], 108.0 / 105.0 - 1.0);
+                for (actual, expected) in stats.returns_matrix.row(1).iter().zip(expected(50.0)) {
+                    assert_close(*actual, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn streaming_statistics_match_the_returns_matrix_path() {
+        let daily: Vec<String> = (1..=20).map(|d| format!("2021-01-{:02}", d)).collect();
+        let mut data = HistoricalData::new();
+        for (i, asset) in ["AAA", "BBB", "CCC"].into_iter().enumerate() {
+            let prices: Vec<f64> = (0..daily.len())
+                .map(|t| 100.0 + 10.0 * i as f64 + ((t * (i + 2)) as f64).sin() + 0.1 * t as f64)
+                .collect();
+            data.extend(records(asset, &daily, &prices));
+        }
+        let full = calculate_portfolio_stats(&data, "daily", false).unwrap();
+        let streamed = calculate_streaming_stats(&data, "daily", false).unwrap();
+        assert_eq!(streamed.assets, full.assets);
+        for (a, e) in streamed.mean_returns.iter().zip(&full.mean_returns) {
+            assert!((a - e).abs() < 1e-15, "{} vs {}", a, e);
+        }
+        for (a, e) in streamed.covariance.iter().zip(&full.covariance) {
+            assert!((a - e).abs() < 1e-15, "{} vs {}", a, e);
+        }
+        assert_eq!(streamed.returns_matrix.ncols(), 0);
+    }
+
+    #[test]
+    fn a_zero_score_tilt_is_a_no_op_and_a_large_one_concentrates_on_the_top_score() {
+        let weights = [0.5, 0.3, 0.2, 0.0];
+        // The unheld asset has the best score, which must not bring it in
+        let scores = [0.1, 0.9, -0.4, 2.0];
+        let untilted = score_tilt(&weights, &scores, 0.0).unwrap();
+        for (a, e) in untilted.iter().zip(&weights) {
+            assert_close(*a, *e);
+        }
+        let tilted = score_tilt(&weights, &scores, 1000.0).unwrap();
+        for (a, e) in tilted.iter().zip(&[0.0, 1.0, 0.0, 0.0]) {
+            assert_close(*a, *e);
+        }
+    }
+
+    #[test]
+    fn merged_chunk_covariances_match_the_sample_covariance() {
+        let returns = Array2::from_shape_fn((3, 50), |(i, t)| {
+            0.01 * ((t * (i + 3)) as f64).sin() + 0.002 * (i as f64 - 1.0)
+        });
+        let expected = compute_sample_covariance(&returns).unwrap();
+        // Single observations, uneven chunks and a single chunk
+        for chunk_len in [1, 7, 50] {
+            let merged = parallel_sample_covariance(returns.view(), chunk_len).unwrap();
+            for (a, e) in merged.iter().zip(&expected) {
+                assert!(
+                    (a - e).abs() < 1e-15,
+                    "chunks of {}: {} vs {}",
+                    chunk_len,
+                    a,
+                    e
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn preselection_keeps_the_top_assets_by_each_metric_and_the_cash() {
+        let returns = array![
+            [0.05, -0.03, 0.05, -0.03],
+            [0.0001, 0.0001, 0.0001, 0.0001],
+            [0.006, 0.004, 0.006, 0.004],
+            [0.001, 0.0, 0.001, 0.0],
+        ];
+        let assets = ["HIGH", "CASH", "STEADY", "LOW"].map(String::from).to_vec();
+        let dates = (1..=4).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(assets, returns, dates).unwrap();
+        let select = |metric: Preselection, n: usize| {
+            let (selected_stats, selected) = stats.preselect(metric, n, 0.0001, &["cash"]).unwrap();
+            let ranked: Vec<String> = selected.into_iter().map(|(asset, _)| asset).collect();
+            (selected_stats.assets, ranked)
+        };
+        // The volatile asset has the best mean and momentum, the steady one the best Sharpe
+        assert_eq!(select(Preselection::MeanReturn, 1).1, ["HIGH"]);
+        assert_eq!(select(Preselection::Momentum, 1).1, ["HIGH"]);
+        let (kept, ranked) = select(Preselection::Sharpe, 2);
+        assert_eq!(ranked, ["STEADY", "LOW"]);
+        assert_eq!(kept, ["CASH", "STEADY", "LOW"]);
+        assert!(matches!(
+            stats.preselect(Preselection::Sharpe, 0, 0.0, &[]),
+            Err(QuarsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn a_synthetic_asset_replaces_its_constituents_by_default() {
+        let synthetic: SyntheticAsset = config::Config::builder()
+            .add_source(config::File::from_str(
+                "name = \"AAA_BBB\"\nweights = { aaa = 1.0, BBB = -1.0 }",
+                config::FileFormat::Toml,
+            ))
+            .build()
+            .and_then(config::Config::try_deserialize)
+            .unwrap();
+        assert!(synthetic.drop_constituents);
+        let returns = array![
+            [0.01, -0.02, 0.03, -0.05, 0.02, 0.00],
+            [0.02, 0.01, -0.01, -0.03, 0.00, 0.01],
+            [0.00, 0.01, 0.02, -0.01, 0.01, -0.02],
+        ];
+        let assets = ["AAA", "BBB", "CCC"].map(String::from).to_vec();
+        let dates = (1..=6).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(assets, returns, dates).unwrap();
+        let mut combined = stats.clone();
+        combined.add_synthetic_assets(&[synthetic]).unwrap();
+        assert_eq!(combined.assets, ["CCC", "AAA_BBB"]);
+        let spread = &stats.returns_matrix.row(0) - &stats.returns_matrix.row(1);
+        assert_eq!(combined.returns_matrix.row(1), spread);
+        let cov = &stats.covariance;
+        assert_close(combined.covariance[[0, 1]], cov[[0, 2]] - cov[[1, 2]]);
+        assert_close(
+            combined.covariance[[1, 1]],
+            cov[[0, 0]] - 2.0 * cov[[0, 1]] + cov[[1, 1]],
+        );
+        // Without the constituents the covariance stays invertible
+        check_positive_definite(&combined.covariance).unwrap();
+    }
+
+    #[test]
+    fn correlated_positions_have_no_diversification_benefit_and_uncorrelated_ones_do() {
+        let base: Vec<f64> = (0..40)
+            .map(|t| 0.01 * ((t * 7 % 11) as f64 - 5.0) / 5.0)
+            .collect();
+        // The same returns twice, then against an unrelated series
+        let other: Vec<f64> = (0..40)
+            .map(|t| 0.01 * ((t * 5 % 13) as f64 - 6.0) / 6.0)
+            .collect();
+        let matrix = |second: &[f64]| {
+            Array2::from_shape_fn(
+                (2, base.len()),
+                |(i, t)| if i == 0 { base[t] } else { second[t] },
+            )
+        };
+        let weights = [0.6, 0.4];
+
+        let correlated = cvar_decomposition(&matrix(&base), &weights, 0.9).unwrap();
+        assert_close(correlated.contribution.iter().sum(), correlated.cvar);
+        assert_close(correlated.diversification_benefit, 0.0);
+        assert!(correlated.subadditive && !correlated.diversified);
+
+        let uncorrelated = cvar_decomposition(&matrix(&other), &weights, 0.9).unwrap();
+        assert_close(uncorrelated.contribution.iter().sum(), uncorrelated.cvar);
+        assert!(uncorrelated.subadditive && uncorrelated.diversified);
+        assert_close(
+            uncorrelated.cvar - uncorrelated.diversification_benefit,
+            uncorrelated.standalone.iter().sum(),
+        );
+    }
+
+    #[test]
+    fn identical_series_have_zero_difference_drawdown_curves_once_aligned() {
+        let returns = [0.02, -0.05, 0.01, -0.03, 0.04, -0.01];
+        let dates: Vec<String> = (2..=7).map(|d| format!("2024-01-{:02}", d)).collect();
+        // The benchmark trades one more day, before the portfolio's first
+        let mut benchmark_dates = vec!["2024-01-01".to_string()];
+        benchmark_dates.extend(dates.iter().cloned());
+        let mut benchmark = vec![-0.2];
+        benchmark.extend(returns);
+
+        let (common, portfolio, benchmark) =
+            align_series(&dates, &returns, &benchmark_dates, &benchmark);
+        assert_eq!(common, dates);
+        let portfolio_curve = drawdown_series(&portfolio);
+        let benchmark_curve = drawdown_series(&benchmark);
+        assert_eq!(portfolio_curve.len(), returns.len());
+        for (p, b) in portfolio_curve.iter().zip(&benchmark_curve) {
+            assert_eq!(p - b, 0.0);
+        }
+        assert!(portfolio_curve.iter().any(|drawdown| *drawdown < 0.0));
+    }
+
+    #[test]
+    fn the_momentum_tilt_blends_the_means_with_the_compounded_trailing_return() {
+        let returns = array![
+            [0.01, -0.02, 0.03, 0.05, -0.01, 0.02],
+            [0.00, 0.01, -0.04, 0.02, 0.03, -0.02],
+        ];
+        let assets = vec!["AAA".to_string(), "BBB".to_string()];
+        let dates = (1..=6).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(assets, returns, dates).unwrap();
+
+        let mut untilted = stats.clone();
+        untilted.apply_momentum_tilt(0.0, 3).unwrap();
+        assert_eq!(untilted.mean_returns, stats.mean_returns);
+
+        let mut momentum = stats.clone();
+        momentum.apply_momentum_tilt(1.0, 3).unwrap();
+        let expected = [
+            (1.05f64 * 0.99 * 1.02).powf(1.0 / 3.0) - 1.0,
+            (1.02f64 * 1.03 * 0.98).powf(1.0 / 3.0) - 1.0,
+        ];
+        for (actual, expected) in momentum.mean_returns.iter().zip(expected) {
+            assert_close(*actual, expected);
+        }
+
+        let mut blended = stats.clone();
+        blended.apply_momentum_tilt(0.25, 3).unwrap();
+        for i in 0..2 {
+            assert_close(
+                blended.mean_returns[i],
+                0.75 * stats.mean_returns[i] + 0.25 * momentum.mean_returns[i],
+            );
+        }
+        for (weight, lookback) in [(1.5, 3), (0.5, 0), (0.5, 7)] {
+            assert!(stats.clone().apply_momentum_tilt(weight, lookback).is_err());
+        }
+    }
+
+    #[test]
+    fn rolling_windows_slide_over_the_time_axis() {
+        let returns = Array2::from_shape_fn((3, 10), |(i, t)| (10 * i + t) as f64);
+        let windows: Vec<_> = rolling_windows(&returns, 4).collect();
+        assert_eq!(windows.len(), 10 - 4 + 1);
+        for (t, window) in windows.iter().enumerate() {
+            assert_eq!(window.dim(), (3, 4));
+            assert_eq!(window, &returns.slice(s![.., t..t + 4]));
+        }
+        assert_eq!(windows.last().unwrap()[[2, 3]], 29.0);
+        assert_eq!(rolling_windows(&returns, 10).count(), 1);
+        assert_eq!(rolling_windows(&returns, 11).count(), 0);
+        assert_eq!(rolling_windows(&returns, 0).count(), 0);
+    }
+
+    #[test]
+    fn the_realized_covariance_without_lags_is_the_sample_covariance() {
+        let increments = array![
+            [0.01, -0.02, 0.015, 0.003, -0.007],
+            [0.002, 0.004, -0.01, 0.006, 0.001]
+        ];
+        let realized = realized_covariance(&increments, 0).unwrap();
+        // 1/(T-1) Σ_t (x_t - x̄)(y_t - ȳ), pair by pair
+        let n_obs = increments.ncols() as f64;
+        for i in 0..2 {
+            for j in 0..2 {
+                let (x, y) = (increments.row(i), increments.row(j));
+                let (x_mean, y_mean) = (x.sum() / n_obs, y.sum() / n_obs);
+                let expected = x
+                    .iter()
+                    .zip(y.iter())
+                    .map(|(a, b)| (a - x_mean) * (b - y_mean))
+                    .sum::<f64>()
+                    / (n_obs - 1.0);
+                assert_close(realized[[i, j]], expected);
+            }
+        }
+        // A lag of the increments adds their autocovariance
+        assert!(realized_covariance(&increments, 1).unwrap() != realized);
+        assert!(realized_covariance(&increments, 5).is_err());
+    }
+
+    #[test]
+    fn exposures_of_a_long_short_portfolio() {
+        // 130/30: 1.3 long, 0.3 short
+        let metrics = exposure_metrics(&[0.8, 0.5, -0.2, -0.1]);
+        assert_close(metrics.long, 1.3);
+        assert_close(metrics.short, 0.3);
+        assert_close(metrics.gross, 1.6);
+        assert_close(metrics.net, 1.0);
+        assert_close(metrics.leverage, 1.6);
+
+        // The end of the CAL at twice the tangency portfolio is 2x levered
+        let levered = exposure_metrics(&[1.2, 0.8]);
+        assert_close(levered.leverage, 2.0);
+        assert_close(levered.short, 0.0);
+        let normalized = normalize_weights(&[0.8, 0.5, -0.2, -0.1]).unwrap();
+        assert_close(normalized.iter().sum(), 1.0);
+        assert!(normalize_weights(&[0.5, -0.5]).is_err());
+    }
+
+    #[test]
+    fn annualizing_by_one_is_a_no_op_and_by_252_scales_linearly() {
+        let stats = two_asset_stats();
+        assert_eq!(stats.annualized_covariance(1.0), stats.covariance);
+        assert_eq!(stats.annualized_mean_returns(1.0), stats.mean_returns);
+
+        let covariance = stats.annualized_covariance(252.0);
+        let means = stats.annualized_mean_returns(252.0);
+        for i in 0..2 {
+            assert_close(means[i], 252.0 * stats.mean_returns[i]);
+            for j in 0..2 {
+                assert_close(covariance[[i, j]], 252.0 * stats.covariance[[i, j]]);
+            }
+        }
+        // Volatilities scale with the square root, correlations are unchanged
+        assert_close(
+            covariance[[0, 0]].sqrt(),
+            252f64.sqrt() * stats.covariance[[0, 0]].sqrt(),
+        );
+        let correlation = |c: &Array2<f64>| c[[0, 1]] / (c[[0, 0]] * c[[1, 1]]).sqrt();
+        assert_close(correlation(&covariance), correlation(&stats.covariance));
+    }
+
+    #[test]
+    fn a_constant_volatility_input_gets_a_constant_leverage() {
+        // Alternating ±1% around a drift, every even window has the same volatility
+        let returns: Vec<f64> = (0..120)
+            .map(|t| 0.0005 + if t % 2 == 0 { 0.01 } else { -0.01 })
+            .collect();
+        let (target, window) = (0.10, 20);
+        let realized = realized_volatility(&returns[..window], 252.0);
+        let multipliers = volatility_target(&returns, target, window, 252.0);
+        assert_eq!(multipliers.len(), returns.len());
+        assert!(multipliers[..window].iter().all(|m| *m == 1.0));
+        for m in &multipliers[window..] {
+            assert!((m - target / realized).abs() < 1e-9, "{}", m);
+        }
+        // The levered series runs at the target, up to the n - 1 of the longer sample
+        let levered = apply_leverage(&returns[window..], &multipliers[window..]);
+        let levered_vol = realized_volatility(&levered, 252.0);
+        assert!((levered_vol / target - 1.0).abs() < 0.03, "{}", levered_vol);
+    }
+
+    #[test]
+    fn a_zero_benchmark_is_a_no_op_and_a_constant_one_shifts_every_mean() {
+        let stats = two_asset_stats();
+        let n_obs = stats.returns_matrix.ncols();
+        let assert_matrix_close = |actual: &Array2<f64>, expected: &Array2<f64>| {
+            for (a, e) in actual.iter().zip(expected.iter()) {
+                assert_close(*a, *e);
+            }
+        };
+
+        let zero = stats.excess_over(&vec![0.0; n_obs]).unwrap();
+        assert_eq!(zero.mean_returns, stats.mean_returns);
+        assert_matrix_close(&zero.covariance, &stats.covariance);
+
+        let constant = stats.excess_over(&vec![0.002; n_obs]).unwrap();
+        for (excess, raw) in constant.mean_returns.iter().zip(stats.mean_returns.iter()) {
+            assert_close(*excess, raw - 0.002);
+        }
+        // A constant carries no risk, the covariance is unchanged
+        assert_matrix_close(&constant.covariance, &stats.covariance);
+
+        assert!(matches!(
+            stats.excess_over(&vec![0.0; n_obs - 1]),
+            Err(QuarsError::InsufficientData(_))
+        ));
+    }
+
+    #[test]
+    fn a_monthly_schedule_rebalances_once_a_month_on_its_first_trading_day() {
+        use chrono::{NaiveDate, Weekday};
+        // Weekdays from mid-November 2023 to mid-June 2024
+        let start = NaiveDate::from_ymd_opt(2023, 11, 15).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 6, 14).unwrap();
+        let days: Vec<NaiveDate> = start
+            .iter_days()
+            .take_while(|day| *day <= end)
+            .filter(|day| !matches!(day.weekday(), Weekday::Sat | Weekday::Sun))
+            .collect();
+        let dates: Vec<String> = days.iter().map(|day| day.to_string()).collect();
+
+        let monthly = rebalance_indices(&dates, RebalanceSchedule::Monthly).unwrap();
+        let months: Vec<(i32, u32)> = monthly
+            .iter()
+            .map(|&i| (days[i].year(), days[i].month()))
+            .collect();
+        assert_eq!(
+            months,
+            [
+                (2023, 11),
+                (2023, 12),
+                (2024, 1),
+                (2024, 2),
+                (2024, 3),
+                (2024, 4),
+                (2024, 5),
+                (2024, 6)
+            ]
+        );
+        // Past the initial allocation, each on the first weekday of its month
+        for &i in &monthly[1..] {
+            assert!(days[i].day() <= 3, "{}", days[i]);
+            assert_ne!(days[i - 1].month(), days[i].month());
+        }
+        assert_eq!(monthly[0], 0);
+
+        let quarterly = rebalance_indices(&dates, RebalanceSchedule::Quarterly).unwrap();
+        let quarters: Vec<String> = quarterly.iter().map(|&i| dates[i].clone()).collect();
+        assert_eq!(quarters, ["2023-11-15", "2024-01-01", "2024-04-01"]);
+    }
+
+    #[test]
+    fn zero_intensity_keeps_the_sample_means_and_full_intensity_collapses_them_to_the_target() {
+        let sample_means = two_asset_stats().mean_returns;
+
+        let mut unshrunk = two_asset_stats();
+        let (intensity, _) = unshrunk
+            .shrink_mean_returns(MeanShrinkage::GrandMean, Some(0.0))
+            .unwrap();
+        assert_eq!(intensity, 0.0);
+        for (shrunk, sample) in unshrunk.mean_returns.iter().zip(sample_means.iter()) {
+            assert_close(*shrunk, *sample);
+        }
+
+        for target in [MeanShrinkage::GrandMean, MeanShrinkage::MinVariance] {
+            let mut collapsed = two_asset_stats();
+            let (_, target_mean) = collapsed.shrink_mean_returns(target, Some(1.0)).unwrap();
+            for shrunk in collapsed.mean_returns.iter() {
+                assert_close(*shrunk, target_mean);
+            }
+        }
+        assert_close(
+            two_asset_stats()
+                .shrink_mean_returns(MeanShrinkage::GrandMean, Some(1.0))
+                .unwrap()
+                .1,
+            sample_means.mean().unwrap(),
+        );
+
+        assert!(matches!(
+            two_asset_stats().shrink_mean_returns(MeanShrinkage::GrandMean, Some(1.5)),
+            Err(QuarsError::Config(_))
+        ));
+    }
+
+    #[test]
+    fn a_one_period_lag_of_a_copy_gives_the_autocovariance() {
+        let series = [
+            0.01, -0.02, 0.03, -0.05, 0.02, 0.00, 0.01, -0.01, 0.04, -0.03,
+        ];
+        let returns = array![series, series];
+        let dates: Vec<String> = (1..=10).map(|t| t.to_string()).collect();
+        let stats = PortfolioStats::from_returns(vec!["AAA".into(), "COPY".into()], returns, dates)
+            .unwrap();
+
+        let lagged = stats
+            .lag_returns(&HashMap::from([("COPY".to_string(), 1)]))
+            .unwrap();
+        assert_eq!(lagged.returns_matrix.ncols(), 9);
+        assert_eq!(lagged.dates.first().map(String::as_str), Some("2"));
+        let (lead, lag) = (&series[1..], &series[..9]);
+        let mean = |x: &[f64]| x.iter().sum::<f64>() / x.len() as f64;
+        let autocovariance = lead
+            .iter()
+            .zip(lag)
+            .map(|(a, b)| (a - mean(lead)) * (b - mean(lag)))
+            .sum::<f64>()
+            / 8.0;
+        assert_close(lagged.covariance[[0, 1]], autocovariance);
+        assert_close(lagged.covariance[[1, 0]], autocovariance);
+        // This series mean-reverts, its lag-1 autocovariance is negative
+        assert!(autocovariance < 0.0);
+
+        assert!(matches!(
+            stats.lag_returns(&HashMap::from([("ZZZ".to_string(), 1)])),
+            Err(QuarsError::Config(_))
+        ));
+    }
+}
@@ -0,0 +1,153 @@
+use comfy_table::{presets::ASCII_FULL_CONDENSED, Cell, CellAlignment, Table};
+
+use crate::portfolio::PortfolioStats;
+
+/// Formats a fraction as a percentage with two decimals, e.g. 0.1234 as "12.34%"
+pub fn percent(value: f64) -> String {
+    let percent = value * 100.0;
+    // Avoid "-0.00%" for tiny negative values, e.g. solver noise on a zero weight
+    let percent = if percent.abs() < 0.005 { 0.0 } else { percent };
+    format!("{:.2}%", percent)
+}
+
+/// Formats a plain number with four decimals
+pub fn number(value: f64) -> String {
+    format!("{:.4}", value)
+}
+
+/// Value of a metric, kept as a number until it is written out
+#[derive(Debug, Clone, Copy)]
+pub enum Metric {
+    Percent(f64),
+    Number(f64),
+}
+
+impl Metric {
+    pub fn format(self) -> String {
+        match self {
+            Self::Percent(value) => percent(value),
+            Self::Number(value) => number(value),
+        }
+    }
+}
+
+/// Bordered table with the labels of the first column left-aligned and the numbers of the
+/// other columns right-aligned
+pub fn table(headers: &[&str], rows: Vec<Vec<String>>) -> Table {
+    let mut table = Table::new();
+    table.load_preset(ASCII_FULL_CONDENSED);
+    let aligned = |column: usize, content: &str| {
+        Cell::new(content).set_alignment(if column == 0 {
+            CellAlignment::Left
+        } else {
+            CellAlignment::Right
+        })
+    };
+    table.set_header(
+        headers
+            .iter()
+            .enumerate()
+            .map(|(i, header)| aligned(i, header)),
+    );
+    for row in rows {
+        table.add_row(row.iter().enumerate().map(|(i, value)| aligned(i, value)));
+    }
+    table
+}
+
+/// Two-column table of named metrics
+pub fn metrics_table(metrics: Vec<(String, String)>) -> Table {
+    table(
+        &["Metric", "Value"],
+        metrics
+            .into_iter()
+            .map(|(name, value)| vec![name, value])
+            .collect(),
+    )
+}
+
+/// Per-asset summary of a portfolio: weight, annualized expected return and volatility
+pub fn asset_table(stats: &PortfolioStats, weights: &[f64], periods_per_year: f64) -> Table {
+    let returns = stats.annualized_mean_returns(periods_per_year);
+    let vols = stats.asset_volatilities() * periods_per_year.sqrt();
+    table(
+        &["Asset", "Weight", "Return (ann.)", "Volatility (ann.)"],
+        stats
+            .assets
+            .iter()
+            .enumerate()
+            .map(|(i, asset)| {
+                vec![
+                    asset.clone(),
+                    percent(weights[i]),
+                    percent(returns[i]),
+                    percent(vols[i]),
+                ]
+            })
+            .collect(),
+    )
+}
+
+/// Wall-clock seconds per stage of the run
+pub fn timings_table(timings: &[(&str, f64)]) -> Table {
+    table(
+        &["Stage", "Seconds"],
+        timings
+            .iter()
+            .map(|(stage, seconds)| vec![stage.to_string(), format!("{:.3}", seconds)])
+            .collect(),
+    )
+}
+
+/// Prints a table under a title
+pub fn print_table(title: &str, table: &Table) {
+    println!("\n{}\n{}", title, table);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::{array, Array2};
+
+    #[test]
+    fn the_asset_table_holds_a_row_per_asset_under_its_headers() {
+        let stats = PortfolioStats {
+            assets: vec!["AAA".to_string(), "BBB".to_string()],
+            mean_returns: array![0.0004, 0.0008],
+            covariance: array![[0.0001, 0.0], [0.0, 0.0004]],
+            returns_matrix: Array2::zeros((2, 0)),
+            dates: Vec::new(),
+        };
+        let rendered = asset_table(&stats, &[0.6, 0.4], 252.0).to_string();
+        let lines: Vec<&str> = rendered.lines().collect();
+        let row = |label: &str| {
+            lines
+                .iter()
+                .find(|line| line.contains(label))
+                .unwrap_or_else(|| panic!("no {} row in\n{}", label, rendered))
+                .split('|')
+                .map(str::trim)
+                .filter(|cell| !cell.is_empty())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            row("Asset"),
+            ["Asset", "Weight", "Return (ann.)", "Volatility (ann.)"]
+        );
+        // 0.04% a day is 10.08% a year, a 1% daily volatility is 15.87% a year
+        assert_eq!(row("AAA"), ["AAA", "60.00%", "10.08%", "15.87%"]);
+        assert_eq!(row("BBB"), ["BBB", "40.00%", "20.16%", "31.75%"]);
+        // Numbers right-aligned against the column border
+        let aaa = lines.iter().find(|line| line.contains("AAA")).unwrap();
+        assert!(aaa.contains(" 60.00% |"), "{}", aaa);
+
+        let metrics = metrics_table(vec![
+            ("Sharpe ratio".to_string(), number(1.23456)),
+            ("Weight noise".to_string(), percent(-0.00001)),
+        ])
+        .to_string();
+        assert!(metrics.contains("Metric") && metrics.contains("Value"));
+        assert!(metrics.contains("1.2346") && metrics.contains("0.00%"));
+        assert!(!metrics.contains("-0.00%"));
+    }
+}
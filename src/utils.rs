@@ -1,17 +1,234 @@
 use std::{
-    collections::{BTreeSet, HashMap},
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
     path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use chrono::{NaiveDate, ParseError};
-use csv::WriterBuilder;
+use csv::{Writer, WriterBuilder};
+use ndarray::{Array1, Array2};
+use rand::{rngs::StdRng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use tracing::{debug, info_span, warn};
 
-use crate::data::HistoricalData;
+use crate::config::General;
+use crate::data::{read_csv, HistoricalData, Record};
+use crate::error::QuarsError;
+use crate::optimization::OptimizationResults;
+use crate::portfolio::{exposure_metrics, ExposureMetrics, PortfolioStats};
+#[cfg(feature = "xlsx")]
+use crate::report::Metric;
+
+// Set by the Ctrl-C handler, the fetch and compute stages poll it to stop cleanly
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Error of a stage stopped on a cancellation request
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Cancelled on user request")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Asks the running stages to stop at their next checkpoint
+pub fn request_cancellation() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+/// Checkpoint of a long-running stage, errors once cancellation was requested
+pub fn check_cancelled() -> Result<(), Cancelled> {
+    if CANCELLED.load(Ordering::SeqCst) {
+        Err(Cancelled)
+    } else {
+        Ok(())
+    }
+}
+
+/// Generator of sub-stream `index` of a master seed. Parallel work split into fixed units,
+/// each drawing from its own sub-stream, draws the same numbers whatever the number of
+/// threads and the order the units run in.
+pub fn substream(seed: u64, index: u64) -> StdRng {
+    // Distinct ChaCha keys give independent streams
+    let mut key = [0u8; 32];
+    key[..8].copy_from_slice(&seed.to_le_bytes());
+    key[8..16].copy_from_slice(&index.to_le_bytes());
+    StdRng::from_seed(key)
+}
+
+// Wall-clock time spent in each stage of the run, in the order the stages first ran
+static TIMINGS: Mutex<Vec<(&str, Duration)>> = Mutex::new(Vec::new());
+
+/// Adds `elapsed` to the time spent in `stage`
+pub fn record_timing(stage: &'static str, elapsed: Duration) {
+    let mut timings = TIMINGS.lock().unwrap_or_else(|e| e.into_inner());
+    match timings.iter_mut().find(|(name, _)| *name == stage) {
+        Some((_, total)) => *total += elapsed,
+        None => timings.push((stage, elapsed)),
+    }
+}
+
+/// Runs `f` within a span of `stage`, adding its wall-clock time to the stage
+pub fn timed<T>(stage: &'static str, f: impl FnOnce() -> T) -> T {
+    let _span = info_span!("stage", name = stage).entered();
+    let start = Instant::now();
+    let result = f();
+    let elapsed = start.elapsed();
+    record_timing(stage, elapsed);
+    debug!(seconds = elapsed.as_secs_f64(), "stage done");
+    result
+}
+
+/// Time spent in each stage so far, in seconds. Stages may nest, e.g. the covariance
+/// inversion runs within the optimization, so they do not add up to the run time, and a
+/// stage run on several threads adds up the time of each.
+pub fn timings() -> Vec<(&'static str, f64)> {
+    TIMINGS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .map(|(stage, elapsed)| (*stage, elapsed.as_secs_f64()))
+        .collect()
+}
+
+/// Writes a file atomically: the contents go to a temporary file next to it, which is then
+/// renamed over the target, so an interrupted write never leaves a partial file behind
+pub fn write_atomic(path: impl AsRef<Path>, contents: &[u8]) -> std::io::Result<()> {
+    let path = path.as_ref();
+    let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+    let written =
+        std::fs::write(&tmp_path, contents).and_then(|_| std::fs::rename(&tmp_path, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp_path);
+    }
+    written
+}
+
+// Writes the CSV built in memory by `build` to `path` atomically
+pub fn write_csv_atomic(
+    path: impl AsRef<Path>,
+    build: impl FnOnce(&mut Writer<Vec<u8>>) -> Result<(), csv::Error>,
+) -> Result<(), csv::Error> {
+    write_csv_atomic_with(path, b',', build)
+}
+
+// `write_csv_atomic` with a custom field delimiter
+fn write_csv_atomic_with(
+    path: impl AsRef<Path>,
+    delimiter: u8,
+    build: impl FnOnce(&mut Writer<Vec<u8>>) -> Result<(), csv::Error>,
+) -> Result<(), csv::Error> {
+    let mut wtr = WriterBuilder::new()
+        .delimiter(delimiter)
+        .from_writer(Vec::new());
+    build(&mut wtr)?;
+    let contents = wtr.into_inner().map_err(|e| e.into_error())?;
+    write_atomic(path, &contents)?;
+    Ok(())
+}
+
+// Returns read back from CSV may drift from the exact ones by at most this much
+const MAX_RETURN_ROUNDING_ERROR: f64 = 1e-6;
+
+/// Number format of the CSV output: rounding and decimal separator
+#[derive(Debug, Clone, Copy)]
+pub struct CsvFormat {
+    pub decimals: Option<usize>,
+    pub decimal_separator: char,
+}
+
+impl CsvFormat {
+    /// Format of the prices written to CSV
+    pub fn prices(general: &General) -> Result<Self, QuarsError> {
+        Self::new(general.price_decimals, general.output_decimal_separator)
+    }
+
+    /// Format of the returns written to CSV
+    pub fn returns(general: &General) -> Result<Self, QuarsError> {
+        Self::new(general.return_decimals, general.output_decimal_separator)
+    }
+
+    fn new(decimals: Option<usize>, decimal_separator: char) -> Result<Self, QuarsError> {
+        if decimal_separator != '.' && decimal_separator != ',' {
+            return Err(QuarsError::Config(format!(
+                "Unsupported output_decimal_separator '{}', use '.' or ','",
+                decimal_separator
+            )));
+        }
+        Ok(Self {
+            decimals,
+            decimal_separator,
+        })
+    }
+
+    /// Field delimiter, ';' when the decimal separator is a comma
+    pub fn delimiter(&self) -> char {
+        if self.decimal_separator == ',' {
+            ';'
+        } else {
+            ','
+        }
+    }
+
+    /// Formats a number with the configured decimals and decimal separator
+    pub fn format(&self, value: f64) -> String {
+        let formatted = match self.decimals {
+            Some(decimals) => format!("{:.*}", decimals, value),
+            None => value.to_string(),
+        };
+        if self.decimal_separator == '.' {
+            formatted
+        } else {
+            formatted.replace('.', &self.decimal_separator.to_string())
+        }
+    }
+
+    /// Largest change of a simple return computed from prices rounded to the configured
+    /// decimals, zero at full precision. Each of the two prices of a return moves by at most
+    /// half a unit of the last decimal.
+    pub fn return_rounding_error(&self, data: &HistoricalData) -> f64 {
+        let Some(decimals) = self.decimals else {
+            return 0.0;
+        };
+        let half_unit = 0.5 * 10f64.powi(-(decimals as i32));
+        data.iter()
+            .map(|record| record.price.abs())
+            .filter(|&price| price > 0.0)
+            .map(|price| 2.0 * half_unit / price)
+            .fold(0.0, f64::max)
+    }
+
+    /// Warns when the rounding would shift the returns read back from the CSV
+    pub fn check_precision(&self, data: &HistoricalData) {
+        let error = self.return_rounding_error(data);
+        if error > MAX_RETURN_ROUNDING_ERROR {
+            warn!(
+                "price_decimals = {} may shift the returns read back from the CSV by up to {:.2e}, raise it",
+                self.decimals.unwrap_or_default(),
+                error
+            );
+        }
+    }
+}
 
 /// Writes a HistoricalData to CSV
-pub fn write_to_csv(data: &HistoricalData, output_path: &str) -> Result<(), csv::Error> {
+pub fn write_to_csv(
+    data: &HistoricalData,
+    format: CsvFormat,
+    output_path: &str,
+) -> Result<(), csv::Error> {
     if let Some(parent) = Path::new(output_path).parent() {
-        std::fs::create_dir_all(parent).expect("Failed to create directories for CSV output");
+        std::fs::create_dir_all(parent)?;
     }
     let mut date_set = BTreeSet::new();
     let mut asset_set = BTreeSet::new();
@@ -21,35 +238,781 @@ pub fn write_to_csv(data: &HistoricalData, output_path: &str) -> Result<(), csv:
     }
     let dates: Vec<String> = date_set.into_iter().collect();
     let assets: Vec<String> = asset_set.into_iter().collect();
-    let mut wtr = WriterBuilder::new()
-        .has_headers(true)
-        .from_path(output_path)?;
+    write_csv_atomic_with(output_path, format.delimiter() as u8, |wtr| {
+        let mut header = vec!["date".to_string()];
+        header.extend(assets.iter().cloned());
+        wtr.write_record(&header)?;
 
-    let mut header = vec!["date".to_string()];
-    header.extend(assets.iter().cloned());
-    wtr.write_record(&header)?;
+        let mut lookup: HashMap<(String, String), f64> = HashMap::new();
+        for record in data {
+            lookup.insert((record.date.clone(), record.asset.clone()), record.price);
+        }
+
+        for date in dates {
+            let mut row = vec![date.clone()];
+            for asset in &assets {
+                // If no price found, leave the cell blank.
+                if let Some(price) = lookup.get(&(date.clone(), asset.clone())) {
+                    row.push(format.format(*price));
+                } else {
+                    row.push("".to_string());
+                }
+            }
+            wtr.write_record(&row)?;
+        }
+        Ok(())
+    })
+}
 
-    let mut lookup: HashMap<(String, String), f64> = HashMap::new();
+/// Merges the data into the CSV store at `output_path`, written as by `write_to_csv`, and
+/// returns the union without duplicates, a price of the data replacing a stored one of the
+/// same (date, asset)
+pub fn append_to_csv(
+    data: HistoricalData,
+    format: CsvFormat,
+    output_path: &str,
+) -> Result<HistoricalData, QuarsError> {
+    let mut merged: BTreeMap<(String, String), f64> = BTreeMap::new();
+    if Path::new(output_path).exists() {
+        let stored = read_csv(
+            output_path,
+            format.delimiter(),
+            format.decimal_separator,
+            true,
+            None,
+            None,
+            None,
+        )?;
+        for record in stored {
+            merged.insert((record.date, record.asset), record.price);
+        }
+    }
     for record in data {
-        lookup.insert((record.date.clone(), record.asset.clone()), record.price);
+        merged.insert((record.date, record.asset), record.price);
     }
+    let union: HistoricalData = merged
+        .into_iter()
+        .map(|((date, asset), price)| Record { date, asset, price })
+        .collect();
+    write_to_csv(&union, format, output_path)?;
+    Ok(union)
+}
 
-    for date in dates {
-        let mut row = vec![date.clone()];
-        for asset in &assets {
-            // If no price found, leave the cell blank.
-            if let Some(price) = lookup.get(&(date.clone(), asset.clone())) {
-                row.push(price.to_string());
-            } else {
-                row.push("".to_string());
-            }
-        }
-        wtr.write_record(&row)?;
+/// Writes a dated portfolio return series as a `date,portfolio_return` CSV
+pub fn write_portfolio_returns_csv(
+    dates: &[String],
+    portfolio_returns: &[f64],
+    format: CsvFormat,
+    output_path: &str,
+) -> Result<(), QuarsError> {
+    if dates.len() != portfolio_returns.len() {
+        return Err(QuarsError::InsufficientData(format!(
+            "{} dates for {} portfolio returns",
+            dates.len(),
+            portfolio_returns.len()
+        )));
     }
-    wtr.flush()?;
+    if let Some(parent) = Path::new(output_path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    write_csv_atomic_with(output_path, format.delimiter() as u8, |wtr| {
+        wtr.write_record(["date", "portfolio_return"])?;
+        for (date, value) in dates.iter().zip(portfolio_returns) {
+            wtr.write_record([date.clone(), format.format(*value)])?;
+        }
+        Ok(())
+    })?;
     Ok(())
 }
 
+/// Number of observations per year for a configured timeframe
+pub fn periods_per_year(timeframe: &str) -> Result<f64, QuarsError> {
+    match timeframe.to_lowercase().as_str() {
+        "daily" => Ok(252.0),
+        "weekly" => Ok(52.0),
+        "monthly" => Ok(12.0),
+        _ => Err(QuarsError::Config(format!(
+            "Unsupported timeframe: {}",
+            timeframe
+        ))),
+    }
+}
+
 pub fn parse_date(date_str: &str) -> Result<NaiveDate, ParseError> {
     NaiveDate::parse_from_str(date_str, "%Y-%m-%d")
 }
+
+/// Metadata of a run recorded alongside its results
+#[derive(Clone, Copy, Debug)]
+pub struct RunMetadata<'a> {
+    pub seed: u64,
+    // Date of the cached data used when the API fetch failed
+    pub fallback_data_date: Option<&'a str>,
+    // Date a point-in-time run was run as of
+    pub as_of_date: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct ResultsExport<'a> {
+    seed: u64,
+    assets: &'a [String],
+    annualized_mean_returns: Vec<f64>,
+    annualized_covariance: Vec<Vec<f64>>,
+    results: &'a OptimizationResults,
+    exposure: ExposureMetrics,
+    frontier_exposure: Vec<ExposureMetrics>,
+    // Risky weights of each frontier point by asset
+    frontier_weights: Vec<BTreeMap<&'a str, f64>>,
+    // Date of the cached data used when the API fetch failed
+    fallback_data_date: Option<&'a str>,
+    // Date a point-in-time run was run as of
+    as_of_date: Option<&'a str>,
+    // Wall-clock seconds per stage of the run
+    timings: Vec<StageTiming>,
+}
+
+#[derive(Serialize)]
+struct StageTiming {
+    stage: &'static str,
+    seconds: f64,
+}
+
+/// Writes the annualized statistics and the optimization results, with exposure metrics and
+/// the weights of every frontier point by asset, to JSON.
+/// The run seed is recorded alongside so the results can be reproduced, with the date of the
+/// cached data when the API fetch fell back to it and the time spent per stage.
+pub fn write_results_json(
+    stats: &PortfolioStats,
+    results: &OptimizationResults,
+    periods_per_year: f64,
+    run: RunMetadata,
+    output_path: &str,
+) -> Result<(), QuarsError> {
+    if let Some(parent) = Path::new(output_path).parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let export = ResultsExport {
+        seed: run.seed,
+        assets: &stats.assets,
+        annualized_mean_returns: stats.annualized_mean_returns(periods_per_year).to_vec(),
+        annualized_covariance: stats
+            .annualized_covariance(periods_per_year)
+            .outer_iter()
+            .map(|row| row.to_vec())
+            .collect(),
+        results,
+        exposure: exposure_metrics(&results.optimal_risky_portfolio),
+        frontier_exposure: results
+            .frontier
+            .iter()
+            .map(|pt| pt.exposure_metrics())
+            .collect(),
+        frontier_weights: results
+            .frontier
+            .iter()
+            .map(|pt| {
+                stats
+                    .assets
+                    .iter()
+                    .map(String::as_str)
+                    .zip(pt.risky_weights.iter().copied())
+                    .collect()
+            })
+            .collect(),
+        fallback_data_date: run.fallback_data_date,
+        as_of_date: run.as_of_date,
+        timings: timings()
+            .into_iter()
+            .map(|(stage, seconds)| StageTiming { stage, seconds })
+            .collect(),
+    };
+    write_atomic(
+        output_path,
+        serde_json::to_string_pretty(&export)?.as_bytes(),
+    )?;
+    Ok(())
+}
+
+/// Writes the results to an Excel workbook with one sheet each for the tangency weights, the
+/// frontier, the per-asset statistics, the risk metrics and the correlation matrix. Returns
+/// and volatilities are annualized, as in the printed reports.
+#[cfg(feature = "xlsx")]
+pub fn write_results_xlsx(
+    stats: &PortfolioStats,
+    results: &OptimizationResults,
+    metrics: &[(String, Metric)],
+    periods_per_year: f64,
+    output_path: &str,
+) -> Result<(), QuarsError> {
+    use rust_xlsxwriter::{Format, Workbook};
+
+    let percent = Format::new().set_num_format("0.00%");
+    let number = Format::new().set_num_format("0.0000");
+    let mut workbook = Workbook::new();
+
+    let sheet = xlsx_sheet(&mut workbook, "Weights", &["Asset", "Weight"])?;
+    for (i, (asset, weight)) in stats
+        .assets
+        .iter()
+        .zip(&results.optimal_risky_portfolio)
+        .enumerate()
+    {
+        let row = i as u32 + 1;
+        sheet.write_string(row, 0, asset)?;
+        sheet.write_number_with_format(row, 1, *weight, &percent)?;
+    }
+
+    let mut headers = vec!["Return (ann.)", "Volatility (ann.)", "Risk-Free Weight"];
+    headers.extend(stats.assets.iter().map(String::as_str));
+    let sheet = xlsx_sheet(&mut workbook, "Frontier", &headers)?;
+    for (i, pt) in results.frontier.iter().enumerate() {
+        let row = i as u32 + 1;
+        sheet.write_number_with_format(row, 0, pt.expected_return * periods_per_year, &percent)?;
+        sheet.write_number_with_format(
+            row,
+            1,
+            pt.portfolio_std * periods_per_year.sqrt(),
+            &percent,
+        )?;
+        sheet.write_number_with_format(row, 2, pt.risk_free_weight, &percent)?;
+        for (j, weight) in pt.risky_weights.iter().enumerate() {
+            sheet.write_number_with_format(row, j as u16 + 3, *weight, &percent)?;
+        }
+    }
+
+    let sheet = xlsx_sheet(
+        &mut workbook,
+        "Assets",
+        &["Asset", "Return (ann.)", "Volatility (ann.)"],
+    )?;
+    let returns = stats.annualized_mean_returns(periods_per_year);
+    let vols = stats.asset_volatilities() * periods_per_year.sqrt();
+    for (i, asset) in stats.assets.iter().enumerate() {
+        let row = i as u32 + 1;
+        sheet.write_string(row, 0, asset)?;
+        sheet.write_number_with_format(row, 1, returns[i], &percent)?;
+        sheet.write_number_with_format(row, 2, vols[i], &percent)?;
+    }
+
+    let sheet = xlsx_sheet(&mut workbook, "Risk", &["Metric", "Value"])?;
+    for (i, (name, metric)) in metrics.iter().enumerate() {
+        let row = i as u32 + 1;
+        sheet.write_string(row, 0, name)?;
+        match metric {
+            Metric::Percent(value) => sheet.write_number_with_format(row, 1, *value, &percent)?,
+            Metric::Number(value) => sheet.write_number_with_format(row, 1, *value, &number)?,
+        };
+    }
+
+    let mut headers = vec![""];
+    headers.extend(stats.assets.iter().map(String::as_str));
+    let sheet = xlsx_sheet(&mut workbook, "Correlation", &headers)?;
+    for (i, row_values) in stats.correlation().outer_iter().enumerate() {
+        let row = i as u32 + 1;
+        sheet.write_string(row, 0, &stats.assets[i])?;
+        for (j, value) in row_values.iter().enumerate() {
+            sheet.write_number_with_format(row, j as u16 + 1, *value, &number)?;
+        }
+    }
+
+    write_atomic(output_path, &workbook.save_to_buffer()?)?;
+    Ok(())
+}
+
+// Adds a named sheet with a bold header row, frozen above the data
+#[cfg(feature = "xlsx")]
+fn xlsx_sheet<'a>(
+    workbook: &'a mut rust_xlsxwriter::Workbook,
+    name: &str,
+    headers: &[&str],
+) -> Result<&'a mut rust_xlsxwriter::Worksheet, QuarsError> {
+    let sheet = workbook.add_worksheet();
+    sheet.set_name(name)?;
+    sheet.write_row_with_format(
+        0,
+        0,
+        headers.iter().copied(),
+        &rust_xlsxwriter::Format::new().set_bold(),
+    )?;
+    sheet.set_freeze_panes(1, 0)?;
+    Ok(sheet)
+}
+
+// File name of the cached statistics inside a run directory
+const STATS_FILE: &str = "stats.json";
+
+#[derive(Serialize, Deserialize)]
+struct StatsCache {
+    assets: Vec<String>,
+    mean_returns: Vec<f64>,
+    covariance: Vec<Vec<f64>>,
+    // Informational, derived from the covariance when loading
+    correlation: Vec<Vec<f64>>,
+    returns_matrix: Vec<Vec<f64>>,
+    dates: Vec<String>,
+    // Benchmark split off the universe, with its returns on the same dates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    benchmark: Option<CachedBenchmark>,
+}
+
+/// Ticker, dates and per-period returns of the benchmark split off the universe
+pub type BenchmarkReturns = (String, Vec<String>, Vec<f64>);
+
+#[derive(Serialize, Deserialize)]
+struct CachedBenchmark {
+    ticker: String,
+    // Dates of the returns, those of the cached statistics when absent
+    #[serde(default)]
+    dates: Vec<String>,
+    returns: Vec<f64>,
+}
+
+fn matrix_rows(matrix: &Array2<f64>) -> Vec<Vec<f64>> {
+    matrix.outer_iter().map(|row| row.to_vec()).collect()
+}
+
+fn matrix_from_rows(rows: &[Vec<f64>], n_cols: usize) -> Result<Array2<f64>, QuarsError> {
+    let flat: Vec<f64> = rows.iter().flatten().cloned().collect();
+    Array2::from_shape_vec((rows.len(), n_cols), flat)
+        .map_err(|e| QuarsError::Parse(format!("Malformed cached matrix: {}", e)))
+}
+
+// Writes a square asset-by-asset matrix with the assets as header and first column
+fn write_asset_matrix(
+    assets: &[String],
+    matrix: &Array2<f64>,
+    output_path: &str,
+) -> Result<(), csv::Error> {
+    write_csv_atomic(output_path, |wtr| {
+        let mut header = vec!["asset".to_string()];
+        header.extend(assets.iter().cloned());
+        wtr.write_record(&header)?;
+        for (asset, row) in assets.iter().zip(matrix.outer_iter()) {
+            let mut record = vec![asset.clone()];
+            record.extend(row.iter().map(|v| v.to_string()));
+            wtr.write_record(&record)?;
+        }
+        Ok(())
+    })
+}
+
+/// Caches the statistics in `dir`: stats.json holding everything needed to run the
+/// optimizer again, plus the mean returns, covariance and correlation as CSV for inspection
+pub fn write_stats(
+    stats: &PortfolioStats,
+    benchmark: Option<&BenchmarkReturns>,
+    dir: &str,
+) -> Result<(), QuarsError> {
+    std::fs::create_dir_all(dir)?;
+    let correlation = stats.correlation();
+    let cache = StatsCache {
+        assets: stats.assets.clone(),
+        mean_returns: stats.mean_returns.to_vec(),
+        covariance: matrix_rows(&stats.covariance),
+        correlation: matrix_rows(&correlation),
+        returns_matrix: matrix_rows(&stats.returns_matrix),
+        dates: stats.dates.clone(),
+        benchmark: benchmark.map(|(ticker, dates, returns)| CachedBenchmark {
+            ticker: ticker.clone(),
+            dates: dates.clone(),
+            returns: returns.clone(),
+        }),
+    };
+    write_atomic(
+        Path::new(dir).join(STATS_FILE),
+        serde_json::to_string_pretty(&cache)?.as_bytes(),
+    )?;
+
+    write_csv_atomic(Path::new(dir).join("mean_returns.csv"), |wtr| {
+        wtr.write_record(["asset", "mean_return"])?;
+        for (asset, mean) in stats.assets.iter().zip(stats.mean_returns.iter()) {
+            wtr.write_record([asset.clone(), mean.to_string()])?;
+        }
+        Ok(())
+    })?;
+    write_asset_matrix(
+        &stats.assets,
+        &stats.covariance,
+        &format!("{}/covariance.csv", dir),
+    )?;
+    write_asset_matrix(
+        &stats.assets,
+        &correlation,
+        &format!("{}/correlation.csv", dir),
+    )?;
+    Ok(())
+}
+
+/// Loads the statistics cached by `write_stats` in `dir`, with the benchmark returns when
+/// they were cached
+pub fn read_stats(dir: &str) -> Result<(PortfolioStats, Option<BenchmarkReturns>), QuarsError> {
+    let content = std::fs::read_to_string(Path::new(dir).join(STATS_FILE))?;
+    let cache: StatsCache = serde_json::from_str(&content)?;
+    let n_assets = cache.assets.len();
+    if cache.mean_returns.len() != n_assets
+        || cache.covariance.len() != n_assets
+        || cache.returns_matrix.len() != n_assets
+        || cache.benchmark.as_ref().is_some_and(|b| {
+            b.returns.len() != b.dates.len() && b.returns.len() != cache.dates.len()
+        })
+    {
+        return Err(QuarsError::Parse(format!(
+            "Cached statistics in {} are inconsistent with {} assets",
+            dir, n_assets
+        )));
+    }
+    let benchmark = cache.benchmark.map(|b| {
+        let dates = if b.dates.len() == b.returns.len() {
+            b.dates
+        } else {
+            cache.dates.clone()
+        };
+        (b.ticker, dates, b.returns)
+    });
+    let stats = PortfolioStats {
+        mean_returns: Array1::from(cache.mean_returns),
+        covariance: matrix_from_rows(&cache.covariance, n_assets)?,
+        returns_matrix: matrix_from_rows(&cache.returns_matrix, cache.dates.len())?,
+        assets: cache.assets,
+        dates: cache.dates,
+    };
+    Ok((stats, benchmark))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ndarray::array;
+
+    #[test]
+    fn cached_statistics_keep_the_benchmark_returns() {
+        let returns = array![[0.01, -0.02, 0.03], [0.02, 0.01, -0.01]];
+        let dates: Vec<String> = ["2024-01-02", "2024-01-03", "2024-01-04"]
+            .map(String::from)
+            .to_vec();
+        let stats =
+            PortfolioStats::from_returns(vec!["AAA".into(), "BBB".into()], returns, dates).unwrap();
+        let benchmark = (
+            "SPY".to_string(),
+            stats.dates.clone(),
+            vec![0.005, -0.01, 0.02],
+        );
+        let dir = std::env::temp_dir().join(format!("quars-stats-{}", std::process::id()));
+        let dir = dir.to_string_lossy();
+
+        write_stats(&stats, Some(&benchmark), &dir).unwrap();
+        let (cached, cached_benchmark) = read_stats(&dir).unwrap();
+        assert_eq!(cached.assets, stats.assets);
+        assert_eq!(cached.dates, stats.dates);
+        assert_eq!(cached.mean_returns, stats.mean_returns);
+        assert_eq!(cached.covariance, stats.covariance);
+        assert_eq!(cached.returns_matrix, stats.returns_matrix);
+        assert_eq!(cached_benchmark, Some(benchmark));
+
+        write_stats(&stats, None, &dir).unwrap();
+        assert_eq!(read_stats(&dir).unwrap().1, None);
+        std::fs::remove_dir_all(&*dir).unwrap();
+    }
+
+    #[test]
+    fn portfolio_returns_round_trip_through_csv() {
+        let dates: Vec<String> = ["2024-01-02", "2024-01-03", "2024-01-04"]
+            .map(String::from)
+            .to_vec();
+        let returns = [0.012345678901, -0.0002, 1.0 / 3.0];
+        let path = std::env::temp_dir().join(format!("quars-returns-{}.csv", std::process::id()));
+        let path = path.to_string_lossy();
+
+        for (format, tolerance) in [
+            (CsvFormat::new(None, '.').unwrap(), 0.0),
+            (CsvFormat::new(Some(8), ',').unwrap(), 0.5e-8),
+        ] {
+            write_portfolio_returns_csv(&dates, &returns, format, &path).unwrap();
+            let mut rdr = csv::ReaderBuilder::new()
+                .delimiter(format.delimiter() as u8)
+                .from_path(&*path)
+                .unwrap();
+            assert_eq!(rdr.headers().unwrap(), vec!["date", "portfolio_return"]);
+            let rows: Vec<(String, f64)> = rdr
+                .records()
+                .map(|record| {
+                    let record = record.unwrap();
+                    let value = record[1].replace(',', ".").parse().unwrap();
+                    (record[0].to_string(), value)
+                })
+                .collect();
+            assert_eq!(rows.len(), returns.len());
+            for ((date, value), (expected_date, expected)) in
+                rows.iter().zip(dates.iter().zip(returns))
+            {
+                assert_eq!(date, expected_date);
+                assert!(
+                    (value - expected).abs() <= tolerance,
+                    "{} vs {}",
+                    value,
+                    expected
+                );
+            }
+        }
+        std::fs::remove_file(&*path).unwrap();
+    }
+
+    #[test]
+    fn appending_to_a_store_keeps_one_price_per_date_and_asset() {
+        let records = |rows: &[(&str, &str, f64)]| -> HistoricalData {
+            rows.iter()
+                .map(|(date, asset, price)| Record {
+                    date: date.to_string(),
+                    asset: asset.to_string(),
+                    price: *price,
+                })
+                .collect()
+        };
+        let as_rows = |data: &HistoricalData| -> Vec<(String, String, f64)> {
+            data.iter()
+                .map(|r| (r.date.clone(), r.asset.clone(), r.price))
+                .collect()
+        };
+        let path = std::env::temp_dir().join(format!("quars-store-{}.csv", std::process::id()));
+        let path = path.to_string_lossy();
+        let _ = std::fs::remove_file(&*path);
+        let format = CsvFormat::new(None, '.').unwrap();
+
+        let first = [
+            ("2024-01-02", "AAA", 10.0),
+            ("2024-01-02", "BBB", 20.0),
+            ("2024-01-03", "AAA", 10.5),
+            ("2024-01-03", "BBB", 20.5),
+        ];
+        append_to_csv(records(&first), format, &path).unwrap();
+        // The last stored date fetched again with a revised price, then a new one
+        let second = [
+            ("2024-01-03", "AAA", 10.25),
+            ("2024-01-03", "BBB", 20.5),
+            ("2024-01-04", "AAA", 11.0),
+            ("2024-01-04", "BBB", 21.0),
+        ];
+        let union = append_to_csv(records(&second), format, &path).unwrap();
+        let expected = records(&[
+            ("2024-01-02", "AAA", 10.0),
+            ("2024-01-02", "BBB", 20.0),
+            ("2024-01-03", "AAA", 10.25),
+            ("2024-01-03", "BBB", 20.5),
+            ("2024-01-04", "AAA", 11.0),
+            ("2024-01-04", "BBB", 21.0),
+        ]);
+        assert_eq!(as_rows(&union), as_rows(&expected));
+        let mut stored = read_csv(&path, ',', '.', true, None, None, None).unwrap();
+        stored.sort_by(|a, b| (&a.date, &a.asset).cmp(&(&b.date, &b.asset)));
+        assert_eq!(as_rows(&stored), as_rows(&expected));
+        std::fs::remove_file(&*path).unwrap();
+    }
+
+    #[cfg(feature = "xlsx")]
+    #[test]
+    fn the_workbook_holds_one_sheet_per_table() {
+        use std::io::Read;
+
+        let returns = array![
+            [0.01, -0.02, 0.03, 0.01, -0.01],
+            [0.02, 0.01, -0.01, 0.00, 0.02]
+        ];
+        let dates = (1..=5).map(|t| t.to_string()).collect();
+        let stats =
+            PortfolioStats::from_returns(vec!["AAA".into(), "BBB".into()], returns, dates).unwrap();
+        let results = crate::optimization::optimize_min_variance(&stats, 0.0, 5, false).unwrap();
+        let metrics = [("VaR 95%".to_string(), Metric::Percent(0.02))];
+        let path = std::env::temp_dir().join(format!("quars-results-{}.xlsx", std::process::id()));
+        let path = path.to_string_lossy();
+
+        write_results_xlsx(&stats, &results, &metrics, 252.0, &path).unwrap();
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&*path).unwrap()).unwrap();
+        let mut workbook = String::new();
+        archive
+            .by_name("xl/workbook.xml")
+            .unwrap()
+            .read_to_string(&mut workbook)
+            .unwrap();
+        let sheets: Vec<&str> = workbook
+            .split("<sheet name=\"")
+            .skip(1)
+            .filter_map(|rest| rest.split('"').next())
+            .collect();
+        assert_eq!(
+            sheets,
+            ["Weights", "Frontier", "Assets", "Risk", "Correlation"]
+        );
+        std::fs::remove_file(&*path).unwrap();
+    }
+
+    #[test]
+    fn the_exported_weights_of_each_frontier_point_add_up_to_its_leverage() {
+        let stats = PortfolioStats {
+            assets: vec!["AAA".to_string(), "BBB".to_string()],
+            mean_returns: array![0.001, 0.002],
+            covariance: array![[0.0004, 0.0001], [0.0001, 0.0009]],
+            returns_matrix: ndarray::Array2::zeros((2, 0)),
+            dates: Vec::new(),
+        };
+        let results = crate::optimization::optimize_cara(&stats, 4.0, 0.0001, 10, false).unwrap();
+        let path = std::env::temp_dir().join(format!("quars-results-{}.json", std::process::id()));
+        let run = RunMetadata {
+            seed: 1,
+            fallback_data_date: None,
+            as_of_date: None,
+        };
+        write_results_json(&stats, &results, 252.0, run, path.to_str().unwrap()).unwrap();
+        let export: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let frontier = export["results"]["frontier"].as_array().unwrap();
+        let weights = export["frontier_weights"].as_array().unwrap();
+        let exposures = export["frontier_exposure"].as_array().unwrap();
+        assert_eq!(weights.len(), frontier.len());
+        for ((point, weights), exposure) in frontier.iter().zip(weights).zip(exposures) {
+            let weights = weights.as_object().unwrap();
+            assert_eq!(weights.keys().collect::<Vec<_>>(), ["AAA", "BBB"]);
+            let net: f64 = weights.values().map(|w| w.as_f64().unwrap()).sum();
+            let gross: f64 = weights.values().map(|w| w.as_f64().unwrap().abs()).sum();
+            // The risky weights hold what the risk-free leg does not
+            let risky = 1.0 - point["risk_free_weight"].as_f64().unwrap();
+            assert!((net - risky).abs() < 1e-9, "{} for {}", net, risky);
+            assert!((net - exposure["net"].as_f64().unwrap()).abs() < 1e-12);
+            assert!((gross - exposure["leverage"].as_f64().unwrap()).abs() < 1e-12);
+        }
+        // Up to the levered end of the capital allocation line
+        let last = exposures.last().unwrap()["leverage"].as_f64().unwrap();
+        assert!(last > 1.0, "{}", last);
+    }
+
+    #[test]
+    fn prices_are_written_with_the_configured_decimals_and_separator() {
+        let data = vec![
+            Record {
+                date: "2024-01-02".to_string(),
+                asset: "AAA".to_string(),
+                price: 123.45000000000001,
+            },
+            Record {
+                date: "2024-01-03".to_string(),
+                asset: "AAA".to_string(),
+                price: 99.999,
+            },
+        ];
+        let path = std::env::temp_dir().join(format!("quars-decimals-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let written = |decimals: Option<usize>, decimal_separator: char| {
+            let format = CsvFormat::new(decimals, decimal_separator).unwrap();
+            write_to_csv(&data, format, path).unwrap();
+            std::fs::read_to_string(path).unwrap()
+        };
+
+        assert_eq!(
+            written(Some(2), '.'),
+            "date,AAA\n2024-01-02,123.45\n2024-01-03,100.00\n"
+        );
+        assert_eq!(
+            written(Some(4), ','),
+            "date;AAA\n2024-01-02;123,4500\n2024-01-03;99,9990\n"
+        );
+        // Full precision by default, with the noise of the binary representation
+        assert_eq!(
+            written(None, '.'),
+            format!(
+                "date,AAA\n2024-01-02,{}\n2024-01-03,99.999\n",
+                data[0].price
+            )
+        );
+        assert_ne!(data[0].price.to_string(), "123.45");
+        // Read back in the same format, the prices stay within half a unit of the last decimal
+        written(Some(4), ',');
+        let read = read_csv(path, ';', ',', true, None, None, None).unwrap();
+        for (read, original) in read.iter().zip(&data) {
+            assert!((read.price - original.price).abs() <= 0.5e-4);
+        }
+        std::fs::remove_file(path).unwrap();
+        assert!(CsvFormat::new(Some(2), ' ').is_err());
+    }
+
+    #[test]
+    fn every_stage_of_a_run_is_timed_within_the_total() {
+        use std::thread::sleep;
+        // The stages of a run, optimizing with a covariance inversion nested in it. Other tests
+        // may time the inversion concurrently, the top-level stages are the run's own
+        let pause = Duration::from_millis(5);
+        let run_start = Instant::now();
+        timed("fetch", || sleep(pause));
+        timed("statistics", || sleep(pause));
+        timed("optimization", || {
+            sleep(pause);
+            timed("covariance inversion", || sleep(pause));
+        });
+        timed("plotting", || sleep(pause));
+        record_timing("total", run_start.elapsed());
+
+        let recorded = timings();
+        let seconds = |stage: &str| {
+            recorded
+                .iter()
+                .find(|(name, _)| *name == stage)
+                .unwrap_or_else(|| panic!("no {} timing in {:?}", stage, recorded))
+                .1
+        };
+        let stages = ["fetch", "statistics", "optimization", "plotting"];
+        for stage in stages {
+            assert!(seconds(stage) >= pause.as_secs_f64(), "{:?}", recorded);
+        }
+        assert!(seconds("optimization") >= 2.0 * pause.as_secs_f64());
+        assert!(seconds("covariance inversion") >= pause.as_secs_f64());
+        let top_level: f64 = stages.iter().map(|stage| seconds(stage)).sum();
+        assert!(seconds("total") >= top_level, "{:?}", recorded);
+        // In the order the stages first ran
+        let order: Vec<&str> = recorded
+            .iter()
+            .map(|(name, _)| *name)
+            .filter(|name| stages.contains(name) || *name == "total")
+            .collect();
+        assert_eq!(
+            order,
+            ["fetch", "statistics", "optimization", "plotting", "total"]
+        );
+    }
+
+    #[test]
+    fn coarse_price_decimals_log_a_warning() {
+        let data = vec![Record {
+            date: "2024-01-02".to_string(),
+            asset: "PENNY".to_string(),
+            price: 0.05,
+        }];
+        // Each check logged as JSON lines to its own file, the way the log file of a run is
+        let logged = |format: CsvFormat, name: &str| {
+            let path =
+                std::env::temp_dir().join(format!("quars-{}-{}.log", name, std::process::id()));
+            let file = std::fs::File::create(&path).unwrap();
+            let subscriber = tracing_subscriber::fmt()
+                .json()
+                .with_writer(std::sync::Mutex::new(file))
+                .finish();
+            tracing::subscriber::with_default(subscriber, || format.check_precision(&data));
+            let log = std::fs::read_to_string(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            log
+        };
+
+        let coarse = logged(CsvFormat::new(Some(2), '.').unwrap(), "coarse");
+        let events: Vec<serde_json::Value> = coarse
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0]["level"], "WARN");
+        assert!(events[0]["fields"]["message"]
+            .as_str()
+            .unwrap()
+            .starts_with("price_decimals = 2 may shift the returns"));
+
+        assert_eq!(logged(CsvFormat::new(None, '.').unwrap(), "exact"), "");
+    }
+}
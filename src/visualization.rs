@@ -1,127 +1,762 @@
+use ndarray::Array2;
+use plotters::coord::ranged1d::{AsRangedCoord, ValueFormatter};
+use plotters::coord::Shift;
 use plotters::prelude::*;
-use std::error::Error;
+use std::fs;
+use std::path::PathBuf;
 
-use crate::optimization::{annual_to_daily_rate, OptimizationResults};
+use crate::error::QuarsError;
+use crate::config::VizSettings;
+use crate::optimization::{
+    cal_return, ActiveFrontierPoint, FrontierPoint, OptimizationResults, SpecialPortfolios,
+    MAX_LEVERAGE,
+};
+use crate::utils::write_csv_atomic;
 
 
-pub fn plot_efficient_frontier(results: &OptimizationResults, risk_free_rate: f64) -> Result<(), Box<dyn Error>> {
-    let root = BitMapBackend::new("efficient_frontier.png", (800, 600)).into_drawing_area();
+/// Scale the frontier plot displays its figures in, per period or annualized
+#[derive(Debug, Clone, Copy)]
+pub struct FrontierScale {
+    return_factor: f64,
+    risk_factor: f64,
+}
+
+impl FrontierScale {
+    /// Annualized returns scale with the number of periods per year and risk with its
+    /// square root, the same arithmetic scaling as `PortfolioStats::annualized_mean_returns`
+    pub fn new(annualized: bool, periods_per_year: f64) -> Self {
+        if annualized {
+            Self {
+                return_factor: periods_per_year,
+                risk_factor: periods_per_year.sqrt(),
+            }
+        } else {
+            Self {
+                return_factor: 1.0,
+                risk_factor: 1.0,
+            }
+        }
+    }
+
+    /// (risk, return) of a portfolio in this scale
+    pub fn point(&self, std: f64, ret: f64) -> (f64, f64) {
+        (std * self.risk_factor, ret * self.return_factor)
+    }
+}
+
+// Title of a chart, dated in a point-in-time run
+fn caption(title: &str, viz: &VizSettings) -> String {
+    match &viz.as_of_date {
+        Some(as_of) => format!("{} (as of {})", title, as_of),
+        None => title.to_string(),
+    }
+}
+
+// Writes the data behind a chart to a CSV named after its image, one header row and then the
+// rows, so that the chart can be redrawn elsewhere
+fn export_plot_data(
+    image_path: &str,
+    header: &[String],
+    rows: impl IntoIterator<Item = Vec<String>>,
+) -> Result<(), QuarsError> {
+    let path = std::path::Path::new(image_path).with_extension("csv");
+    write_csv_atomic(&path, |wtr| {
+        wtr.write_record(header)?;
+        for row in rows {
+            wtr.write_record(&row)?;
+        }
+        Ok(())
+    })?;
+    println!("Chart data saved to {}", path.display());
+    Ok(())
+}
+
+// Image rendered to a temporary file next to its target and renamed over it once complete,
+// so that a failed or interrupted run never leaves a partial chart behind
+struct PendingImage {
+    path: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl PendingImage {
+    fn new(path: &str) -> Self {
+        let path = PathBuf::from(path);
+        // The bitmap backend picks the image format from the extension, keep it last
+        let mut tmp_name = path.file_stem().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        if let Some(extension) = path.extension() {
+            tmp_name.push(".");
+            tmp_name.push(extension);
+        }
+        let tmp_path = path.with_file_name(tmp_name);
+        Self { path, tmp_path }
+    }
+
+    fn drawing_area(&self, size: (u32, u32)) -> DrawingArea<BitMapBackend<'_>, Shift> {
+        BitMapBackend::new(&self.tmp_path, size).into_drawing_area()
+    }
+
+    // Writes out the image drawn on `root` and moves it to the target
+    fn save(&self, root: DrawingArea<BitMapBackend<'_>, Shift>) -> Result<(), QuarsError> {
+        root.present()?;
+        drop(root);
+        fs::rename(&self.tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+impl Drop for PendingImage {
+    fn drop(&mut self) {
+        // Left over only when the image was not saved
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+/// Per-period rates of the capital allocation line, lending up to the tangency portfolio and
+/// borrowing beyond it
+#[derive(Clone, Copy, Debug)]
+pub struct CalRates {
+    pub lending: f64,
+    pub borrowing: f64,
+}
+
+/// Points of the capital allocation line in the displayed scale: the risk-free rate on that
+/// scale, the tangency portfolio, and the end of the line, the tangency portfolio again or
+/// the maximum leverage of the frontier when extended. The leveraged part is financed at the
+/// borrowing rate, the line kinks at the tangency portfolio when it exceeds the lending rate.
+pub fn cal_points(
+    results: &OptimizationResults,
+    rates: CalRates,
+    scale: FrontierScale,
+    extend: bool,
+) -> [(f64, f64); 3] {
+    // The frontier may have been truncated before reaching MAX_LEVERAGE
+    let leverage = match results.frontier.last() {
+        Some(last) if extend => (1.0 - last.risk_free_weight).clamp(1.0, MAX_LEVERAGE),
+        _ => 1.0,
+    };
+    let end_std = leverage * results.optimal_risky_std;
+    let end_return = cal_return(
+        1.0 - leverage,
+        results.optimal_risky_return,
+        rates.lending,
+        rates.borrowing,
+    );
+    [
+        scale.point(0.0, rates.lending),
+        scale.point(results.optimal_risky_std, results.optimal_risky_return),
+        scale.point(end_std, end_return),
+    ]
+}
+
+/// Plots the frontier and CAL, with the minimum-variance frontier of the risky assets and the
+/// special reference portfolios when given, to `image_path`. When a covariance is given, the diversification
+/// ratio along the minimum-variance frontier is drawn as a secondary series on the right axis;
+/// along the CAL it would be flat, every point holding the same risky portfolio.
+pub fn plot_efficient_frontier(
+    image_path: &str,
+    results: &OptimizationResults,
+    risky_frontier: &[FrontierPoint],
+    special: Option<&SpecialPortfolios>,
+    rates: CalRates,
+    covariance: Option<&Array2<f64>>,
+    periods_per_year: f64,
+    viz: &VizSettings,
+) -> Result<(), QuarsError> {
+    let image = PendingImage::new(image_path);
+    let root = image.drawing_area((800, 600));
     root.fill(&WHITE)?;
 
+    let scale = FrontierScale::new(viz.annualized, periods_per_year);
+    let cal = cal_points(results, rates, scale, viz.extend_cal);
+
     // Identify bounding box for chart
     // x-axis: standard deviation (0..some max)
     // y-axis: return (some range)
-    let max_std = results.frontier
+    let points: Vec<(f64, f64)> = results
+        .frontier
+        .iter()
+        .map(|pt| scale.point(pt.portfolio_std, pt.expected_return))
+        .collect();
+    let risky_points: Vec<(f64, f64)> = risky_frontier
+        .iter()
+        .map(|pt| scale.point(pt.portfolio_std, pt.expected_return))
+        .collect();
+    let special_points: Vec<(&str, (f64, f64))> = special
+        .map(|special| {
+            special
+                .named()
+                .into_iter()
+                .map(|(name, pt)| (name, scale.point(pt.portfolio_std, pt.expected_return)))
+                .collect()
+        })
+        .unwrap_or_default();
+    let max_std = points
         .iter()
-        .map(|pt| pt.portfolio_std)
-        .fold(0.0/0.0, f64::max);
-    let max_ret = results.frontier
+        .chain(&risky_points)
+        .chain(special_points.iter().map(|(_, point)| point))
+        .map(|(std, _)| *std)
+        .fold(cal[2].0, f64::max);
+    let max_ret = points
         .iter()
-        .map(|pt| pt.expected_return)
-        .fold(f64::MIN, f64::max);
+        .chain(&risky_points)
+        .chain(special_points.iter().map(|(_, point)| point))
+        .map(|(_, ret)| *ret)
+        .fold(cal[2].1, f64::max);
     // Padding
     let x_max = max_std * 1.1;
     let y_max = max_ret * 1.1;
 
-    let mut chart = ChartBuilder::on(&root)
-        .caption("Efficient Frontier", ("sans-serif", 30))
+    let chart = FrontierChart {
+        title: caption("Efficient Frontier", viz),
+        results,
+        risky_frontier,
+        covariance,
+        scale,
+        points: &points,
+        risky_points: &risky_points,
+        special_points: &special_points,
+        cal,
+        y_max,
+    };
+    if viz.log_risk_axis {
+        // A log axis cannot show zero risk, start it just below the least risky positive point
+        let min_std = points
+            .iter()
+            .map(|(std, _)| *std)
+            .filter(|std| *std > 0.0)
+            .fold(f64::INFINITY, f64::min);
+        if !min_std.is_finite() {
+            return Err(QuarsError::InsufficientData(
+                "No frontier point with positive risk to plot on a log axis.".to_string(),
+            ));
+        }
+        let x_min = min_std * 0.9;
+        draw_frontier(&root, &chart, (x_min..x_max).log_scale(), x_min)?;
+    } else {
+        draw_frontier(&root, &chart, 0f64..x_max, 0.0)?;
+    }
+
+    image.save(root)?;
+    println!("Efficient frontier saved to {}", image_path);
+
+    if viz.export_plot_data {
+        // Points in the displayed scale, the diversification ratio on the minimum-variance
+        // frontier only
+        let row = |series: &str, (std, ret): (f64, f64), pt: Option<&FrontierPoint>, dr: bool| {
+            vec![
+                series.to_string(),
+                std.to_string(),
+                ret.to_string(),
+                pt.map_or(String::new(), |pt| pt.risk_free_weight.to_string()),
+                pt.zip(covariance)
+                    .filter(|_| dr)
+                    .and_then(|(pt, cov)| pt.diversification_ratio(cov))
+                    .map_or(String::new(), |dr| dr.to_string()),
+            ]
+        };
+        let tangency = scale.point(results.optimal_risky_std, results.optimal_risky_return);
+        let rows = results
+            .frontier
+            .iter()
+            .zip(&points)
+            .map(|(pt, point)| row("frontier", *point, Some(pt), false))
+            .chain(
+                risky_frontier
+                    .iter()
+                    .zip(&risky_points)
+                    .map(|(pt, point)| row("min_variance_frontier", *point, Some(pt), true)),
+            )
+            .chain(std::iter::once(row("tangency", tangency, None, false)))
+            .chain(special_points.iter().map(|(name, point)| {
+                let series = name.to_lowercase().replace(' ', "_");
+                row(&series, *point, None, false)
+            }))
+            .chain(cal.iter().map(|point| row("cal", *point, None, false)))
+            .collect::<Vec<_>>();
+        export_plot_data(
+            image_path,
+            &[
+                "series",
+                "risk",
+                "expected_return",
+                "risk_free_weight",
+                "diversification_ratio",
+            ]
+            .map(String::from),
+            rows,
+        )?;
+    }
+    Ok(())
+}
+
+struct FrontierChart<'a> {
+    title: String,
+    results: &'a OptimizationResults,
+    risky_frontier: &'a [FrontierPoint],
+    covariance: Option<&'a Array2<f64>>,
+    scale: FrontierScale,
+    // Frontier (risk, return) in the displayed scale
+    points: &'a [(f64, f64)],
+    // Minimum-variance frontier of the risky assets, in the displayed scale
+    risky_points: &'a [(f64, f64)],
+    // Named reference portfolios, in the displayed scale
+    special_points: &'a [(&'a str, (f64, f64))],
+    // Risk-free point, tangency and end of the CAL, in the displayed scale
+    cal: [(f64, f64); 3],
+    y_max: f64,
+}
+
+// Draws the frontier chart on either a linear or a logarithmic risk axis
+fn draw_frontier<X>(
+    root: &DrawingArea<BitMapBackend, Shift>,
+    frontier: &FrontierChart,
+    x_spec: X,
+    x_min: f64,
+) -> Result<(), QuarsError>
+where
+    X: AsRangedCoord<Value = f64> + Clone,
+    X::CoordDescType: ValueFormatter<f64>,
+{
+    let scale = frontier.scale;
+    // Diversification ratio along the minimum-variance frontier, drawn against the right axis
+    let div_ratios: Vec<(f64, f64)> = frontier
+        .covariance
+        .map(|cov| {
+            frontier
+                .risky_frontier
+                .iter()
+                .zip(frontier.risky_points)
+                .filter(|(_, (std, _))| *std >= x_min)
+                .filter_map(|(pt, (std, _))| pt.diversification_ratio(cov).map(|dr| (*std, dr)))
+                .collect()
+        })
+        .unwrap_or_default();
+    let max_dr = div_ratios.iter().map(|(_, dr)| *dr).fold(1.0, f64::max);
+
+    // The secondary coordinate has to be attached before the mesh is drawn, otherwise the
+    // primary labels are drawn on the right axis as well
+    let mut chart = ChartBuilder::on(root)
+        .caption(&frontier.title, ("sans-serif", 30))
         .margin(20)
         .x_label_area_size(40)
         .y_label_area_size(40)
-        .build_cartesian_2d(0f64..x_max, 0f64..y_max)?;
+        .right_y_label_area_size(if frontier.covariance.is_some() { 40 } else { 0 })
+        .build_cartesian_2d(x_spec.clone(), 0f64..frontier.y_max)?
+        .set_secondary_coord(x_spec, 0f64..max_dr * 1.1);
 
-    chart.configure_mesh()
-        .x_desc("Standard Deviation (Risk)")
-        .y_desc("Expected Return")
+    let (x_desc, y_desc) = if scale.return_factor != 1.0 {
+        (
+            "Annualized Standard Deviation (Risk)",
+            "Annualized Expected Return",
+        )
+    } else {
+        ("Standard Deviation (Risk)", "Expected Return")
+    };
+    chart
+        .configure_mesh()
+        .x_desc(x_desc)
+        .y_desc(y_desc)
+        .x_label_formatter(&format_tick)
         .draw()?;
 
     chart.draw_series(
-        results.frontier.iter().map(|pt| {
-            Circle::new((pt.portfolio_std, pt.expected_return), 3, &BLUE)
-        })
+        frontier
+            .points
+            .iter()
+            .filter(|(std, _)| *std >= x_min)
+            .map(|(std, ret)| Circle::new((*std, *ret), 3, BLUE)),
     )?;
 
-    let tang_x = results.optimal_risky_std;
-    let tang_y = results.optimal_risky_return;
-    chart.draw_series(std::iter::once(Circle::new((tang_x, tang_y), 5, RED)))?
+    if !frontier.risky_points.is_empty() {
+        let visible: Vec<(f64, f64)> = frontier
+            .risky_points
+            .iter()
+            .copied()
+            .filter(|(std, _)| *std >= x_min)
+            .collect();
+        chart.draw_series(LineSeries::new(visible.clone(), MAGENTA))?;
+        chart
+            .draw_series(
+                visible
+                    .into_iter()
+                    .map(|point| Circle::new(point, 2, MAGENTA.filled())),
+            )?
+            .label("Minimum-Variance Frontier")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], MAGENTA));
+    }
+
+    let tangency = scale.point(
+        frontier.results.optimal_risky_std,
+        frontier.results.optimal_risky_return,
+    );
+    chart
+        .draw_series(std::iter::once(Circle::new(tangency, 5, RED)))?
         .label("Tangency Portfolio")
         .legend(|(x, y)| Circle::new((x, y), 5, RED));
 
-    // Plot capital allocation line from risk-free (0, r_f) to tangency
-    let daily_risk_free = annual_to_daily_rate(risk_free_rate);
-    let cal_points = vec![
-        (0.0, daily_risk_free),
-        (tang_x, tang_y)
-    ];
-    chart.draw_series(LineSeries::new(cal_points, BLACK))?
+    for (idx, (name, point)) in frontier.special_points.iter().enumerate() {
+        if point.0 < x_min {
+            continue;
+        }
+        let color = Palette99::pick(idx + 4).to_rgba();
+        let marker = TriangleMarker::new(*point, 7, color.filled());
+        chart
+            .draw_series(std::iter::once(marker))?
+            .label(*name)
+            .legend(move |(x, y)| TriangleMarker::new((x, y), 7, color.filled()));
+    }
+
+    // Plot capital allocation line from risk-free (0, r_f), starting at the left edge of
+    // the axis when it does not include zero risk. Past the tangency portfolio the line follows
+    // the borrowing slope.
+    let [(_, anchor), (tangency_x, tangency_y), (end_x, end_y)] = frontier.cal;
+    let lending_slope = (tangency_y - anchor) / tangency_x;
+    let borrowing_slope = (end_y - tangency_y) / (end_x - tangency_x);
+    // Sampled rather than drawn as a segment, the line is curved on a log axis
+    let cal_points: Vec<(f64, f64)> = (0..=50)
+        .map(|i| {
+            let x = x_min + (end_x - x_min) * i as f64 / 50.0;
+            if x <= tangency_x {
+                (x, anchor + lending_slope * x)
+            } else {
+                (x, tangency_y + borrowing_slope * (x - tangency_x))
+            }
+        })
+        .collect();
+    chart
+        .draw_series(LineSeries::new(cal_points, BLACK))?
         .label("Capital Allocation Line")
-        .legend(|(x, y)| PathElement::new(vec![(x, y), (x+10, y)], BLACK));
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], BLACK));
 
+    if frontier.covariance.is_some() {
+        chart
+            .configure_secondary_axes()
+            .y_desc("Diversification Ratio")
+            .draw()?;
+        chart
+            .draw_secondary_series(LineSeries::new(div_ratios, GREEN))?
+            .label("Diversification Ratio")
+            .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], GREEN));
+    }
     chart.configure_series_labels().draw()?;
-
-    root.present()?;
-    println!("Efficient frontier saved to efficient_frontier.png");
     Ok(())
 }
 
+// Method name, frontier points and tangency portfolio in the chart scale
+type MethodSeries<'a> = (&'a str, Vec<(f64, f64)>, (f64, f64));
 
-pub fn plot_portfolio(
-    asset_labels: &[String],
-    weights: &[f64],
-) -> Result<(), Box<dyn std::error::Error>> {
-    use plotters::prelude::*;
-
-    let root = BitMapBackend::new("portfolio.png", (640, 480)).into_drawing_area();
+/// Overlays the frontiers of several optimization methods, each in its own color with its
+/// tangency portfolio marked, on a linear risk axis
+pub fn plot_method_comparison(
+    comparison: &[(&str, OptimizationResults)],
+    periods_per_year: f64,
+    viz: &VizSettings,
+) -> Result<(), QuarsError> {
+    let image_path = "method_comparison.png";
+    let image = PendingImage::new(image_path);
+    let root = image.drawing_area((800, 600));
     root.fill(&WHITE)?;
 
-    // Determine max weight for the y-axis
-    let max_weight = weights.iter().cloned().fold(f64::NAN, f64::max).max(1.0); // ensure minimum range
+    let scale = FrontierScale::new(viz.annualized, periods_per_year);
+    let series: Vec<MethodSeries> = comparison
+        .iter()
+        .map(|(method, results)| {
+            let points = results
+                .frontier
+                .iter()
+                .map(|pt| scale.point(pt.portfolio_std, pt.expected_return))
+                .collect();
+            let tangency = scale.point(results.optimal_risky_std, results.optimal_risky_return);
+            (*method, points, tangency)
+        })
+        .collect();
+    let all_points = || {
+        series
+            .iter()
+            .flat_map(|(_, points, tangency)| points.iter().chain(std::iter::once(tangency)))
+    };
+    let x_max = all_points().map(|(std, _)| *std).fold(0.0, f64::max) * 1.1;
+    let y_min = all_points().map(|(_, ret)| *ret).fold(0.0, f64::min) * 1.1;
+    let y_max = all_points().map(|(_, ret)| *ret).fold(0.0, f64::max) * 1.1;
+    if x_max <= 0.0 {
+        return Err(QuarsError::InsufficientData(
+            "No frontier point with positive risk to plot.".to_string(),
+        ));
+    }
 
     let mut chart = ChartBuilder::on(&root)
-        .caption("Portfolio Weights", ("sans-serif", 30))
+        .caption(caption("Method Comparison", viz), ("sans-serif", 30))
         .margin(20)
         .x_label_area_size(40)
         .y_label_area_size(40)
-        .build_cartesian_2d(0..weights.len(), -max_weight * 1.2..max_weight * 1.2)?;
+        .build_cartesian_2d(0f64..x_max, y_min..y_max)?;
+    let (x_desc, y_desc) = if scale.return_factor != 1.0 {
+        (
+            "Annualized Standard Deviation (Risk)",
+            "Annualized Expected Return",
+        )
+    } else {
+        ("Standard Deviation (Risk)", "Expected Return")
+    };
+    chart
+        .configure_mesh()
+        .x_desc(x_desc)
+        .y_desc(y_desc)
+        .x_label_formatter(&format_tick)
+        .draw()?;
+    for (idx, (method, points, tangency)) in series.iter().enumerate() {
+        let color = Palette99::pick(idx).to_rgba();
+        chart.draw_series(LineSeries::new(points.clone(), color))?;
+        chart
+            .draw_series(std::iter::once(Circle::new(*tangency, 5, color.filled())))?
+            .label(*method)
+            .legend(move |(x, y)| Circle::new((x, y), 5, color.filled()));
+    }
+    chart.configure_series_labels().border_style(BLACK).draw()?;
+
+    image.save(root)?;
+    println!("Method comparison saved to {}", image_path);
+
+    if viz.export_plot_data {
+        let rows = series
+            .iter()
+            .flat_map(|(method, points, tangency)| {
+                points
+                    .iter()
+                    .map(move |point| ("frontier", point))
+                    .chain(std::iter::once(("tangency", tangency)))
+                    .map(move |(kind, (std, ret))| {
+                        vec![
+                            method.to_string(),
+                            kind.to_string(),
+                            std.to_string(),
+                            ret.to_string(),
+                        ]
+                    })
+            })
+            .collect::<Vec<_>>();
+        export_plot_data(
+            image_path,
+            &["method", "series", "risk", "expected_return"].map(String::from),
+            rows,
+        )?;
+    }
+    Ok(())
+}
+/// Plots the active frontier, active return against tracking error, with the benchmark at
+/// the origin
+pub fn plot_active_frontier(
+    points: &[ActiveFrontierPoint],
+    periods_per_year: f64,
+    viz: &VizSettings,
+) -> Result<(), QuarsError> {
+    let image_path = "active_frontier.png";
+    let image = PendingImage::new(image_path);
+    let root = image.drawing_area((800, 600));
+    root.fill(&WHITE)?;
+
+    let scale = FrontierScale::new(viz.annualized, periods_per_year);
+    let curve: Vec<(f64, f64)> = points
+        .iter()
+        .map(|pt| scale.point(pt.tracking_error, pt.active_return))
+        .collect();
+    let x_max = curve.iter().map(|(te, _)| *te).fold(0.0, f64::max) * 1.1;
+    let y_min = curve.iter().map(|(_, ret)| *ret).fold(0.0, f64::min) * 1.1;
+    let y_max = curve.iter().map(|(_, ret)| *ret).fold(0.0, f64::max) * 1.1;
+    if x_max <= 0.0 || y_max <= y_min {
+        return Err(QuarsError::InsufficientData(
+            "No active frontier point with positive tracking error to plot.".to_string(),
+        ));
+    }
 
+    let mut chart = ChartBuilder::on(&root)
+        .caption(caption("Active Frontier", viz), ("sans-serif", 30))
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(40)
+        .build_cartesian_2d(0f64..x_max, y_min..y_max)?;
+    let (x_desc, y_desc) = if scale.return_factor != 1.0 {
+        ("Annualized Tracking Error", "Annualized Active Return")
+    } else {
+        ("Tracking Error", "Active Return")
+    };
     chart
         .configure_mesh()
-        .disable_mesh() // optional, for aesthetics
-        .x_labels(weights.len())
-        .x_label_formatter(&|x| {
-            let idx = *x;
-            if idx < asset_labels.len() {
-                asset_labels[idx].clone()
-            } else {
-                "".to_string() // Out-of-range check
-            }
-        })
-        .x_desc("Assets")
-        .y_desc("Weight")
+        .x_desc(x_desc)
+        .y_desc(y_desc)
+        .x_label_formatter(&format_tick)
         .draw()?;
+    chart
+        .draw_series(LineSeries::new(curve.clone(), BLUE))?
+        .label("Active Frontier")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], BLUE));
+    chart.draw_series(curve.iter().map(|point| Circle::new(*point, 3, BLUE)))?;
+    if let Some(first) = curve.first() {
+        chart
+            .draw_series(std::iter::once(Circle::new(*first, 5, RED.filled())))?
+            .label("Minimum Tracking Error")
+            .legend(|(x, y)| Circle::new((x, y), 5, RED.filled()));
+    }
+    let benchmark = TriangleMarker::new((0.0, 0.0), 7, BLACK.filled());
+    chart
+        .draw_series(std::iter::once(benchmark))?
+        .label("Benchmark")
+        .legend(|(x, y)| TriangleMarker::new((x, y), 7, BLACK.filled()));
+    chart.configure_series_labels().border_style(BLACK).draw()?;
 
-    // Draw a bar from (i, 0.0) to (i+1, weight)
-    chart.draw_series(
-        weights
+    image.save(root)?;
+    println!("Active frontier saved to {}", image_path);
+
+    if viz.export_plot_data {
+        let rows = curve
             .iter()
-            .enumerate()
-            .map(|(i, &w)| Rectangle::new([(i, 0.0), (i + 1, w)], BLUE.filled())),
-    )?;
+            .map(|(te, ret)| vec![te.to_string(), ret.to_string()])
+            .collect::<Vec<_>>();
+        export_plot_data(
+            image_path,
+            &["tracking_error", "active_return"].map(String::from),
+            rows,
+        )?;
+    }
+    Ok(())
+}
+
+// Drops the floating point noise log scaled key points carry, e.g. 0.006000000001
+fn format_tick(value: &f64) -> String {
+    let label = format!("{:.4}", value);
+    label
+        .trim_end_matches('0')
+        .trim_end_matches('.')
+        .to_string()
+}
+
+
+/// Bar of the portfolio weights chart
+#[derive(Debug, Clone, PartialEq)]
+pub struct WeightBar {
+    pub asset: String,
+    pub weight: f64,
+    // Long and short weights are told apart by color
+    pub color: RGBColor,
+}
+
+/// Bars of the portfolio weights chart, in the asset order or sorted by weight, largest first
+pub fn weight_bars(asset_labels: &[String], weights: &[f64], sort: bool) -> Vec<WeightBar> {
+    let mut bars: Vec<WeightBar> = asset_labels
+        .iter()
+        .zip(weights)
+        .map(|(asset, &weight)| WeightBar {
+            asset: asset.clone(),
+            weight,
+            color: if weight < 0.0 { RED } else { BLUE },
+        })
+        .collect();
+    if sort {
+        bars.sort_by(|a, b| b.weight.total_cmp(&a.weight));
+    }
+    bars
+}
+
+pub fn plot_portfolio(
+    asset_labels: &[String],
+    weights: &[f64],
+    viz: &VizSettings,
+) -> Result<(), QuarsError> {
+    use plotters::prelude::*;
+
+    let image = PendingImage::new("portfolio.png");
+    let root = image.drawing_area((640, 480));
+    root.fill(&WHITE)?;
+
+    let bars = weight_bars(asset_labels, weights, viz.sort_weights);
+    // Determine max weight for the weight axis, short weights included
+    let max_weight = bars
+        .iter()
+        .map(|bar| bar.weight.abs())
+        .fold(f64::NAN, f64::max)
+        .max(1.0); // ensure minimum range
+    let label = |idx: &usize| {
+        bars.get(*idx)
+            .map_or_else(String::new, |bar| bar.asset.clone())
+    };
+
+    if viz.horizontal_weights {
+        // The first bar on top, the weight axis wide enough for long tickers
+        let n = bars.len();
+        let mut chart = ChartBuilder::on(&root)
+            .caption(caption("Portfolio Weights", viz), ("sans-serif", 30))
+            .margin(20)
+            .x_label_area_size(40)
+            .y_label_area_size(80)
+            .build_cartesian_2d(-max_weight * 1.2..max_weight * 1.2, 0..n)?;
+
+        chart
+            .configure_mesh()
+            .disable_mesh()
+            .y_labels(n)
+            .y_label_formatter(&|y| match n.checked_sub(y + 1) {
+                Some(idx) => label(&idx),
+                None => String::new(),
+            })
+            .x_desc("Weight")
+            .y_desc("Assets")
+            .draw()?;
+
+        // Draw a bar from (0.0, n - 1 - i) to (weight, n - i)
+        chart.draw_series(bars.iter().enumerate().map(|(i, bar)| {
+            Rectangle::new([(0.0, n - 1 - i), (bar.weight, n - i)], bar.color.filled())
+        }))?;
+    } else {
+        let mut chart = ChartBuilder::on(&root)
+            .caption(caption("Portfolio Weights", viz), ("sans-serif", 30))
+            .margin(20)
+            .x_label_area_size(40)
+            .y_label_area_size(40)
+            .build_cartesian_2d(0..bars.len(), -max_weight * 1.2..max_weight * 1.2)?;
+
+        chart
+            .configure_mesh()
+            .disable_mesh() // optional, for aesthetics
+            .x_labels(bars.len())
+            .x_label_formatter(&label)
+            .x_desc("Assets")
+            .y_desc("Weight")
+            .draw()?;
+
+        // Draw a bar from (i, 0.0) to (i+1, weight)
+        chart.draw_series(
+            bars.iter().enumerate().map(|(i, bar)| {
+                Rectangle::new([(i, 0.0), (i + 1, bar.weight)], bar.color.filled())
+            }),
+        )?;
+    }
 
-    root.present()?;
+    image.save(root)?;
     println!("Portfolio chart saved to portfolio.png");
+
+    if viz.export_plot_data {
+        export_plot_data(
+            "portfolio.png",
+            &["asset", "weight"].map(String::from),
+            bars.iter()
+                .map(|bar| vec![bar.asset.clone(), bar.weight.to_string()]),
+        )?;
+    }
     Ok(())
 }
 
 
 pub fn plot_return_distribution(
+    output_path: &str,
     returns: &Vec<f64>,
     var: f64,
     cvar: f64,
-) -> Result<(), Box<dyn Error>> {
-    // Define output file and create drawing area.
-    let output_path = "portfolio_distribution.png";
-    let root = BitMapBackend::new(output_path, (800, 600)).into_drawing_area();
+    viz: &VizSettings,
+) -> Result<(), QuarsError> {
+    // Create drawing area.
+    let image = PendingImage::new(output_path);
+    let root = image.drawing_area((800, 600));
     root.fill(&WHITE)?;
 
     // Calculate min and max returns for the x-axis
@@ -132,6 +767,47 @@ pub fn plot_return_distribution(
     let num_bins = 50;
     let bin_width = (max_return - min_return) / num_bins as f64;
 
+    let bins = histogram_bins(returns, min_return, bin_width, num_bins);
+    let max_count = bins.iter().cloned().max().unwrap_or(1) as f64;
+
+    let histogram = Histogram {
+        title: caption("Portfolio Returns Distribution", viz),
+        bins: &bins,
+        min_return,
+        max_return,
+        bin_width,
+        var,
+        cvar,
+    };
+    if viz.log_frequency_axis {
+        // Empty bins have no place on a log axis, bars start just below a count of one
+        draw_histogram(&root, &histogram, (0.5..max_count * 2.0).log_scale(), 0.5)?;
+    } else {
+        draw_histogram(&root, &histogram, 0f64..max_count, 0.0)?;
+    }
+
+    image.save(root)?;
+    println!("Portfolio returns distribution saved to {}", output_path);
+
+    if viz.export_plot_data {
+        export_plot_data(
+            output_path,
+            &["bin_start", "bin_end", "count"].map(String::from),
+            bins.iter().enumerate().map(|(i, count)| {
+                let start = min_return + i as f64 * bin_width;
+                vec![
+                    start.to_string(),
+                    (start + bin_width).to_string(),
+                    count.to_string(),
+                ]
+            }),
+        )?;
+    }
+    Ok(())
+}
+
+// Counts the returns falling in each of `num_bins` bins of `bin_width` starting at `min_return`
+fn histogram_bins(returns: &[f64], min_return: f64, bin_width: f64, num_bins: usize) -> Vec<usize> {
     let mut bins = vec![0; num_bins];
     for r in returns {
         let mut bin = ((*r - min_return) / bin_width) as usize;
@@ -140,51 +816,511 @@ pub fn plot_return_distribution(
         }
         bins[bin] += 1;
     }
-    let max_count = bins.iter().cloned().max().unwrap_or(1);
+    bins
+}
+
+/// Overlays the return histograms of several portfolios, each given as (name, returns, VaR,
+/// CVaR), with their own VaR (solid) and CVaR (dashed) markers. All series share the same
+/// bins, spanning the returns of every series.
+pub fn plot_return_distributions(
+    output_path: &str,
+    series: &[(String, Vec<f64>, f64, f64)],
+    viz: &VizSettings,
+) -> Result<(), QuarsError> {
+    let image = PendingImage::new(output_path);
+    let root = image.drawing_area((800, 600));
+    root.fill(&WHITE)?;
+
+    // Shared x-axis over all the series
+    let all_returns = || {
+        series
+            .iter()
+            .flat_map(|(_, returns, _, _)| returns.iter().cloned())
+    };
+    let min_return = all_returns().fold(f64::INFINITY, f64::min);
+    let max_return = all_returns().fold(f64::NEG_INFINITY, f64::max);
+    if min_return >= max_return || min_return.is_nan() || max_return.is_nan() {
+        return Err(QuarsError::InsufficientData(
+            "Need returns spanning a non-empty range to plot distributions.".to_string(),
+        ));
+    }
+
+    let num_bins = 50;
+    let bin_width = (max_return - min_return) / num_bins as f64;
+    let all_bins: Vec<Vec<usize>> = series
+        .iter()
+        .map(|(_, returns, _, _)| histogram_bins(returns, min_return, bin_width, num_bins))
+        .collect();
+    let max_count = all_bins.iter().flatten().cloned().max().unwrap_or(1) as f64 * 1.1;
 
     let mut chart = ChartBuilder::on(&root)
-        .caption("Portfolio Returns Distribution", ("sans-serif", 30))
+        .caption(
+            caption("Portfolio Returns Distributions", viz),
+            ("sans-serif", 30),
+        )
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(40)
+        .build_cartesian_2d(min_return..max_return, 0f64..max_count)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Return")
+        .y_desc("Frequency")
+        .draw()?;
+
+    for (idx, ((name, _, var, cvar), bins)) in series.iter().zip(&all_bins).enumerate() {
+        let color = Palette99::pick(idx).to_rgba();
+        // Semi-transparent so the overlapping histograms stay readable
+        chart
+            .draw_series(bins.iter().enumerate().map(|(i, count)| {
+                let x0 = min_return + i as f64 * bin_width;
+                Rectangle::new(
+                    [(x0, 0.0), (x0 + bin_width, *count as f64)],
+                    color.mix(0.3).filled(),
+                )
+            }))?
+            .label(name.as_str())
+            .legend(move |(x, y)| {
+                Rectangle::new([(x, y - 5), (x + 10, y + 5)], color.mix(0.3).filled())
+            });
+
+        chart
+            .draw_series(std::iter::once(PathElement::new(
+                vec![(*var, 0.0), (*var, max_count)],
+                color.stroke_width(2),
+            )))?
+            .label(format!("{} VaR(95%): {:.2}%", name, var * 100.0))
+            .legend(move |(x, y)| {
+                PathElement::new(vec![(x, y), (x + 20, y)], color.stroke_width(2))
+            });
+
+        chart
+            .draw_series(DashedLineSeries::new(
+                vec![(*cvar, 0.0), (*cvar, max_count)],
+                5,
+                5,
+                color.stroke_width(2),
+            ))?
+            .label(format!("{} CVaR(95%): {:.2}%", name, cvar * 100.0))
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+    }
+
+    chart.configure_series_labels().border_style(BLACK).draw()?;
+
+    image.save(root)?;
+    println!("Portfolio returns distributions saved to {}", output_path);
+
+    if viz.export_plot_data {
+        // One count column per series, over the shared bins
+        let header = ["bin_start".to_string(), "bin_end".to_string()]
+            .into_iter()
+            .chain(series.iter().map(|(name, _, _, _)| name.clone()))
+            .collect::<Vec<_>>();
+        export_plot_data(
+            output_path,
+            &header,
+            (0..num_bins).map(|i| {
+                let start = min_return + i as f64 * bin_width;
+                [start.to_string(), (start + bin_width).to_string()]
+                    .into_iter()
+                    .chain(all_bins.iter().map(|bins| bins[i].to_string()))
+                    .collect()
+            }),
+        )?;
+    }
+    Ok(())
+}
+
+struct Histogram<'a> {
+    title: String,
+    bins: &'a [usize],
+    min_return: f64,
+    max_return: f64,
+    bin_width: f64,
+    var: f64,
+    cvar: f64,
+}
+
+// Draws the return histogram on either a linear or a logarithmic frequency axis
+fn draw_histogram<Y>(
+    root: &DrawingArea<BitMapBackend, Shift>,
+    histogram: &Histogram,
+    y_spec: Y,
+    y_base: f64,
+) -> Result<(), QuarsError>
+where
+    Y: AsRangedCoord<Value = f64>,
+    Y::CoordDescType: ValueFormatter<f64>,
+{
+    let mut chart = ChartBuilder::on(root)
+        .caption(&histogram.title, ("sans-serif", 30))
         .margin(20)
         .x_label_area_size(40)
         .y_label_area_size(40)
-        .build_cartesian_2d(min_return..max_return, 0..max_count)?;
+        .build_cartesian_2d(histogram.min_return..histogram.max_return, y_spec)?;
 
     chart
         .configure_mesh()
         .x_desc("Return")
         .y_desc("Frequency")
+        .y_label_formatter(&|y| format!("{:.0}", y))
         .draw()?;
 
-    for (i, count) in bins.iter().enumerate() {
-        let x0 = min_return + i as f64 * bin_width;
-        let x1 = x0 + bin_width;
+    for (i, count) in histogram.bins.iter().enumerate() {
+        let count = *count as f64;
+        if count <= y_base {
+            continue;
+        }
+        let x0 = histogram.min_return + i as f64 * histogram.bin_width;
+        let x1 = x0 + histogram.bin_width;
         chart.draw_series(std::iter::once(Rectangle::new(
-            [(x0, 0), (x1, *count)],
+            [(x0, y_base), (x1, count)],
             BLUE.filled(),
         )))?;
     }
+    let y_top = chart.y_range().end;
 
     // VaR line
     chart
         .draw_series(std::iter::once(PathElement::new(
-            vec![(var, 0), (var, max_count)],
+            vec![(histogram.var, y_base), (histogram.var, y_top)],
             RED,
         )))?
-        .label(format!("VaR(95%): {:.2}%", var * 100.0))
+        .label(format!("VaR(95%): {:.2}%", histogram.var * 100.0))
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
 
     // CVaR line
     chart
         .draw_series(std::iter::once(PathElement::new(
-            vec![(cvar, 0), (cvar, max_count)],
+            vec![(histogram.cvar, y_base), (histogram.cvar, y_top)],
             BLACK,
         )))?
-        .label(format!("CVaR(95%): {:.2}%", cvar * 100.0))
+        .label(format!("CVaR(95%): {:.2}%", histogram.cvar * 100.0))
         .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLACK));
 
     chart.configure_series_labels().border_style(BLACK).draw()?;
+    Ok(())
+}
 
-    root.present()?;
-    println!("Portfolio returns distribution saved to {}", output_path);
+
+/// Overlays the underwater (drawdown) curves of the portfolio and a benchmark
+pub fn plot_drawdown_comparison(
+    portfolio_drawdown: &[f64],
+    benchmark_drawdown: &[f64],
+    benchmark_label: &str,
+    viz: &VizSettings,
+) -> Result<(), QuarsError> {
+    let output_path = "drawdown_comparison.png";
+    let image = PendingImage::new(output_path);
+    let root = image.drawing_area((800, 600));
+    root.fill(&WHITE)?;
+
+    let n = portfolio_drawdown.len().max(benchmark_drawdown.len());
+    let min_dd = portfolio_drawdown
+        .iter()
+        .chain(benchmark_drawdown.iter())
+        .cloned()
+        .fold(0.0, f64::min);
+    // Keep a visible range for series that never draw down
+    let y_min = if min_dd < 0.0 { min_dd * 1.1 } else { -0.01 };
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption(caption("Drawdown Comparison", viz), ("sans-serif", 30))
+        .margin(20)
+        .x_label_area_size(40)
+        .y_label_area_size(60)
+        .build_cartesian_2d(0..n, y_min..0.0)?;
+
+    chart
+        .configure_mesh()
+        .x_desc("Period")
+        .y_desc("Drawdown")
+        .y_label_formatter(&|y| format!("{:.0}%", y * 100.0))
+        .draw()?;
+
+    chart
+        .draw_series(LineSeries::new(
+            portfolio_drawdown.iter().cloned().enumerate(),
+            BLUE,
+        ))?
+        .label("Portfolio")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], BLUE));
+
+    chart
+        .draw_series(LineSeries::new(
+            benchmark_drawdown.iter().cloned().enumerate(),
+            RED,
+        ))?
+        .label(benchmark_label.to_string())
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], RED));
+
+    chart
+        .configure_series_labels()
+        .position(SeriesLabelPosition::LowerLeft)
+        .border_style(BLACK)
+        .draw()?;
+
+    image.save(root)?;
+    println!("Drawdown comparison saved to {}", output_path);
+
+    if viz.export_plot_data {
+        let value = |series: &[f64], t: usize| series.get(t).map_or(String::new(), f64::to_string);
+        export_plot_data(
+            output_path,
+            &["period", "portfolio", benchmark_label].map(String::from),
+            (0..n).map(|t| {
+                vec![
+                    t.to_string(),
+                    value(portfolio_drawdown, t),
+                    value(benchmark_drawdown, t),
+                ]
+            }),
+        )?;
+    }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sorted_bars_run_from_the_largest_weight_with_shorts_in_their_own_color() {
+        let assets = ["AAA", "BBB", "CCC", "DDD"].map(String::from);
+        let weights = [0.2, -0.3, 0.7, 0.4];
+        let bars = weight_bars(&assets, &weights, true);
+        let order: Vec<&str> = bars.iter().map(|bar| bar.asset.as_str()).collect();
+        assert_eq!(order, ["CCC", "DDD", "AAA", "BBB"]);
+        assert!(bars.windows(2).all(|pair| pair[0].weight >= pair[1].weight));
+        let short = &bars[3];
+        assert_eq!(short.weight, -0.3);
+        assert!(bars[..3].iter().all(|bar| bar.color != short.color));
+
+        let unsorted = weight_bars(&assets, &weights, false);
+        let order: Vec<&str> = unsorted.iter().map(|bar| bar.asset.as_str()).collect();
+        assert_eq!(order, ["AAA", "BBB", "CCC", "DDD"]);
+    }
+
+    #[test]
+    fn a_cancelled_run_leaves_no_partial_output_files() {
+        let dir = std::env::temp_dir().join(format!("quars-partial-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files = || {
+            let mut names: Vec<String> = fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+                .collect();
+            names.sort();
+            names
+        };
+
+        let image_path = dir.join("chart.png");
+        let render = |cancelled: bool| -> Result<(), QuarsError> {
+            let image = PendingImage::new(image_path.to_str().unwrap());
+            let root = image.drawing_area((64, 48));
+            root.fill(&WHITE)?;
+            if cancelled {
+                return Err(crate::utils::Cancelled.into());
+            }
+            image.save(root)
+        };
+        assert!(matches!(render(true), Err(QuarsError::Cancelled(_))));
+        assert!(files().is_empty());
+        render(false).unwrap();
+        assert_eq!(files(), ["chart.png"]);
+        assert!(fs::metadata(&image_path).unwrap().len() > 0);
+
+        // A CSV stopped halfway is not written either
+        let written = write_csv_atomic(dir.join("chart.csv"), |wtr| {
+            wtr.write_record(["risk", "return"])?;
+            Err(std::io::Error::new(std::io::ErrorKind::Interrupted, "cancelled").into())
+        });
+        assert!(written.is_err());
+        assert_eq!(files(), ["chart.png"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Renders a chart into its own temporary directory, returning the size of the image
+    fn rendered_size(name: &str, render: impl FnOnce(&str) -> Result<(), QuarsError>) -> u64 {
+        let dir = std::env::temp_dir().join(format!("quars-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.png", name));
+        render(path.to_str().unwrap()).unwrap();
+        let size = fs::metadata(&path).unwrap().len();
+        fs::remove_dir_all(&dir).unwrap();
+        size
+    }
+
+    fn returns(seed: u64, scale: f64) -> Vec<f64> {
+        use rand::{Rng, SeedableRng};
+        let mut rng = rand::rngs::StdRng::seed_from_u64(seed);
+        (0..250).map(|_| rng.gen_range(-1.0..1.0) * scale).collect()
+    }
+
+    // Two assets and their optimized frontier, at a risk-free rate of 0.01% per period
+    fn two_asset_results() -> (crate::portfolio::PortfolioStats, OptimizationResults) {
+        let stats = crate::portfolio::PortfolioStats {
+            assets: ["AAA", "BBB"].map(String::from).to_vec(),
+            mean_returns: ndarray::array![0.001, 0.002],
+            covariance: ndarray::array![[0.0004, 0.0001], [0.0001, 0.0009]],
+            returns_matrix: Array2::zeros((2, 0)),
+            dates: Vec::new(),
+        };
+        let results = crate::optimization::optimize_cara(&stats, 4.0, 0.0001, 10, false).unwrap();
+        (stats, results)
+    }
+
+    #[test]
+    fn the_log_axis_charts_render() {
+        let (stats, results) = two_asset_results();
+        let risky_frontier = crate::optimization::min_variance_frontier(
+            &stats,
+            0.0001,
+            10,
+            crate::optimization::FrontierSpacing::Uniform,
+        )
+        .unwrap();
+        let viz = VizSettings {
+            log_risk_axis: true,
+            log_frequency_axis: true,
+            ..VizSettings::default()
+        };
+        let rates = CalRates {
+            lending: 0.0001,
+            borrowing: 0.0001,
+        };
+        let frontier = rendered_size("efficient_frontier", |path| {
+            plot_efficient_frontier(
+                path,
+                &results,
+                &risky_frontier,
+                None,
+                rates,
+                Some(&stats.covariance),
+                252.0,
+                &viz,
+            )
+        });
+        assert!(frontier > 0);
+        let distribution = rendered_size("portfolio_distribution", |path| {
+            plot_return_distribution(path, &returns(1, 0.02), -0.03, -0.035, &viz)
+        });
+        assert!(distribution > 0);
+    }
+
+    #[test]
+    fn overlaid_return_distributions_render() {
+        let series = [
+            ("Tangency".to_string(), returns(2, 0.02), -0.03, -0.035),
+            ("Min variance".to_string(), returns(3, 0.01), -0.015, -0.018),
+        ];
+        let size = rendered_size("portfolio_distributions", |path| {
+            plot_return_distributions(path, &series, &VizSettings::default())
+        });
+        assert!(size > 0);
+    }
+
+    #[test]
+    fn the_cal_endpoints_are_in_the_displayed_scale() {
+        let (_, results) = two_asset_results();
+        let (std, ret) = (results.optimal_risky_std, results.optimal_risky_return);
+        let flat = CalRates {
+            lending: 0.0001,
+            borrowing: 0.0001,
+        };
+        let assert_point = |actual: (f64, f64), expected: (f64, f64)| {
+            assert!(
+                (actual.0 - expected.0).abs() < 1e-12 && (actual.1 - expected.1).abs() < 1e-12,
+                "{:?} instead of {:?}",
+                actual,
+                expected
+            );
+        };
+
+        let per_period = cal_points(&results, flat, FrontierScale::new(false, 252.0), false);
+        assert_point(per_period[0], (0.0, 0.0001));
+        assert_point(per_period[1], (std, ret));
+        assert_point(per_period[2], (std, ret));
+
+        // Annualized, the anchor is the annual rate and the tangency scales with it
+        let annualized = cal_points(&results, flat, FrontierScale::new(true, 252.0), false);
+        assert_point(annualized[0], (0.0, 0.0252));
+        assert_point(annualized[1], (std * 252f64.sqrt(), ret * 252.0));
+
+        // Extended to the leverage of the frontier end, along the same line
+        let leverage = 1.0 - results.frontier.last().unwrap().risk_free_weight;
+        assert!(leverage > 1.0, "{}", leverage);
+        let extended = cal_points(&results, flat, FrontierScale::new(false, 252.0), true);
+        assert_point(
+            extended[2],
+            (leverage * std, 0.0001 + leverage * (ret - 0.0001)),
+        );
+        // Borrowing above the lending rate bends the leveraged part down
+        let kinked = CalRates {
+            lending: 0.0001,
+            borrowing: 0.0002,
+        };
+        let bent = cal_points(&results, kinked, FrontierScale::new(false, 252.0), true);
+        assert_point(
+            bent[2],
+            (leverage * std, extended[2].1 - (leverage - 1.0) * 0.0001),
+        );
+    }
+
+    #[test]
+    fn exporting_the_plot_data_writes_the_frontier_csv_next_to_the_image() {
+        let (stats, results) = two_asset_results();
+        let risky_frontier = crate::optimization::min_variance_frontier(
+            &stats,
+            0.0001,
+            10,
+            crate::optimization::FrontierSpacing::Uniform,
+        )
+        .unwrap();
+        let viz = VizSettings {
+            export_plot_data: true,
+            ..VizSettings::default()
+        };
+        let rates = CalRates {
+            lending: 0.0001,
+            borrowing: 0.0001,
+        };
+        let dir = std::env::temp_dir().join(format!("quars-plot-data-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("efficient_frontier.png");
+        plot_efficient_frontier(
+            path.to_str().unwrap(),
+            &results,
+            &risky_frontier,
+            None,
+            rates,
+            Some(&stats.covariance),
+            252.0,
+            &viz,
+        )
+        .unwrap();
+        assert!(fs::metadata(&path).unwrap().len() > 0);
+        let csv = fs::read_to_string(dir.join("efficient_frontier.csv")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut lines = csv.lines();
+        assert_eq!(
+            lines.next(),
+            Some("series,risk,expected_return,risk_free_weight,diversification_ratio")
+        );
+        let rows: Vec<Vec<&str>> = lines.map(|line| line.split(',').collect()).collect();
+        assert!(rows.iter().all(|row| row.len() == 5));
+        let series = |name: &str| rows.iter().filter(|row| row[0] == name).collect::<Vec<_>>();
+        assert_eq!(series("frontier").len(), results.frontier.len());
+        assert_eq!(series("min_variance_frontier").len(), risky_frontier.len());
+        // Only the minimum-variance frontier carries a diversification ratio
+        assert!(series("min_variance_frontier")
+            .iter()
+            .all(|row| row[4].parse::<f64>().unwrap() >= 1.0));
+        assert!(series("frontier")
+            .iter()
+            .all(|row| row[4].is_empty() && !row[3].is_empty()));
+        assert_eq!(series("tangency").len(), 1);
+    }
+}